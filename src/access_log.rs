@@ -0,0 +1,149 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::server::ServerKind;
+
+tokio::task_local! {
+    /// Access log entry of the request being handled by the current task
+    static ACCESS_LOG: Arc<Mutex<AccessLogEntry>>;
+}
+
+/// Structured fields of the access log line written when a request completes
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct AccessLogEntry {
+    pub request_id: String,
+    pub route: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downstream_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downstream_id: Option<String>,
+    pub status: u16,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// Run the handling of a request with its access log entry, so that the handlers can record the
+/// user and the downstream server of the request. Returns the output of the handling and the
+/// entry.
+pub(crate) async fn scope<F: Future>(entry: AccessLogEntry, fut: F) -> (F::Output, AccessLogEntry) {
+    let entry = Arc::new(Mutex::new(entry));
+    let output = ACCESS_LOG.scope(entry.clone(), fut).await;
+    let entry = entry.lock().unwrap().clone();
+    (output, entry)
+}
+
+/// Spawn a task that keeps recording to the access log entry of the request being handled
+pub(crate) fn spawn<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match ACCESS_LOG.try_with(|entry| entry.clone()) {
+        Ok(entry) => tokio::spawn(ACCESS_LOG.scope(entry, fut)),
+        Err(_) => tokio::spawn(fut),
+    }
+}
+
+/// Record the user who sent the request being handled
+pub(crate) fn record_user(user: Option<&str>) {
+    let _ = ACCESS_LOG.try_with(|entry| {
+        entry.lock().unwrap().user = user.map(|user| user.to_string());
+    });
+}
+
+/// Record the downstream server the request being handled was forwarded to
+pub(crate) fn record_downstream(kind: ServerKind, id: &str, url: &str) {
+    replay_downstream(&Downstream {
+        kind: kind.to_string(),
+        id: id.to_string(),
+        url: url.to_string(),
+    });
+}
+
+/// Downstream server that produced a response, replayed with the response when it is shared
+/// with other requests
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Downstream {
+    pub kind: String,
+    pub id: String,
+    pub url: String,
+}
+
+/// The downstream server recorded for the request being handled
+pub(crate) fn downstream() -> Option<Downstream> {
+    ACCESS_LOG
+        .try_with(|entry| {
+            let entry = entry.lock().unwrap();
+            Some(Downstream {
+                kind: entry.server_kind.clone()?,
+                id: entry.downstream_id.clone()?,
+                url: entry.downstream_url.clone()?,
+            })
+        })
+        .ok()
+        .flatten()
+}
+
+/// Record the downstream server of a response replayed for the request being handled
+pub(crate) fn replay_downstream(downstream: &Downstream) {
+    let _ = ACCESS_LOG.try_with(|entry| {
+        let mut entry = entry.lock().unwrap();
+        entry.server_kind = Some(downstream.kind.clone());
+        entry.downstream_url = Some(downstream.url.clone());
+        entry.downstream_id = Some(downstream.id.clone());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{LOG_FORMAT, LogFormat, format_log_line};
+
+    #[tokio::test]
+    async fn test_access_log_line_is_json() {
+        let _ = LOG_FORMAT.set(LogFormat::Json);
+
+        let entry = AccessLogEntry {
+            request_id: "req-1".to_string(),
+            route: "/v1/chat/completions".to_string(),
+            ..Default::default()
+        };
+        let (_, mut entry) = scope(entry, async {
+            record_user(Some("alice"));
+            record_downstream(ServerKind::chat, "chat-server", "http://localhost:8080/v1");
+        })
+        .await;
+        entry.status = 200;
+        entry.latency_ms = 42;
+
+        let fields = serde_json::to_value(&entry).ok();
+        let line = format_log_line("INFO", "Request completed", fields);
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        for key in [
+            "timestamp",
+            "level",
+            "message",
+            "request_id",
+            "route",
+            "server_kind",
+            "downstream_url",
+            "downstream_id",
+            "status",
+            "latency_ms",
+            "user",
+        ] {
+            assert!(line.get(key).is_some(), "missing key: {key}");
+        }
+        assert_eq!(line["user"], "alice");
+        assert_eq!(line["server_kind"], "chat");
+        assert_eq!(line["status"], 200);
+    }
+}
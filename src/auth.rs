@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::State,
+    http::{HeaderMap, HeaderValue, Request, header::AUTHORIZATION},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    config::ApiKeyAuthConfig,
+    dual_error, dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+};
+
+/// Paths that are reachable without an API key
+const UNAUTHENTICATED_PATHS: [&str; 2] = ["/health", "/ready"];
+
+/// Header carrying the hash of the validated API key of the gateway to the handlers, which never
+/// see the key itself
+pub(crate) const API_KEY_ID_HEADER: &str = "x-llama-nexus-key-id";
+
+/// Identity of the caller of a request: the hash of the API key of the gateway and of the
+/// `Authorization` header forwarded to the downstream servers. None for an anonymous caller.
+pub(crate) fn caller_identity(headers: &HeaderMap) -> Option<String> {
+    let key_id = headers.get(API_KEY_ID_HEADER);
+    let authorization = headers.get(AUTHORIZATION);
+    if key_id.is_none() && authorization.is_none() {
+        return None;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    key_id.map(HeaderValue::as_bytes).hash(&mut hasher);
+    authorization.map(HeaderValue::as_bytes).hash(&mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+/// The set of API keys allowed to call the gateway
+#[derive(Debug)]
+pub(crate) struct ApiKeyAuth {
+    keys: HashSet<String>,
+}
+impl ApiKeyAuth {
+    /// Load the allowed keys from the config. Returns None if authentication is disabled.
+    pub(crate) fn from_config(config: Option<&ApiKeyAuthConfig>) -> ServerResult<Option<Self>> {
+        let Some(config) = config.filter(|config| config.enable) else {
+            return Ok(None);
+        };
+
+        let mut keys: HashSet<String> = config
+            .api_keys
+            .iter()
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .collect();
+
+        if let Some(path) = &config.api_keys_file {
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                let err_msg = format!("Failed to read the API keys file {path}: {e}");
+                dual_error!("{}", &err_msg);
+                ServerError::FailedToLoadConfig(err_msg)
+            })?;
+            keys.extend(
+                content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+
+        if keys.is_empty() {
+            let err_msg = "API key authentication is enabled but no API key is configured";
+            dual_error!("{}", err_msg);
+            return Err(ServerError::FailedToLoadConfig(err_msg.to_string()));
+        }
+
+        dual_info!("API key authentication is enabled with {} keys", keys.len());
+
+        Ok(Some(Self { keys }))
+    }
+
+    fn is_allowed(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+}
+
+/// Require a valid API key on every request of the router if authentication is enabled
+pub(crate) fn with_api_key_auth(router: Router, auth: Option<Arc<ApiKeyAuth>>) -> Router {
+    match auth {
+        Some(auth) => router.layer(axum::middleware::from_fn_with_state(auth, require_api_key)),
+        None => router,
+    }
+}
+
+/// Validate the API key of the request. The key is removed from the request once validated, so
+/// that the keys of the gateway are never forwarded to the downstream servers.
+async fn require_api_key(
+    State(auth): State<Arc<ApiKeyAuth>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if UNAUTHENTICATED_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    let key = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim);
+
+    match key {
+        Some(key) if auth.is_allowed(key) => {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            let key_id = HeaderValue::from_str(&format!("{:016x}", hasher.finish())).unwrap();
+
+            req.headers_mut().remove(AUTHORIZATION);
+            req.headers_mut().insert(API_KEY_ID_HEADER, key_id);
+            next.run(req).await
+        }
+        Some(_) => {
+            dual_warn!("Reject a request with an invalid API key: {}", req.uri());
+            ServerError::Unauthorized("Invalid API key".to_string()).into_response()
+        }
+        None => {
+            dual_warn!("Reject a request without an API key: {}", req.uri());
+            ServerError::Unauthorized(
+                "Missing API key. Please set the `Authorization: Bearer <key>` header".to_string(),
+            )
+            .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn create_router(enable: bool) -> Router {
+        let config: ApiKeyAuthConfig = serde_json::from_value(serde_json::json!({
+            "enable": enable,
+            "api_keys": ["valid-key"],
+        }))
+        .unwrap();
+        let auth = ApiKeyAuth::from_config(Some(&config))
+            .unwrap()
+            .map(Arc::new);
+
+        let router = Router::new()
+            .route(
+                "/v1/models",
+                get(|headers: axum::http::HeaderMap| async move {
+                    // the key of the gateway must not reach the handlers
+                    assert!(!headers.contains_key(AUTHORIZATION));
+                    "models"
+                }),
+            )
+            .route("/health", get(|| async { "ok" }));
+        with_api_key_auth(router, auth)
+    }
+
+    async fn send(router: Router, path: &str, key: Option<&str>) -> StatusCode {
+        let mut req = Request::builder().uri(path);
+        if let Some(key) = key {
+            req = req.header(AUTHORIZATION, format!("Bearer {key}"));
+        }
+        router
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth() {
+        let router = create_router(true);
+
+        assert_eq!(
+            send(router.clone(), "/v1/models", Some("valid-key")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(router.clone(), "/v1/models", Some("invalid-key")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(router.clone(), "/v1/models", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(send(router, "/health", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth_disabled() {
+        let router = create_router(false);
+
+        assert_eq!(send(router, "/v1/models", None).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,86 @@
+use endpoints::chat::{
+    ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionUserMessageContent,
+};
+
+use crate::{
+    AppState,
+    chat::{downstream_body, react, utils::*},
+    config::ChatMode,
+    dual_debug, dual_error, dual_warn,
+    error::{ServerError, ServerResult},
+};
+
+/// Assemble the request that would be sent to the downstream chat server, without sending it
+///
+/// The messages are rebuilt from the conversation memory of the user the way the chat modes
+/// rebuild them, but neither the user message nor the system message is stored, and no
+/// conversation is created for a new user.
+pub(crate) async fn assemble_request(
+    state: &AppState,
+    mut request: ChatCompletionRequest,
+    memory_enabled: bool,
+    chat_mode: ChatMode,
+    react_system_prompt: Option<String>,
+    request_id: &str,
+) -> ServerResult<serde_json::Value> {
+    if let Some(memory) = state.memory.as_ref().filter(|_| memory_enabled)
+        && let Some(user) = &request.user
+        && let Some(user_message) = extract_user_message(&request)
+    {
+        let system_message = extract_system_message(&request);
+        let conv_id = memory
+            .find_user_conversation(user)
+            .await
+            .unwrap_or_else(|e| {
+                dual_warn!(
+                    "Failed to find the conversation of user {}: {} - request_id: {}",
+                    user,
+                    e,
+                    request_id
+                );
+                None
+            });
+
+        request.messages = match conv_id {
+            Some(conv_id) => memory
+                .preview_model_context(&conv_id, system_message.as_deref(), &user_message)
+                .await
+                .map_err(|e| {
+                    let err_msg = format!("Failed to get model context: {e}");
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    ServerError::Operation(err_msg)
+                })?
+                .into_iter()
+                .map(|model_msg| model_msg.into())
+                .collect(),
+            // a new conversation holds only the system message and the user message
+            None => system_message
+                .iter()
+                .map(|sys_msg| ChatCompletionRequestMessage::new_system_message(sys_msg, None))
+                .chain(std::iter::once(
+                    ChatCompletionRequestMessage::new_user_message(
+                        ChatCompletionUserMessageContent::Text(user_message),
+                        None,
+                    ),
+                ))
+                .collect(),
+        };
+    }
+
+    if let ChatMode::React = chat_mode {
+        request.stream = Some(false);
+        react::add_configured_react_system_prompt(
+            state,
+            &mut request.messages,
+            react_system_prompt,
+        )
+        .await;
+    }
+
+    dual_debug!(
+        "Return the assembled chat request of the dry run - request_id: {}",
+        request_id
+    );
+
+    Ok(downstream_body(&request))
+}
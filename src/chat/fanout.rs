@@ -0,0 +1,325 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Response, StatusCode},
+};
+use endpoints::{
+    chat::{ChatCompletionObject, ChatCompletionRequest},
+    common::Usage,
+};
+use futures_util::{StreamExt, stream::FuturesUnordered};
+use reqwest::header::CONTENT_TYPE;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    AppState,
+    chat::utils::send_chat_request,
+    config::FanoutPartialPolicy,
+    dual_error, dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+};
+
+/// Returns the fan-out concurrency and partial failure policy if `n > 1` is emulated
+pub(super) async fn fanout_config(state: &AppState) -> Option<(usize, FanoutPartialPolicy)> {
+    let config = state.config.read().await;
+    let chat_config = config.chat.as_ref()?;
+    chat_config
+        .n_fanout_concurrency
+        .map(|concurrency| (concurrency, chat_config.n_fanout_partial_policy))
+}
+
+/// Emulate a chat request with `n > 1` by sending `n` single-choice requests in parallel
+///
+/// At most `concurrency` requests are in flight at a time. The choices keep the index of the
+/// request that produced them, and the usage of all requests is summed up.
+pub(super) async fn fanout_chat(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    request: &ChatCompletionRequest,
+    concurrency: usize,
+    partial_policy: FanoutPartialPolicy,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<(ChatCompletionObject, Option<String>)> {
+    let n = request.n_choice.unwrap_or(1) as usize;
+
+    // every emulated request asks for a single choice
+    let mut single_request = serde_json::to_value(request).map_err(|e| {
+        let err_msg = format!("Failed to serialize the chat request: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })?;
+    single_request["n"] = 1.into();
+    let single_request: ChatCompletionRequest =
+        serde_json::from_value(single_request).map_err(|e| {
+            let err_msg = format!("Failed to build the single-choice chat request: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+
+    dual_info!(
+        "Emulate n={} with parallel completions (concurrency: {}) - request_id: {}",
+        n,
+        concurrency,
+        request_id
+    );
+
+    let semaphore = Semaphore::new(concurrency.max(1));
+    let mut pending: FuturesUnordered<_> = (0..n)
+        .map(|index| {
+            let semaphore = &semaphore;
+            let single_request = &single_request;
+            async move {
+                let _permit = semaphore.acquire().await.unwrap();
+                let result =
+                    send_single_request(state, headers, single_request, cancel_token, request_id)
+                        .await;
+                (index, result)
+            }
+        })
+        .collect();
+
+    // aggregate the results as they complete
+    let mut completions = Vec::with_capacity(n);
+    let mut errors = Vec::new();
+    while let Some((index, result)) = pending.next().await {
+        match result {
+            Ok(completion) => completions.push((index, completion)),
+            Err(e) => {
+                dual_warn!(
+                    "Completion {} of {} failed: {} - request_id: {}",
+                    index,
+                    n,
+                    e,
+                    request_id
+                );
+                errors.push(e);
+            }
+        }
+    }
+
+    if completions.is_empty() || (!errors.is_empty() && partial_policy == FanoutPartialPolicy::Fail)
+    {
+        let err_msg = format!(
+            "{} of {} completions failed. The first error: {}",
+            errors.len(),
+            n,
+            errors[0]
+        );
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg));
+    }
+
+    let warning = match errors.is_empty() {
+        true => None,
+        false => {
+            let warn_msg = format!(
+                "{} of {} completions failed; returning the {} that succeeded",
+                errors.len(),
+                n,
+                completions.len()
+            );
+            dual_warn!("{} - request_id: {}", warn_msg, request_id);
+            Some(warn_msg)
+        }
+    };
+
+    completions.sort_by_key(|(index, _)| *index);
+    let prompt_tokens = completions[0].1.usage.prompt_tokens;
+    let completion_tokens = completions
+        .iter()
+        .map(|(_, completion)| completion.usage.completion_tokens)
+        .sum::<u64>();
+
+    let choices = completions
+        .iter_mut()
+        .filter_map(|(index, completion)| {
+            let mut choice =
+                (!completion.choices.is_empty()).then(|| completion.choices.remove(0))?;
+            choice.index = *index as u32;
+            Some(choice)
+        })
+        .collect();
+    let (_, mut chat_completion) = completions.swap_remove(0);
+    chat_completion.choices = choices;
+    chat_completion.usage = Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    };
+
+    Ok((chat_completion, warning))
+}
+
+/// Build the JSON response of an emulated `n > 1` request, with the warning of a partial failure
+pub(super) fn fanout_response(
+    chat_completion: &ChatCompletionObject,
+    warning: Option<String>,
+    request_id: &str,
+) -> ServerResult<axum::response::Response> {
+    let mut body = serde_json::to_value(chat_completion).map_err(|e| {
+        let err_msg = format!("Failed to serialize the chat completion: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })?;
+    if let Some(warning) = warning {
+        body["warning"] = warning.into();
+    }
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .status(StatusCode::OK)
+        .body(Body::from(body.to_string()))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create the response: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })
+}
+
+async fn send_single_request(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    request: &ChatCompletionRequest,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<ChatCompletionObject> {
+    let (_, response) =
+        send_chat_request(state, headers, request, cancel_token, request_id).await?;
+
+    let status = response.status();
+    if status != StatusCode::OK {
+        let body = response.text().await.unwrap_or_default();
+        return Err(ServerError::Operation(format!(
+            "The downstream chat server returned {status}: {body}"
+        )));
+    }
+
+    response.json::<ChatCompletionObject>().await.map_err(|e| {
+        let err_msg = format!("Failed to parse the chat completion: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use axum::{Json, Router, extract::State, routing::post};
+
+    use super::*;
+    use crate::{
+        chat::normal::chat,
+        config::Config,
+        test_utils::{chat_completion_json, create_test_state, spawn_mock_server},
+    };
+
+    #[derive(Default)]
+    struct MockCounters {
+        hits: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    /// Spawn a chat server that fails its second request and tracks the number of requests in flight
+    async fn spawn_chat_server_with_one_failure(counters: Arc<MockCounters>) -> String {
+        let router = Router::new()
+            .route(
+                "/v1/chat/completions",
+                post(|State(counters): State<Arc<MockCounters>>| async move {
+                    let hit = counters.hits.fetch_add(1, Ordering::SeqCst);
+                    let in_flight = counters.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    counters
+                        .max_in_flight
+                        .fetch_max(in_flight, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    match hit {
+                        1 => Err((StatusCode::INTERNAL_SERVER_ERROR, "simulated failure")),
+                        _ => Ok(Json(chat_completion_json(&format!("answer {hit}")))),
+                    }
+                }),
+            )
+            .with_state(counters);
+        spawn_mock_server(router).await
+    }
+
+    async fn run_fanout_chat(
+        policy: &str,
+        counters: Arc<MockCounters>,
+    ) -> ServerResult<axum::response::Response> {
+        let url = spawn_chat_server_with_one_failure(counters).await;
+        let config = Config {
+            chat: Some(
+                serde_json::from_value(serde_json::json!({
+                    "url": url,
+                    "api_key": "",
+                    "n_fanout_concurrency": 2,
+                    "n_fanout_partial_policy": policy,
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "Tell me a joke." }],
+            "n": 4,
+        }))
+        .unwrap();
+
+        chat(
+            State(state),
+            axum::Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            "test-request",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_partial_failure_returns_succeeded_choices() {
+        let counters = Arc::new(MockCounters::default());
+        let response = run_fanout_chat("partial", counters.clone()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        let choices = body["choices"].as_array().unwrap();
+        assert_eq!(choices.len(), 3);
+        let indices: Vec<u64> = choices
+            .iter()
+            .map(|choice| choice["index"].as_u64().unwrap())
+            .collect();
+        assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(indices.iter().all(|index| *index < 4));
+        assert!(body["warning"].as_str().unwrap().contains("1 of 4"));
+        assert_eq!(body["usage"]["prompt_tokens"], 10);
+        assert_eq!(body["usage"]["completion_tokens"], 15);
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 4);
+        assert!(counters.max_in_flight.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[tokio::test]
+    async fn test_partial_failure_fails_with_fail_policy() {
+        let counters = Arc::new(MockCounters::default());
+        let result = run_fanout_chat("fail", counters.clone()).await;
+
+        assert!(matches!(result, Err(ServerError::Operation(msg)) if msg.contains("1 of 4")));
+        assert_eq!(counters.hits.load(Ordering::SeqCst), 4);
+    }
+}
@@ -1,7 +1,86 @@
+mod dry_run;
+mod fanout;
 pub mod normal;
+mod postprocess;
 pub mod react;
 mod utils;
 
+pub(crate) use dry_run::assemble_request;
+pub(crate) use postprocess::postprocess_answer;
+pub(crate) use utils::{SseContentCollector, include_usage, send_chat_request, sse_with_keepalive};
+
+use endpoints::chat::ChatCompletionRequest;
+use serde::{Deserialize, Serialize};
+
+/// Header that keeps a chat request out of the conversation memory when set to `true` or `1`
+pub(crate) const DISABLE_MEMORY_HEADER: &str = "x-disable-memory";
+
+/// Header that turns a chat request into a dry run when set to `true` or `1`
+pub(crate) const DRY_RUN_HEADER: &str = "x-dry-run";
+
+/// A chat request, as accepted by the `/v1/chat/completions` endpoint
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ChatRequest {
+    #[serde(flatten)]
+    pub request: ChatCompletionRequest,
+    /// Set to false to neither read nor record the conversation memory for this request
+    #[serde(default)]
+    pub memory: Option<bool>,
+    /// Set to true to return the thought/action/observation trace of the ReAct loop in a
+    /// `reasoning` field of the response. Only used in react mode.
+    #[serde(default)]
+    pub include_reasoning: Option<bool>,
+    /// System prompt teaching the model the ReAct format, overriding the configured one for
+    /// this request. Only used in react mode.
+    #[serde(default)]
+    pub react_system_prompt: Option<String>,
+    /// Seed for reproducible sampling, forwarded to the downstream chat servers
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Set to true to return the request that would be sent to the downstream chat server
+    /// instead of sending it. The conversation memory is read but not recorded.
+    #[serde(default)]
+    pub dry_run: Option<bool>,
+}
+
+tokio::task_local! {
+    /// Seed of the chat request being handled by the current task
+    static SEED: Option<u64>;
+    /// User id sent by the client of the chat request being handled by the current task
+    static CLIENT_USER: Option<String>;
+}
+
+/// Handle a chat request with the user id sent by its client, None for an anonymous client.
+/// The usage is only accounted to such users, not to the ids generated for anonymous clients.
+pub(crate) async fn with_client_user<F: Future>(user: Option<String>, fut: F) -> F::Output {
+    CLIENT_USER.scope(user, fut).await
+}
+
+/// The user id sent by the client of the chat request being handled
+pub(super) fn client_user() -> Option<String> {
+    CLIENT_USER.try_with(|user| user.clone()).ok().flatten()
+}
+
+/// Handle a chat request with its seed, so that every request sent to the downstream chat
+/// servers on its behalf carries it
+pub(crate) async fn with_seed<F: Future>(seed: Option<u64>, fut: F) -> F::Output {
+    SEED.scope(seed, fut).await
+}
+
+/// Build the JSON body of a request to a downstream chat server
+///
+/// `ChatCompletionRequest` has no `seed` field, so the seed of the chat request being handled
+/// is added to the body.
+pub(super) fn downstream_body(request: &ChatCompletionRequest) -> serde_json::Value {
+    let mut body = serde_json::to_value(request).unwrap_or_default();
+    if let Ok(Some(seed)) = SEED.try_with(|seed| *seed)
+        && let Some(body) = body.as_object_mut()
+    {
+        body.insert("seed".to_string(), seed.into());
+    }
+    body
+}
+
 // Generate a unique chat id for the chat completion request
 pub(crate) fn gen_chat_id() -> String {
     format!("chatcmpl-{}", uuid::Uuid::new_v4())
@@ -12,24 +12,34 @@ use endpoints::{
         ChatCompletionAssistantMessage, ChatCompletionChunk, ChatCompletionChunkChoice,
         ChatCompletionChunkChoiceDelta, ChatCompletionObject, ChatCompletionRequest,
         ChatCompletionRequestMessage, ChatCompletionRole, ChatCompletionToolMessage,
-        ChatCompletionUserMessageContent, Function, ToolCall, ToolChoice,
+        ChatCompletionUserMessageContent, Function, StreamOptions, ToolCall, ToolChoice,
     },
     common::FinishReason,
 };
 use futures_util::{StreamExt, stream};
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-use rmcp::model::{CallToolRequestParam, RawContent};
+use rmcp::model::CallToolRequestParam;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
     AppState,
-    chat::{gen_chat_id, utils::*},
+    chat::{
+        client_user, downstream_body,
+        fanout::{fanout_chat, fanout_config, fanout_response},
+        gen_chat_id,
+        utils::*,
+    },
+    config::RequiredToolMissingPolicy,
     dual_debug, dual_error, dual_info, dual_warn,
     error::{ServerError, ServerResult},
-    mcp::{DEFAULT_SEARCH_FALLBACK_MESSAGE, MCP_SEPARATOR, MCP_SERVICES, SEARCH_MCP_SERVER_NAMES},
+    mcp::{
+        DEFAULT_SEARCH_FALLBACK_MESSAGE, MCP_SEPARATOR, MCP_SERVICES, SEARCH_MCP_SERVER_NAMES,
+        call_tool_with_retry, check_tool_allowed, content_to_text, resolve_tool_name,
+    },
     memory::{ModelRole, ModelToolCall, StoredToolCall},
-    server::{RoutingPolicy, ServerKind, TargetServerInfo},
+    request_id::REQUEST_ID_HEADER,
+    server::TargetServerInfo,
 };
 
 pub(crate) async fn chat(
@@ -48,9 +58,6 @@ pub(crate) async fn chat(
     // Extract system message for memory storage
     let system_message = extract_system_message(&request);
 
-    // Get target server
-    let chat_server = get_chat_server(&state, request_id).await?;
-
     // Store the latest user message to memory
     if let Some(memory) = &state.memory
         && let Some(conv_id) = &conv_id
@@ -117,69 +124,140 @@ pub(crate) async fn chat(
         }
     }
 
-    // set non-stream mode
+    // stream the answer of the downstream server straight through if no tool can be called
     let stream = request.stream.unwrap_or(false);
-    if stream {
-        request.stream = Some(false);
+    let has_tools = request
+        .tools
+        .as_ref()
+        .is_some_and(|tools| !tools.is_empty());
+    if stream && !has_tools {
+        return stream_chat(
+            &state,
+            &headers,
+            request,
+            conv_id,
+            &cancel_token,
+            request_id,
+        )
+        .await;
     }
 
-    // Build and send request
-    let response = {
-        let url = format!("{}/chat/completions", chat_server.url.trim_end_matches('/'));
-        let mut client = reqwest::Client::new().post(&url);
-
-        // Add common headers
-        client = client.header(CONTENT_TYPE, "application/json");
-
-        // Add authorization header
-        if let Some(api_key) = &chat_server.api_key
-            && !api_key.is_empty()
-        {
-            let auth_info = if api_key.starts_with("Bearer ") {
-                api_key.clone()
-            } else {
-                format!("Bearer {api_key}")
-            };
-
-            dual_info!("auth_info: {}", &auth_info);
-
-            client = client.header(AUTHORIZATION, auth_info);
-        } else if let Some(auth) = headers.get("authorization")
-            && let Ok(auth_str) = auth.to_str()
+    // emulate `n > 1` with parallel single-choice completions if configured
+    if !stream
+        && !has_tools
+        && request.n_choice.unwrap_or(1) > 1
+        && let Some((concurrency, partial_policy)) = fanout_config(&state).await
+    {
+        let (chat_completion, warning) = fanout_chat(
+            &state,
+            &headers,
+            &request,
+            concurrency,
+            partial_policy,
+            &cancel_token,
+            request_id,
+        )
+        .await?;
+        state.record_usage(client_user().as_deref(), &chat_completion.usage);
+
+        // Store the first choice to memory
+        if let Some(memory) = &state.memory
+            && let Some(conv_id) = &conv_id
+            && let Some(content) = chat_completion.choices[0].message.content.as_deref()
+            && let Err(e) = memory.add_assistant_message(conv_id, content, vec![]).await
         {
-            client = client.header(AUTHORIZATION, auth_str);
+            dual_error!(
+                "Failed to add assistant message to memory: {} - request_id: {}",
+                e,
+                request_id
+            );
         }
 
-        dual_info!(
-            "Request to downstream chat server - request_id: {}\n{}",
-            request_id,
-            serde_json::to_string_pretty(&request).unwrap()
-        );
+        return fanout_response(&chat_completion, warning, request_id);
+    }
 
-        // Use select! to support cancellation
-        let response = select! {
-            response = client.json(&request).send() => {
-                response.map_err(|e| ServerError::Operation(format!("Failed to forward request: {e}")))
-            }
-            _ = cancel_token.cancelled() => {
-                let warn_msg = "Request was cancelled by client";
-                dual_warn!("{}", warn_msg);
-                Err(ServerError::Operation(warn_msg.to_string()))
-            }
-        };
+    // set non-stream mode, so that tool calls can be intercepted
+    if stream {
+        request.stream = Some(false);
+    }
 
-        response?
-    };
+    // Build and send request
+    dual_info!(
+        "Request to downstream chat server - request_id: {}\n{}",
+        request_id,
+        serde_json::to_string_pretty(&request).unwrap()
+    );
+    let (chat_server, response) =
+        send_chat_request(&state, &headers, &request, &cancel_token, request_id).await?;
 
     // check the status code
     let status = response.status();
     let response_result = match status {
         StatusCode::OK => {
-            let response_headers = response.headers().clone();
+            let mut response_headers = response.headers().clone();
 
             // Read the response body
-            let bytes = read_response_bytes(response, request_id, cancel_token.clone()).await?;
-            let chat_completion = parse_chat_completion(&bytes, request_id)?;
+            let mut bytes = read_response_bytes(response, request_id, cancel_token.clone()).await?;
+            let mut chat_completion = parse_chat_completion(&bytes, request_id)?;
+            state.record_usage(client_user().as_deref(), &chat_completion.usage);
+
+            // Enforce `tool_choice` if it requires a tool call but the model answered directly
+            if chat_completion.choices[0].message.tool_calls.is_empty()
+                && is_tool_call_required(&request)
+            {
+                let warn_msg =
+                    "The model did not call any tool although `tool_choice` requires a tool call";
+                match get_required_tool_missing_policy(&state).await {
+                    RequiredToolMissingPolicy::Ignore => {
+                        dual_warn!("{} - request_id: {}", warn_msg, request_id);
+                    }
+                    RequiredToolMissingPolicy::Reject => {
+                        dual_error!("{} - request_id: {}", warn_msg, request_id);
+                        return Err(ServerError::RequiredToolCallMissing);
+                    }
+                    RequiredToolMissingPolicy::Retry => {
+                        dual_warn!(
+                            "{}. Retry with a stronger instruction - request_id: {}",
+                            warn_msg,
+                            request_id
+                        );
+
+                        // the instruction only applies to the retried request
+                        request
+                            .messages
+                            .push(ChatCompletionRequestMessage::new_system_message(
+                                REQUIRED_TOOL_CALL_INSTRUCTION,
+                                None,
+                            ));
+                        let retry_response = send_chat_request(
+                            &state,
+                            &headers,
+                            &request,
+                            &cancel_token,
+                            request_id,
+                        )
+                        .await;
+                        request.messages.pop();
+                        let (_, retry_response) = retry_response?;
+
+                        if retry_response.status() != StatusCode::OK {
+                            return forward_error_response(retry_response, request_id).await;
+                        }
+
+                        response_headers = retry_response.headers().clone();
+                        bytes =
+                            read_response_bytes(retry_response, request_id, cancel_token.clone())
+                                .await?;
+
+                        chat_completion = parse_chat_completion(&bytes, request_id)?;
+                        state.record_usage(client_user().as_deref(), &chat_completion.usage);
+                        if chat_completion.choices[0].message.tool_calls.is_empty() {
+                            dual_error!("{} after retry - request_id: {}", warn_msg, request_id);
+                            return Err(ServerError::RequiredToolCallMissing);
+                        }
+                    }
+                }
+            }
 
             // Check if the response requires tool call
             let requires_tool_call = !chat_completion.choices[0].message.tool_calls.is_empty();
@@ -226,6 +304,7 @@ pub(crate) async fn chat(
                     return Err(ServerError::Operation(err_msg));
                 }
             } else {
+                // Only the first choice is stored to memory; all choices are returned
                 let assistant_msg = match &chat_completion.choices[0].message.content {
                     Some(content) if !content.is_empty() => content.clone(),
                     _ => String::new(),
@@ -248,19 +327,40 @@ pub(crate) async fn chat(
                 // Return chat completion
                 match stream {
                     true => {
-                        let chunks = gen_chunks_with_formatting(&assistant_msg, 10);
+                        // split the content of every choice into chunks, the last chunk of a
+                        // choice carrying its finish reason
+                        let mut chunks = Vec::new();
+                        for choice in chat_completion.choices.iter() {
+                            let content = choice.message.content.as_deref().unwrap_or_default();
+                            let mut choice_chunks = gen_chunks_with_formatting(content, 10);
+                            if choice_chunks.is_empty() {
+                                choice_chunks.push(String::new());
+                            }
+                            let last = choice_chunks.len() - 1;
+                            chunks.extend(choice_chunks.into_iter().enumerate().map(
+                                |(i, chunk)| {
+                                    let finish_reason = (i == last).then_some(FinishReason::stop);
+                                    (choice.index, chunk, finish_reason)
+                                },
+                            ));
+                        }
                         let id = match &request.user {
                             Some(id) => id.clone(),
                             None => gen_chat_id(),
                         };
                         let model = chat_completion.model.clone();
-                        let usage = chat_completion.usage;
-                        let chunks_len = chunks.len();
+                        let usage_event = include_usage(&request).then(|| {
+                            let created = SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .map(|created| created.as_secs())
+                                .unwrap_or(chat_completion.created);
+                            usage_event(&id, created, &model, chat_completion.usage)
+                        });
 
                         // Create SSE stream
                         let request_id_owned = request_id.to_string();
-                        let stream =
-                            stream::iter(chunks.into_iter().enumerate().map(move |(i, chunk)| {
+                        let stream = stream::iter(chunks.into_iter().map(
+                            move |(index, chunk, finish_reason)| {
                                 let created = SystemTime::now()
                                     .duration_since(std::time::UNIX_EPOCH)
                                     .map_err(|e| {
@@ -277,40 +377,33 @@ pub(crate) async fn chat(
                                     })
                                     .unwrap();
 
-                                let mut chat_completion_chunk = ChatCompletionChunk {
+                                let chat_completion_chunk = ChatCompletionChunk {
                                     id: id.clone(),
                                     object: "chat.completion.chunk".to_string(),
                                     created: created.as_secs(),
                                     model: model.clone(),
                                     system_fingerprint: "fp_44709d6fcb".to_string(),
                                     choices: vec![ChatCompletionChunkChoice {
-                                        index: i as u32,
+                                        index,
                                         delta: ChatCompletionChunkChoiceDelta {
                                             role: ChatCompletionRole::Assistant,
                                             content: Some(chunk),
                                             tool_calls: vec![],
                                         },
                                         logprobs: None,
-                                        finish_reason: None,
+                                        finish_reason,
                                     }],
                                     usage: None,
                                 };
 
-                                if i == chunks_len - 1 {
-                                    // update finish_reason
-                                    chat_completion_chunk.choices[0].finish_reason =
-                                        Some(FinishReason::stop);
-
-                                    // update usage
-                                    chat_completion_chunk.usage = Some(usage);
-                                }
-
                                 let json_str =
                                     serde_json::to_string(&chat_completion_chunk).unwrap();
                                 format!("data: {json_str}\n\n")
-                            }))
-                            .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
-                            .map(|s| Ok::<_, std::convert::Infallible>(s.into_bytes()));
+                            },
+                        ))
+                        .chain(stream::iter(usage_event))
+                        .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
+                        .map(|s| Ok::<_, std::convert::Infallible>(s.into_bytes()));
 
                         // Build streaming response
                         let response = Response::builder()
@@ -339,22 +432,7 @@ pub(crate) async fn chat(
                 }
             }
         }
-        _ => {
-            // Convert reqwest::Response to axum::Response
-            let status = response.status();
-
-            let err_msg = format!("{status}");
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-
-            let headers = response.headers().clone();
-            let bytes = response.bytes().await.map_err(|e| {
-                let err_msg = format!("Failed to get response bytes: {e}");
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                ServerError::Operation(err_msg)
-            })?;
-
-            build_response(status, headers, bytes, request_id)
-        }
+        _ => forward_error_response(response, request_id).await,
     };
 
     // Print chat history
@@ -402,30 +480,6 @@ pub(crate) async fn chat(
     response_result
 }
 
-async fn get_chat_server(
-    state: &Arc<AppState>,
-    request_id: &str,
-) -> ServerResult<crate::server::TargetServerInfo> {
-    let servers = state.server_group.read().await;
-    let chat_servers = match servers.get(&ServerKind::chat) {
-        Some(servers) => servers,
-        None => {
-            let err_msg = "No chat server available. Please register a chat server via the `/admin/servers/register` endpoint.";
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg.to_string()));
-        }
-    };
-
-    match chat_servers.next().await {
-        Ok(target_server_info) => Ok(target_server_info),
-        Err(e) => {
-            let err_msg = format!("Failed to get the chat server: {e}");
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            Err(ServerError::Operation(err_msg))
-        }
-    }
-}
-
 /// Copy HTTP response headers to response builder
 ///
 /// Selectively copy response headers based on whether it's a streaming response.
@@ -464,6 +518,93 @@ fn copy_response_headers(
         })
 }
 
+/// Forward the streaming chat request and pipe the SSE events of the downstream server
+/// straight through to the client
+///
+/// The content deltas are collected along the way, so that the full answer is stored to memory
+/// once the stream ends.
+async fn stream_chat(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    mut request: ChatCompletionRequest,
+    conv_id: Option<String>,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<axum::response::Response> {
+    dual_info!(
+        "Streaming request to downstream chat server - request_id: {}\n{}",
+        request_id,
+        serde_json::to_string_pretty(&request).unwrap()
+    );
+    // always ask the downstream server for the usage, so that streamed requests are accounted
+    // too; the usage chunk is dropped again if the client did not ask for it
+    let collector = match include_usage(&request) {
+        true => SseContentCollector::default(),
+        false => SseContentCollector::hiding_usage(),
+    };
+    request.stream_options = Some(StreamOptions {
+        include_usage: Some(true),
+    });
+
+    let (_, response) =
+        send_chat_request(state, headers, &request, cancel_token, request_id).await?;
+
+    if response.status() != StatusCode::OK {
+        return forward_error_response(response, request_id).await;
+    }
+
+    let collector = Arc::new(std::sync::Mutex::new(collector));
+    let events = {
+        let collector = collector.clone();
+        response
+            .bytes_stream()
+            .map(move |chunk| chunk.map(|bytes| collector.lock().unwrap().forward(&bytes)))
+    };
+
+    // store the answer to memory and record the usage once the downstream stream is exhausted;
+    // the client user is read here, as the tail is polled outside the scope of the request
+    let memory = state.memory.clone().zip(conv_id);
+    let usage_state = state.clone();
+    let user = client_user();
+    let request_id_owned = request_id.to_string();
+    let store_answer = stream::once(async move {
+        if let Some(usage) = collector.lock().unwrap().usage.take() {
+            usage_state.record_usage(user.as_deref(), &usage);
+        }
+        if let Some((memory, conv_id)) = memory {
+            let assistant_msg = std::mem::take(&mut collector.lock().unwrap().content);
+            if let Err(e) = memory
+                .add_assistant_message(&conv_id, &assistant_msg, vec![])
+                .await
+            {
+                dual_error!(
+                    "Failed to add assistant message to memory: {} - request_id: {}",
+                    e,
+                    request_id_owned
+                );
+            }
+        }
+        dual_info!(
+            "Streaming response sent successfully - request_id: {}",
+            request_id_owned
+        );
+        None
+    })
+    .filter_map(|chunk: Option<Result<Bytes, reqwest::Error>>| async move { chunk });
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .status(StatusCode::OK)
+        .body(Body::from_stream(events.chain(store_answer)))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create streaming response: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })
+}
+
 /// Read HTTP response body data with cancellation support
 ///
 /// This function uses select! macro to simultaneously monitor response reading and cancellation signals.
@@ -490,7 +631,7 @@ async fn read_response_bytes(
 }
 
 fn parse_chat_completion(bytes: &Bytes, request_id: &str) -> ServerResult<ChatCompletionObject> {
-    serde_json::from_slice(bytes).map_err(|e| {
+    let chat_completion = serde_json::from_slice(bytes).map_err(|e| {
         let value = serde_json::from_slice::<serde_json::Value>(bytes).unwrap();
 
         dual_error!(
@@ -504,7 +645,10 @@ fn parse_chat_completion(bytes: &Bytes, request_id: &str) -> ServerResult<ChatCo
         dual_error!("{} - request_id: {}", err_msg, request_id);
 
         ServerError::Operation(err_msg)
-    })
+    })?;
+    check_choices(&chat_completion, request_id)?;
+
+    Ok(chat_completion)
 }
 
 /// Build HTTP response object
@@ -591,28 +735,37 @@ async fn call_mcp_server(
                 let err_msg =
                     format!("Not found mcp client connected with {mcp_server_name} mcp server");
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::McpOperation(err_msg.to_string()));
+                return Err(ServerError::McpNotFoundClient(mcp_server_name.to_string()));
             }
         };
 
+        // map a truncated tool name back to the name of the tool on the mcp server
+        let mcp_tool_name = state
+            .config
+            .read()
+            .await
+            .mcp
+            .as_ref()
+            .and_then(|mcp_config| resolve_tool_name(mcp_config, &tool_call.function.name))
+            .unwrap_or_else(|| mcp_tool_name.to_string());
+        check_tool_allowed(&state, mcp_server_name, &mcp_tool_name, request_id).await?;
+
         // call a tool
+        state.record_tool_call(&tool_call.function.name);
         let request_param = CallToolRequestParam {
-            name: mcp_tool_name.to_string().into(),
+            name: mcp_tool_name.clone().into(),
             arguments: serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(
                 mcp_tool_args,
             )
             .ok(),
         };
-        let tool_result = service
-            .read()
-            .await
-            .raw
-            .call_tool(request_param)
-            .await
-            .map_err(|e| {
-                dual_error!("Failed to call the mcp tool. {}", e);
-                ServerError::Operation(e.to_string())
-            })?;
+        let tool_result =
+            call_tool_with_retry(&state, service, request_param, &cancel_token, request_id)
+                .await
+                .map_err(|e| {
+                    dual_error!("Failed to call the mcp tool. {}", e);
+                    ServerError::Operation(e.to_string())
+                })?;
         dual_debug!("{}", serde_json::to_string_pretty(&tool_result).unwrap());
 
         match tool_result.is_error {
@@ -620,366 +773,324 @@ async fn call_mcp_server(
                 match !tool_result.content.is_empty() {
                     true => {
                         let content = &tool_result.content[0];
-                        match &content.raw {
-                            RawContent::Text(text) => {
-                                dual_info!(
-                                    "The tool call result returned by {} mcp server: {:#?}",
-                                    &mcp_server_name,
-                                    text.text
-                                );
+                        let text = content_to_text(&content.raw);
+                        dual_info!(
+                            "The tool call result returned by {} mcp server: {:#?}",
+                            &mcp_server_name,
+                            text
+                        );
 
-                                let content = match SEARCH_MCP_SERVER_NAMES
-                                    .contains(&mcp_server_name)
-                                {
-                                    true => {
-                                        // get the fallback message from the mcp client
-                                        let fallback = if service
-                                            .read()
-                                            .await
-                                            .has_fallback_message()
-                                        {
-                                            service.read().await.fallback_message.clone().unwrap()
-                                        } else {
-                                            DEFAULT_SEARCH_FALLBACK_MESSAGE.to_string()
-                                        };
+                        let content = match SEARCH_MCP_SERVER_NAMES.contains(&mcp_server_name) {
+                            true => {
+                                // get the fallback message from the mcp client
+                                let fallback = if service.read().await.has_fallback_message() {
+                                    service.read().await.fallback_message.clone().unwrap()
+                                } else {
+                                    DEFAULT_SEARCH_FALLBACK_MESSAGE.to_string()
+                                };
 
-                                        dual_debug!(
-                                            "fallback message: {} - request_id: {}",
-                                            fallback,
-                                            request_id
-                                        );
+                                dual_debug!(
+                                    "fallback message: {} - request_id: {}",
+                                    fallback,
+                                    request_id
+                                );
 
-                                        // add tool results as context
-                                        let content = format!(
-                                            "Please answer the question based on the information between **---BEGIN CONTEXT---** and **---END CONTEXT---**. Do not use any external knowledge. If the information between **---BEGIN CONTEXT---** and **---END CONTEXT---** is empty, please respond with `{fallback}`. Note that DO NOT use any tools if provided.\n\n---BEGIN CONTEXT---\n\n{context}\n\n---END CONTEXT---",
-                                            fallback = fallback,
-                                            context = &text.text,
-                                        );
+                                // add tool results as context
+                                let content = format!(
+                                    "Please answer the question based on the information between **---BEGIN CONTEXT---** and **---END CONTEXT---**. Do not use any external knowledge. If the information between **---BEGIN CONTEXT---** and **---END CONTEXT---** is empty, please respond with `{fallback}`. Note that DO NOT use any tools if provided.\n\n---BEGIN CONTEXT---\n\n{context}\n\n---END CONTEXT---",
+                                    fallback = fallback,
+                                    context = &text,
+                                );
 
-                                        content
-                                    }
-                                    false => text.text.clone(),
-                                };
+                                content
+                            }
+                            false => text.clone(),
+                        };
 
-                                dual_debug!("context:\n{}", &content);
+                        dual_debug!("context:\n{}", &content);
+
+                        // Store tool calls and results to memory
+                        if let (Some(conv_id), Some(stored_tcs), Some(memory)) =
+                            (conv_id, stored_tool_calls.as_mut(), &state.memory)
+                        {
+                            // Add tool results to stored tool calls
+                            add_tool_results_to_stored(stored_tcs, std::slice::from_ref(&content));
+
+                            if let Err(e) = memory
+                                .add_assistant_message(conv_id, "", stored_tcs.clone())
+                                .await
+                            {
+                                dual_error!(
+                                    "Failed to store tool calls to memory: {} - request_id: {}",
+                                    e,
+                                    request_id
+                                );
+                            }
+                        }
 
-                                // Store tool calls and results to memory
-                                if let (Some(conv_id), Some(stored_tcs), Some(memory)) =
-                                    (conv_id, stored_tool_calls.as_mut(), &state.memory)
-                                {
-                                    // Add tool results to stored tool calls
-                                    add_tool_results_to_stored(
-                                        stored_tcs,
-                                        std::slice::from_ref(&content),
-                                    );
+                        // update request messages
+                        if let (Some(conv_id), Some(memory)) = (conv_id, &state.memory) {
+                            let context = memory.get_model_context(conv_id).await.map_err(|e| {
+                                let err_msg = format!("Failed to get model context: {e}");
+                                dual_error!("{} - request_id: {}", err_msg, request_id);
+                                ServerError::Operation(err_msg)
+                            })?;
+                            let context: Vec<ChatCompletionRequestMessage> = context
+                                .into_iter()
+                                .map(|model_msg| model_msg.into())
+                                .collect();
+
+                            // Update request messages with context
+                            request.messages = context;
+                        } else {
+                            // append assistant message with tool call to request messages
+                            let assistant_completion_message =
+                                ChatCompletionRequestMessage::Assistant(
+                                    ChatCompletionAssistantMessage::new(
+                                        None,
+                                        None,
+                                        Some(vec![tool_call.clone()]),
+                                    ),
+                                );
+                            request.messages.push(assistant_completion_message);
 
-                                    if let Err(e) = memory
-                                        .add_assistant_message(conv_id, "", stored_tcs.clone())
-                                        .await
-                                    {
-                                        dual_error!(
-                                            "Failed to store tool calls to memory: {} - request_id: {}",
-                                            e,
-                                            request_id
-                                        );
-                                    }
-                                }
+                            // append tool message with tool result to request messages
+                            let tool_completion_message = ChatCompletionRequestMessage::Tool(
+                                ChatCompletionToolMessage::new(&content, tool_call_id),
+                            );
+                            request.messages.push(tool_completion_message);
+                        }
 
-                                // update request messages
-                                if let (Some(conv_id), Some(memory)) = (conv_id, &state.memory) {
-                                    let context =
-                                        memory.get_model_context(conv_id).await.map_err(|e| {
-                                            let err_msg =
-                                                format!("Failed to get model context: {e}");
-                                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                                            ServerError::Operation(err_msg)
-                                        })?;
-                                    let context: Vec<ChatCompletionRequestMessage> = context
-                                        .into_iter()
-                                        .map(|model_msg| model_msg.into())
-                                        .collect();
-
-                                    // Update request messages with context
-                                    request.messages = context;
-                                } else {
-                                    // append assistant message with tool call to request messages
-                                    let assistant_completion_message =
-                                        ChatCompletionRequestMessage::Assistant(
-                                            ChatCompletionAssistantMessage::new(
-                                                None,
-                                                None,
-                                                Some(vec![tool_call.clone()]),
-                                            ),
-                                        );
-                                    request.messages.push(assistant_completion_message);
+                        // disable tool choice
+                        if request.tool_choice.is_some() {
+                            request.tool_choice = Some(ToolChoice::None);
+                        }
 
-                                    // append tool message with tool result to request messages
-                                    let tool_completion_message =
-                                        ChatCompletionRequestMessage::Tool(
-                                            ChatCompletionToolMessage::new(&content, tool_call_id),
-                                        );
-                                    request.messages.push(tool_completion_message);
-                                }
+                        // Create a request client that can be cancelled
+                        let ds_request = if let Some(api_key) = &chat_server.api_key
+                            && !api_key.is_empty()
+                        {
+                            let auth_info = if api_key.starts_with("Bearer ") {
+                                api_key.clone()
+                            } else {
+                                format!("Bearer {api_key}")
+                            };
+
+                            reqwest::Client::new()
+                                .post(&chat_service_url)
+                                .header(CONTENT_TYPE, "application/json")
+                                .header(AUTHORIZATION, auth_info)
+                                .json(&downstream_body(request))
+                        } else if headers.contains_key("authorization") {
+                            let authorization = headers
+                                .get("authorization")
+                                .unwrap()
+                                .to_str()
+                                .unwrap()
+                                .to_string();
+
+                            reqwest::Client::new()
+                                .post(&chat_service_url)
+                                .header(CONTENT_TYPE, "application/json")
+                                .header(AUTHORIZATION, authorization)
+                                .json(&downstream_body(request))
+                        } else {
+                            reqwest::Client::new()
+                                .post(&chat_service_url)
+                                .header(CONTENT_TYPE, "application/json")
+                                .json(&downstream_body(request))
+                        };
 
-                                // disable tool choice
-                                if request.tool_choice.is_some() {
-                                    request.tool_choice = Some(ToolChoice::None);
-                                }
+                        dual_debug!(
+                            "Request to downstream chat server - request_id: {}\n{}",
+                            request_id,
+                            serde_json::to_string_pretty(&request).unwrap()
+                        );
 
-                                // Create a request client that can be cancelled
-                                let ds_request = if let Some(api_key) = &chat_server.api_key
-                                    && !api_key.is_empty()
-                                {
-                                    let auth_info = if api_key.starts_with("Bearer ") {
-                                        api_key.clone()
-                                    } else {
-                                        format!("Bearer {api_key}")
-                                    };
-
-                                    reqwest::Client::new()
-                                        .post(&chat_service_url)
-                                        .header(CONTENT_TYPE, "application/json")
-                                        .header(AUTHORIZATION, auth_info)
-                                        .json(&request)
-                                } else if headers.contains_key("authorization") {
-                                    let authorization = headers
-                                        .get("authorization")
-                                        .unwrap()
-                                        .to_str()
-                                        .unwrap()
-                                        .to_string();
-
-                                    reqwest::Client::new()
-                                        .post(&chat_service_url)
-                                        .header(CONTENT_TYPE, "application/json")
-                                        .header(AUTHORIZATION, authorization)
-                                        .json(&request)
-                                } else {
-                                    reqwest::Client::new()
-                                        .post(&chat_service_url)
-                                        .header(CONTENT_TYPE, "application/json")
-                                        .json(&request)
-                                };
+                        // Use select! to handle request cancellation
+                        let ds_response = select! {
+                            response = chat_server.add_extra_headers(ds_request).header(REQUEST_ID_HEADER, request_id).send() => {
+                                response.map_err(|e| {
+                                    let err_msg = format!(
+                                        "Failed to forward the request to the downstream server: {e}"
+                                    );
+                                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                                    ServerError::Operation(err_msg)
+                                })?
+                            }
+                            _ = cancel_token.cancelled() => {
+                                let warn_msg = "Request was cancelled by client";
+                                dual_warn!("{} - request_id: {}", warn_msg, request_id);
+                                return Err(ServerError::Operation(warn_msg.to_string()));
+                            }
+                        };
 
-                                dual_debug!(
-                                    "Request to downstream chat server - request_id: {}\n{}",
-                                    request_id,
-                                    serde_json::to_string_pretty(&request).unwrap()
-                                );
+                        let status = ds_response.status();
+                        match status {
+                            StatusCode::OK => {
+                                let mut response_builder = Response::builder().status(status);
+
+                                // copy the response headers
+                                let headers = ds_response.headers().clone();
 
-                                // Use select! to handle request cancellation
-                                let ds_response = select! {
-                                    response = ds_request.send() => {
-                                        response.map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to forward the request to the downstream server: {e}"
-                                            );
+                                // Handle response body reading with cancellation
+                                let bytes = select! {
+                                    bytes = ds_response.bytes() => {
+                                        bytes.map_err(|e| {
+                                            let err_msg = format!("Failed to get the full response as bytes: {e}");
                                             dual_error!("{} - request_id: {}", err_msg, request_id);
                                             ServerError::Operation(err_msg)
                                         })?
                                     }
                                     _ = cancel_token.cancelled() => {
-                                        let warn_msg = "Request was cancelled by client";
+                                        let warn_msg = "Request was cancelled while reading response";
                                         dual_warn!("{} - request_id: {}", warn_msg, request_id);
                                         return Err(ServerError::Operation(warn_msg.to_string()));
                                     }
                                 };
 
-                                let status = ds_response.status();
-                                match status {
-                                    StatusCode::OK => {
-                                        let mut response_builder =
-                                            Response::builder().status(status);
-
-                                        // copy the response headers
-                                        let headers = ds_response.headers().clone();
-
-                                        // Handle response body reading with cancellation
-                                        let bytes = select! {
-                                            bytes = ds_response.bytes() => {
-                                                bytes.map_err(|e| {
-                                                    let err_msg = format!("Failed to get the full response as bytes: {e}");
-                                                    dual_error!("{} - request_id: {}", err_msg, request_id);
-                                                    ServerError::Operation(err_msg)
-                                                })?
-                                            }
-                                            _ = cancel_token.cancelled() => {
-                                                let warn_msg = "Request was cancelled while reading response";
-                                                dual_warn!("{} - request_id: {}", warn_msg, request_id);
-                                                return Err(ServerError::Operation(warn_msg.to_string()));
-                                            }
-                                        };
+                                let chat_completion = parse_chat_completion(&bytes, request_id)?;
+                                state
+                                    .record_usage(client_user().as_deref(), &chat_completion.usage);
 
-                                        let chat_completion =
-                                            parse_chat_completion(&bytes, request_id)?;
-
-                                        let assistant_message = chat_completion
-                                            .choices
-                                            .first()
-                                            .and_then(|choice| choice.message.content.clone())
-                                            .unwrap_or_default();
-
-                                        // Store final assistant message to memory
-                                        if let (Some(conv_id), Some(memory)) =
-                                            (conv_id, &state.memory)
-                                            && let Err(e) = memory
-                                                .add_assistant_message(
-                                                    conv_id,
-                                                    &assistant_message,
-                                                    vec![],
-                                                )
-                                                .await
-                                        {
-                                            dual_warn!(
-                                                "Failed to add assistant message to memory: {e} - request_id: {}",
-                                                request_id
-                                            );
-                                        }
+                                let assistant_message = chat_completion
+                                    .choices
+                                    .first()
+                                    .and_then(|choice| choice.message.content.clone())
+                                    .unwrap_or_default();
 
-                                        // Return final response
-                                        match stream {
-                                            true => {
-                                                let chunks = gen_chunks_with_formatting(
-                                                    &assistant_message,
-                                                    10,
-                                                );
-                                                let id = match &request.user {
-                                                    Some(id) => id.clone(),
-                                                    None => gen_chat_id(),
-                                                };
-                                                let model = chat_completion.model.clone();
-                                                let usage = chat_completion.usage;
-                                                let chunks_len = chunks.len();
-
-                                                // Create SSE stream
-                                                let request_id_owned = request_id.to_string();
-                                                let stream = stream::iter(chunks.into_iter().enumerate().map(
-                                                    move |(i, chunk)| {
-                                                        let created = SystemTime::now()
-                                                            .duration_since(std::time::UNIX_EPOCH)
-                                                            .map_err(|e| {
-                                                                let err_msg = format!(
-                                                                    "Failed to get the current time. Reason: {e}"
-                                                                );
-
-                                                                dual_error!(
-                                                                    "{} - request_id: {}",
-                                                                    err_msg,
-                                                                    request_id_owned
-                                                                );
-
-                                                                ServerError::Operation(err_msg)
-                                                            })
-                                                            .unwrap();
-
-                                                        let mut chat_completion_chunk = ChatCompletionChunk {
-                                                            id: id.clone(),
-                                                            object: "chat.completion.chunk".to_string(),
-                                                            created: created.as_secs(),
-                                                            model: model.clone(),
-                                                            system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                            choices: vec![ChatCompletionChunkChoice {
-                                                                index: i as u32,
-                                                                delta: ChatCompletionChunkChoiceDelta {
-                                                                    role: ChatCompletionRole::Assistant,
-                                                                    content: Some(chunk),
-                                                                    tool_calls: vec![],
-                                                                },
-                                                                logprobs: None,
-                                                                finish_reason: None,
-                                                            }],
-                                                            usage: None,
-                                                        };
-
-                                                        if i == chunks_len - 1 {
-                                                            // update finish_reason
-                                                            chat_completion_chunk.choices[0].finish_reason =
-                                                                Some(FinishReason::stop);
-
-                                                            // update usage
-                                                            chat_completion_chunk.usage = Some(usage);
-                                                        }
-
-                                                        let json_str =
-                                                            serde_json::to_string(&chat_completion_chunk).unwrap();
-                                                        format!("data: {json_str}\n\n")
-                                                    },
-                                                ))
-                                                .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
-                                                .map(|s| Ok::<_, std::convert::Infallible>(s.into_bytes()));
-
-                                                // Build streaming response
-                                                Response::builder()
-                                                    .header(
-                                                        CONTENT_TYPE,
-                                                        "text/event-stream",
-                                                    )
-                                                    .header(
-                                                        "Cache-Control",
-                                                        "no-cache",
-                                                    )
-                                                    .header(
-                                                        "Connection",
-                                                        "keep-alive",
-                                                    )
-                                                    .status(StatusCode::OK)
-                                                    .body(Body::from_stream(
-                                                        stream,
-                                                    )).map_err(|e| {
+                                // Store final assistant message to memory
+                                if let (Some(conv_id), Some(memory)) = (conv_id, &state.memory)
+                                    && let Err(e) = memory
+                                        .add_assistant_message(conv_id, &assistant_message, vec![])
+                                        .await
+                                {
+                                    dual_warn!(
+                                        "Failed to add assistant message to memory: {e} - request_id: {}",
+                                        request_id
+                                    );
+                                }
+
+                                // Return final response
+                                match stream {
+                                    true => {
+                                        let mut chunks =
+                                            gen_chunks_with_formatting(&assistant_message, 10);
+                                        if chunks.is_empty() {
+                                            chunks.push(String::new());
+                                        }
+                                        let id = match &request.user {
+                                            Some(id) => id.clone(),
+                                            None => gen_chat_id(),
+                                        };
+                                        let model = chat_completion.model.clone();
+                                        let usage_event = include_usage(request).then(|| {
+                                            let created = SystemTime::now()
+                                                .duration_since(std::time::UNIX_EPOCH)
+                                                .map(|created| created.as_secs())
+                                                .unwrap_or(chat_completion.created);
+                                            usage_event(&id, created, &model, chat_completion.usage)
+                                        });
+                                        let chunks_len = chunks.len();
+
+                                        // Create SSE stream
+                                        let request_id_owned = request_id.to_string();
+                                        let stream = stream::iter(chunks.into_iter().enumerate().map(
+                                            move |(i, chunk)| {
+                                                let created = SystemTime::now()
+                                                    .duration_since(std::time::UNIX_EPOCH)
+                                                    .map_err(|e| {
                                                         let err_msg = format!(
-                                                            "Failed to create streaming response: {e}"
+                                                            "Failed to get the current time. Reason: {e}"
                                                         );
+
                                                         dual_error!(
                                                             "{} - request_id: {}",
                                                             err_msg,
-                                                            request_id
+                                                            request_id_owned
                                                         );
+
                                                         ServerError::Operation(err_msg)
                                                     })
-                                            }
-                                            false => {
-                                                let response_body =
-                                                    serde_json::to_string(&chat_completion)
-                                                        .unwrap();
-
-                                                response_builder = copy_response_headers(
-                                                    response_builder,
-                                                    &headers,
-                                                );
+                                                    .unwrap();
+
+                                                let mut chat_completion_chunk = ChatCompletionChunk {
+                                                    id: id.clone(),
+                                                    object: "chat.completion.chunk".to_string(),
+                                                    created: created.as_secs(),
+                                                    model: model.clone(),
+                                                    system_fingerprint: "fp_44709d6fcb".to_string(),
+                                                    choices: vec![ChatCompletionChunkChoice {
+                                                        index: i as u32,
+                                                        delta: ChatCompletionChunkChoiceDelta {
+                                                            role: ChatCompletionRole::Assistant,
+                                                            content: Some(chunk),
+                                                            tool_calls: vec![],
+                                                        },
+                                                        logprobs: None,
+                                                        finish_reason: None,
+                                                    }],
+                                                    usage: None,
+                                                };
 
-                                                response_builder.body(Body::from(response_body)).map_err(|e| {
-                                                    let err_msg = format!("Failed to create the response body: {e}");
-                                                    dual_error!("{} - request_id: {}", err_msg, request_id);
-                                                    ServerError::Operation(err_msg)
-                                                })
-                                            }
-                                        }
+                                                if i == chunks_len - 1 {
+                                                    // update finish_reason
+                                                    chat_completion_chunk.choices[0].finish_reason =
+                                                        Some(FinishReason::stop);
+                                                }
+
+                                                let json_str =
+                                                    serde_json::to_string(&chat_completion_chunk).unwrap();
+                                                format!("data: {json_str}\n\n")
+                                            },
+                                        ))
+                                        .chain(stream::iter(usage_event))
+                                        .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
+                                        .map(|s| Ok::<_, std::convert::Infallible>(s.into_bytes()));
+
+                                        // Build streaming response
+                                        Response::builder()
+                                            .header(CONTENT_TYPE, "text/event-stream")
+                                            .header("Cache-Control", "no-cache")
+                                            .header("Connection", "keep-alive")
+                                            .status(StatusCode::OK)
+                                            .body(Body::from_stream(stream))
+                                            .map_err(|e| {
+                                                let err_msg = format!(
+                                                    "Failed to create streaming response: {e}"
+                                                );
+                                                dual_error!(
+                                                    "{} - request_id: {}",
+                                                    err_msg,
+                                                    request_id
+                                                );
+                                                ServerError::Operation(err_msg)
+                                            })
                                     }
-                                    _ => {
-                                        // Convert reqwest::Response to axum::Response
-                                        let status = ds_response.status();
+                                    false => {
+                                        let response_body =
+                                            serde_json::to_string(&chat_completion).unwrap();
 
-                                        let err_msg = format!("{status}");
-                                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                                        response_builder =
+                                            copy_response_headers(response_builder, &headers);
 
-                                        let headers = ds_response.headers().clone();
-                                        let bytes = ds_response.bytes().await.map_err(|e| {
-                                            let err_msg =
-                                                format!("Failed to get response bytes: {e}");
-                                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                                            ServerError::Operation(err_msg)
-                                        })?;
-
-                                        build_response(status, headers, bytes, request_id)
+                                        response_builder.body(Body::from(response_body)).map_err(
+                                            |e| {
+                                                let err_msg = format!(
+                                                    "Failed to create the response body: {e}"
+                                                );
+                                                dual_error!(
+                                                    "{} - request_id: {}",
+                                                    err_msg,
+                                                    request_id
+                                                );
+                                                ServerError::Operation(err_msg)
+                                            },
+                                        )
                                     }
                                 }
                             }
-                            _ => {
-                                let err_msg =
-                                    "Only text content is supported for tool call results";
-                                dual_error!("{} - request_id: {}", err_msg, request_id);
-                                Err(ServerError::Operation(err_msg.to_string()))
-                            }
+                            _ => forward_error_response(ds_response, request_id).await,
                         }
                     }
                     false => {
@@ -998,7 +1109,7 @@ async fn call_mcp_server(
     } else {
         let err_msg = "Empty MCP CLIENTS";
         dual_error!("{} - request_id: {}", err_msg, request_id);
-        Err(ServerError::McpOperation(err_msg.to_string()))
+        Err(ServerError::McpNotFoundClient(mcp_server_name.to_string()))
     }
 }
 
@@ -1058,3 +1169,505 @@ impl From<ModelToolCall> for ToolCall {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{Router, routing::post};
+
+    use super::*;
+    use crate::{config::Config, test_utils::*};
+
+    fn create_config(policy: &str) -> Config {
+        Config {
+            chat: Some(
+                serde_json::from_value(serde_json::json!({
+                    "url": "http://localhost:8080/v1",
+                    "api_key": "",
+                    "on_required_tool_missing": policy,
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn create_request() -> ChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+            "tool_choice": "required",
+        }))
+        .unwrap()
+    }
+
+    /// Spawn a chat server that never calls a tool and count the requests it receives
+    async fn spawn_chat_server_without_tool_calls(hits: Arc<AtomicUsize>) -> String {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let hits = hits.clone();
+                async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    axum::Json(chat_completion_json("It is sunny in Paris."))
+                }
+            }),
+        );
+        spawn_mock_server(router).await
+    }
+
+    async fn run_chat(
+        policy: &str,
+        hits: Arc<AtomicUsize>,
+    ) -> ServerResult<axum::response::Response> {
+        let url = spawn_chat_server_without_tool_calls(hits).await;
+        let state = create_test_state(create_config(policy), &[(&url, "chat")]).await;
+
+        chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(create_request()),
+            None,
+            "test-request",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_required_tool_missing_is_rejected() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let result = run_chat("reject", hits.clone()).await;
+
+        assert!(matches!(result, Err(ServerError::RequiredToolCallMissing)));
+        let response = axum::response::IntoResponse::into_response(result.unwrap_err());
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_required_tool_missing_is_retried_once() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let result = run_chat("retry", hits.clone()).await;
+
+        assert!(matches!(result, Err(ServerError::RequiredToolCallMissing)));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_required_tool_missing_is_ignored_by_default() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let response = run_chat("ignore", hits.clone()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let chat_completion: ChatCompletionObject = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            chat_completion.choices[0].message.content.as_deref(),
+            Some("It is sunny in Paris.")
+        );
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    async fn run_chat_with_failover(
+        config: Config,
+        hits: Arc<AtomicUsize>,
+    ) -> ServerResult<axum::response::Response> {
+        let dead_url = refused_server_url().await;
+        let live_url = spawn_chat_server_without_tool_calls(hits).await;
+        let state = create_test_state(config, &[(&dead_url, "chat"), (&live_url, "chat")]).await;
+
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+        }))
+        .unwrap();
+
+        chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            "test-request",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_chat_server_fails_over() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let response = run_chat_with_failover(create_config("ignore"), hits.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failover_is_bounded_by_max_attempts() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let config = Config {
+            routing: Some(
+                serde_json::from_value(serde_json::json!({ "max_attempts": 1 })).unwrap(),
+            ),
+            ..create_config("ignore")
+        };
+        let result = run_chat_with_failover(config, hits.clone()).await;
+
+        assert!(matches!(result, Err(ServerError::Operation(_))));
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_downstream_error_is_propagated() {
+        let error_body = r#"{"error":{"message":"Rate limit exceeded","type":"rate_limit_error"}}"#;
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move || async move {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(CONTENT_TYPE, "application/json")],
+                    error_body,
+                )
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(create_config("ignore"), &[(&url, "chat")]).await;
+
+        let response = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(create_request()),
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(bytes, error_body.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_stream_is_passed_through_incrementally() {
+        // the mock server holds back the rest of the answer until the test releases it
+        let release = Arc::new(tokio::sync::Notify::new());
+        let router = {
+            let release = release.clone();
+            Router::new().route(
+                "/v1/chat/completions",
+                post(move |Json(request): Json<serde_json::Value>| {
+                    let release = release.clone();
+                    async move {
+                        assert_eq!(request["stream"], true);
+
+                        let (tx, rx) = tokio::sync::mpsc::channel::<String>(4);
+                        tokio::spawn(async move {
+                            tx.send(sse_chunk("Hello")).await.unwrap();
+                            release.notified().await;
+                            tx.send(sse_chunk(", world")).await.unwrap();
+                            tx.send("data: [DONE]\n\n".to_string()).await.unwrap();
+                        });
+                        let events = stream::unfold(rx, |mut rx| async move {
+                            rx.recv()
+                                .await
+                                .map(|event| (Ok::<_, std::convert::Infallible>(event), rx))
+                        });
+                        (
+                            [(CONTENT_TYPE, "text/event-stream")],
+                            Body::from_stream(events),
+                        )
+                    }
+                }),
+            )
+        };
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(create_config("ignore"), &[(&url, "chat")]).await;
+
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "stream": true,
+        }))
+        .unwrap();
+        let response = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+
+        // the first token arrives before the downstream server has finished the answer
+        let mut body = response.into_body().into_data_stream();
+        let first = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+            .await
+            .expect("the first chunk was not streamed")
+            .unwrap()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&first), sse_chunk("Hello"));
+
+        release.notify_one();
+        let mut rest = Vec::new();
+        while let Some(chunk) = body.next().await {
+            rest.extend_from_slice(&chunk.unwrap());
+        }
+        let rest = String::from_utf8(rest).unwrap();
+        assert!(rest.contains(", world"));
+        assert!(rest.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[test]
+    fn test_sse_content_collector() {
+        let mut collector = SseContentCollector::default();
+        let events = format!(
+            "{}{}data: [DONE]\n\n",
+            sse_chunk("Hello"),
+            sse_chunk(", world")
+        );
+
+        // events may be split at any byte by the network
+        let (head, tail) = events.as_bytes().split_at(42);
+        collector.feed(head);
+        collector.feed(tail);
+
+        assert_eq!(collector.content, "Hello, world");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_usage_is_recorded() {
+        // the mock server only sends the usage chunk if it is asked for
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|Json(request): Json<serde_json::Value>| async move {
+                let mut events = sse_chunk("Hello");
+                if request["stream_options"]["include_usage"] == true {
+                    let usage = serde_json::json!({
+                        "id": "chatcmpl-test",
+                        "object": "chat.completion.chunk",
+                        "created": 1_700_000_000u64,
+                        "model": "test-model",
+                        "choices": [],
+                        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+                    });
+                    events.push_str(&format!("data: {usage}\n\n"));
+                }
+                events.push_str("data: [DONE]\n\n");
+                ([(CONTENT_TYPE, "text/event-stream")], events)
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(create_config("ignore"), &[(&url, "chat")]).await;
+
+        for include_usage in [false, true] {
+            let request = serde_json::from_value(serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "Hello" }],
+                "stream": true,
+                "stream_options": { "include_usage": include_usage },
+            }))
+            .unwrap();
+            let chat = chat(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                HeaderMap::new(),
+                Json(request),
+                None,
+                "test-request",
+            );
+            let response = crate::chat::with_client_user(Some("alice".to_string()), chat)
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+            // the usage chunk only reaches the clients asking for it
+            assert_eq!(body.contains("\"usage\""), include_usage);
+            assert!(body.starts_with(&sse_chunk("Hello")));
+            assert!(body.ends_with("data: [DONE]\n\n"));
+        }
+
+        let totals = state.usage.totals("alice", None, None);
+        assert_eq!(totals.requests, 2);
+        assert_eq!(totals.total_tokens, 30);
+    }
+
+    #[tokio::test]
+    async fn test_all_choices_reach_the_client() {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                let mut completion = chat_completion_json("Sunny");
+                completion["choices"] = serde_json::json!(
+                    ["Sunny", "Cloudy", "Rainy"]
+                        .iter()
+                        .enumerate()
+                        .map(|(index, content)| serde_json::json!({
+                            "index": index,
+                            "message": { "role": "assistant", "content": content },
+                            "logprobs": null,
+                            "finish_reason": "stop"
+                        }))
+                        .collect::<Vec<_>>()
+                );
+                axum::Json(completion)
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+
+        // a tool is offered, so the answer goes through the tool-call interception
+        for stream in [false, true] {
+            let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+                "n": 3,
+                "stream": stream,
+                "tools": [{
+                    "type": "function",
+                    "function": { "name": "get_weather", "parameters": { "type": "object" } }
+                }],
+            }))
+            .unwrap();
+
+            let response = chat(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                HeaderMap::new(),
+                Json(request),
+                None,
+                "test-request",
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+
+            let mut contents = vec![String::new(); 3];
+            match stream {
+                false => {
+                    let completion: ChatCompletionObject = serde_json::from_slice(&bytes).unwrap();
+                    for choice in completion.choices {
+                        contents[choice.index as usize] = choice.message.content.unwrap();
+                    }
+                }
+                true => {
+                    let body = String::from_utf8(bytes.to_vec()).unwrap();
+                    for data in body.lines().filter_map(|line| line.strip_prefix("data: ")) {
+                        if data == "[DONE]" {
+                            continue;
+                        }
+                        let chunk: ChatCompletionChunk = serde_json::from_str(data).unwrap();
+                        let choice = &chunk.choices[0];
+                        contents[choice.index as usize]
+                            .push_str(choice.delta.content.as_deref().unwrap_or_default());
+                    }
+                }
+            }
+            assert_eq!(contents, ["Sunny", "Cloudy", "Rainy"], "stream: {stream}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_usage_chunk_ends_the_stream_when_requested() {
+        // the model repeats the message of the user
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|Json(request): Json<serde_json::Value>| async move {
+                let content = request["messages"][0]["content"].as_str().unwrap();
+                axum::Json(chat_completion_json(content))
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+
+        // a tool is offered, so the answer is streamed by the gateway
+        for content in ["Hi", ""] {
+            for include_usage in [true, false] {
+                let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                    "model": "test-model",
+                    "messages": [{ "role": "user", "content": content }],
+                    "stream": true,
+                    "stream_options": { "include_usage": include_usage },
+                    "tools": [{
+                        "type": "function",
+                        "function": { "name": "get_weather", "parameters": { "type": "object" } }
+                    }],
+                }))
+                .unwrap();
+                let response = chat(
+                    State(state.clone()),
+                    Extension(CancellationToken::new()),
+                    HeaderMap::new(),
+                    Json(request),
+                    None,
+                    "test-request",
+                )
+                .await
+                .unwrap();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body = String::from_utf8(bytes.to_vec()).unwrap();
+                assert!(body.ends_with("data: [DONE]\n\n"));
+
+                let chunks: Vec<ChatCompletionChunk> = body
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data: "))
+                    .filter(|data| *data != "[DONE]")
+                    .map(|data| serde_json::from_str(data).unwrap())
+                    .collect();
+                let (last, content_chunks) = chunks.split_last().unwrap();
+                match include_usage {
+                    true => {
+                        assert!(last.choices.is_empty());
+                        assert_eq!(last.usage.unwrap().total_tokens, 15);
+                        assert!(content_chunks.iter().all(|chunk| chunk.usage.is_none()));
+                    }
+                    false => assert!(chunks.iter().all(|chunk| chunk.usage.is_none())),
+                }
+
+                // the content ends with a chunk carrying the finish reason, even if empty
+                let content_chunks = match include_usage {
+                    true => content_chunks,
+                    false => &chunks[..],
+                };
+                let streamed: String = content_chunks
+                    .iter()
+                    .filter_map(|chunk| chunk.choices[0].delta.content.clone())
+                    .collect();
+                assert_eq!(streamed, content);
+                assert!(
+                    content_chunks.last().unwrap().choices[0]
+                        .finish_reason
+                        .is_some()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Response, StatusCode},
+};
+use endpoints::chat::{ChatCompletionObject, ChatCompletionRequest};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    AppState,
+    chat::utils::*,
+    config::AnswerPostprocessConfig,
+    dual_error, dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+};
+
+/// Rewrite the answer of a chat response with the configured post-processing prompt
+///
+/// The chat response must be a non-stream chat completion. If `stream` is set, the rewritten
+/// answer is returned to the client as SSE events, ending with the usage if `include_usage` is
+/// set. Error responses and answers calling tools are returned untouched. The draft stored in the
+/// memory of the conversation `conv_id` is replaced with the rewritten answer.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn postprocess_answer(
+    state: &Arc<AppState>,
+    config: &AnswerPostprocessConfig,
+    response: axum::response::Response,
+    headers: &HeaderMap,
+    stream: bool,
+    include_usage: bool,
+    conv_id: Option<&str>,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<axum::response::Response> {
+    if response.status() != StatusCode::OK {
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+        let err_msg = format!("Failed to read the draft answer: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })?;
+    let mut chat_completion: ChatCompletionObject =
+        serde_json::from_slice(&bytes).map_err(|e| {
+            let err_msg = format!("Failed to parse the draft answer: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+
+    let draft = match chat_completion.choices.first() {
+        Some(choice) if choice.message.tool_calls.is_empty() => {
+            choice.message.content.clone().unwrap_or_default()
+        }
+        _ => String::new(),
+    };
+    if draft.is_empty() {
+        dual_warn!(
+            "No draft answer to post-process - request_id: {}",
+            request_id
+        );
+        return Ok(Response::from_parts(parts, Body::from(bytes)));
+    }
+
+    // ask the model to rewrite the draft answer
+    let model = config
+        .model
+        .clone()
+        .unwrap_or_else(|| chat_completion.model.clone());
+    let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": model,
+        "messages": [
+            { "role": "system", "content": config.prompt },
+            { "role": "user", "content": draft },
+        ],
+        "stream": false,
+        "max_completion_tokens": config.max_tokens,
+    }))
+    .map_err(|e| {
+        let err_msg = format!("Failed to build the post-processing request: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })?;
+
+    dual_info!(
+        "Post-process the answer with model {} - request_id: {}",
+        model,
+        request_id
+    );
+    let (_, postprocess_response) =
+        send_chat_request(state, headers, &request, cancel_token, request_id).await?;
+    if postprocess_response.status() != StatusCode::OK {
+        return forward_error_response(postprocess_response, request_id).await;
+    }
+    let rewritten = postprocess_response
+        .json::<ChatCompletionObject>()
+        .await
+        .map_err(|e| {
+            let err_msg = format!("Failed to parse the post-processed answer: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+    let answer = rewritten
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default();
+    if answer.is_empty() {
+        dual_warn!(
+            "The post-processing returned an empty answer; keep the draft - request_id: {}",
+            request_id
+        );
+    } else {
+        if let Some(memory) = &state.memory
+            && let Some(conv_id) = conv_id
+            && let Err(e) = memory
+                .replace_last_assistant_message(conv_id, &answer)
+                .await
+        {
+            dual_warn!(
+                "Failed to store the post-processed answer: {} - request_id: {}",
+                e,
+                request_id
+            );
+        }
+        chat_completion.choices[0].message.content = Some(answer);
+    }
+
+    chat_completion_response(
+        chat_completion,
+        stream,
+        include_usage,
+        Default::default(),
+        request_id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Json, Router, extract::Extension, extract::State, routing::post};
+    use endpoints::chat::ChatCompletionChunk;
+    use reqwest::header::CONTENT_TYPE;
+
+    use super::*;
+    use crate::{config::Config, handlers::chat_handler, request_id::RequestId, test_utils::*};
+
+    const PROMPT: &str = "Rewrite the answer in a formal tone.";
+
+    /// Spawn a chat server that drafts an answer, and rewrites it when asked with the prompt
+    async fn spawn_chat_server() -> String {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|Json(request): Json<serde_json::Value>| async move {
+                assert_eq!(request["stream"], false);
+                let content = if request["messages"][0]["content"] == PROMPT {
+                    assert_eq!(request["max_completion_tokens"], 256);
+                    format!(
+                        "Rewritten: {}",
+                        request["messages"][1]["content"].as_str().unwrap()
+                    )
+                } else {
+                    "hey, it's sunny".to_string()
+                };
+                Json(chat_completion_json(&content))
+            }),
+        );
+        spawn_mock_server(router).await
+    }
+
+    async fn run_chat(stream: bool) -> axum::response::Response {
+        let url = spawn_chat_server().await;
+        let config = Config {
+            answer_postprocess: Some(AnswerPostprocessConfig {
+                enable: true,
+                prompt: PROMPT.to_string(),
+                model: None,
+                max_tokens: 256,
+            }),
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+            "stream": stream,
+        }))
+        .unwrap();
+        chat_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(request),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_postprocess_prompt_is_applied() {
+        let response = run_chat(false).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let chat_completion: ChatCompletionObject = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            chat_completion.choices[0].message.content.as_deref(),
+            Some("Rewritten: hey, it's sunny")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_postprocess_prompt_is_applied_to_stream() {
+        let response = run_chat(true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let answer: String = String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != "[DONE]")
+            .map(|data| {
+                let chunk: ChatCompletionChunk = serde_json::from_str(data).unwrap();
+                chunk.choices[0].delta.content.clone().unwrap_or_default()
+            })
+            .collect();
+        assert_eq!(answer, "Rewritten: hey, it's sunny");
+    }
+
+    #[tokio::test]
+    async fn test_postprocessed_answer_is_stored_to_memory() {
+        use crate::{
+            config::MemoryConfig,
+            info::ServerInfo,
+            memory::{CompleteChatMemory, ModelRole},
+            server::Server,
+        };
+
+        let url = spawn_chat_server().await;
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = Config {
+            answer_postprocess: Some(AnswerPostprocessConfig {
+                enable: true,
+                prompt: PROMPT.to_string(),
+                model: None,
+                max_tokens: 256,
+            }),
+            ..Default::default()
+        };
+        let memory = Arc::new(memory);
+        let state =
+            Arc::new(AppState::new(config, ServerInfo::default()).with_memory(memory.clone()));
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        state.register_downstream_server(server).await.unwrap();
+
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+            "user": "alice",
+        }))
+        .unwrap();
+        let response = chat_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the memory holds the answer returned to the client, not the draft
+        let conv_id = memory
+            .find_user_conversation("alice")
+            .await
+            .unwrap()
+            .unwrap();
+        let history = memory.get_full_history(&conv_id, false).await.unwrap();
+        assert_eq!(
+            history.last().unwrap().content,
+            "Rewritten: hey, it's sunny"
+        );
+        let context = memory.get_model_context(&conv_id).await.unwrap();
+        let answer = context.last().unwrap();
+        assert_eq!(answer.role, ModelRole::Assistant);
+        assert_eq!(answer.content, "Rewritten: hey, it's sunny");
+
+        let _ = std::fs::remove_file(database_path);
+    }
+}
@@ -1,55 +1,366 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
 use axum::{
     Json,
-    body::Body,
     extract::{Extension, State},
-    http::{HeaderMap, Response, StatusCode},
+    http::{HeaderMap, HeaderValue},
 };
 use endpoints::{
     chat::{
-        ChatCompletionAssistantMessage, ChatCompletionChunk, ChatCompletionChunkChoice,
-        ChatCompletionChunkChoiceDelta, ChatCompletionObject, ChatCompletionRequest,
-        ChatCompletionRequestMessage, ChatCompletionRole, ChatCompletionToolMessage,
+        ChatCompletionAssistantMessage, ChatCompletionObject, ChatCompletionRequest,
+        ChatCompletionRequestMessage, ChatCompletionToolMessage, ToolCall,
     },
     common::FinishReason,
 };
-use futures_util::{
-    StreamExt,
-    stream::{self},
-};
-use regex::Regex;
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-use rmcp::model::{CallToolRequestParam, RawContent};
+use futures_util::future::join_all;
+use rmcp::model::CallToolRequestParam;
+use serde::Serialize;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
     AppState,
-    chat::{gen_chat_id, utils::*},
+    chat::{client_user, utils::*},
+    config::{Config, ReactConfig, ReactTags, RequiredToolMissingPolicy},
     dual_debug, dual_error, dual_info, dual_warn,
-    error::{ServerError, ServerResult},
-    mcp::{DEFAULT_SEARCH_FALLBACK_MESSAGE, MCP_SEPARATOR, MCP_SERVICES, SEARCH_MCP_SERVER_NAMES},
-    server::{RoutingPolicy, ServerKind},
+    error::{AgentStep, ServerError, ServerResult},
+    mcp::{
+        DEFAULT_SEARCH_FALLBACK_MESSAGE, MCP_SEPARATOR, MCP_SERVICES, SEARCH_MCP_SERVER_NAMES,
+        call_tool_with_retry, check_tool_allowed, content_to_text, resolve_tool_name,
+    },
 };
 
+/// Observation given to the model for a tool call that timed out
+const TOOL_TIMEOUT_OBSERVATION: &str = "<observation>tool timed out</observation>";
+
+/// Reason attached to the response when the ReAct loop stops without a final answer
+const MAX_STEPS_REACHED_REASON: &str = "max_steps_reached";
+
+/// System prompt teaching the model the ReAct format, used if none is configured
+const DEFAULT_REACT_SYSTEM_PROMPT: &str = "You are a helpful assistant that solves tasks step by step. \
+In each response, first explain your reasoning between <thought> and </thought> tags. \
+If you need a tool, describe the action between <action> and </action> tags and call the tool; \
+its result is given back to you as an observation. \
+Once you know the answer, give it between <final_answer> and </final_answer> tags.";
+
+/// Header set on the response when the request fell back from ReAct to normal mode
+const REACT_FALLBACK_HEADER: &str = "x-react-fallback";
+
+/// Opening and closing delimiters of a ReAct tag
+#[derive(Debug)]
+struct Tag {
+    open: String,
+    close: String,
+}
+impl Tag {
+    fn new(name: &str) -> Self {
+        Self {
+            open: format!("<{name}>"),
+            close: format!("</{name}>"),
+        }
+    }
+
+    /// Body of the first tag of the content. None if the tag is missing or empty.
+    fn first_body<'a>(&self, content: &'a str) -> Option<&'a str> {
+        let start = content.find(&self.open)? + self.open.len();
+        self.body(&content[start..])
+    }
+
+    /// Body of the last tag of the content. None if the tag is missing or empty.
+    fn last_body<'a>(&self, content: &'a str) -> Option<&'a str> {
+        let start = content.rfind(&self.open)? + self.open.len();
+        self.body(&content[start..])
+    }
+
+    // the body runs to the closing delimiter or, if the model did not close the tag, to the end
+    // of the content
+    fn body<'a>(&self, rest: &'a str) -> Option<&'a str> {
+        let body = match rest.find(&self.close) {
+            Some(end) => &rest[..end],
+            None => rest,
+        };
+        (!body.trim().is_empty()).then_some(body)
+    }
+}
+
+/// Delimiters of the ReAct tags, as configured in `react.tags`
+#[derive(Debug)]
+struct ReactTagSet {
+    thought: Tag,
+    action: Tag,
+    final_answer: Tag,
+}
+impl ReactTagSet {
+    fn new(tags: &ReactTags) -> Self {
+        Self {
+            thought: Tag::new(&tags.thought),
+            action: Tag::new(&tags.action),
+            final_answer: Tag::new(&tags.final_answer),
+        }
+    }
+
+    async fn from_config(state: &AppState) -> Self {
+        let config = state.config.read().await;
+        match config.react.as_ref() {
+            Some(react_config) => Self::new(&react_config.tags),
+            None => Self::new(&ReactTags::default()),
+        }
+    }
+
+    /// Replace the default delimiters of a prompt, such as the built-in system prompt, with the
+    /// configured ones
+    fn adapt_prompt(&self, prompt: &str) -> String {
+        let defaults = ReactTags::default();
+        [
+            (&defaults.thought, &self.thought),
+            (&defaults.action, &self.action),
+            (&defaults.final_answer, &self.final_answer),
+        ]
+        .into_iter()
+        .fold(prompt.to_string(), |prompt, (name, tag)| {
+            let default = Tag::new(name);
+            prompt
+                .replace(&default.open, &tag.open)
+                .replace(&default.close, &tag.close)
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn chat(
     State(state): State<Arc<AppState>>,
     Extension(cancel_token): Extension<CancellationToken>,
     headers: HeaderMap,
-    Json(mut request): Json<ChatCompletionRequest>,
+    Json(request): Json<ChatCompletionRequest>,
     conv_id: Option<String>,
+    include_reasoning: bool,
+    system_prompt: Option<String>,
     request_id: impl AsRef<str>,
 ) -> ServerResult<axum::response::Response> {
     let request_id = request_id.as_ref();
 
-    // Get target server
-    let chat_server = get_chat_server(&state, request_id).await?;
+    // keep the original request to retry it in normal mode
+    let fallback = match state.config.read().await.react.clone() {
+        Some(react_config) if react_config.fallback_to_normal => Some((
+            react_config.max_tag_failures,
+            serde_json::to_value(&request).map_err(|e| {
+                let err_msg = format!("Failed to serialize the chat request: {e}");
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?,
+        )),
+        _ => None,
+    };
+
+    // the messages stored from this sequence on belong to this turn, and are removed if the
+    // turn fails before it is answered
+    let turn = match (&state.memory, &conv_id) {
+        (Some(memory), Some(conv_id)) => memory
+            .next_sequence(conv_id)
+            .await
+            .ok()
+            .map(|sequence| (conv_id.clone(), sequence)),
+        _ => None,
+    };
+
+    // errors raised inside the loop carry the context of the failing step
+    let mut agent_step = AgentStep::default();
+    let result = react_loop(
+        state.clone(),
+        cancel_token.clone(),
+        headers.clone(),
+        request,
+        conv_id,
+        include_reasoning,
+        system_prompt,
+        request_id,
+        fallback
+            .as_ref()
+            .map(|(max_tag_failures, _)| *max_tag_failures),
+        &mut agent_step,
+    )
+    .await;
+
+    let result = match (result, fallback) {
+        (Err(ServerError::ReactTagFailures(count)), Some((_, original_request))) => {
+            dual_warn!(
+                "The model failed to follow the ReAct format {} times. Fall back to normal mode - request_id: {}",
+                count,
+                request_id
+            );
+
+            // normal mode stores the user message again along with its answer
+            rollback_turn(&state, turn.as_ref(), request_id).await;
+            fallback_to_normal(
+                state.clone(),
+                cancel_token,
+                headers,
+                original_request,
+                turn.as_ref().map(|(conv_id, _)| conv_id.clone()),
+                request_id,
+            )
+            .await
+        }
+        (result, _) => result.map_err(|e| match agent_step.step {
+            0 => e,
+            _ => e.with_agent_step(agent_step),
+        }),
+    };
+
+    if !result
+        .as_ref()
+        .is_ok_and(|response| response.status().is_success())
+    {
+        rollback_turn(&state, turn.as_ref(), request_id).await;
+    }
+
+    result
+}
+
+/// Remove the messages stored to memory by a turn that failed, so that a retry of the request
+/// does not store its user message twice
+async fn rollback_turn(state: &AppState, turn: Option<&(String, i64)>, request_id: &str) {
+    if let Some(memory) = &state.memory
+        && let Some((conv_id, from_sequence)) = turn
+    {
+        match memory.rollback_messages(conv_id, *from_sequence).await {
+            Ok(0) => {}
+            Ok(count) => dual_debug!(
+                "Removed {} messages of the failed turn from conversation {} - request_id: {}",
+                count,
+                conv_id,
+                request_id
+            ),
+            Err(e) => dual_warn!(
+                "Failed to remove the messages of the failed turn from conversation {}: {} - request_id: {}",
+                conv_id,
+                e,
+                request_id
+            ),
+        }
+    }
+}
+
+/// Retry the original request in normal mode without the ReAct system prompt
+///
+/// The user message and the answer are stored to the memory of the conversation `conv_id` the way
+/// normal mode stores them.
+async fn fallback_to_normal(
+    state: Arc<AppState>,
+    cancel_token: CancellationToken,
+    headers: HeaderMap,
+    original_request: serde_json::Value,
+    conv_id: Option<String>,
+    request_id: &str,
+) -> ServerResult<axum::response::Response> {
+    let mut request: ChatCompletionRequest =
+        serde_json::from_value(original_request).map_err(|e| {
+            let err_msg = format!("Failed to deserialize the chat request: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+    let tags = ReactTagSet::from_config(&state).await;
+    request
+        .messages
+        .retain(|message| !is_react_scaffolding(message, &tags));
+
+    let mut response = crate::chat::normal::chat(
+        State(state),
+        Extension(cancel_token),
+        headers,
+        Json(request),
+        conv_id,
+        request_id,
+    )
+    .await?;
+    response
+        .headers_mut()
+        .insert(REACT_FALLBACK_HEADER, HeaderValue::from_static("true"));
+
+    Ok(response)
+}
+
+/// Whether the message is a system message describing the ReAct tag format
+fn is_react_scaffolding(message: &ChatCompletionRequestMessage, tags: &ReactTagSet) -> bool {
+    match message {
+        ChatCompletionRequestMessage::System(system_message) => {
+            let content = system_message.content();
+            [&tags.thought, &tags.action, &tags.final_answer]
+                .iter()
+                .any(|tag| content.contains(&tag.open))
+        }
+        _ => false,
+    }
+}
+
+/// Count a response with missing or malformed ReAct tags, and give up on the ReAct loop once
+/// `max_tag_failures` is reached
+fn record_tag_failure(
+    tag_failures: &mut usize,
+    max_tag_failures: usize,
+    request_id: &str,
+) -> ServerResult<()> {
+    *tag_failures += 1;
+    dual_warn!(
+        "ReAct tag failure {}/{} - request_id: {}",
+        tag_failures,
+        max_tag_failures,
+        request_id
+    );
+
+    match *tag_failures >= max_tag_failures {
+        true => Err(ServerError::ReactTagFailures(*tag_failures)),
+        false => Ok(()),
+    }
+}
+
+/// Count the failed tool calls of a turn. The count is reset by a turn with a successful call,
+/// and the loop is aborted once `max_tool_failures` calls failed in a row.
+fn record_tool_failures(
+    tool_failures: &mut usize,
+    failed: usize,
+    total: usize,
+    max_tool_failures: usize,
+    request_id: &str,
+) -> ServerResult<()> {
+    if failed < total {
+        *tool_failures = 0;
+        return Ok(());
+    }
 
-    let action_pattern = Regex::new(r"(?s)<action>(.*?)</action>").unwrap();
-    let thought_pattern = Regex::new(r"(?s)<thought>(.*?)</thought>").unwrap();
-    let final_answer_pattern = Regex::new(r"(?s).*<final_answer>(.*?)</final_answer>").unwrap();
+    *tool_failures += failed;
+    dual_warn!(
+        "Tool call failures in a row {}/{} - request_id: {}",
+        tool_failures,
+        max_tool_failures,
+        request_id
+    );
+
+    match *tool_failures >= max_tool_failures {
+        true => Err(ServerError::ReactToolFailures(*tool_failures)),
+        false => Ok(()),
+    }
+}
 
+#[allow(clippy::too_many_arguments)]
+async fn react_loop(
+    state: Arc<AppState>,
+    cancel_token: CancellationToken,
+    headers: HeaderMap,
+    mut request: ChatCompletionRequest,
+    conv_id: Option<String>,
+    include_reasoning: bool,
+    system_prompt: Option<String>,
+    request_id: &str,
+    max_tag_failures: Option<usize>,
+    agent_step: &mut AgentStep,
+) -> ServerResult<axum::response::Response> {
     // Extract user message for memory storage
     let user_message = extract_user_message(&request);
 
@@ -124,54 +435,58 @@ pub(crate) async fn chat(
 
     // set non-stream mode
     let stream = request.stream.unwrap_or(false);
+    let include_usage = include_usage(&request);
     if stream {
         request.stream = Some(false);
     }
 
-    loop {
-        // * build request
-        let url = format!("{}/chat/completions", chat_server.url.trim_end_matches('/'));
-        let mut client = reqwest::Client::new().post(&url);
-
-        // Add common headers
-        client = client.header(CONTENT_TYPE, "application/json");
-
-        // Add authorization header
-        if let Some(api_key) = &chat_server.api_key
-            && !api_key.is_empty()
-        {
-            let auth_info = if api_key.starts_with("Bearer ") {
-                api_key.clone()
-            } else {
-                format!("Bearer {api_key}")
-            };
+    let tags = ReactTagSet::from_config(&state).await;
+    let (max_react_steps, tool_timeout, max_tool_failures, system_prompt) = {
+        let config = state.config.read().await;
+        let react_config = config.react.as_ref();
+        (
+            config.server.max_react_steps,
+            react_config
+                .and_then(|react_config| react_config.mcp_tool_timeout_secs)
+                .map(Duration::from_secs),
+            react_config.map_or(ReactConfig::default().max_tool_failures, |react_config| {
+                react_config.max_tool_failures
+            }),
+            configured_system_prompt(&config, &tags, system_prompt),
+        )
+    };
 
-            client = client.header(AUTHORIZATION, auth_info);
-        } else if let Some(auth) = headers.get("authorization")
-            && let Ok(auth_str) = auth.to_str()
-        {
-            client = client.header(AUTHORIZATION, auth_str);
-        }
+    // teach the model the ReAct format unless the request brings its own instructions
+    add_react_system_prompt(&mut request.messages, &system_prompt);
 
+    // the thought/action/observation trace returned to the client, if requested
+    let mut reasoning = include_reasoning.then(Vec::new);
+
+    let mut step = 0;
+    let mut tag_failures = 0;
+    let mut tool_failures = 0;
+    let mut has_called_tool = false;
+    let mut required_tool_retried = false;
+    let mut required_tool_instruction_idx = None;
+    loop {
+        // * send request to downstream server
         dual_debug!(
             "Request to downstream chat server - request_id: {}\n{}",
             request_id,
             serde_json::to_string_pretty(&request).unwrap()
         );
-
-        // * send request to downstream server
-
-        // Use select! to support cancellation
-        let ds_response = select! {
-            response = client.json(&request).send() => {
-                response.map_err(|e| ServerError::Operation(format!("Failed to forward request: {e}")))
-            }
-            _ = cancel_token.cancelled() => {
-                let warn_msg = "Request was cancelled by client";
-                dual_warn!("{}", warn_msg);
-                Err(ServerError::Operation(warn_msg.to_string()))
-            }
-        }?;
+        let (_, ds_response) =
+            send_chat_request(&state, &headers, &request, &cancel_token, request_id).await?;
+        step += 1;
+        *agent_step = AgentStep {
+            step,
+            ..Default::default()
+        };
+
+        // return the error of the downstream server as is
+        if !ds_response.status().is_success() {
+            return forward_error_response(ds_response, request_id).await;
+        }
 
         // get the response body
         let mut chat_completion =
@@ -183,15 +498,104 @@ pub(crate) async fn chat(
                     dual_error!("{} - request_id: {}", err_msg, request_id);
                     ServerError::Operation(err_msg)
                 })?;
+        check_choices(&chat_completion, request_id)?;
+        state.record_usage(client_user().as_deref(), &chat_completion.usage);
 
         dual_debug!(
             "chat completion:\n{}",
             serde_json::to_string_pretty(&chat_completion).unwrap()
         );
 
+        // the retry instruction only applies to the request it was added to
+        if let Some(idx) = required_tool_instruction_idx.take() {
+            request.messages.remove(idx);
+        }
+
         // Check if the response requires tool call
         let requires_tool_call = !chat_completion.choices[0].message.tool_calls.is_empty();
+
+        // Stop the loop if the model is still acting after the last allowed step
+        let content = chat_completion.choices[0]
+            .message
+            .content
+            .clone()
+            .unwrap_or_default();
+        let is_final = !requires_tool_call
+            && (content.contains(&tags.final_answer.open) || !content.contains(&tags.action.open));
+        if step >= max_react_steps && !is_final {
+            dual_warn!(
+                "No final answer after {} ReAct steps. Return the last assistant content as the final answer - request_id: {}",
+                step,
+                request_id
+            );
+
+            // Store assistant message to memory
+            if let (Some(memory), Some(conv_id)) = (&state.memory, &conv_id)
+                && let Err(e) = memory
+                    .add_assistant_message(conv_id, &content, vec![])
+                    .await
+            {
+                dual_error!(
+                    "Failed to add assistant message to memory: {} - request_id: {}",
+                    e,
+                    request_id
+                );
+            }
+
+            let choice = &mut chat_completion.choices[0];
+            choice.message.tool_calls.clear();
+            choice.finish_reason = FinishReason::stop;
+
+            let mut extra_fields = reasoning_fields(reasoning.take());
+            extra_fields.insert("reason".to_string(), MAX_STEPS_REACHED_REASON.into());
+            return chat_completion_response(
+                chat_completion,
+                stream,
+                include_usage,
+                extra_fields,
+                request_id,
+            );
+        }
+
+        // Enforce `tool_choice` if it requires a tool call but the model answered directly
+        if !requires_tool_call && !has_called_tool && is_tool_call_required(&request) {
+            let warn_msg =
+                "The model did not call any tool although `tool_choice` requires a tool call";
+            match get_required_tool_missing_policy(&state).await {
+                RequiredToolMissingPolicy::Ignore => {
+                    dual_warn!("{} - request_id: {}", warn_msg, request_id);
+                }
+                RequiredToolMissingPolicy::Reject => {
+                    dual_error!("{} - request_id: {}", warn_msg, request_id);
+                    return Err(ServerError::RequiredToolCallMissing);
+                }
+                RequiredToolMissingPolicy::Retry if !required_tool_retried => {
+                    dual_warn!(
+                        "{}. Retry with a stronger instruction - request_id: {}",
+                        warn_msg,
+                        request_id
+                    );
+
+                    required_tool_retried = true;
+                    required_tool_instruction_idx = Some(request.messages.len());
+                    request
+                        .messages
+                        .push(ChatCompletionRequestMessage::new_system_message(
+                            REQUIRED_TOOL_CALL_INSTRUCTION,
+                            None,
+                        ));
+                    continue;
+                }
+                RequiredToolMissingPolicy::Retry => {
+                    dual_error!("{} after retry - request_id: {}", warn_msg, request_id);
+                    return Err(ServerError::RequiredToolCallMissing);
+                }
+            }
+        }
+
         if requires_tool_call {
+            has_called_tool = true;
+
             // Convert tool calls to stored format for memory
             let mut stored_tool_calls = if let Some(conv_id) = &conv_id {
                 Some(convert_tool_calls_to_stored(
@@ -204,388 +608,132 @@ pub(crate) async fn chat(
 
             if let Some(content) = chat_completion.choices[0].message.content.as_ref() {
                 // Detect <thought> tags
-                if content.contains("<thought>") {
-                    // get the text between <thought> and </thought>
-                    let thought = thought_pattern
-                        .captures(content)
-                        .unwrap()
-                        .get(1)
-                        .unwrap()
-                        .as_str();
+                if let Some(thought) = tags.thought.first_body(content) {
                     dual_info!("💭 Thought: {}", thought);
+                    agent_step.thought = Some(thought.to_string());
                 }
 
                 // Detect <action> tags
-                if content.contains("<action>") {
-                    match action_pattern.captures(content) {
-                        Some(captures) => {
-                            let action = captures.get(1).unwrap().as_str();
+                if content.contains(&tags.action.open) {
+                    match tags.action.first_body(content) {
+                        Some(action) => {
                             dual_info!("🔧 Action: {}", action);
+                            agent_step.action = Some(action.to_string());
                         }
                         None => {
                             let err_msg = format!(
-                                "No <action> tags found in the response. The message content in the response: {content}"
+                                "No {} tags found in the response. The message content in the response: {content}",
+                                tags.action.open
                             );
                             dual_error!("{} - request_id: {}", err_msg, request_id);
+
+                            // retry the request until the fallback threshold is reached
+                            if let Some(max_tag_failures) = max_tag_failures {
+                                record_tag_failure(
+                                    &mut tag_failures,
+                                    max_tag_failures,
+                                    request_id,
+                                )?;
+                                continue;
+                            }
+
                             return Err(ServerError::Operation(err_msg));
                         }
                     }
                 }
             }
 
-            // * call MCP server to execute the action
-
-            // TODO: to support multiple tool calls
-            let tool_call = &chat_completion.choices[0].message.tool_calls[0];
-            let contains = tool_call.function.name.as_str().contains(MCP_SEPARATOR);
-            let parts: Vec<&str> = tool_call
-                .function
-                .name
-                .as_str()
-                .split(MCP_SEPARATOR)
-                .collect();
-            if contains && parts.len() == 2 {
-                let mcp_tool_name = parts[0];
-                let mcp_server_name = parts[1];
-                let mcp_tool_args = tool_call.function.arguments.as_str();
-                let tool_call_id = tool_call.id.as_str();
-
-                dual_info!(
-                    "Mcp server: {}, tool: {}, Tool args: {} - request_id: {}",
-                    mcp_server_name,
-                    mcp_tool_name,
-                    mcp_tool_args,
-                    request_id
-                );
-
-                if let Some(services) = MCP_SERVICES.get() {
-                    let service_map = services.read().await;
-                    // get the mcp client
-                    let service = match service_map.get(mcp_server_name) {
-                        Some(mcp_client) => mcp_client,
-                        None => {
-                            let err_msg = format!(
-                                "Not found mcp client connected with {mcp_server_name} mcp server"
-                            );
-                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                            return Err(ServerError::McpOperation(err_msg.to_string()));
-                        }
-                    };
+            // * call MCP servers to execute the actions in parallel
+            let tool_calls = &chat_completion.choices[0].message.tool_calls;
+            let failed_tool_calls = AtomicUsize::new(0);
+            let tool_contents = execute_tool_calls(
+                tool_calls,
+                |tool_call| {
+                    state.record_tool_call(&tool_call.function.name);
+                    with_tool_timeout(
+                        observe_tool_error(
+                            call_mcp_tool(&state, tool_call, &cancel_token, request_id),
+                            &failed_tool_calls,
+                            tool_call,
+                            request_id,
+                        ),
+                        tool_timeout,
+                        tool_call,
+                        request_id,
+                    )
+                },
+                &cancel_token,
+                request_id,
+            )
+            .await?;
+            record_tool_failures(
+                &mut tool_failures,
+                failed_tool_calls.into_inner(),
+                tool_calls.len(),
+                max_tool_failures,
+                request_id,
+            )?;
+            record_reasoning(&mut reasoning, agent_step, &tool_contents);
+
+            // Store tool calls and results to memory
+            if let (Some(conv_id), Some(stored_tcs), Some(memory)) =
+                (&conv_id, stored_tool_calls.as_mut(), &state.memory)
+            {
+                // Add tool results to stored tool calls
+                add_tool_results_to_stored(stored_tcs, &tool_contents);
+
+                if let Err(e) = memory
+                    .add_assistant_message(conv_id, "", stored_tcs.clone())
+                    .await
+                {
+                    dual_error!(
+                        "Failed to store tool calls to memory: {} - request_id: {}",
+                        e,
+                        request_id
+                    );
+                }
+            }
 
-                    // call a tool
-                    let request_param = CallToolRequestParam {
-                        name: mcp_tool_name.to_string().into(),
-                        arguments:
-                            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(
-                                mcp_tool_args,
-                            )
-                            .ok(),
-                    };
-                    let tool_result = service
-                        .read()
-                        .await
-                        .raw
-                        .call_tool(request_param)
-                        .await
-                        .map_err(|e| {
-                            dual_error!("Failed to call the tool: {}", e);
-                            ServerError::Operation(e.to_string())
-                        })?;
-                    dual_debug!("{}", serde_json::to_string_pretty(&tool_result).unwrap());
-
-                    match tool_result.is_error {
-                        Some(false) => {
-                            match !tool_result.content.is_empty() {
-                                true => {
-                                    let content = &tool_result.content[0];
-                                    match &content.raw {
-                                        RawContent::Text(text) => {
-                                            dual_info!(
-                                                "The mcp tool call result: {:#?}",
-                                                text.text
-                                            );
-
-                                            match SEARCH_MCP_SERVER_NAMES.contains(&mcp_server_name)
-                                            {
-                                                true => {
-                                                    dual_info!("🔍 Observation:\n{}", &text.text);
-
-                                                    // get the fallback message from the mcp client
-                                                    let fallback = if service
-                                                        .read()
-                                                        .await
-                                                        .has_fallback_message()
-                                                    {
-                                                        service
-                                                            .read()
-                                                            .await
-                                                            .fallback_message
-                                                            .clone()
-                                                            .unwrap()
-                                                    } else {
-                                                        DEFAULT_SEARCH_FALLBACK_MESSAGE.to_string()
-                                                    };
-
-                                                    dual_debug!(
-                                                        "fallback message: {} - request_id: {}",
-                                                        fallback,
-                                                        request_id
-                                                    );
-
-                                                    // format the content
-                                                    let content = format!(
-                                                        "Please answer the question based on the information between **---BEGIN CONTEXT---** and **---END CONTEXT---**. Do not use any external knowledge. If the information between **---BEGIN CONTEXT---** and **---END CONTEXT---** is empty, please respond with `{fallback}`. Note that DO NOT use any tools if provided.\n\n---BEGIN CONTEXT---\n\n{context}\n\n---END CONTEXT---",
-                                                        fallback = fallback,
-                                                        context = &text.text,
-                                                    );
-
-                                                    // tool content
-                                                    let tool_content = format!(
-                                                        "<observation>{}</observation>",
-                                                        &content
-                                                    );
-
-                                                    // Store tool calls and results to memory
-                                                    if let (
-                                                        Some(conv_id),
-                                                        Some(stored_tcs),
-                                                        Some(memory),
-                                                    ) = (
-                                                        &conv_id,
-                                                        stored_tool_calls.as_mut(),
-                                                        &state.memory,
-                                                    ) {
-                                                        // Add tool results to stored tool calls
-                                                        add_tool_results_to_stored(
-                                                            stored_tcs,
-                                                            std::slice::from_ref(&tool_content),
-                                                        );
-
-                                                        if let Err(e) = memory
-                                                            .add_assistant_message(
-                                                                conv_id,
-                                                                "",
-                                                                stored_tcs.clone(),
-                                                            )
-                                                            .await
-                                                        {
-                                                            dual_error!(
-                                                                "Failed to store tool calls to memory: {} - request_id: {}",
-                                                                e,
-                                                                request_id
-                                                            );
-                                                        }
-                                                    }
-
-                                                    if let (Some(conv_id), Some(memory)) =
-                                                        (&conv_id, &state.memory)
-                                                    {
-                                                        let context = memory
-                                                            .get_model_context(conv_id)
-                                                            .await
-                                                            .map_err(|e| {
-                                                                let err_msg = format!(
-                                                                    "Failed to get model context: {e}"
-                                                                );
-                                                                dual_error!(
-                                                                    "{} - request_id: {}",
-                                                                    err_msg,
-                                                                    request_id
-                                                                );
-                                                                ServerError::Operation(err_msg)
-                                                            })?;
-                                                        let context: Vec<
-                                                            ChatCompletionRequestMessage,
-                                                        > = context
-                                                            .into_iter()
-                                                            .map(|model_msg| model_msg.into())
-                                                            .collect();
-
-                                                        // Update request messages with context
-                                                        request.messages = context;
-                                                    } else {
-                                                        // append assistant message with tool call to request messages
-                                                        let assistant_completion_message =
-                                                            ChatCompletionRequestMessage::Assistant(
-                                                                ChatCompletionAssistantMessage::new(
-                                                                    None,
-                                                                    None,
-                                                                    Some(vec![tool_call.clone()]),
-                                                                ),
-                                                            );
-                                                        request
-                                                            .messages
-                                                            .push(assistant_completion_message);
-
-                                                        // append tool message with tool result to request messages
-                                                        let tool_completion_message =
-                                                            ChatCompletionRequestMessage::Tool(
-                                                                ChatCompletionToolMessage::new(
-                                                                    &tool_content,
-                                                                    tool_call_id,
-                                                                ),
-                                                            );
-                                                        request
-                                                            .messages
-                                                            .push(tool_completion_message);
-                                                    }
-                                                }
-                                                false => {
-                                                    dual_info!("🔍 Observation: {}", &text.text);
-
-                                                    // tool content
-                                                    let tool_content = format!(
-                                                        "<observation>{}</observation>",
-                                                        &text.text
-                                                    );
-
-                                                    // Store tool calls and results to memory
-                                                    if let (
-                                                        Some(conv_id),
-                                                        Some(stored_tcs),
-                                                        Some(memory),
-                                                    ) = (
-                                                        &conv_id,
-                                                        stored_tool_calls.as_mut(),
-                                                        &state.memory,
-                                                    ) {
-                                                        // Add tool results to stored tool calls
-                                                        add_tool_results_to_stored(
-                                                            stored_tcs,
-                                                            std::slice::from_ref(&tool_content),
-                                                        );
-
-                                                        if let Err(e) = memory
-                                                            .add_assistant_message(
-                                                                conv_id,
-                                                                "",
-                                                                stored_tcs.clone(),
-                                                            )
-                                                            .await
-                                                        {
-                                                            dual_error!(
-                                                                "Failed to store tool calls to memory: {} - request_id: {}",
-                                                                e,
-                                                                request_id
-                                                            );
-                                                        }
-                                                    }
-
-                                                    if let (Some(memory), Some(conv_id)) =
-                                                        (&state.memory, &conv_id)
-                                                    {
-                                                        let context = memory
-                                                            .get_model_context(conv_id)
-                                                            .await
-                                                            .map_err(|e| {
-                                                                let err_msg = format!(
-                                                                    "Failed to get model context: {e}"
-                                                                );
-                                                                dual_error!(
-                                                                    "{} - request_id: {}",
-                                                                    err_msg,
-                                                                    request_id
-                                                                );
-                                                                ServerError::Operation(err_msg)
-                                                            })?;
-                                                        let context: Vec<
-                                                            ChatCompletionRequestMessage,
-                                                        > = context
-                                                            .into_iter()
-                                                            .map(|model_msg| model_msg.into())
-                                                            .collect();
-
-                                                        // Update request messages with context
-                                                        request.messages = context;
-                                                    } else {
-                                                        // append assistant message with tool call to request messages
-                                                        let assistant_completion_message =
-                                                            ChatCompletionRequestMessage::Assistant(
-                                                                ChatCompletionAssistantMessage::new(
-                                                                    None,
-                                                                    None,
-                                                                    Some(vec![tool_call.clone()]),
-                                                                ),
-                                                            );
-                                                        request
-                                                            .messages
-                                                            .push(assistant_completion_message);
-
-                                                        // append tool message with tool result to request messages
-                                                        let tool_completion_message =
-                                                            ChatCompletionRequestMessage::Tool(
-                                                                ChatCompletionToolMessage::new(
-                                                                    &tool_content,
-                                                                    tool_call_id,
-                                                                ),
-                                                            );
-                                                        request
-                                                            .messages
-                                                            .push(tool_completion_message);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        _ => {
-                                            let err_msg = "Only text content is supported for tool call results";
-                                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                                            return Err(ServerError::Operation(
-                                                err_msg.to_string(),
-                                            ));
-                                        }
-                                    }
-                                }
-                                false => {
-                                    let err_msg = "The mcp tool result is empty";
-                                    dual_error!("{} - request_id: {}", err_msg, request_id);
-                                    return Err(ServerError::McpEmptyContent);
-                                }
-                            }
-                        }
-                        _ => {
-                            let err_msg = format!("Failed to call the tool: {mcp_tool_name}");
-                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                            return Err(ServerError::Operation(err_msg));
-                        }
-                    }
-                } else {
-                    let err_msg = "Empty MCP CLIENTS";
+            if let (Some(conv_id), Some(memory)) = (&conv_id, &state.memory) {
+                let context = memory.get_model_context(conv_id).await.map_err(|e| {
+                    let err_msg = format!("Failed to get model context: {e}");
                     dual_error!("{} - request_id: {}", err_msg, request_id);
-                    return Err(ServerError::McpOperation(err_msg.to_string()));
-                }
+                    ServerError::Operation(err_msg)
+                })?;
+                let context: Vec<ChatCompletionRequestMessage> = context
+                    .into_iter()
+                    .map(|model_msg| model_msg.into())
+                    .collect();
+
+                // Update request messages with context
+                request.messages = context;
             } else {
-                let err_msg = format!(
-                    "The tool call '{}' is not supported.",
-                    tool_call.function.name
+                // append assistant message with tool calls to request messages
+                let assistant_completion_message = ChatCompletionRequestMessage::Assistant(
+                    ChatCompletionAssistantMessage::new(None, None, Some(tool_calls.clone())),
                 );
-                dual_error!("{}", err_msg);
-                return Err(ServerError::Operation(err_msg));
+                request.messages.push(assistant_completion_message);
+
+                // append tool messages with tool results in the order of the tool calls
+                for (tool_call, tool_content) in tool_calls.iter().zip(tool_contents.iter()) {
+                    let tool_completion_message = ChatCompletionRequestMessage::Tool(
+                        ChatCompletionToolMessage::new(tool_content, &tool_call.id),
+                    );
+                    request.messages.push(tool_completion_message);
+                }
             }
         } else {
             match chat_completion.choices[0].message.content.as_ref() {
                 Some(content) => {
                     // Detect <thought> tags
-                    if content.contains("<thought>") {
-                        // get the text between <thought> and </thought>
-                        let thought = thought_pattern
-                            .captures(content)
-                            .unwrap()
-                            .get(1)
-                            .unwrap()
-                            .as_str();
+                    if let Some(thought) = tags.thought.first_body(content) {
                         dual_info!("💭 Thought: {}", thought);
+                        agent_step.thought = Some(thought.to_string());
                     }
 
-                    // Detect <final_answer> tags
-                    if content.contains("<final_answer>") {
-                        // get the text between <final_answer> and </final_answer>
-                        let final_answer = final_answer_pattern
-                            .captures(content)
-                            .unwrap()
-                            .get(1)
-                            .unwrap()
-                            .as_str()
-                            .to_string(); // Convert to String to avoid borrowing issues
+                    // Detect <final_answer> tags, keeping the last one
+                    if let Some(final_answer) = tags.final_answer.last_body(content) {
+                        let final_answer = final_answer.to_string(); // Convert to String to avoid borrowing issues
                         dual_info!("✅ Final answer: {}", final_answer);
 
                         // Store assistant message to memory
@@ -601,136 +749,59 @@ pub(crate) async fn chat(
                             );
                         }
 
-                        // Return chat completion
-                        match stream {
-                            true => {
-                                let chunks = gen_chunks_with_formatting(content, 10);
-                                let id = match &request.user {
-                                    Some(id) => id.clone(),
-                                    None => gen_chat_id(),
-                                };
-                                let model = chat_completion.model.clone();
-                                let usage = chat_completion.usage;
-                                let chunks_len = chunks.len();
-
-                                // Create SSE stream
-                                let request_id_owned = request_id.to_string();
-                                let stream = stream::iter(chunks.into_iter().enumerate().map(
-                                    move |(i, chunk)| {
-                                        let created = SystemTime::now()
-                                            .duration_since(std::time::UNIX_EPOCH)
-                                            .map_err(|e| {
-                                                let err_msg = format!(
-                                                    "Failed to get the current time. Reason: {e}"
-                                                );
-
-                                                dual_error!(
-                                                    "{} - request_id: {}",
-                                                    err_msg,
-                                                    request_id_owned
-                                                );
-
-                                                ServerError::Operation(err_msg)
-                                            })
-                                            .unwrap();
-
-                                        let mut chat_completion_chunk = ChatCompletionChunk {
-                                            id: id.clone(),
-                                            object: "chat.completion.chunk".to_string(),
-                                            created: created.as_secs(),
-                                            model: model.clone(),
-                                            system_fingerprint: "fp_44709d6fcb".to_string(),
-                                            choices: vec![ChatCompletionChunkChoice {
-                                                index: i as u32,
-                                                delta: ChatCompletionChunkChoiceDelta {
-                                                    role: ChatCompletionRole::Assistant,
-                                                    content: Some(chunk),
-                                                    tool_calls: vec![],
-                                                },
-                                                logprobs: None,
-                                                finish_reason: None,
-                                            }],
-                                            usage: None,
-                                        };
-
-                                        if i == chunks_len - 1 {
-                                            // update finish_reason
-                                            chat_completion_chunk.choices[0].finish_reason =
-                                                Some(FinishReason::stop);
-
-                                            // update usage
-                                            chat_completion_chunk.usage = Some(usage);
-                                        }
-
-                                        let json_str =
-                                            serde_json::to_string(&chat_completion_chunk).unwrap();
-                                        format!("data: {json_str}\n\n")
-                                    },
-                                ))
-                                .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
-                                .map(|s| Ok::<_, std::convert::Infallible>(s.into_bytes()));
-
-                                // Build streaming response
-                                let response = Response::builder()
-                                    .header(CONTENT_TYPE, "text/event-stream")
-                                    .header("Cache-Control", "no-cache")
-                                    .header("Connection", "keep-alive")
-                                    .status(StatusCode::OK)
-                                    .body(Body::from_stream(stream));
-
-                                match response {
-                                    Ok(response) => {
-                                        dual_info!(
-                                            "Streaming response sent successfully - request_id: {}",
-                                            request_id
-                                        );
-                                        return Ok(response);
-                                    }
-                                    Err(e) => {
-                                        let err_msg =
-                                            format!("Failed to create streaming response: {e}");
-                                        dual_error!("{} - request_id: {}", err_msg, request_id);
-                                        return Err(ServerError::Operation(err_msg));
-                                    }
-                                }
-                            }
-                            false => {
-                                chat_completion.choices[0].message.content =
-                                    Some(final_answer.to_string());
-                                let response_body =
-                                    serde_json::to_string(&chat_completion).unwrap();
-
-                                // build the response builder
-                                let response_builder = Response::builder()
-                                    .header(CONTENT_TYPE, "application/json")
-                                    .status(StatusCode::OK);
-
-                                match response_builder.body(Body::from(response_body)) {
-                                    Ok(response) => {
-                                        return Ok(response);
-                                    }
-                                    Err(e) => {
-                                        let err_msg = format!("Failed to create the response: {e}");
-                                        dual_error!("{} - request_id: {}", err_msg, request_id);
-                                        return Err(ServerError::Operation(err_msg));
-                                    }
-                                }
-                            }
-                        }
+                        record_reasoning(&mut reasoning, agent_step, &[]);
+
+                        // Return chat completion. The streamed answer keeps the tags of the
+                        // model content.
+                        let answer = match stream {
+                            true => content.to_string(),
+                            false => final_answer,
+                        };
+                        chat_completion.choices[0].message.content = Some(answer);
+                        return chat_completion_response(
+                            chat_completion,
+                            stream,
+                            include_usage,
+                            reasoning_fields(reasoning.take()),
+                            request_id,
+                        );
                     }
 
                     // Detect <action> tags
-                    match action_pattern.captures(content) {
-                        Some(captures) => {
-                            let action = captures.get(1).unwrap().as_str();
+                    match tags.action.first_body(content) {
+                        Some(action) => {
                             dual_info!("🔧 Action: {}", action);
+                            agent_step.action = Some(action.to_string());
+                            record_reasoning(&mut reasoning, agent_step, &[]);
+
+                            // the action was not issued as a tool call
+                            if let Some(max_tag_failures) = max_tag_failures {
+                                record_tag_failure(
+                                    &mut tag_failures,
+                                    max_tag_failures,
+                                    request_id,
+                                )?;
+                            }
                         }
                         None => {
                             let warn_msg = format!(
-                                "No <action> or <final_answer> tags found in the response: {content}"
+                                "No {} or {} tags found in the response: {content}",
+                                tags.action.open, tags.final_answer.open
                             );
                             dual_warn!("{} - request_id: {}", warn_msg, request_id);
 
+                            // retry a malformed action until the fallback threshold is reached
+                            if let Some(max_tag_failures) = max_tag_failures
+                                && content.contains(&tags.action.open)
+                            {
+                                record_tag_failure(
+                                    &mut tag_failures,
+                                    max_tag_failures,
+                                    request_id,
+                                )?;
+                                continue;
+                            }
+
                             dual_info!("✅ Final answer: {}", content);
 
                             // Store assistant message to memory
@@ -745,154 +816,1113 @@ pub(crate) async fn chat(
                                 );
                             }
 
+                            record_reasoning(&mut reasoning, agent_step, &[]);
+
                             // Return chat completion
-                            match stream {
-                                true => {
-                                    let chunks = gen_chunks_with_formatting(content, 10);
-                                    let id = match &request.user {
-                                        Some(id) => id.clone(),
-                                        None => gen_chat_id(),
-                                    };
-                                    let model = chat_completion.model.clone();
-                                    let usage = chat_completion.usage;
-                                    let chunks_len = chunks.len();
-
-                                    // Create SSE stream
-                                    let request_id_owned = request_id.to_string();
-                                    let stream = stream::iter(chunks.into_iter().enumerate().map(
-                                        move |(i, chunk)| {
-                                            let created = SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .map_err(|e| {
-                                                    let err_msg = format!(
-                                                        "Failed to get the current time. Reason: {e}"
-                                                    );
-
-                                                    dual_error!(
-                                                        "{} - request_id: {}",
-                                                        err_msg,
-                                                        request_id_owned
-                                                    );
-
-                                                    ServerError::Operation(err_msg)
-                                                })
-                                                .unwrap();
-
-                                            let mut chat_completion_chunk = ChatCompletionChunk {
-                                                id: id.clone(),
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: created.as_secs(),
-                                                model: model.clone(),
-                                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                choices: vec![ChatCompletionChunkChoice {
-                                                    index: i as u32,
-                                                    delta: ChatCompletionChunkChoiceDelta {
-                                                        role: ChatCompletionRole::Assistant,
-                                                        content: Some(chunk),
-                                                        tool_calls: vec![],
-                                                    },
-                                                    logprobs: None,
-                                                    finish_reason: None,
-                                                }],
-                                                usage: None,
-                                            };
-
-                                            if i == chunks_len - 1 {
-                                                // update finish_reason
-                                                chat_completion_chunk.choices[0].finish_reason =
-                                                    Some(FinishReason::stop);
-
-                                                // update usage
-                                                chat_completion_chunk.usage = Some(usage);
-                                            }
-
-                                            let json_str =
-                                                serde_json::to_string(&chat_completion_chunk).unwrap();
-                                            format!("data: {json_str}\n\n")
-                                        },
-                                    ))
-                                    .chain(stream::once(async { "data: [DONE]\n\n".to_string() }))
-                                    .map(|s| Ok::<_, std::convert::Infallible>(s.into_bytes()));
-
-                                    // Build streaming response
-                                    let response = Response::builder()
-                                        .header(CONTENT_TYPE, "text/event-stream")
-                                        .header("Cache-Control", "no-cache")
-                                        .header("Connection", "keep-alive")
-                                        .status(StatusCode::OK)
-                                        .body(Body::from_stream(stream));
-
-                                    match response {
-                                        Ok(response) => {
-                                            dual_info!(
-                                                "Streaming response sent successfully - request_id: {}",
-                                                request_id
-                                            );
-                                            return Ok(response);
-                                        }
-                                        Err(e) => {
-                                            let err_msg =
-                                                format!("Failed to create streaming response: {e}");
-                                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                                            return Err(ServerError::Operation(err_msg));
-                                        }
-                                    }
-                                }
-                                false => {
-                                    chat_completion.choices[0].message.content =
-                                        Some(content.to_string());
-                                    let response_body =
-                                        serde_json::to_string(&chat_completion).unwrap();
-
-                                    // build the response builder
-                                    let response_builder = Response::builder()
-                                        .header(CONTENT_TYPE, "application/json")
-                                        .status(StatusCode::OK);
-
-                                    match response_builder.body(Body::from(response_body)) {
-                                        Ok(response) => {
-                                            return Ok(response);
-                                        }
-                                        Err(e) => {
-                                            let err_msg =
-                                                format!("Failed to create the response: {e}");
-                                            dual_error!("{} - request_id: {}", err_msg, request_id);
-                                            return Err(ServerError::Operation(err_msg));
-                                        }
-                                    }
-                                }
-                            }
+                            chat_completion.choices[0].message.content = Some(content.to_string());
+                            return chat_completion_response(
+                                chat_completion,
+                                stream,
+                                include_usage,
+                                reasoning_fields(reasoning.take()),
+                                request_id,
+                            );
                         }
                     }
                 }
                 None => {
-                    todo!()
+                    let err_msg = "The model returned neither content nor tool calls";
+                    dual_error!("{} - request_id: {}", err_msg, request_id);
+                    return Err(ServerError::Operation(err_msg.to_string()));
                 }
             }
         }
     }
 }
 
-async fn get_chat_server(
-    state: &Arc<AppState>,
+/// A step of the ReAct loop returned in the `reasoning` field of the response
+#[derive(Debug, Serialize)]
+struct ReasoningStep {
+    #[serde(flatten)]
+    agent_step: AgentStep,
+    /// The observations of the tool calls of the step
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    observations: Vec<String>,
+}
+
+/// Record a step of the ReAct loop in the reasoning trace, if it was requested
+fn record_reasoning(
+    reasoning: &mut Option<Vec<ReasoningStep>>,
+    agent_step: &AgentStep,
+    observations: &[String],
+) {
+    if let Some(reasoning) = reasoning.as_mut() {
+        reasoning.push(ReasoningStep {
+            agent_step: agent_step.clone(),
+            observations: observations.to_vec(),
+        });
+    }
+}
+
+/// Extra fields of the response carrying the reasoning trace, if it was requested
+fn reasoning_fields(
+    reasoning: Option<Vec<ReasoningStep>>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut fields = serde_json::Map::new();
+    if let Some(reasoning) = reasoning {
+        fields.insert(
+            "reasoning".to_string(),
+            serde_json::to_value(reasoning).unwrap_or_default(),
+        );
+    }
+    fields
+}
+
+/// The ReAct system prompt of a request: its own, else the configured one, else the default one
+/// adapted to the configured tags
+fn configured_system_prompt(
+    config: &Config,
+    tags: &ReactTagSet,
+    system_prompt: Option<String>,
+) -> String {
+    system_prompt
+        .or_else(|| {
+            config
+                .react
+                .as_ref()
+                .and_then(|react_config| react_config.system_prompt.clone())
+        })
+        .unwrap_or_else(|| tags.adapt_prompt(DEFAULT_REACT_SYSTEM_PROMPT))
+}
+
+/// Add the ReAct system prompt the loop would send to the messages of a request
+pub(super) async fn add_configured_react_system_prompt(
+    state: &AppState,
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    system_prompt: Option<String>,
+) {
+    let tags = ReactTagSet::from_config(state).await;
+    let system_prompt = configured_system_prompt(&*state.config.read().await, &tags, system_prompt);
+    add_react_system_prompt(messages, &system_prompt);
+}
+
+/// Add the ReAct system prompt as the first message if the messages have no system message.
+/// An empty prompt is not added.
+fn add_react_system_prompt(messages: &mut Vec<ChatCompletionRequestMessage>, system_prompt: &str) {
+    let has_system_message = messages
+        .iter()
+        .any(|message| matches!(message, ChatCompletionRequestMessage::System(_)));
+    if !has_system_message && !system_prompt.is_empty() {
+        messages.insert(
+            0,
+            ChatCompletionRequestMessage::new_system_message(system_prompt, None),
+        );
+    }
+}
+
+/// Execute the tool calls of a single assistant turn concurrently and return their
+/// observations in the order of the tool calls. Cancelling the token drops all in-flight calls.
+async fn execute_tool_calls<'a, F, Fut>(
+    tool_calls: &'a [ToolCall],
+    call_tool: F,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<Vec<String>>
+where
+    F: Fn(&'a ToolCall) -> Fut,
+    Fut: Future<Output = ServerResult<String>>,
+{
+    dual_info!(
+        "Execute {} tool call(s) - request_id: {}",
+        tool_calls.len(),
+        request_id
+    );
+
+    let results = select! {
+        results = join_all(tool_calls.iter().map(call_tool)) => results,
+        _ = cancel_token.cancelled() => {
+            let warn_msg = "Tool calls were cancelled by client";
+            dual_warn!("{} - request_id: {}", warn_msg, request_id);
+            return Err(ServerError::Operation(warn_msg.to_string()));
+        }
+    };
+
+    let mut observations = Vec::with_capacity(results.len());
+    for (result, tool_call) in results.into_iter().zip(tool_calls) {
+        match result {
+            Ok(observation) => observations.push(observation),
+            Err(e) => {
+                return Err(e.with_agent_step(AgentStep {
+                    tool: Some(tool_call.function.name.clone()),
+                    ..Default::default()
+                }));
+            }
+        }
+    }
+
+    Ok(observations)
+}
+
+/// Abandon the tool call if it does not complete within `timeout`. The model is then told that
+/// the tool timed out, so that it can carry on without the result.
+async fn with_tool_timeout(
+    call: impl Future<Output = ServerResult<String>>,
+    timeout: Option<Duration>,
+    tool_call: &ToolCall,
+    request_id: &str,
+) -> ServerResult<String> {
+    let Some(timeout) = timeout else {
+        return call.await;
+    };
+
+    match tokio::time::timeout(timeout, call).await {
+        Ok(result) => result,
+        Err(_) => {
+            dual_warn!(
+                "The tool call {} timed out after {:?} - request_id: {}",
+                tool_call.function.name,
+                timeout,
+                request_id
+            );
+            Ok(TOOL_TIMEOUT_OBSERVATION.to_string())
+        }
+    }
+}
+
+/// Report a failed tool call to the model as an error observation instead of aborting the loop,
+/// so that it can try another way. The failure is counted in `failed_tool_calls`.
+async fn observe_tool_error(
+    call: impl Future<Output = ServerResult<String>>,
+    failed_tool_calls: &AtomicUsize,
+    tool_call: &ToolCall,
+    request_id: &str,
+) -> ServerResult<String> {
+    match call.await {
+        Err(ServerError::McpToolFailed(e)) => {
+            dual_warn!(
+                "The tool call {} failed, reported to the model: {} - request_id: {}",
+                tool_call.function.name,
+                e,
+                request_id
+            );
+            failed_tool_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(format!("<observation>Error: {e}</observation>"))
+        }
+        result => result,
+    }
+}
+
+/// Call the MCP tool of the given tool call and return the result as an `<observation>` block
+async fn call_mcp_tool(
+    state: &AppState,
+    tool_call: &ToolCall,
+    cancel_token: &CancellationToken,
     request_id: &str,
-) -> ServerResult<crate::server::TargetServerInfo> {
-    let servers = state.server_group.read().await;
-    let chat_servers = match servers.get(&ServerKind::chat) {
-        Some(servers) => servers,
+) -> ServerResult<String> {
+    let parts: Vec<&str> = tool_call
+        .function
+        .name
+        .as_str()
+        .split(MCP_SEPARATOR)
+        .collect();
+    if parts.len() != 2 {
+        let err_msg = format!(
+            "The tool call '{}' is not supported.",
+            tool_call.function.name
+        );
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg));
+    }
+
+    let mcp_tool_name = parts[0];
+    let mcp_server_name = parts[1];
+    let mcp_tool_args = tool_call.function.arguments.as_str();
+
+    dual_info!(
+        "Mcp server: {}, tool: {}, Tool args: {} - request_id: {}",
+        mcp_server_name,
+        mcp_tool_name,
+        mcp_tool_args,
+        request_id
+    );
+
+    let Some(services) = MCP_SERVICES.get() else {
+        let err_msg = "Empty MCP CLIENTS";
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::McpNotFoundClient(mcp_server_name.to_string()));
+    };
+
+    let service_map = services.read().await;
+    // get the mcp client
+    let service = match service_map.get(mcp_server_name) {
+        Some(mcp_client) => mcp_client,
         None => {
-            let err_msg = "No chat server available. Please register a chat server via the `/admin/servers/register` endpoint.";
+            let err_msg =
+                format!("Not found mcp client connected with {mcp_server_name} mcp server");
             dual_error!("{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg.to_string()));
+            return Err(ServerError::McpNotFoundClient(mcp_server_name.to_string()));
         }
     };
 
-    match chat_servers.next().await {
-        Ok(target_server_info) => Ok(target_server_info),
-        Err(e) => {
-            let err_msg = format!("Failed to get the chat server: {e}");
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            Err(ServerError::Operation(err_msg))
+    // map a truncated tool name back to the name of the tool on the mcp server
+    let mcp_tool_name = state
+        .config
+        .read()
+        .await
+        .mcp
+        .as_ref()
+        .and_then(|mcp_config| resolve_tool_name(mcp_config, &tool_call.function.name))
+        .unwrap_or_else(|| mcp_tool_name.to_string());
+    check_tool_allowed(state, mcp_server_name, &mcp_tool_name, request_id).await?;
+
+    // call a tool
+    let request_param = CallToolRequestParam {
+        name: mcp_tool_name.clone().into(),
+        arguments: serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(
+            mcp_tool_args,
+        )
+        .ok(),
+    };
+    let tool_result = call_tool_with_retry(state, service, request_param, cancel_token, request_id)
+        .await
+        .map_err(|e| {
+            dual_error!(
+                "Failed to call the tool: {} - request_id: {}",
+                e,
+                request_id
+            );
+            ServerError::McpToolFailed(e.to_string())
+        })?;
+    dual_debug!("{}", serde_json::to_string_pretty(&tool_result).unwrap());
+
+    if tool_result.is_error != Some(false) {
+        // the content of an error result describes the error
+        let err_msg = match tool_result.content.first() {
+            Some(content) => content_to_text(&content.raw),
+            None => format!("Failed to call the tool: {mcp_tool_name}"),
+        };
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::McpToolFailed(err_msg));
+    }
+
+    let Some(content) = tool_result.content.first() else {
+        let err_msg = "The mcp tool result is empty";
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::McpEmptyContent);
+    };
+
+    let text = content_to_text(&content.raw);
+    dual_info!("The mcp tool call result: {:#?}", text);
+
+    match SEARCH_MCP_SERVER_NAMES.contains(&mcp_server_name) {
+        true => {
+            dual_info!("🔍 Observation:\n{}", &text);
+
+            // get the fallback message from the mcp client
+            let fallback = if service.read().await.has_fallback_message() {
+                service.read().await.fallback_message.clone().unwrap()
+            } else {
+                DEFAULT_SEARCH_FALLBACK_MESSAGE.to_string()
+            };
+
+            dual_debug!(
+                "fallback message: {} - request_id: {}",
+                fallback,
+                request_id
+            );
+
+            // format the content
+            let content = format!(
+                "Please answer the question based on the information between **---BEGIN CONTEXT---** and **---END CONTEXT---**. Do not use any external knowledge. If the information between **---BEGIN CONTEXT---** and **---END CONTEXT---** is empty, please respond with `{fallback}`. Note that DO NOT use any tools if provided.\n\n---BEGIN CONTEXT---\n\n{context}\n\n---END CONTEXT---",
+                fallback = fallback,
+                context = &text,
+            );
+
+            Ok(format!("<observation>{}</observation>", &content))
+        }
+        false => {
+            dual_info!("🔍 Observation: {}", &text);
+
+            Ok(format!("<observation>{}</observation>", &text))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use axum::{Router, http::StatusCode, routing::post};
+    use endpoints::chat::ChatCompletionChunk;
+
+    use super::*;
+    use crate::{
+        config::{ChatMode, ServerConfig},
+        info::ServerInfo,
+        memory::MessageRole,
+        test_utils::*,
+    };
+
+    fn create_tool_call(id: &str, name: &str) -> ToolCall {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "type": "function",
+            "function": { "name": name, "arguments": "{}" }
+        }))
+        .unwrap()
+    }
+
+    /// Mock MCP tools that sleep for the number of milliseconds given in their name
+    async fn sleepy_tool(tool_call: &ToolCall) -> ServerResult<String> {
+        let millis: u64 = tool_call.function.name.parse().unwrap();
+        tokio::time::sleep(Duration::from_millis(millis)).await;
+        Ok(format!("<observation>{}</observation>", tool_call.id))
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_are_executed_in_parallel() {
+        let tool_calls = vec![
+            create_tool_call("call-1", "400"),
+            create_tool_call("call-2", "300"),
+        ];
+
+        let start = Instant::now();
+        let observations =
+            execute_tool_calls(&tool_calls, sleepy_tool, &CancellationToken::new(), "test")
+                .await
+                .unwrap();
+        let elapsed = start.elapsed();
+
+        // observations keep the order of the tool calls even though the second call finishes first
+        assert_eq!(
+            observations,
+            vec![
+                "<observation>call-1</observation>",
+                "<observation>call-2</observation>"
+            ]
+        );
+        assert!(elapsed >= Duration::from_millis(400));
+        assert!(elapsed < Duration::from_millis(650), "elapsed: {elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_are_cancelled() {
+        let tool_calls = vec![
+            create_tool_call("call-1", "5000"),
+            create_tool_call("call-2", "5000"),
+        ];
+        let cancel_token = CancellationToken::new();
+        let token = cancel_token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token.cancel();
+        });
+
+        let start = Instant::now();
+        let result = execute_tool_calls(&tool_calls, sleepy_tool, &cancel_token, "test").await;
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_timeout_is_an_observation() {
+        let tool_calls = vec![
+            create_tool_call("call-1", "5000"),
+            create_tool_call("call-2", "10"),
+        ];
+
+        let start = Instant::now();
+        let observations = execute_tool_calls(
+            &tool_calls,
+            |tool_call| {
+                with_tool_timeout(
+                    sleepy_tool(tool_call),
+                    Some(Duration::from_millis(100)),
+                    tool_call,
+                    "test",
+                )
+            },
+            &CancellationToken::new(),
+            "test",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            observations,
+            vec![
+                TOOL_TIMEOUT_OBSERVATION,
+                "<observation>call-2</observation>"
+            ]
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_failed_tool_call_is_an_error_observation() {
+        let tool_calls = vec![create_tool_call("call-1", "get_weather")];
+        let attempts = AtomicUsize::new(0);
+        // the tool fails on its first call only
+        let flaky_tool = |_: &ToolCall| async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(ServerError::McpToolFailed("city not found".to_string())),
+                _ => Ok("<observation>sunny</observation>".to_string()),
+            }
+        };
+
+        let mut tool_failures = 0;
+        let run_step = async || {
+            let failed_tool_calls = AtomicUsize::new(0);
+            let observations = execute_tool_calls(
+                &tool_calls,
+                |tool_call| {
+                    observe_tool_error(flaky_tool(tool_call), &failed_tool_calls, tool_call, "test")
+                },
+                &CancellationToken::new(),
+                "test",
+            )
+            .await
+            .unwrap();
+            let failed = failed_tool_calls.into_inner();
+            (observations, failed)
+        };
+
+        // the failure is reported to the model, which calls the tool again on the next step
+        let (observations, failed) = run_step().await;
+        assert_eq!(
+            observations,
+            ["<observation>Error: city not found</observation>"]
+        );
+        assert!(record_tool_failures(&mut tool_failures, failed, 1, 2, "test").is_ok());
+        assert_eq!(tool_failures, 1);
+
+        let (observations, failed) = run_step().await;
+        assert_eq!(observations, ["<observation>sunny</observation>"]);
+        assert!(record_tool_failures(&mut tool_failures, failed, 1, 2, "test").is_ok());
+        assert_eq!(tool_failures, 0);
+
+        // the loop is aborted once the tools failed too many times in a row
+        assert!(record_tool_failures(&mut tool_failures, 1, 1, 2, "test").is_ok());
+        assert!(matches!(
+            record_tool_failures(&mut tool_failures, 1, 1, 2, "test"),
+            Err(ServerError::ReactToolFailures(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_tool_call_is_rejected() {
+        let tool_calls = vec![create_tool_call("call-1", "get_weather")];
+
+        let state = AppState::new(Config::default(), ServerInfo::default());
+        let cancel_token = CancellationToken::new();
+        let result = execute_tool_calls(
+            &tool_calls,
+            |tool_call| call_mcp_tool(&state, tool_call, &cancel_token, "test"),
+            &cancel_token,
+            "test",
+        )
+        .await;
+        match result {
+            Err(ServerError::ReactStep { step, .. }) => {
+                assert_eq!(step.tool.as_deref(), Some("get_weather"));
+            }
+            result => panic!("unexpected result: {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loop_stops_after_max_react_steps() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Json(chat_completion_json(
+                        "<thought>I need to search</thought><action>search the web</action>",
+                    ))
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let config = Config {
+            server: ServerConfig {
+                max_react_steps: 3,
+                ..Config::default().server
+            },
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+        }))
+        .unwrap();
+
+        let response = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            false,
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["reason"], MAX_STEPS_REACHED_REASON);
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            "<thought>I need to search</thought><action>search the web</action>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_action_tag_error_has_step_context() {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                // the action tag is left empty
+                let mut completion =
+                    chat_completion_json("<thought>I need the weather</thought><action>");
+                completion["choices"][0]["message"]["tool_calls"] = serde_json::json!([{
+                    "id": "call-1",
+                    "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{}" }
+                }]);
+                Json(completion)
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+        }))
+        .unwrap();
+
+        let err = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            false,
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap_err();
+
+        match &err {
+            ServerError::ReactStep { step, .. } => {
+                assert_eq!(step.step, 1);
+                assert_eq!(step.thought.as_deref(), Some("I need the weather"));
+                assert_eq!(step.action, None);
+            }
+            e => panic!("unexpected error: {e:?}"),
+        }
+
+        let response = axum::response::IntoResponse::into_response(err);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"]["code"], "operation_failed");
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("No <action> tags found")
+        );
+        assert_eq!(body["error"]["agent_step"]["step"], 1);
+        assert_eq!(body["error"]["agent_step"]["thought"], "I need the weather");
+    }
+
+    #[tokio::test]
+    async fn test_completion_without_content_is_an_error() {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                let mut completion = chat_completion_json("");
+                completion["choices"][0]["message"]["content"] = serde_json::Value::Null;
+                Json(completion)
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+        }))
+        .unwrap();
+
+        let err = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            false,
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap_err();
+        match &err {
+            ServerError::ReactStep { error, step } => {
+                assert!(matches!(**error, ServerError::Operation(_)), "{error:?}");
+                assert_eq!(step.step, 1);
+            }
+            e => panic!("unexpected error: {e:?}"),
+        }
+        let response = axum::response::IntoResponse::into_response(err);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_unclosed_tags_run_to_the_end_of_the_content() {
+        let tags = ReactTagSet::new(&ReactTags::default());
+
+        let content = "<thought>I need the weather<action>get_weather(Paris)";
+        assert_eq!(
+            tags.thought.first_body(content),
+            Some("I need the weather<action>get_weather(Paris)")
+        );
+        assert_eq!(tags.action.first_body(content), Some("get_weather(Paris)"));
+        assert_eq!(tags.final_answer.last_body(content), None);
+
+        let content = "<final_answer>draft</final_answer><final_answer>It is sunny.";
+        assert_eq!(tags.final_answer.last_body(content), Some("It is sunny."));
+        let content = "<thought>I know it</thought><final_answer>It is sunny.</final_answer>";
+        assert_eq!(tags.thought.first_body(content), Some("I know it"));
+        assert_eq!(tags.final_answer.last_body(content), Some("It is sunny."));
+
+        // empty tags count as missing
+        assert_eq!(tags.action.first_body("<action>  </action>"), None);
+        assert_eq!(tags.final_answer.last_body("<final_answer>"), None);
+
+        // the built-in prompt follows the configured tags
+        let tags = ReactTagSet::new(&ReactTags {
+            thought: "think".to_string(),
+            final_answer: "answer".to_string(),
+            ..Default::default()
+        });
+        let prompt = tags.adapt_prompt(DEFAULT_REACT_SYSTEM_PROMPT);
+        assert!(prompt.contains("<think> and </think>"));
+        assert!(prompt.contains("<action> and </action>"));
+        assert!(prompt.contains("<answer> and </answer>"));
+        assert!(!prompt.contains("final_answer"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_unclosed_final_answer_tag_is_extracted() {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|Json(request): Json<serde_json::Value>| async move {
+                // the system prompt uses the configured tags
+                let system_prompt = request["messages"][0]["content"].as_str().unwrap();
+                assert!(system_prompt.contains("<answer>"));
+                Json(chat_completion_json(
+                    "<think>I know it</think><answer>It is sunny in Paris.",
+                ))
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let config = Config {
+            react: Some(ReactConfig {
+                tags: ReactTags {
+                    thought: "think".to_string(),
+                    final_answer: "answer".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+        }))
+        .unwrap();
+
+        let response = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            true,
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            "It is sunny in Paris."
+        );
+        assert_eq!(body["reasoning"][0]["thought"], "I know it");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_normal_on_repeated_tag_failures() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move |Json(request): Json<serde_json::Value>| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    // the model never follows the ReAct format when asked to
+                    let content = match request.to_string().contains("<action>") {
+                        true => "<thought>I need to search</thought><action>search the web",
+                        false => "It is sunny in Paris.",
+                    };
+                    Json(chat_completion_json(content))
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let config = Config {
+            react: Some(ReactConfig {
+                fallback_to_normal: true,
+                max_tag_failures: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [
+                { "role": "system", "content": "Think in <thought> tags, act in <action> tags and answer in <final_answer> tags." },
+                { "role": "user", "content": "What is the weather in Paris?" }
+            ],
+        }))
+        .unwrap();
+
+        let response = chat(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            Json(request),
+            None,
+            false,
+            None,
+            "test-request",
+        )
+        .await
+        .unwrap();
+
+        // two failed ReAct steps and one request in normal mode
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(REACT_FALLBACK_HEADER).unwrap(),
+            "true"
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            "It is sunny in Paris."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback_stores_each_turn_to_memory_once() {
+        use std::sync::atomic::AtomicBool;
+
+        use crate::{config::MemoryConfig, memory::CompleteChatMemory};
+
+        let fail = Arc::new(AtomicBool::new(false));
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post({
+                let fail = fail.clone();
+                move |Json(request): Json<serde_json::Value>| {
+                    let fail = fail.clone();
+                    async move {
+                        if fail.load(Ordering::SeqCst) {
+                            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                        }
+                        // the model never follows the ReAct format when asked to
+                        let content = match request.to_string().contains("<action>") {
+                            true => "<thought>I need to search</thought><action>search the web",
+                            false => "It is sunny in Paris.",
+                        };
+                        Ok(Json(chat_completion_json(content)))
+                    }
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = Arc::new(
+            CompleteChatMemory::new(MemoryConfig {
+                enable: true,
+                database_path: database_path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let conv_id = memory
+            .get_or_create_user_conversation("alice", "test-model")
+            .await
+            .unwrap();
+        let config = Config {
+            react: Some(ReactConfig {
+                fallback_to_normal: true,
+                max_tag_failures: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let state =
+            Arc::new(AppState::new(config, ServerInfo::default()).with_memory(memory.clone()));
+        let server: crate::server::Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        state.register_downstream_server(server).await.unwrap();
+
+        let send = || {
+            let state = state.clone();
+            let conv_id = conv_id.clone();
+            async move {
+                let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                    "model": "test-model",
+                    "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+                    "user": "alice",
+                }))
+                .unwrap();
+                chat(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    HeaderMap::new(),
+                    Json(request),
+                    Some(conv_id),
+                    false,
+                    None,
+                    "test-request",
+                )
+                .await
+            }
+        };
+        let roles = || async {
+            memory
+                .get_full_history(&conv_id, false)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|message| message.role)
+                .collect::<Vec<_>>()
+        };
+
+        // a failed turn leaves no unanswered user message behind
+        fail.store(true, Ordering::SeqCst);
+        let response = send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(roles().await.is_empty());
+
+        // the fallback stores the user message once, with its answer
+        fail.store(false, Ordering::SeqCst);
+        for _ in 0..2 {
+            let response = send().await.unwrap();
+            assert_eq!(
+                response.headers().get(REACT_FALLBACK_HEADER).unwrap(),
+                "true"
+            );
+        }
+        assert_eq!(
+            roles().await,
+            [
+                MessageRole::User,
+                MessageRole::Assistant,
+                MessageRole::User,
+                MessageRole::Assistant
+            ]
+        );
+        let history = memory.get_full_history(&conv_id, false).await.unwrap();
+        assert_eq!(history[1].content, "It is sunny in Paris.");
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_is_sent_during_slow_react_steps() {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                // a slow reasoning step before the final answer
+                tokio::time::sleep(Duration::from_millis(1300)).await;
+                Json(chat_completion_json(
+                    "<thought>I know it</thought><final_answer>It is sunny in Paris.</final_answer>",
+                ))
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let config = Config {
+            server: ServerConfig {
+                chat_mode: ChatMode::React,
+                sse_keepalive_secs: 1,
+                ..Config::default().server
+            },
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+        let request: crate::chat::ChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+            "stream": true,
+        }))
+        .unwrap();
+
+        let response = crate::handlers::chat_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            crate::request_id::RequestId::new(),
+            Json(request),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+        // the keepalive comments come first and the chunks after them still parse
+        assert!(body.starts_with(": keepalive\n\n"), "body: {body}");
+        let mut content = String::new();
+        for event in body.split("\n\n").filter(|event| !event.is_empty()) {
+            if event.starts_with(':') {
+                continue;
+            }
+            let data = event.strip_prefix("data: ").unwrap();
+            if data == "[DONE]" {
+                continue;
+            }
+            let chunk: ChatCompletionChunk = serde_json::from_str(data).unwrap();
+            if let Some(delta) = chunk.choices[0].delta.content.as_deref() {
+                content.push_str(delta);
+            }
+        }
+        assert!(
+            content.contains("It is sunny in Paris."),
+            "content: {content}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_is_returned_only_when_requested() {
+        // the model acts at odd calls and answers at even calls
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let hits = hits.clone();
+                async move {
+                    let content = match hits.fetch_add(1, Ordering::SeqCst) % 2 {
+                        0 => "<thought>I need to search</thought><action>search the web</action>",
+                        _ => {
+                            "<thought>I know it</thought><final_answer>It is sunny.</final_answer>"
+                        }
+                    };
+                    Json(chat_completion_json(content))
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+
+        for include_reasoning in [true, false] {
+            let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "What is the weather in Paris?" }],
+            }))
+            .unwrap();
+            let response = chat(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                HeaderMap::new(),
+                Json(request),
+                None,
+                include_reasoning,
+                None,
+                "test-request",
+            )
+            .await
+            .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "It is sunny.");
+
+            match include_reasoning {
+                true => assert_eq!(
+                    body["reasoning"],
+                    serde_json::json!([
+                        { "step": 1, "thought": "I need to search", "action": "search the web" },
+                        { "step": 2, "thought": "I know it" }
+                    ])
+                ),
+                false => assert!(body.get("reasoning").is_none()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_react_system_prompt_is_added_once() {
+        let mut messages = vec![ChatCompletionRequestMessage::new_user_message(
+            endpoints::chat::ChatCompletionUserMessageContent::Text("Hi".to_string()),
+            None,
+        )];
+
+        // the prompt is prepended to messages without a system message, and only once
+        add_react_system_prompt(&mut messages, DEFAULT_REACT_SYSTEM_PROMPT);
+        add_react_system_prompt(&mut messages, DEFAULT_REACT_SYSTEM_PROMPT);
+        assert_eq!(messages.len(), 2);
+        match &messages[0] {
+            ChatCompletionRequestMessage::System(message) => {
+                assert_eq!(message.content(), DEFAULT_REACT_SYSTEM_PROMPT)
+            }
+            message => panic!("unexpected message: {message:?}"),
+        }
+
+        // an existing system message is kept as is
+        let mut messages = vec![ChatCompletionRequestMessage::new_system_message(
+            "Answer in French",
+            None,
+        )];
+        add_react_system_prompt(&mut messages, DEFAULT_REACT_SYSTEM_PROMPT);
+        assert_eq!(messages.len(), 1);
+
+        // an empty prompt is not added
+        let mut messages = Vec::new();
+        add_react_system_prompt(&mut messages, "");
+        assert!(messages.is_empty());
+    }
+}
@@ -1,6 +1,44 @@
-use endpoints::chat::{ChatCompletionRequest, ChatCompletionUserMessageContent, ToolCall};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime},
+};
 
-use crate::memory::{StoredToolCall, StoredToolResult};
+use axum::{
+    body::Body,
+    http::{HeaderMap, Response, StatusCode},
+};
+use bytes::Bytes;
+use endpoints::{
+    chat::{
+        ChatCompletionChunk, ChatCompletionChunkChoice, ChatCompletionChunkChoiceDelta,
+        ChatCompletionObject, ChatCompletionRequest, ChatCompletionRole,
+        ChatCompletionUserMessageContent, ToolCall, ToolChoice,
+    },
+    common::{FinishReason, Usage},
+};
+use futures_util::{StreamExt, stream};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    AppState, access_log,
+    chat::{downstream_body, gen_chat_id},
+    config::RequiredToolMissingPolicy,
+    dual_debug, dual_error, dual_warn,
+    error::{ServerError, ServerResult},
+    memory::{StoredToolCall, StoredToolResult},
+    server::{ServerKind, TargetServerInfo},
+};
+
+/// Instruction appended to the messages when retrying a request whose `tool_choice` requires a tool call
+pub(super) const REQUIRED_TOOL_CALL_INSTRUCTION: &str =
+    "You MUST respond by calling one of the provided tools. Do NOT answer the question directly.";
+
+const SSE_KEEPALIVE_COMMENT: &[u8] = b": keepalive\n\n";
 
 /// Extract user messages from the chat request
 pub(super) fn extract_user_message(request: &ChatCompletionRequest) -> Option<String> {
@@ -32,6 +70,28 @@ pub(super) fn extract_user_message(request: &ChatCompletionRequest) -> Option<St
     })
 }
 
+/// Check if the `tool_choice` of the chat request forces the model to call a tool
+pub(super) fn is_tool_call_required(request: &ChatCompletionRequest) -> bool {
+    matches!(
+        request.tool_choice,
+        Some(ToolChoice::Required) | Some(ToolChoice::Tool(_))
+    )
+}
+
+/// Get the policy applied when a required tool call is missing from the model response
+pub(super) async fn get_required_tool_missing_policy(
+    state: &AppState,
+) -> RequiredToolMissingPolicy {
+    state
+        .config
+        .read()
+        .await
+        .chat
+        .as_ref()
+        .map(|chat_config| chat_config.on_required_tool_missing)
+        .unwrap_or_default()
+}
+
 /// Extract system message from the chat request
 pub(super) fn extract_system_message(request: &ChatCompletionRequest) -> Option<String> {
     request.messages.iter().find_map(|msg| match msg {
@@ -83,6 +143,98 @@ pub(super) fn convert_tool_calls_to_stored(
         .collect()
 }
 
+/// Send the chat request to the next downstream chat server, preferring the servers serving the
+/// requested model
+///
+/// Fails over to another chat server if the picked one cannot be reached. Returns the server
+/// that answered along with its response, or an error if the request is cancelled by the client.
+pub(crate) async fn send_chat_request(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: &ChatCompletionRequest,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<(TargetServerInfo, reqwest::Response)> {
+    state
+        .send_with_failover_for_model(
+            ServerKind::chat,
+            request.model.as_deref(),
+            |chat_server| build_chat_request(chat_server, headers, request),
+            cancel_token,
+            request_id,
+        )
+        .await
+}
+
+/// Return the error response of the downstream chat server verbatim
+///
+/// The status, content type and body are kept as is, so that errors such as a 400 "context
+/// length exceeded" or a 429 from the backend reach the client intact.
+pub(super) async fn forward_error_response(
+    response: reqwest::Response,
+    request_id: &str,
+) -> ServerResult<Response<Body>> {
+    let status = response.status();
+    let content_type = response.headers().get(CONTENT_TYPE).cloned();
+    let bytes = response.bytes().await.map_err(|e| {
+        let err_msg = format!("Failed to get response bytes: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })?;
+
+    dual_error!(
+        "The downstream chat server returned {}: {} - request_id: {}",
+        status,
+        String::from_utf8_lossy(&bytes),
+        request_id
+    );
+
+    let mut response_builder = Response::builder().status(status);
+    if let Some(content_type) = content_type {
+        response_builder = response_builder.header(CONTENT_TYPE, content_type);
+    }
+    response_builder.body(Body::from(bytes)).map_err(|e| {
+        let err_msg = format!("Failed to create the response: {e}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        ServerError::Operation(err_msg)
+    })
+}
+
+/// Build the chat request to the given downstream chat server
+///
+/// The API key of the chat server takes precedence over the `authorization` header of the
+/// incoming request.
+fn build_chat_request(
+    chat_server: &TargetServerInfo,
+    headers: &HeaderMap,
+    request: &ChatCompletionRequest,
+) -> reqwest::RequestBuilder {
+    let url = format!("{}/chat/completions", chat_server.url.trim_end_matches('/'));
+    let mut client = reqwest::Client::new().post(&url);
+
+    // Add common headers
+    client = client.header(CONTENT_TYPE, "application/json");
+
+    // Add authorization header
+    if let Some(api_key) = &chat_server.api_key
+        && !api_key.is_empty()
+    {
+        let auth_info = if api_key.starts_with("Bearer ") {
+            api_key.clone()
+        } else {
+            format!("Bearer {api_key}")
+        };
+
+        client = client.header(AUTHORIZATION, auth_info);
+    } else if let Some(auth) = headers.get("authorization")
+        && let Ok(auth_str) = auth.to_str()
+    {
+        client = client.header(AUTHORIZATION, auth_str);
+    }
+
+    client.json(&downstream_body(request))
+}
+
 /// Intelligently chunk text while maintaining word integrity and formatting
 ///
 /// # Parameters
@@ -135,3 +287,448 @@ pub(super) fn gen_chunks_with_formatting(text: impl AsRef<str>, chunk_size: usiz
 
     chunks
 }
+
+/// Emit `: keepalive` comment lines every `interval` until `chat` resolves. The body of the
+/// resolved response is then streamed through as is.
+///
+/// SSE comment lines are ignored by compliant clients, so they only keep intermediate
+/// proxies from closing the idle connection while tools or preprocessing are running.
+/// If `chat` resolves before the first keepalive is due, its response is returned unchanged,
+/// with its status and headers. Otherwise the SSE response is started with the first
+/// keepalive, and if `chat` then fails or resolves to a non-success response, its error body
+/// is sent as a single `data:` event since the status line has already been written.
+pub(crate) async fn sse_with_keepalive<F>(
+    chat: F,
+    interval: Duration,
+    request_id: String,
+) -> axum::response::Response
+where
+    F: Future<Output = ServerResult<axum::response::Response>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<Result<Bytes, axum::Error>>(16);
+    // the response of `chat` if it resolved before the first keepalive, None once it is sent
+    let (early_tx, early_rx) = oneshot::channel::<Option<ServerResult<axum::response::Response>>>();
+
+    access_log::spawn(async move {
+        let mut chat = std::pin::pin!(chat);
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+        select! {
+            response = &mut chat => {
+                let _ = early_tx.send(Some(response));
+                return;
+            }
+            _ = ticker.tick() => {
+                let _ = early_tx.send(None);
+            }
+        }
+
+        let response = loop {
+            dual_debug!("Send SSE keepalive - request_id: {}", request_id);
+            if tx
+                .send(Ok(Bytes::from_static(SSE_KEEPALIVE_COMMENT)))
+                .await
+                .is_err()
+            {
+                dual_warn!(
+                    "Client disconnected while waiting for the first chunk - request_id: {}",
+                    request_id
+                );
+                return;
+            }
+
+            select! {
+                response = &mut chat => break response,
+                _ = ticker.tick() => {}
+            }
+        };
+
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => axum::response::IntoResponse::into_response(e),
+        };
+
+        if response.status().is_success() {
+            let mut body = response.into_body().into_data_stream();
+            while let Some(chunk) = body.next().await {
+                if tx.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        } else {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap_or_default();
+            let event = format!("data: {}\n\n", String::from_utf8_lossy(&bytes));
+            let _ = tx.send(Ok(Bytes::from(event))).await;
+        }
+    });
+
+    if let Ok(Some(response)) = early_rx.await {
+        return response.unwrap_or_else(axum::response::IntoResponse::into_response);
+    }
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+
+    let mut response = Response::new(Body::from_stream(body));
+    *response.status_mut() = StatusCode::OK;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    headers.insert("Cache-Control", "no-cache".parse().unwrap());
+    headers.insert("Connection", "keep-alive".parse().unwrap());
+    response
+}
+
+/// Collects the content deltas and the usage of the chat completion chunks of an SSE stream
+#[derive(Debug, Default)]
+pub(crate) struct SseContentCollector {
+    /// Incomplete line left over from the previous network chunk
+    pending: Vec<u8>,
+    pub(crate) content: String,
+    /// Usage of the whole completion, sent by the downstream server in the last chunk
+    pub(crate) usage: Option<Usage>,
+    /// Drop the usage chunk from the forwarded events, as the client did not ask for it
+    hide_usage: bool,
+    /// The blank line ending a dropped usage event is dropped as well
+    skip_blank: bool,
+}
+impl SseContentCollector {
+    /// Collector that drops the usage chunk from the events returned by [`Self::forward`]
+    pub(crate) fn hiding_usage() -> Self {
+        Self {
+            hide_usage: true,
+            ..Default::default()
+        }
+    }
+
+    /// Feed the next network chunk of the stream and return the content deltas it completed
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.feed_lines(bytes, None)
+    }
+
+    /// Feed the next network chunk of the stream and return the bytes to forward to the client
+    pub(crate) fn forward(&mut self, bytes: &[u8]) -> Bytes {
+        if !self.hide_usage {
+            self.feed_lines(bytes, None);
+            return Bytes::copy_from_slice(bytes);
+        }
+
+        let mut forwarded = Vec::with_capacity(bytes.len());
+        self.feed_lines(bytes, Some(&mut forwarded));
+        Bytes::from(forwarded)
+    }
+
+    fn feed_lines(&mut self, bytes: &[u8], mut forwarded: Option<&mut Vec<u8>>) -> Vec<String> {
+        let mut deltas = Vec::new();
+        self.pending.extend_from_slice(bytes);
+        while let Some(pos) = self.pending.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = self.pending.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&raw);
+            let mut keep = true;
+            if let Some(data) = line.trim().strip_prefix("data:") {
+                self.skip_blank = false;
+                if let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data.trim()) {
+                    if let Some(delta) = chunk["choices"][0]["delta"]["content"].as_str()
+                        && !delta.is_empty()
+                    {
+                        self.content.push_str(delta);
+                        deltas.push(delta.to_string());
+                    }
+                    if !chunk["usage"].is_null()
+                        && let Ok(usage) = serde_json::from_value(chunk["usage"].clone())
+                    {
+                        self.usage = Some(usage);
+                        // the usage chunk carries no choices, so the whole event is dropped
+                        if chunk["choices"].as_array().is_some_and(|c| c.is_empty()) {
+                            keep = false;
+                            self.skip_blank = true;
+                        }
+                    }
+                }
+            } else if line.trim().is_empty() && self.skip_blank {
+                keep = false;
+                self.skip_blank = false;
+            }
+
+            if keep && let Some(forwarded) = forwarded.as_deref_mut() {
+                forwarded.extend_from_slice(&raw);
+            }
+        }
+
+        deltas
+    }
+}
+
+/// Check that a chat completion received from a downstream server has at least one choice, as
+/// the message and tool calls of the model are read from the first choice
+pub(super) fn check_choices(
+    chat_completion: &ChatCompletionObject,
+    request_id: &str,
+) -> ServerResult<()> {
+    if chat_completion.choices.is_empty() {
+        let err_msg = "The chat completion received from the downstream server has no choices";
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg.to_string()));
+    }
+    Ok(())
+}
+
+/// Whether the client asked for the usage of a streamed chat completion with
+/// `stream_options.include_usage`
+pub(crate) fn include_usage(request: &ChatCompletionRequest) -> bool {
+    request
+        .stream_options
+        .as_ref()
+        .and_then(|stream_options| stream_options.include_usage)
+        .unwrap_or(false)
+}
+
+/// SSE event of the last chunk of a streamed chat completion, sent before `[DONE]` when the
+/// client sets `stream_options.include_usage`. As with OpenAI, it has no choices and carries the
+/// usage of the whole completion, so that the usage is sent even if the answer is empty.
+pub(super) fn usage_event(id: &str, created: u64, model: &str, usage: Usage) -> String {
+    let chat_completion_chunk = ChatCompletionChunk {
+        id: id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created,
+        model: model.to_string(),
+        system_fingerprint: "fp_44709d6fcb".to_string(),
+        choices: vec![],
+        usage: Some(usage),
+    };
+    let json_str = serde_json::to_string(&chat_completion_chunk).unwrap();
+    format!("data: {json_str}\n\n")
+}
+
+/// Build the response returning a chat completion to the client, as JSON or, if `stream` is
+/// set, as SSE events. The `extra_fields` are added as top-level fields of the chat completion
+/// or of its last content chunk. A streamed completion ends with a chunk carrying its usage if
+/// `include_usage` is set.
+pub(super) fn chat_completion_response(
+    chat_completion: ChatCompletionObject,
+    stream: bool,
+    include_usage: bool,
+    extra_fields: serde_json::Map<String, serde_json::Value>,
+    request_id: &str,
+) -> ServerResult<axum::response::Response> {
+    if !stream {
+        let mut body = serde_json::to_value(&chat_completion).map_err(|e| {
+            let err_msg = format!("Failed to serialize the chat completion: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
+        if let Some(body) = body.as_object_mut() {
+            body.extend(extra_fields);
+        }
+
+        return Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .status(StatusCode::OK)
+            .body(Body::from(body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create the response: {e}");
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            });
+    }
+
+    let answer = chat_completion.choices[0]
+        .message
+        .content
+        .clone()
+        .unwrap_or_default();
+    let mut chunks = gen_chunks_with_formatting(&answer, 10);
+    if chunks.is_empty() {
+        chunks.push(String::new());
+    }
+    let chunks_len = chunks.len();
+    let created = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|created| created.as_secs())
+        .unwrap_or(chat_completion.created);
+    let id = gen_chat_id();
+
+    let events = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let last = i == chunks_len - 1;
+            let chat_completion_chunk = ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: chat_completion.model.clone(),
+                system_fingerprint: "fp_44709d6fcb".to_string(),
+                choices: vec![ChatCompletionChunkChoice {
+                    index: 0,
+                    delta: ChatCompletionChunkChoiceDelta {
+                        role: ChatCompletionRole::Assistant,
+                        content: Some(chunk),
+                        tool_calls: vec![],
+                    },
+                    logprobs: None,
+                    finish_reason: last.then_some(FinishReason::stop),
+                }],
+                usage: None,
+            };
+            let mut json = serde_json::to_value(&chat_completion_chunk).unwrap();
+            if last && let Some(json) = json.as_object_mut() {
+                json.extend(extra_fields.clone());
+            }
+            format!("data: {json}\n\n")
+        })
+        .chain(
+            include_usage
+                .then(|| usage_event(&id, created, &chat_completion.model, chat_completion.usage)),
+        )
+        .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+        .map(|event| Ok::<_, std::convert::Infallible>(event.into_bytes()))
+        .collect::<Vec<_>>();
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .status(StatusCode::OK)
+        .body(Body::from_stream(stream::iter(events)))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create streaming response: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_completion_without_choices_is_rejected() {
+        let mut chat_completion: ChatCompletionObject =
+            serde_json::from_value(crate::test_utils::chat_completion_json("hello")).unwrap();
+        assert!(check_choices(&chat_completion, "test").is_ok());
+
+        chat_completion.choices.clear();
+        let err = check_choices(&chat_completion, "test").unwrap_err();
+        assert!(matches!(err, ServerError::Operation(msg) if msg.contains("no choices")));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_empty_answer_ends_with_usage() {
+        let chat_completion: ChatCompletionObject =
+            serde_json::from_value(crate::test_utils::chat_completion_json("")).unwrap();
+        let response =
+            chat_completion_response(chat_completion, true, true, Default::default(), "test")
+                .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+        let events: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+        assert_eq!(events.len(), 3);
+        let content: ChatCompletionChunk = serde_json::from_str(events[0]).unwrap();
+        assert_eq!(content.choices[0].finish_reason, Some(FinishReason::stop));
+        let usage: ChatCompletionChunk = serde_json::from_str(events[1]).unwrap();
+        assert!(usage.choices.is_empty());
+        assert_eq!(usage.usage.unwrap().prompt_tokens, 10);
+        assert_eq!(events[2], "[DONE]");
+    }
+
+    #[tokio::test]
+    async fn test_sse_with_keepalive_emits_comments_before_first_chunk() {
+        let chat = async {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "text/event-stream")
+                .body(Body::from(
+                    "data: {\"id\":\"chatcmpl-1\"}\n\ndata: [DONE]\n\n",
+                ))
+                .unwrap())
+        };
+
+        let response =
+            sse_with_keepalive(chat, Duration::from_millis(50), "test-request".to_string()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+        let keepalives = body.matches(": keepalive\n\n").count();
+        assert!(keepalives >= 2, "expected keepalives, got: {body}");
+        assert!(body.starts_with(": keepalive\n\n"));
+        assert!(body.ends_with("data: {\"id\":\"chatcmpl-1\"}\n\ndata: [DONE]\n\n"));
+
+        // every event that is not a comment must still be valid data for the client
+        body.split("\n\n")
+            .filter(|event| !event.is_empty() && !event.starts_with(':'))
+            .for_each(|event| assert!(event.starts_with("data: ")));
+    }
+
+    #[tokio::test]
+    async fn test_sse_with_keepalive_forwards_errors_as_event() {
+        let chat = async {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            Err(crate::error::ServerError::Operation("boom".to_string()))
+        };
+
+        let response =
+            sse_with_keepalive(chat, Duration::from_millis(50), "test-request".to_string()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+        // the error follows the keepalives already sent
+        assert!(body.starts_with(": keepalive\n\n"), "body: {body}");
+        let event = body.rsplit(": keepalive\n\n").next().unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(event.trim_start_matches("data: ").trim()).unwrap();
+        assert_eq!(json["error"]["message"], "boom");
+    }
+
+    #[tokio::test]
+    async fn test_sse_with_keepalive_returns_early_errors_unchanged() {
+        use crate::error::ServerError;
+
+        for (err, status) in [
+            (
+                ServerError::RateLimited {
+                    retry_after_secs: 7,
+                },
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                ServerError::CircuitOpen {
+                    kind: "chat".to_string(),
+                    retry_after_secs: 7,
+                },
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+        ] {
+            let chat = async move { Err(err) };
+            let response =
+                sse_with_keepalive(chat, Duration::from_secs(10), "test-request".to_string()).await;
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()["retry-after"], "7");
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert!(json["error"]["message"].is_string());
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+use axum::response::{IntoResponse, Response};
+use tokio::sync::oneshot;
+
+use crate::{access_log, dual_debug, dual_error, error::ServerError, idempotency::CachedResponse};
+
+/// Response of a coalesced request
+#[derive(Debug)]
+pub(crate) enum Coalesced {
+    /// The request ran itself
+    Ran(Response),
+    /// The response of an identical request that was already running
+    Shared(CachedResponse),
+}
+
+/// Single-flight coalescing of identical concurrent requests
+///
+/// The first request of a key runs; the requests with the same key arriving while it runs wait
+/// for its response instead of running themselves.
+#[derive(Debug, Default)]
+pub(crate) struct RequestCoalescer {
+    /// Waiters of the running request of each key
+    flights: Mutex<HashMap<u64, Vec<oneshot::Sender<CachedResponse>>>>,
+}
+impl RequestCoalescer {
+    /// Run the request unless an identical one is already running, in which case its response
+    /// is returned. A waiter whose running request is cancelled runs its own request.
+    pub(crate) async fn run<F>(&self, key: u64, fut: F, request_id: &str) -> Coalesced
+    where
+        F: Future<Output = Response>,
+    {
+        let waiter = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get_mut(&key) {
+                Some(waiters) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Some(rx)
+                }
+                None => {
+                    flights.insert(key, Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(rx) = waiter {
+            dual_debug!(
+                "Wait for the response of an identical request - request_id: {}",
+                request_id
+            );
+            if let Ok(response) = rx.await {
+                return Coalesced::Shared(response);
+            }
+            return Coalesced::Ran(fut.await);
+        }
+
+        // drops the waiters if the request is cancelled, so that they run their own requests
+        let flight = Flight {
+            coalescer: self,
+            key,
+        };
+        let response = fut.await;
+
+        let (parts, body) = response.into_parts();
+        let response = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => CachedResponse {
+                status: parts.status,
+                headers: parts.headers,
+                body,
+                downstream: access_log::downstream(),
+            },
+            Err(e) => {
+                let err_msg = format!("Failed to read the response body: {e}");
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                return Coalesced::Ran(ServerError::Operation(err_msg).into_response());
+            }
+        };
+
+        let waiters = flight.land();
+        if !waiters.is_empty() {
+            dual_debug!(
+                "Share the response with {} identical requests - request_id: {}",
+                waiters.len(),
+                request_id
+            );
+        }
+        for waiter in waiters {
+            let _ = waiter.send(response.clone());
+        }
+
+        Coalesced::Ran(response.into_response())
+    }
+}
+
+/// The running request of a key
+struct Flight<'a> {
+    coalescer: &'a RequestCoalescer,
+    key: u64,
+}
+impl Flight<'_> {
+    /// Stop accepting waiters and return the ones waiting for the response
+    fn land(self) -> Vec<oneshot::Sender<CachedResponse>> {
+        let waiters = self
+            .coalescer
+            .flights
+            .lock()
+            .unwrap()
+            .remove(&self.key)
+            .unwrap_or_default();
+        // the key may already belong to the next request
+        std::mem::forget(self);
+        waiters
+    }
+}
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        self.coalescer.flights.lock().unwrap().remove(&self.key);
+    }
+}
@@ -28,7 +28,8 @@ use tokio::{
 use crate::{
     dual_debug, dual_error, dual_info,
     error::{ServerError, ServerResult},
-    mcp::{MCP_SERVICES, McpService},
+    mcp::{MCP_SEPARATOR, MCP_SERVICES, McpService, RawMcpService},
+    server::{RoutingStrategy, ServerKind},
 };
 
 const MCP_REDIRECT_URI: &str = "http://localhost:8080/callback";
@@ -52,9 +53,56 @@ pub struct Config {
     pub server_health_push_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mcp: Option<McpConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency: Option<IdempotencyConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing: Option<RoutingConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_postprocess: Option<AnswerPostprocessConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub react: Option<ReactConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<ApiKeyAuthConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readiness: Option<ReadinessConfig>,
+    /// Friendly model names requested by the clients, mapped to the models of the servers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_aliases: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<RegistryConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coalescing: Option<CoalescingConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_cache: Option<ResponseCacheConfig>,
 }
 impl Config {
+    /// Load the config file and connect to the mcp servers it lists
     pub async fn load(path: impl AsRef<std::path::Path>) -> ServerResult<Self> {
+        let mut config = Self::from_file(path)?;
+
+        if let Some(mcp_config) = config.mcp.as_mut()
+            && !mcp_config.server.tool_servers.is_empty()
+        {
+            for server_config in mcp_config.server.tool_servers.iter_mut() {
+                server_config.connect_mcp_server().await?;
+            }
+        }
+
+        dual_debug!("config:\n{:#?}", config);
+
+        Ok(config)
+    }
+
+    /// Parse and validate the config file, without connecting to the mcp servers
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> ServerResult<Self> {
         let config = config::Config::builder()
             .add_source(config::File::with_name(path.as_ref().to_str().unwrap()))
             .build()
@@ -64,23 +112,35 @@ impl Config {
                 ServerError::FailedToLoadConfig(err_msg)
             })?;
 
-        let mut config = config.try_deserialize::<Self>().map_err(|e| {
+        let config = config.try_deserialize::<Self>().map_err(|e| {
             let err_msg = format!("Failed to deserialize config: {e}");
             dual_error!("{}", &err_msg);
             ServerError::FailedToLoadConfig(err_msg)
         })?;
+        config.validate()?;
 
-        if let Some(mcp_config) = config.mcp.as_mut()
-            && !mcp_config.server.tool_servers.is_empty()
-        {
-            for server_config in mcp_config.server.tool_servers.iter_mut() {
-                server_config.connect_mcp_server().await?;
-            }
+        Ok(config)
+    }
+
+    /// Check the values that deserialization alone does not catch
+    pub fn validate(&self) -> ServerResult<()> {
+        if self.server.host.parse::<std::net::IpAddr>().is_err() {
+            let err_msg = format!("Invalid server host: {}", self.server.host);
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::FailedToLoadConfig(err_msg));
         }
 
-        dual_debug!("config:\n{:#?}", config);
+        if let Some(memory_config) = &self.memory
+            && memory_config.enable
+            && memory_config.backend == MemoryBackend::Redis
+            && memory_config.redis_url.is_none()
+        {
+            let err_msg = "The redis memory backend requires `redis_url`".to_string();
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::FailedToLoadConfig(err_msg));
+        }
 
-        Ok(config)
+        Ok(())
     }
 }
 
@@ -92,6 +152,13 @@ impl Default for Config {
                 host: "127.0.0.1".to_string(),
                 port: 3389,
                 chat_mode: ChatMode::default(),
+                sse_keepalive_secs: 0,
+                max_react_steps: default_max_react_steps(),
+                upstream_server_header: false,
+                verify_server_kind: default_verify_server_kind(),
+                max_request_body_bytes: default_max_request_body_bytes(),
+                default_model: None,
+                advertise_model_aliases: false,
             },
             chat: None,
             embedding: None,
@@ -100,6 +167,20 @@ impl Default for Config {
             server_info_push_url: None,
             server_health_push_url: None,
             mcp: None,
+            idempotency: None,
+            routing: None,
+            shadow: None,
+            answer_postprocess: None,
+            react: None,
+            health_check: None,
+            metrics: None,
+            auth: None,
+            rate_limit: None,
+            readiness: None,
+            model_aliases: None,
+            registry: None,
+            coalescing: None,
+            response_cache: None,
         }
     }
 }
@@ -119,12 +200,62 @@ pub struct ServerConfig {
     pub port: u16,
     #[serde(default)]
     pub chat_mode: ChatMode,
+    /// Interval in seconds between SSE keepalive comments sent while a streaming request is
+    /// waiting for its first chunk, including the whole ReAct loop in react mode. `0` disables
+    /// keepalives.
+    #[serde(default)]
+    pub sse_keepalive_secs: u64,
+    /// Maximum number of model calls in a ReAct loop. If the model is still acting after the
+    /// last step, its last content is returned as the final answer.
+    #[serde(default = "default_max_react_steps")]
+    pub max_react_steps: usize,
+    /// Return the url and id of the downstream server that handled a request in the
+    /// `x-upstream-server` and `x-upstream-server-id` response headers
+    #[serde(default)]
+    pub upstream_server_header: bool,
+    /// Check that a server registered through the admin API reports a model for each of its
+    /// kinds in its `/info` endpoint. Disable it for servers without `/info`.
+    #[serde(default = "default_verify_server_kind")]
+    pub verify_server_kind: bool,
+    /// Maximum size of a request body in bytes. Larger requests are rejected with 413.
+    #[serde(default = "default_max_request_body_bytes")]
+    pub max_request_body_bytes: usize,
+    /// Model used for chat requests without a `model`. Such requests are rejected if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
+    /// List the model aliases in `/v1/models`, next to the models they stand for
+    #[serde(default)]
+    pub advertise_model_aliases: bool,
+}
+
+fn default_max_request_body_bytes() -> usize {
+    100 * 1024 * 1024
+}
+
+fn default_verify_server_kind() -> bool {
+    true
+}
+
+fn default_max_react_steps() -> usize {
+    10
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ChatConfig {
     pub url: String,
     api_key: String,
+    #[serde(default)]
+    pub on_required_tool_missing: RequiredToolMissingPolicy,
+    /// Emulate `n > 1` with parallel single-choice completions, running at most this many at a
+    /// time. If unset, `n` is forwarded to the downstream server as is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n_fanout_concurrency: Option<usize>,
+    /// What to do when some of the emulated completions fail
+    #[serde(default)]
+    pub n_fanout_partial_policy: FanoutPartialPolicy,
+    /// Seed forwarded to the downstream chat servers when the request has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_seed: Option<u64>,
 }
 
 impl ChatConfig {
@@ -137,10 +268,41 @@ impl ChatConfig {
     }
 }
 
+/// What to do when some of the completions of an emulated `n > 1` request fail
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+pub enum FanoutPartialPolicy {
+    /// Return the choices that succeeded with a warning
+    #[default]
+    #[serde(rename = "partial")]
+    Partial,
+    /// Fail the request
+    #[serde(rename = "fail")]
+    Fail,
+}
+
+/// What to do when `tool_choice` requires a tool call but the model answers without one
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+pub enum RequiredToolMissingPolicy {
+    /// Return the answer of the model as is
+    #[default]
+    #[serde(rename = "ignore")]
+    Ignore,
+    /// Retry once with an instruction telling the model to call a tool
+    #[serde(rename = "retry")]
+    Retry,
+    /// Reject the request with a 422 error
+    #[serde(rename = "reject")]
+    Reject,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct EmbeddingConfig {
     pub url: String,
     api_key: String,
+    /// Split embedding requests with more inputs than this into sub-batches. If unset, requests
+    /// are forwarded as is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_embedding_batch: Option<usize>,
 }
 
 impl EmbeddingConfig {
@@ -182,8 +344,14 @@ impl std::fmt::Display for SummarizationStrategy {
 pub struct MemoryConfig {
     /// Enable or disable memory functionality
     pub enable: bool,
+    /// Storage backend of the conversation history
+    #[serde(default)]
+    pub backend: MemoryBackend,
     /// Path to SQLite database file for storing conversation history
     pub database_path: String,
+    /// URL of the Redis instance used by the `redis` backend, e.g. `redis://:password@127.0.0.1:6379/0`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis_url: Option<String>,
     /// Maximum context window size in tokens
     pub context_window: u64,
     /// Enable automatic message summarization when limits are reached
@@ -208,6 +376,8 @@ pub struct MemoryConfig {
     /// This should be GREATER than summarize_threshold for proper operation.
     ///
     /// This name accurately reflects its purpose as the trigger point for summarization.
+    /// It can also be set as `summarize_after`.
+    #[serde(alias = "summarize_after")]
     pub max_stored_messages: u32,
 
     /// Base URL for the summary service used to generate conversation summaries.
@@ -217,13 +387,26 @@ pub struct MemoryConfig {
     /// API key for authenticating with the summary service.
     /// Leave empty if the summary service doesn't require authentication.
     pub summary_service_api_key: String,
+
+    /// Token budget of the context sent to the model.
+    /// When set, the oldest working messages are dropped from the context until its estimated
+    /// token count fits the budget. The system message and the latest user message are always kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_token_budget: Option<usize>,
+
+    /// Number of seconds without activity after which a conversation is deleted, together with
+    /// its messages. `0` keeps the conversations forever.
+    #[serde(default)]
+    pub conversation_ttl_secs: u64,
 }
 
 impl Default for MemoryConfig {
     fn default() -> Self {
         Self {
             enable: false,
+            backend: MemoryBackend::default(),
             database_path: "data/memory.db".to_string(),
+            redis_url: None,
             context_window: 8192,
             auto_summarize: true,
             summarization_strategy: SummarizationStrategy::default(),
@@ -233,6 +416,338 @@ impl Default for MemoryConfig {
             max_stored_messages: 20, // Trigger summarization at 20 messages
             summary_service_base_url: "http://localhost:10086/v1".to_string(),
             summary_service_api_key: String::new(),
+            context_token_budget: None,
+            conversation_ttl_secs: 0,
+        }
+    }
+}
+
+/// Storage backend of the conversation memory
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryBackend {
+    /// SQLite database at `database_path`
+    #[default]
+    Sqlite,
+    /// Redis instance at `redis_url`
+    Redis,
+}
+
+/// Routing configuration for the downstream server groups
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct RoutingConfig {
+    /// Routing strategy per server kind. Kinds not listed use round-robin.
+    #[serde(default)]
+    pub strategy: HashMap<ServerKind, RoutingStrategy>,
+    /// Maximum number of servers tried for a request when a downstream server cannot be
+    /// reached. Defaults to the number of servers in the group; `1` disables failover.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<usize>,
+    /// Skip downstream servers that keep failing for a cooldown period. Disabled if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+impl RoutingConfig {
+    pub fn strategy(&self, kind: ServerKind) -> RoutingStrategy {
+        self.strategy.get(&kind).copied().unwrap_or_default()
+    }
+}
+
+/// Circuit breaker configuration of the downstream servers
+///
+/// A server that fails `failure_threshold` times within `window_secs` is opened and skipped by
+/// the router for `cooldown_secs`. After the cooldown, a single probe request is let through:
+/// its success closes the circuit, its failure opens it again. While the circuits of all the
+/// servers are open, requests are rejected with 503 and a `Retry-After` header.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures that opens the circuit
+    #[serde(default = "default_circuit_breaker_failure_threshold")]
+    pub failure_threshold: usize,
+    /// Window in which the failures are counted, in seconds
+    #[serde(default = "default_circuit_breaker_window_secs")]
+    pub window_secs: u64,
+    /// How long an open circuit skips the server before probing it, in seconds
+    #[serde(default = "default_circuit_breaker_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_circuit_breaker_failure_threshold() -> usize {
+    5
+}
+
+fn default_circuit_breaker_window_secs() -> u64 {
+    60
+}
+
+fn default_circuit_breaker_cooldown_secs() -> u64 {
+    30
+}
+
+/// Health check configuration of the downstream servers
+///
+/// When enabled, a background task probes every registered server at each interval. Servers
+/// that fail the probe are skipped by the router until a later probe succeeds. Overrides the
+/// `--check-health` and `--check-health-interval` command line options.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HealthCheckConfig {
+    /// Enable or disable the background health check
+    pub enable: bool,
+    /// Interval between two health checks, in seconds
+    #[serde(default = "default_health_check_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    60
+}
+
+/// Server registry configuration
+///
+/// When enabled, the servers registered via `/admin/servers/register` are saved to a JSON file
+/// and registered again at startup.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RegistryConfig {
+    /// Enable or disable the server registry
+    pub enable: bool,
+    /// Path of the registry file
+    #[serde(default = "default_registry_path")]
+    pub path: String,
+}
+
+fn default_registry_path() -> String {
+    "data/servers.json".to_string()
+}
+
+/// Readiness probe configuration
+///
+/// `/ready` returns 503 until every required kind has a healthy server registered, so that no
+/// traffic is routed to an empty gateway.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReadinessConfig {
+    /// Kinds of server that must have a healthy server registered. Defaults to `["chat"]`.
+    #[serde(default = "default_required_kinds")]
+    pub required_kinds: Vec<ServerKind>,
+}
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            required_kinds: default_required_kinds(),
+        }
+    }
+}
+
+fn default_required_kinds() -> Vec<ServerKind> {
+    vec![ServerKind::chat]
+}
+
+/// Metrics configuration
+///
+/// When enabled, operational metrics are exposed in the Prometheus text format at `/metrics`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MetricsConfig {
+    /// Enable or disable the `/metrics` endpoint
+    pub enable: bool,
+}
+
+/// Inbound API key authentication configuration
+///
+/// When enabled, every request except `/health` must carry an `Authorization: Bearer <key>`
+/// header with one of the allowed keys.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiKeyAuthConfig {
+    /// Enable or disable API key authentication
+    pub enable: bool,
+    /// Allowed API keys
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// Path to a file with additional allowed API keys, one per line. Empty lines and lines
+    /// starting with `#` are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_keys_file: Option<String>,
+}
+
+/// Per-user rate limiting configuration
+///
+/// When enabled, the chat, embeddings and image requests of every user are limited with a
+/// token bucket. Requests over the limit are rejected with 429 and a `Retry-After` header.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimitConfig {
+    /// Enable or disable rate limiting
+    pub enable: bool,
+    /// Sustained number of requests a user may send per minute
+    pub requests_per_minute: u32,
+    /// Maximum number of requests a user may send in a burst. Defaults to `requests_per_minute`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+/// Request deduplication configuration
+///
+/// When enabled, chat requests carrying an `Idempotency-Key` header are cached per caller (API
+/// key and user), and retries with the same key replay the cached response instead of
+/// re-running. The requests of anonymous callers are not cached.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IdempotencyConfig {
+    /// Enable or disable idempotency key handling
+    pub enable: bool,
+    /// How long a cached response is kept, in seconds
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Maximum number of cached responses kept in memory
+    #[serde(default = "default_idempotency_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    600
+}
+
+fn default_idempotency_max_entries() -> usize {
+    1000
+}
+
+/// Response cache configuration
+///
+/// When enabled, the responses to deterministic chat requests (a zero temperature or a seed,
+/// no tools, no streaming and no memory) are cached, and the same request of the same caller is
+/// answered from the cache instead of the downstream server.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ResponseCacheConfig {
+    /// Enable or disable the response cache
+    pub enable: bool,
+    /// How long a cached response is kept, in seconds
+    #[serde(default = "default_response_cache_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Maximum number of cached responses kept in memory
+    #[serde(default = "default_response_cache_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_response_cache_ttl_secs() -> u64 {
+    300
+}
+
+fn default_response_cache_max_entries() -> usize {
+    1000
+}
+
+/// Request coalescing configuration
+///
+/// When enabled, concurrent non-streaming chat requests of the same caller with identical bodies
+/// share a single downstream call, and its response is returned to each of them.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CoalescingConfig {
+    /// Enable or disable request coalescing
+    pub enable: bool,
+}
+
+/// Shadow traffic configuration
+///
+/// When enabled, a sample of the chat requests is mirrored to the shadow chat server. The
+/// shadow responses are discarded; only their latency and errors are recorded.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ShadowConfig {
+    /// Enable or disable shadow traffic
+    pub enable: bool,
+    /// Base url of the shadow chat server, e.g. `http://localhost:10086/v1`
+    pub url: String,
+    /// API key of the shadow chat server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Fraction of the chat requests mirrored to the shadow server, between 0 and 1
+    #[serde(default = "default_shadow_sample_rate")]
+    pub sample_rate: f64,
+}
+
+fn default_shadow_sample_rate() -> f64 {
+    0.1
+}
+
+/// Answer post-processing configuration
+///
+/// When enabled, the draft answer of the model is rewritten per the prompt by an extra chat
+/// completion before it is returned to the client.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnswerPostprocessConfig {
+    /// Enable or disable answer post-processing
+    pub enable: bool,
+    /// Instructions used to rewrite the draft answer, e.g. to enforce a tone or a format
+    pub prompt: String,
+    /// Model used for the post-processing. Defaults to the model that drafted the answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Maximum number of tokens generated by the post-processing
+    #[serde(default = "default_postprocess_max_tokens")]
+    pub max_tokens: i32,
+}
+
+fn default_postprocess_max_tokens() -> i32 {
+    1024
+}
+
+/// ReAct mode configuration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReactConfig {
+    /// Retry the request in normal mode when the model repeatedly fails to follow the ReAct format
+    #[serde(default)]
+    pub fallback_to_normal: bool,
+    /// Number of responses with missing or malformed ReAct tags before falling back to normal mode
+    #[serde(default = "default_max_tag_failures")]
+    pub max_tag_failures: usize,
+    /// Time limit of an MCP tool call, in seconds. A call running longer is abandoned and the
+    /// model is told that the tool timed out. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcp_tool_timeout_secs: Option<u64>,
+    /// Number of failed MCP tool calls in a row before the ReAct loop is aborted. A failed call
+    /// is reported to the model as an error observation, so that it can try another way.
+    #[serde(default = "default_max_tool_failures")]
+    pub max_tool_failures: usize,
+    /// System prompt teaching the model the ReAct format, added to requests without a system
+    /// message. A built-in prompt is used if not set, and an empty prompt disables it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Names of the tags of the ReAct format, for models using other delimiters
+    #[serde(default)]
+    pub tags: ReactTags,
+}
+
+impl Default for ReactConfig {
+    fn default() -> Self {
+        Self {
+            fallback_to_normal: false,
+            max_tag_failures: default_max_tag_failures(),
+            mcp_tool_timeout_secs: None,
+            max_tool_failures: default_max_tool_failures(),
+            system_prompt: None,
+            tags: ReactTags::default(),
+        }
+    }
+}
+
+fn default_max_tag_failures() -> usize {
+    2
+}
+
+fn default_max_tool_failures() -> usize {
+    3
+}
+
+/// Names of the tags delimiting the steps of the ReAct format, e.g. `thought` for
+/// `<thought>...</thought>`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ReactTags {
+    pub thought: String,
+    pub action: String,
+    pub final_answer: String,
+}
+impl Default for ReactTags {
+    fn default() -> Self {
+        Self {
+            thought: "thought".to_string(),
+            action: "action".to_string(),
+            final_answer: "final_answer".to_string(),
         }
     }
 }
@@ -274,6 +789,103 @@ impl<'de> Deserialize<'de> for RagConfig {
 pub struct McpConfig {
     #[serde(rename = "server")]
     pub server: McpServerConfig,
+    #[serde(default)]
+    pub tool_limits: McpToolLimitsConfig,
+    #[serde(default)]
+    pub retry: McpRetryConfig,
+    /// Tools exposed to the model. All the tools are exposed if empty. A tool is listed by its
+    /// name on the mcp server (`get_weather`) or with its server name (`get_weather---weather`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_tools: Vec<String>,
+    /// Tools never exposed to the model, listed like `allowed_tools`. Takes precedence over
+    /// `allowed_tools`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_tools: Vec<String>,
+}
+impl McpConfig {
+    /// Whether the tool of the given mcp server passes the `allowed_tools` and `blocked_tools`
+    /// lists
+    pub fn is_tool_allowed(&self, server_name: &str, tool_name: &str) -> bool {
+        let full_name = format!("{tool_name}{MCP_SEPARATOR}{server_name}");
+        let listed = |names: &[String]| {
+            names
+                .iter()
+                .any(|name| name == tool_name || *name == full_name)
+        };
+
+        !listed(&self.blocked_tools)
+            && (self.allowed_tools.is_empty() || listed(&self.allowed_tools))
+    }
+}
+
+/// Retry policy of the MCP tool calls
+///
+/// Only the calls failing with a transport error or a timeout are retried; a tool reporting an
+/// error in its result is not. The delay between two attempts starts at `initial_backoff_ms` and
+/// doubles after every failed attempt, up to `max_backoff_ms`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct McpRetryConfig {
+    /// Maximum number of attempts of a tool call, including the first one. `1` disables retries.
+    #[serde(default = "default_mcp_retry_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry, in milliseconds
+    #[serde(default = "default_mcp_retry_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Upper bound of the delay between two attempts, in milliseconds
+    #[serde(default = "default_mcp_retry_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+impl Default for McpRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_mcp_retry_max_attempts(),
+            initial_backoff_ms: default_mcp_retry_initial_backoff_ms(),
+            max_backoff_ms: default_mcp_retry_max_backoff_ms(),
+        }
+    }
+}
+
+fn default_mcp_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_mcp_retry_initial_backoff_ms() -> u64 {
+    200
+}
+
+fn default_mcp_retry_max_backoff_ms() -> u64 {
+    5_000
+}
+
+/// Limits applied to the MCP tools injected into the chat requests
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct McpToolLimitsConfig {
+    /// Maximum length of a tool name as seen by the model, including the `---<server name>`
+    /// suffix. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_name_len: Option<usize>,
+    /// How to handle a tool whose name exceeds `max_name_len`
+    #[serde(default)]
+    pub on_long_name: LongToolNamePolicy,
+    /// Size in bytes of the serialized input schema above which a warning is logged.
+    /// Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_schema_bytes: Option<usize>,
+    /// Drop the optional properties (the ones not listed in `required`) of the input schemas
+    /// larger than `max_schema_bytes`
+    #[serde(default)]
+    pub drop_optional_properties: bool,
+}
+
+/// Policy applied to an MCP tool whose name is longer than the configured limit
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LongToolNamePolicy {
+    /// Truncate the tool name. Tool calls are mapped back to the full name.
+    #[default]
+    Truncate,
+    /// Leave the tool out of the chat requests
+    Reject,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -298,6 +910,81 @@ pub struct McpToolServerConfig {
     pub fallback_message: Option<String>,
 }
 impl McpToolServerConfig {
+    /// Open a new connection to the mcp server at `url`
+    ///
+    /// The servers configured with `oauth_url` are not supported, as the authorization needs the
+    /// user to open the authorization URL in a browser.
+    pub(crate) async fn open_connection(&self) -> ServerResult<RawMcpService> {
+        let Some(url) = self.url.as_deref().map(|url| url.trim_end_matches('/')) else {
+            let err_msg = format!(
+                "Cannot open a connection to mcp server '{}' without the url",
+                self.name
+            );
+            dual_error!("{}", err_msg);
+            return Err(ServerError::McpOperation(err_msg));
+        };
+
+        let client_info = ClientInfo {
+            protocol_version: Default::default(),
+            capabilities: ClientCapabilities::default(),
+            client_info: Implementation {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                title: None,
+                icons: None,
+                website_url: None,
+            },
+        };
+        let service = match self.transport {
+            McpTransport::Sse => {
+                if !url.ends_with("/sse") {
+                    let err_msg = format!(
+                        "Invalid mcp tools sse URL: {url}. The correct format should end with `/sse`",
+                    );
+                    dual_error!("{}", err_msg);
+                    return Err(ServerError::Operation(err_msg.to_string()));
+                }
+                dual_debug!("Sync mcp tools from mcp server: {}", url);
+
+                // create a sse transport
+                let transport = SseClientTransport::start(url).await.map_err(|e| {
+                    let err_msg = format!("Failed to create sse transport: {e}");
+                    dual_error!("{}", &err_msg);
+                    ServerError::McpOperation(err_msg)
+                })?;
+                client_info.into_dyn().serve(transport).await
+            }
+            McpTransport::StreamHttp => {
+                if !url.ends_with("/mcp") {
+                    let err_msg = format!(
+                        "Invalid mcp tools stream-http URL: {url}. The correct format should end with `/mcp`",
+                    );
+                    dual_error!("{}", err_msg);
+                    return Err(ServerError::Operation(err_msg.to_string()));
+                }
+                dual_debug!("Sync mcp tools from mcp server: {}", url);
+
+                // create a stream-http transport
+                let transport = StreamableHttpClientTransport::from_uri(url);
+                client_info.into_dyn().serve(transport).await
+            }
+            _ => {
+                let err_msg = format!("Unsupported transport: {}", self.transport);
+                dual_error!("{}", err_msg);
+                return Err(ServerError::Operation(err_msg.to_string()));
+            }
+        };
+
+        service.map_err(|e| {
+            let err_msg = format!(
+                "Failed to connect to mcp server (name: {}, url: {}, transport: {}). {e}. Please check if the mcp server is running.",
+                self.name, url, self.transport
+            );
+            dual_error!("{}", &err_msg);
+            ServerError::McpOperation(err_msg)
+        })
+    }
+
     /// Connect the mcp server if it is enabled
     pub async fn connect_mcp_server(&mut self) -> ServerResult<()> {
         if self.enable {
@@ -332,44 +1019,7 @@ impl McpToolServerConfig {
                     let url = server_url.trim_end_matches('/');
 
                     let service = match use_oauth {
-                        false => {
-                            if !url.ends_with("/sse") {
-                                let err_msg = format!(
-                                    "Invalid mcp tools sse URL: {url}. The correct format should end with `/sse`",
-                                );
-                                dual_error!("{}", err_msg);
-                                return Err(ServerError::Operation(err_msg.to_string()));
-                            }
-                            dual_debug!("Sync mcp tools from mcp server: {}", url);
-
-                            // create a sse transport
-                            let transport = SseClientTransport::start(url).await.map_err(|e| {
-                                let err_msg = format!("Failed to create sse transport: {e}");
-                                dual_error!("{}", &err_msg);
-                                ServerError::McpOperation(err_msg)
-                            })?;
-
-                            // create a mcp client
-                            let client_info = ClientInfo {
-                                protocol_version: Default::default(),
-                                capabilities: ClientCapabilities::default(),
-                                client_info: Implementation {
-                                    name: env!("CARGO_PKG_NAME").to_string(),
-                                    version: env!("CARGO_PKG_VERSION").to_string(),
-                                    title: None,
-                                    icons: None,
-                                    website_url: None,
-                                },
-                            };
-                            client_info.into_dyn().serve(transport).await.map_err(|e| {
-                                let err_msg = format!(
-                                    "Failed to connect to mcp server (name: {}, url: {}, transport: {}). {e}. Please check if the mcp server is running.",
-                                    self.name, url, self.transport
-                                );
-                                dual_error!("{}", &err_msg);
-                                ServerError::McpOperation(err_msg)
-                            })?
-                        }
+                        false => self.open_connection().await?,
                         true => {
                             // it is a http server for handling callback
                             // Create channel for receiving authorization code
@@ -600,6 +1250,7 @@ impl McpToolServerConfig {
                     let mut client = McpService::new(&service_name, service);
                     client.tools = tools.iter().map(|tool| tool.name.to_string()).collect();
                     client.fallback_message = self.fallback_message.clone();
+                    client.server_config = Some(self.clone());
 
                     // print name of all tools
                     for (idx, tool) in tools.iter().enumerate() {
@@ -640,40 +1291,7 @@ impl McpToolServerConfig {
                     let url = server_url.trim_end_matches('/');
 
                     let service = match use_oauth {
-                        false => {
-                            if !url.ends_with("/mcp") {
-                                let err_msg = format!(
-                                    "Invalid mcp tools stream-http URL: {url}. The correct format should end with `/mcp`",
-                                );
-                                dual_error!("{}", err_msg);
-                                return Err(ServerError::Operation(err_msg.to_string()));
-                            }
-                            dual_debug!("Sync mcp tools from mcp server: {}", url);
-
-                            // create a stream-http transport
-                            let transport = StreamableHttpClientTransport::from_uri(url);
-
-                            // create a mcp client
-                            let client_info = ClientInfo {
-                                protocol_version: Default::default(),
-                                capabilities: ClientCapabilities::default(),
-                                client_info: Implementation {
-                                    name: env!("CARGO_PKG_NAME").to_string(),
-                                    version: env!("CARGO_PKG_VERSION").to_string(),
-                                    title: None,
-                                    icons: None,
-                                    website_url: None,
-                                },
-                            };
-                            client_info.into_dyn().serve(transport).await.map_err(|e| {
-                                let err_msg = format!(
-                                    "Failed to connect to mcp server (name: {}, url: {}, transport: {}). {e}. Please check if the mcp server is running.",
-                                    self.name, server_url, self.transport
-                                );
-                                dual_error!("{}", &err_msg);
-                                ServerError::McpOperation(err_msg)
-                            })?
-                        }
+                        false => self.open_connection().await?,
                         true => {
                             // it is a http server for handling callback
                             // Create channel for receiving authorization code
@@ -897,6 +1515,7 @@ impl McpToolServerConfig {
                     let mut client = McpService::new(&service_name, service);
                     client.tools = tools.iter().map(|tool| tool.name.to_string()).collect();
                     client.fallback_message = self.fallback_message.clone();
+                    client.server_config = Some(self.clone());
 
                     // print name of all tools
                     for (idx, tool) in tools.iter().enumerate() {
@@ -1,6 +1,6 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
@@ -12,22 +12,74 @@ pub type ServerResult<T> = std::result::Result<T, ServerError>;
 pub enum ServerError {
     #[error("{0}")]
     Operation(String),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
     #[error(
         "Not found available server. Please register a(n) {0} server via the `/admin/servers/register` endpoint."
     )]
     NotFoundServer(String),
+    #[error(
+        "The circuits of all the {kind} servers are open. Please retry after {retry_after_secs} seconds"
+    )]
+    CircuitOpen { kind: String, retry_after_secs: u64 },
     #[error("Invalid server kind: {0}")]
     InvalidServerKind(String),
+    #[error("{0}")]
+    NotImplemented(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("Rate limit exceeded. Please retry after {retry_after_secs} seconds")]
+    RateLimited { retry_after_secs: u64 },
     #[error("Failed to load config: {0}")]
     FailedToLoadConfig(String),
     #[error("Mcp server returned empty content")]
     McpEmptyContent,
     #[error("Mcp operation failed: {0}")]
     McpOperation(String),
+    #[error("Not found mcp client connected with {0} mcp server")]
+    McpNotFoundClient(String),
+    #[error("The mcp tool call failed: {0}")]
+    McpToolFailed(String),
+    #[error("The model did not call any tool although `tool_choice` requires a tool call")]
+    RequiredToolCallMissing,
+    #[error("The model failed to follow the ReAct format {0} times")]
+    ReactTagFailures(usize),
+    #[error("The mcp tool calls failed {0} times in a row")]
+    ReactToolFailures(usize),
+    #[error("The request body exceeds the limit of {0} bytes")]
+    PayloadTooLarge(usize),
+    #[error("{error}")]
+    ReactStep {
+        error: Box<ServerError>,
+        step: AgentStep,
+    },
 }
-impl IntoResponse for ServerError {
-    fn into_response(self) -> Response {
-        let (status, message, error_type, param, code) = match &self {
+impl ServerError {
+    /// Attach the context of the ReAct loop step in which the error occurred. Fields already
+    /// recorded closer to the failure take precedence.
+    pub fn with_agent_step(self, step: AgentStep) -> Self {
+        match self {
+            ServerError::ReactStep { error, step: inner } => ServerError::ReactStep {
+                error,
+                step: AgentStep {
+                    step: step.step,
+                    thought: inner.thought.or(step.thought),
+                    action: inner.action.or(step.action),
+                    tool: inner.tool.or(step.tool),
+                },
+            },
+            error => ServerError::ReactStep {
+                error: Box::new(error),
+                step,
+            },
+        }
+    }
+
+    /// Returns the status code, message, type, param and code of the OpenAI error envelope
+    fn error_parts(&self) -> (StatusCode, String, String, Option<String>, Option<String>) {
+        match self {
             ServerError::Operation(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 e.clone(),
@@ -35,15 +87,41 @@ impl IntoResponse for ServerError {
                 None,
                 Some("operation_failed".into()),
             ),
-            ServerError::NotFoundServer(kind) => (
+            ServerError::BadRequest(e) => (
+                StatusCode::BAD_REQUEST,
+                e.clone(),
+                "invalid_request_error".into(),
+                None,
+                Some("bad_request".into()),
+            ),
+            ServerError::NotFound(e) => (
                 StatusCode::NOT_FOUND,
+                e.clone(),
+                "invalid_request_error".into(),
+                None,
+                Some("not_found".into()),
+            ),
+            ServerError::NotFoundServer(kind) => (
+                StatusCode::SERVICE_UNAVAILABLE,
                 format!(
                     "Not found available server. Please register a(n) {kind} server via the `/admin/servers/register` endpoint."
                 ),
-                "not_found".into(),
+                "service_unavailable".into(),
                 Some("server_kind".into()),
                 Some("not_found_server".into()),
             ),
+            ServerError::CircuitOpen {
+                kind,
+                retry_after_secs,
+            } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "The circuits of all the {kind} servers are open. Please retry after {retry_after_secs} seconds"
+                ),
+                "service_unavailable".into(),
+                Some("server_kind".into()),
+                Some("circuit_open".into()),
+            ),
             ServerError::InvalidServerKind(kind) => (
                 StatusCode::BAD_REQUEST,
                 format!("Invalid server kind: {kind}"),
@@ -51,6 +129,27 @@ impl IntoResponse for ServerError {
                 Some("server_kind".into()),
                 Some("invalid_server_kind".into()),
             ),
+            ServerError::NotImplemented(e) => (
+                StatusCode::NOT_IMPLEMENTED,
+                e.clone(),
+                "not_implemented".into(),
+                None,
+                Some("not_implemented".into()),
+            ),
+            ServerError::Unauthorized(e) => (
+                StatusCode::UNAUTHORIZED,
+                e.clone(),
+                "invalid_request_error".into(),
+                None,
+                Some("invalid_api_key".into()),
+            ),
+            ServerError::RateLimited { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("Rate limit exceeded. Please retry after {retry_after_secs} seconds"),
+                "rate_limit_error".into(),
+                None,
+                Some("rate_limit_exceeded".into()),
+            ),
             ServerError::FailedToLoadConfig(e) => (
                 StatusCode::BAD_REQUEST,
                 format!("Failed to load config: {e}"),
@@ -72,6 +171,66 @@ impl IntoResponse for ServerError {
                 None,
                 Some("mcp_operation_failed".into()),
             ),
+            ServerError::McpNotFoundClient(name) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Not found mcp client connected with {name} mcp server"),
+                "service_unavailable".into(),
+                None,
+                Some("mcp_client_not_found".into()),
+            ),
+            ServerError::McpToolFailed(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("The mcp tool call failed: {e}"),
+                "internal_error".into(),
+                None,
+                Some("mcp_tool_failed".into()),
+            ),
+            ServerError::RequiredToolCallMissing => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The model did not call any tool although `tool_choice` requires a tool call"
+                    .into(),
+                "invalid_request_error".into(),
+                Some("tool_choice".into()),
+                Some("required_tool_call_missing".into()),
+            ),
+            ServerError::ReactTagFailures(count) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("The model failed to follow the ReAct format {count} times"),
+                "internal_error".into(),
+                None,
+                Some("react_tag_failures".into()),
+            ),
+            ServerError::ReactToolFailures(count) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("The mcp tool calls failed {count} times in a row"),
+                "internal_error".into(),
+                None,
+                Some("react_tool_failures".into()),
+            ),
+            ServerError::PayloadTooLarge(limit) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("The request body exceeds the limit of {limit} bytes"),
+                "invalid_request_error".into(),
+                None,
+                Some("payload_too_large".into()),
+            ),
+            ServerError::ReactStep { error, .. } => error.error_parts(),
+        }
+    }
+}
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let (status, message, error_type, param, code) = self.error_parts();
+        let retry_after = match &self {
+            ServerError::RateLimited { retry_after_secs }
+            | ServerError::CircuitOpen {
+                retry_after_secs, ..
+            } => Some(*retry_after_secs),
+            _ => None,
+        };
+        let agent_step = match self {
+            ServerError::ReactStep { step, .. } => Some(step),
+            _ => None,
         };
 
         let body = OpenAIErrorResponse {
@@ -80,10 +239,14 @@ impl IntoResponse for ServerError {
                 error_type,
                 param,
                 code,
+                agent_step,
             },
         };
 
-        (status, Json(body)).into_response()
+        match retry_after {
+            Some(secs) => (status, [(RETRY_AFTER, secs.to_string())], Json(body)).into_response(),
+            None => (status, Json(body)).into_response(),
+        }
     }
 }
 
@@ -99,4 +262,74 @@ struct OpenAIError {
     error_type: String,
     param: Option<String>,
     code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    agent_step: Option<AgentStep>,
+}
+
+/// Context of the ReAct loop step in which an error occurred
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AgentStep {
+    /// The 1-based iteration of the ReAct loop
+    pub step: usize,
+    /// The last thought of the model in this step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thought: Option<String>,
+    /// The last action of the model in this step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// The tool call that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_errors_map_to_status_and_openai_body() {
+        let cases = [
+            (
+                ServerError::BadRequest("Invalid `start` timestamp".into()),
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+            ),
+            (
+                ServerError::NotFound("Conversation not found: conv-1".into()),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                ServerError::McpNotFoundClient("weather".into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "mcp_client_not_found",
+            ),
+            (
+                ServerError::NotFoundServer("chat".into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "not_found_server",
+            ),
+            (
+                ServerError::Operation("Failed to parse the response".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "operation_failed",
+            ),
+        ];
+
+        for (error, status, code) in cases {
+            let message = error.to_string();
+            let response = error.into_response();
+            assert_eq!(response.status(), status);
+
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            let error = body["error"].as_object().unwrap();
+            assert_eq!(error["message"], message.as_str());
+            assert_eq!(error["code"], code);
+            assert!(error["type"].is_string());
+            assert!(error.contains_key("param"));
+        }
+    }
 }
@@ -1,4 +1,8 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use axum::{
     Json,
@@ -7,38 +11,156 @@ use axum::{
     http::{HeaderMap, Response, StatusCode},
 };
 use endpoints::{
-    chat::{ChatCompletionRequest, ChatCompletionRequestMessage, Tool, ToolChoice, ToolFunction},
-    embeddings::EmbeddingRequest,
+    chat::{ChatCompletionObject, ChatCompletionRequestMessage, ToolChoice},
+    embeddings::{EmbeddingRequest, EmbeddingsResponse, InputText},
     models::{ListModelsResponse, Model},
 };
+use futures_util::StreamExt;
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    AppState,
-    chat::gen_chat_id,
+    AppState, access_log, auth,
+    chat::{ChatRequest, DISABLE_MEMORY_HEADER, DRY_RUN_HEADER, gen_chat_id},
+    coalesce::Coalesced,
     config::ChatMode,
     dual_debug, dual_error, dual_info, dual_warn,
     error::{ServerError, ServerResult},
+    idempotency::{CachedResponse, IDEMPOTENCY_KEY_HEADER},
     info::ApiServer,
-    mcp::MCP_SEPARATOR,
-    server::{RoutingPolicy, Server, ServerIdToRemove, ServerKind},
+    memory::MemoryError,
+    request_id::RequestId,
+    rerank::{RerankRequest, RerankResponse, rank_by_similarity},
+    response_cache::ResponseCache,
+    server::{Server, ServerIdToRemove, ServerKind, ServerStatusUpdate},
 };
 
 pub(crate) async fn chat_handler(
     State(state): State<Arc<AppState>>,
     Extension(cancel_token): Extension<CancellationToken>,
     headers: HeaderMap,
-    Json(mut request): Json<ChatCompletionRequest>,
+    RequestId(request_id): RequestId,
+    Json(chat_request): Json<ChatRequest>,
 ) -> ServerResult<axum::response::Response> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
+    // every request is rate limited, including the ones sharing the response of an identical
+    // request. The requests without a user id share the bucket of the anonymous user.
+    let user = chat_request.request.user.clone();
+    state.check_rate_limit(user.as_deref(), &request_id)?;
+    access_log::record_user(user.as_deref());
+
+    // identical concurrent non-streaming requests of the same caller share a single downstream
+    // call
+    if let Some(coalescer) = state.coalescer.clone()
+        && chat_request.request.stream != Some(true)
+    {
+        let key = coalescing_key(&chat_request, &headers);
+        let chat = handle_chat(
+            State(state.clone()),
+            Extension(cancel_token),
+            headers,
+            RequestId(request_id.clone()),
+            Json(chat_request),
+        );
+        let coalesced = coalescer
+            .run(
+                key,
+                async {
+                    chat.await
+                        .unwrap_or_else(axum::response::IntoResponse::into_response)
+                },
+                &request_id,
+            )
+            .await;
+        return Ok(match coalesced {
+            Coalesced::Ran(response) => response,
+            Coalesced::Shared(response) => {
+                // the usage is accounted to every request receiving the answer
+                if let Ok(chat_completion) =
+                    serde_json::from_slice::<ChatCompletionObject>(&response.body)
+                {
+                    state.record_usage(user.as_deref(), &chat_completion.usage);
+                }
+                response.into_response()
+            }
+        });
+    }
+
+    handle_chat(
+        State(state),
+        Extension(cancel_token),
+        headers,
+        RequestId(request_id),
+        Json(chat_request),
+    )
+    .await
+}
+
+/// Key of a chat request for coalescing: the hash of its body, of the identity of its caller and
+/// of the headers changing how it is handled
+fn coalescing_key(chat_request: &ChatRequest, headers: &HeaderMap) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(chat_request)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    auth::caller_identity(headers).hash(&mut hasher);
+    for name in [
+        DISABLE_MEMORY_HEADER,
+        DRY_RUN_HEADER,
+        IDEMPOTENCY_KEY_HEADER,
+    ] {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .hash(&mut hasher);
+    }
+    hasher.finish()
+}
 
-    // check if the user id is provided
+async fn handle_chat(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    headers: HeaderMap,
+    RequestId(request_id): RequestId,
+    Json(ChatRequest {
+        mut request,
+        memory: memory_enabled,
+        include_reasoning,
+        react_system_prompt,
+        seed,
+        dry_run,
+    }): Json<ChatRequest>,
+) -> ServerResult<axum::response::Response> {
+    // the caller as sent, before a user id is generated for the anonymous ones
+    let caller = caller_scope(&headers, request.user.as_deref());
+
+    // replay the cached response if the idempotency key was already used by this caller. The
+    // requests of anonymous callers are not cached, as they would share the same keys.
+    let idempotency_key = match &state.idempotency {
+        Some(cache) => match (
+            headers
+                .get(IDEMPOTENCY_KEY_HEADER)
+                .and_then(|h| h.to_str().ok()),
+            caller.clone(),
+        ) {
+            (Some(key), Some(user)) if !key.is_empty() => {
+                if let Some(cached) = cache.get(&user, key) {
+                    dual_info!(
+                        "Return the cached response for idempotency key: {} - request_id: {}",
+                        key,
+                        request_id
+                    );
+                    return Ok(cached.into_response());
+                }
+                Some((user, key.to_string()))
+            }
+            _ => None,
+        },
+        None => None,
+    };
+
+    // check if the user id is provided, the usage is only accounted to the ones sent by clients
+    let client_user = request.user.clone();
     if request.user.is_none() {
         request.user = Some(gen_chat_id());
     };
@@ -47,6 +169,13 @@ pub(crate) async fn chat_handler(
         request.user.as_ref().unwrap(),
         request_id
     );
+    access_log::record_user(request.user.as_deref());
+
+    // map a model alias to its model, or fill in the model if the client omitted it
+    resolve_model_alias(&state, &mut request.model, &request_id).await;
+    if request.model.is_none() {
+        request.model = Some(default_model(&state, &request_id).await?);
+    }
 
     // update the request with MCP tools
     if let Some(mcp_config) = state.config.read().await.mcp.as_ref()
@@ -54,37 +183,9 @@ pub(crate) async fn chat_handler(
     {
         dual_info!("Updating the request with MCP tools");
 
-        let mut more_tools = Vec::new();
-        for server_config in mcp_config.server.tool_servers.iter() {
-            if server_config.enable {
-                server_config
-                    .tools
-                    .as_ref()
-                    .unwrap()
-                    .iter()
-                    .for_each(|mcp_tool| {
-                        let name = format!(
-                            "{}{MCP_SEPARATOR}{}",
-                            &mcp_tool.name,
-                            server_config.server_name.as_deref().unwrap()
-                        );
-                        let tool = Tool::new(ToolFunction {
-                            name,
-                            description: mcp_tool.description.as_ref().map(|s| s.to_string()),
-                            parameters: Some((*mcp_tool.input_schema).clone()),
-                        });
-
-                        more_tools.push(tool.clone());
-                    });
-            }
-        }
-
+        let more_tools = crate::mcp::build_mcp_tools(mcp_config);
         if !more_tools.is_empty() {
-            if let Some(tools) = &mut request.tools {
-                tools.extend(more_tools);
-            } else {
-                request.tools = Some(more_tools);
-            }
+            crate::mcp::merge_mcp_tools(&mut request.tools, more_tools);
 
             // set the tool choice to auto
             if let Some(ToolChoice::None) | None = request.tool_choice {
@@ -93,14 +194,49 @@ pub(crate) async fn chat_handler(
         }
     }
 
+    // the memory can be turned off for a single request by the field or the header
+    let memory_disabled =
+        memory_enabled == Some(false) || header_is_true(&headers, DISABLE_MEMORY_HEADER);
+    if memory_disabled && state.memory.is_some() {
+        dual_info!(
+            "Memory is disabled for this request - request_id: {}",
+            request_id
+        );
+    }
+
+    // a dry run returns the assembled request instead of sending it, and leaves the memory as is
+    if dry_run == Some(true) || header_is_true(&headers, DRY_RUN_HEADER) {
+        dual_info!("Dry run of the chat request - request_id: {}", request_id);
+        let (chat_mode, default_seed) = {
+            let config = state.config.read().await;
+            (
+                config.server.chat_mode,
+                config
+                    .chat
+                    .as_ref()
+                    .and_then(|chat_config| chat_config.default_seed),
+            )
+        };
+        let assembled = crate::chat::with_seed(
+            seed.or(default_seed),
+            crate::chat::assemble_request(
+                &state,
+                request,
+                !memory_disabled,
+                chat_mode,
+                react_system_prompt,
+                &request_id,
+            ),
+        )
+        .await?;
+        return Ok(axum::response::IntoResponse::into_response(Json(assembled)));
+    }
+
     // Create or get conversation ID for memory
-    let conv_id = if let Some(memory) = &state.memory {
+    let conv_id = if let Some(memory) = state.memory.as_ref().filter(|_| !memory_disabled) {
         if let Some(user) = &request.user {
             // Use global persistent conversation management: the same user reuses the same conversation regardless of which model is used
-            let model_name = request
-                .model
-                .clone()
-                .unwrap_or_else(|| "default".to_string());
+            let model_name = request.model.clone().unwrap_or_default();
             match memory
                 .get_or_create_user_conversation(user, &model_name)
                 .await
@@ -131,10 +267,44 @@ pub(crate) async fn chat_handler(
         None
     };
 
-    // Get chat mode from configuration
-    let chat_mode = {
+    // mirror a sample of the requests to the shadow server
+    if let Some(shadow) = &state.shadow
+        && shadow.should_mirror()
+    {
+        match serde_json::to_value(&request) {
+            Ok(shadow_request) => {
+                dual_debug!(
+                    "Mirror the chat request to the shadow server - request_id: {}",
+                    request_id
+                );
+                shadow.mirror(shadow_request, request_id.clone());
+            }
+            Err(e) => {
+                dual_warn!(
+                    "Failed to serialize the shadow request: {} - request_id: {}",
+                    e,
+                    request_id
+                );
+            }
+        }
+    }
+
+    // Get chat mode, SSE keepalive interval, answer post-processing and default seed from
+    // configuration
+    let (chat_mode, sse_keepalive_secs, answer_postprocess, default_seed) = {
         let config = state.config.read().await;
-        config.server.chat_mode
+        (
+            config.server.chat_mode,
+            config.server.sse_keepalive_secs,
+            config
+                .answer_postprocess
+                .clone()
+                .filter(|postprocess_config| postprocess_config.enable),
+            config
+                .chat
+                .as_ref()
+                .and_then(|chat_config| chat_config.default_seed),
+        )
     };
     dual_debug!(
         "Using chat mode: {:?} - request_id: {}",
@@ -142,30 +312,105 @@ pub(crate) async fn chat_handler(
         request_id
     );
 
-    // Route to appropriate chat handler based on configuration
-    let res = match chat_mode {
-        ChatMode::Normal => {
-            crate::chat::normal::chat(
-                State(state.clone()),
-                Extension(cancel_token),
-                headers,
-                Json(request),
-                conv_id.clone(),
-                &request_id,
-            )
-            .await
-        }
-        ChatMode::React => {
-            crate::chat::react::chat(
-                State(state.clone()),
-                Extension(cancel_token),
-                headers,
-                Json(request),
-                conv_id.clone(),
-                &request_id,
-            )
-            .await
+    let is_stream = request.stream == Some(true);
+    let include_usage = crate::chat::include_usage(&request);
+
+    // replay the cached response of a deterministic request that does not use the memory
+    let response_cache_key = match &state.response_cache {
+        Some(cache) if conv_id.is_none() => {
+            let key = ResponseCache::key(
+                &request,
+                seed.or(default_seed),
+                caller.as_deref(),
+                (
+                    format!("{chat_mode:?}"),
+                    include_reasoning,
+                    &react_system_prompt,
+                    answer_postprocess.is_some(),
+                ),
+            );
+            if let Some(cached) = key.and_then(|key| cache.get(key)) {
+                dual_info!(
+                    "Return the cached response of a deterministic request - request_id: {}",
+                    request_id
+                );
+                return Ok(cached.into_response());
+            }
+            key
         }
+        _ => None,
+    };
+
+    // the draft answer is collected in full before it is post-processed
+    if answer_postprocess.is_some() && is_stream {
+        request.stream = Some(false);
+    }
+
+    // Route to appropriate chat handler based on configuration
+    let chat = {
+        let state = state.clone();
+        let conv_id = conv_id.clone();
+        let request_id = request_id.clone();
+        let postprocess_headers = headers.clone();
+        let postprocess_cancel_token = cancel_token.clone();
+        let postprocess_conv_id = conv_id.clone();
+        let chat = crate::chat::with_seed(seed.or(default_seed), async move {
+            let response = match chat_mode {
+                ChatMode::Normal => {
+                    crate::chat::normal::chat(
+                        State(state.clone()),
+                        Extension(cancel_token),
+                        headers,
+                        Json(request),
+                        conv_id,
+                        &request_id,
+                    )
+                    .await
+                }
+                ChatMode::React => {
+                    crate::chat::react::chat(
+                        State(state.clone()),
+                        Extension(cancel_token),
+                        headers,
+                        Json(request),
+                        conv_id,
+                        include_reasoning.unwrap_or(false),
+                        react_system_prompt,
+                        &request_id,
+                    )
+                    .await
+                }
+            }?;
+
+            match answer_postprocess {
+                Some(postprocess_config) => {
+                    crate::chat::postprocess_answer(
+                        &state,
+                        &postprocess_config,
+                        response,
+                        &postprocess_headers,
+                        is_stream,
+                        include_usage,
+                        postprocess_conv_id.as_deref(),
+                        &postprocess_cancel_token,
+                        &request_id,
+                    )
+                    .await
+                }
+                None => Ok(response),
+            }
+        });
+        crate::chat::with_client_user(client_user, Box::pin(chat))
+    };
+    let res = if is_stream && sse_keepalive_secs > 0 {
+        Ok(crate::chat::sse_with_keepalive(
+            chat,
+            std::time::Duration::from_secs(sse_keepalive_secs),
+            request_id.clone(),
+        )
+        .await)
+    } else {
+        chat.await
     };
 
     // Print chat history
@@ -210,53 +455,140 @@ pub(crate) async fn chat_handler(
         );
     }
 
-    res
+    // cache the successful non-streaming response for the idempotency key and the response cache
+    let idempotency = state.idempotency.as_ref().zip(idempotency_key);
+    let response_cache = state.response_cache.as_ref().zip(response_cache_key);
+    match res {
+        Ok(response)
+            if response.status().is_success()
+                && !is_stream
+                && (idempotency.is_some() || response_cache.is_some()) =>
+        {
+            let (parts, body) = response.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+                let err_msg = format!("Failed to read the response body: {e}");
+                dual_error!("{} - request_id: {}", err_msg, request_id);
+                ServerError::Operation(err_msg)
+            })?;
+            let cached = CachedResponse {
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+                downstream: access_log::downstream(),
+            };
+
+            if let Some((cache, (user, key))) = idempotency {
+                cache.insert(&user, &key, cached.clone());
+                dual_debug!(
+                    "Cached the response for idempotency key: {} - request_id: {}",
+                    key,
+                    request_id
+                );
+            }
+            if let Some((cache, key)) = response_cache {
+                cache.insert(key, cached);
+                dual_debug!(
+                    "Cached the response of a deterministic request - request_id: {}",
+                    request_id
+                );
+            }
+
+            Ok(Response::from_parts(parts, Body::from(body)))
+        }
+        res => res,
+    }
+}
+
+/// Scope of the cached responses of a caller: its identity and the user of the request. None
+/// for an anonymous caller without user.
+fn caller_scope(headers: &HeaderMap, user: Option<&str>) -> Option<String> {
+    let identity = auth::caller_identity(headers);
+    let user = user.filter(|user| !user.is_empty());
+    if identity.is_none() && user.is_none() {
+        return None;
+    }
+
+    Some(format!(
+        "{}:{}",
+        identity.unwrap_or_default(),
+        user.unwrap_or_default()
+    ))
 }
 
-pub(crate) async fn embeddings_handler(
-    State(state): State<Arc<AppState>>,
-    Extension(cancel_token): Extension<CancellationToken>,
-    headers: HeaderMap,
-    Json(request): Json<EmbeddingRequest>,
-) -> ServerResult<axum::response::Response> {
-    // Get request ID from headers
-    let request_id = headers
-        .get("x-request-id")
+/// Whether a header is set to `true` or `1`
+fn header_is_true(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
         .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
+        .is_some_and(|h| h.eq_ignore_ascii_case("true") || h == "1")
+}
 
-    dual_info!(
-        "Received a new embeddings request - request_id: {}",
+/// Replace a model alias of `model_aliases` with the model it stands for
+async fn resolve_model_alias(state: &AppState, model: &mut Option<String>, request_id: &str) {
+    let config = state.config.read().await;
+    let Some(target) = model
+        .as_ref()
+        .and_then(|model| config.model_aliases.as_ref()?.get(model))
+    else {
+        return;
+    };
+
+    dual_debug!(
+        "Map the model alias {} to {} - request_id: {}",
+        model.as_deref().unwrap_or_default(),
+        target,
         request_id
     );
+    *model = Some(target.clone());
+}
 
-    // get the embeddings server
-    let servers = state.server_group.read().await;
-    let embeddings_servers = match servers.get(&ServerKind::embeddings) {
-        Some(servers) => servers,
-        None => {
-            let err_msg = "No embedding server available. Please register a embedding server via the `/admin/servers/register` endpoint.";
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg.to_string()));
-        }
+/// Get the model of a chat request without one from `server.default_model`
+///
+/// The default model must be served by a downstream server, unless no server has reported its
+/// models.
+async fn default_model(state: &AppState, request_id: &str) -> ServerResult<String> {
+    let Some(default_model) = state.config.read().await.server.default_model.clone() else {
+        let err_msg = "The request has no `model` and no default model is configured";
+        dual_warn!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::BadRequest(err_msg.to_string()));
     };
 
-    let embedding_server = match embeddings_servers.next().await {
-        Ok(target_server_info) => target_server_info,
-        Err(e) => {
-            let err_msg = format!("Failed to get the embeddings server: {e}");
-            dual_error!("{} - request_id: {}", err_msg, request_id);
-            return Err(ServerError::Operation(err_msg));
-        }
-    };
-    let embeddings_service_url =
-        format!("{}/embeddings", embedding_server.url.trim_end_matches('/'));
+    let models = state.models.read().await;
+    let is_known = models.is_empty()
+        || models
+            .values()
+            .flatten()
+            .any(|model| model.id == default_model);
+    if !is_known {
+        let err_msg = format!(
+            "The request has no `model` and the default model `{default_model}` is not served by any downstream server"
+        );
+        dual_warn!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::BadRequest(err_msg));
+    }
+
+    dual_debug!(
+        "Use the default model {} - request_id: {}",
+        default_model,
+        request_id
+    );
+    Ok(default_model)
+}
+
+pub(crate) async fn embeddings_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    headers: HeaderMap,
+    RequestId(request_id): RequestId,
+    Json(mut request): Json<EmbeddingRequest>,
+) -> ServerResult<axum::response::Response> {
     dual_info!(
-        "Forward the embeddings request to {} - request_id: {}",
-        embeddings_service_url,
+        "Received a new embeddings request - request_id: {}",
         request_id
     );
+    access_log::record_user(request.user.as_deref());
+    state.check_rate_limit(request.user.as_deref(), &request_id)?;
+    resolve_model_alias(&state, &mut request.model, &request_id).await;
 
     // parse the content-type header
     let content_type = headers
@@ -274,53 +606,273 @@ pub(crate) async fn embeddings_handler(
         request_id
     );
 
-    // Create request client
-    let ds_request = if let Some(api_key) = &embedding_server.api_key
-        && !api_key.is_empty()
-    {
-        reqwest::Client::new()
-            .post(embeddings_service_url)
-            .header("Content-Type", content_type)
-            .header(AUTHORIZATION, api_key)
-            .json(&request)
-    } else if headers.contains_key("authorization") {
-        let authorization = headers
-            .get("authorization")
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
+    let max_batch = state
+        .config
+        .read()
+        .await
+        .embedding
+        .as_ref()
+        .and_then(|embedding_config| embedding_config.max_embedding_batch)
+        .filter(|max_batch| *max_batch > 0);
+    let num_inputs = match &request.input {
+        InputText::ArrayOfStrings(inputs) => inputs.len(),
+        InputText::ArrayOfTokenArrays(inputs) => inputs.len(),
+        _ => 1,
+    };
 
-        reqwest::Client::new()
-            .post(embeddings_service_url)
-            .header("Content-Type", content_type)
-            .header("Authorization", authorization)
-            .json(&request)
-    } else {
-        reqwest::Client::new()
-            .post(embeddings_service_url)
-            .header("Content-Type", content_type)
-            .json(&request)
+    let (status, bytes) = match max_batch {
+        Some(max_batch) if num_inputs > max_batch => {
+            send_embedding_batches(
+                &state,
+                &cancel_token,
+                &headers,
+                &content_type,
+                &request,
+                max_batch,
+                &request_id,
+            )
+            .await?
+        }
+        _ => {
+            send_embedding_request(
+                &state,
+                &cancel_token,
+                &headers,
+                &content_type,
+                &request,
+                &request_id,
+            )
+            .await?
+        }
     };
 
-    // Use select! to handle request cancellation
-    let ds_response = select! {
-        response = ds_request.send() => {
-            response.map_err(|e| {
-                let err_msg = format!(
-                    "Failed to forward the request to the downstream server: {e}",
+    match Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(bytes))
+    {
+        Ok(response) => {
+            dual_info!(
+                "Embeddings request completed successfully - request_id: {}",
+                request_id
+            );
+            Ok(response)
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to create the response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            Err(ServerError::Operation(err_msg))
+        }
+    }
+}
+
+// forward an embeddings request and return the status and body of the downstream response
+async fn send_embedding_request(
+    state: &AppState,
+    cancel_token: &CancellationToken,
+    headers: &HeaderMap,
+    content_type: &str,
+    request: &EmbeddingRequest,
+    request_id: &str,
+) -> ServerResult<(StatusCode, bytes::Bytes)> {
+    // Forward the request, failing over to the next embeddings server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover_for_model(
+            ServerKind::embeddings,
+            request.model.as_deref(),
+            |embedding_server| {
+                let embeddings_service_url =
+                    format!("{}/embeddings", embedding_server.url.trim_end_matches('/'));
+                dual_info!(
+                    "Forward the embeddings request to {} - request_id: {}",
+                    embeddings_service_url,
+                    request_id
                 );
+
+                // Create request client
+                let ds_request = reqwest::Client::new()
+                    .post(embeddings_service_url)
+                    .header("Content-Type", content_type)
+                    .json(request);
+                if let Some(api_key) = &embedding_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request.header(AUTHORIZATION, api_key)
+                } else if let Some(authorization) = headers.get("authorization") {
+                    ds_request.header(AUTHORIZATION, authorization)
+                } else {
+                    ds_request
+                }
+            },
+            cancel_token,
+            request_id,
+        )
+        .await?;
+
+    let status = ds_response.status();
+
+    // Handle response body reading with cancellation
+    let bytes = select! {
+        bytes = ds_response.bytes() => {
+            bytes.map_err(|e| {
+                let err_msg = format!("Failed to get the full response as bytes: {e}");
                 dual_error!("{err_msg} - request_id: {request_id}");
                 ServerError::Operation(err_msg)
             })?
         }
         _ = cancel_token.cancelled() => {
-            let warn_msg = "Request was cancelled by client";
+            let warn_msg = "Request was cancelled while reading response";
             dual_warn!("{} - request_id: {}", warn_msg, request_id);
             return Err(ServerError::Operation(warn_msg.to_string()));
         }
     };
 
+    Ok((status, bytes))
+}
+
+// split the inputs into sub-batches of at most `max_batch`, forward them one after another and
+// merge the results. The first downstream error is returned as is.
+async fn send_embedding_batches(
+    state: &AppState,
+    cancel_token: &CancellationToken,
+    headers: &HeaderMap,
+    content_type: &str,
+    request: &EmbeddingRequest,
+    max_batch: usize,
+    request_id: &str,
+) -> ServerResult<(StatusCode, bytes::Bytes)> {
+    let batches: Vec<InputText> = match &request.input {
+        InputText::ArrayOfStrings(inputs) => inputs
+            .chunks(max_batch)
+            .map(|chunk| InputText::ArrayOfStrings(chunk.to_vec()))
+            .collect(),
+        InputText::ArrayOfTokenArrays(inputs) => inputs
+            .chunks(max_batch)
+            .map(|chunk| InputText::ArrayOfTokenArrays(chunk.to_vec()))
+            .collect(),
+        input => vec![input.clone()],
+    };
+
+    dual_info!(
+        "Split the embeddings request into {} batches of at most {} inputs - request_id: {}",
+        batches.len(),
+        max_batch,
+        request_id
+    );
+
+    let mut merged: Option<EmbeddingsResponse> = None;
+    let mut offset = 0;
+    for input in batches {
+        let batch_size = match &input {
+            InputText::ArrayOfStrings(inputs) => inputs.len(),
+            InputText::ArrayOfTokenArrays(inputs) => inputs.len(),
+            _ => 1,
+        };
+        let batch_request = EmbeddingRequest {
+            input,
+            ..request.clone()
+        };
+
+        let (status, bytes) = send_embedding_request(
+            state,
+            cancel_token,
+            headers,
+            content_type,
+            &batch_request,
+            request_id,
+        )
+        .await?;
+        if !status.is_success() {
+            dual_error!(
+                "The embeddings server returned {} for the batch starting at {} - request_id: {}",
+                status,
+                offset,
+                request_id
+            );
+            return Ok((status, bytes));
+        }
+
+        let mut response: EmbeddingsResponse = serde_json::from_slice(&bytes).map_err(|e| {
+            let err_msg = format!("Failed to parse the embeddings response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })?;
+        for object in response.data.iter_mut() {
+            object.index += offset as u64;
+        }
+        offset += batch_size;
+
+        match merged.as_mut() {
+            Some(merged) => {
+                merged.data.append(&mut response.data);
+                merged.usage.prompt_tokens += response.usage.prompt_tokens;
+                merged.usage.completion_tokens += response.usage.completion_tokens;
+                merged.usage.total_tokens += response.usage.total_tokens;
+            }
+            None => merged = Some(response),
+        }
+    }
+
+    let body = serde_json::to_vec(&merged).map_err(|e| {
+        let err_msg = format!("Failed to serialize the embeddings response: {e}");
+        dual_error!("{err_msg} - request_id: {request_id}");
+        ServerError::Operation(err_msg)
+    })?;
+
+    Ok((StatusCode::OK, body.into()))
+}
+
+pub(crate) async fn rerank_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    headers: HeaderMap,
+    RequestId(request_id): RequestId,
+    Json(request): Json<RerankRequest>,
+) -> ServerResult<axum::response::Response> {
+    dual_info!("Received a new rerank request - request_id: {}", request_id);
+
+    // rank the documents via the embeddings server if no rerank server is registered
+    if !state.has_downstream_server(ServerKind::rerank).await {
+        dual_info!(
+            "No rerank server registered. Rank the documents by embedding similarity - request_id: {}",
+            request_id
+        );
+        return rerank_by_embeddings(&state, &cancel_token, &headers, &request, &request_id).await;
+    }
+
+    // Forward the request, failing over to the next rerank server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::rerank,
+            |rerank_server| {
+                let rerank_service_url =
+                    format!("{}/rerank", rerank_server.url.trim_end_matches('/'));
+                dual_info!(
+                    "Forward the rerank request to {} - request_id: {}",
+                    rerank_service_url,
+                    request_id
+                );
+
+                // Create request client
+                let ds_request = reqwest::Client::new()
+                    .post(rerank_service_url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .json(&request);
+                if let Some(api_key) = &rerank_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request.header(AUTHORIZATION, api_key)
+                } else if let Some(authorization) = headers.get("authorization") {
+                    ds_request.header(AUTHORIZATION, authorization)
+                } else {
+                    ds_request
+                }
+            },
+            &cancel_token,
+            &request_id,
+        )
+        .await?;
+
     let status = ds_response.status();
 
     // Handle response body reading with cancellation
@@ -346,7 +898,7 @@ pub(crate) async fn embeddings_handler(
     {
         Ok(response) => {
             dual_info!(
-                "Embeddings request completed successfully - request_id: {}",
+                "Rerank request completed successfully - request_id: {}",
                 request_id
             );
             Ok(response)
@@ -359,94 +911,174 @@ pub(crate) async fn embeddings_handler(
     }
 }
 
-pub(crate) async fn audio_transcriptions_handler(
-    State(state): State<Arc<AppState>>,
-    Extension(cancel_token): Extension<CancellationToken>,
-    req: axum::extract::Request<Body>,
+// rank the documents of a rerank request by the cosine similarity of their embeddings to the query
+async fn rerank_by_embeddings(
+    state: &AppState,
+    cancel_token: &CancellationToken,
+    headers: &HeaderMap,
+    request: &RerankRequest,
+    request_id: &str,
 ) -> ServerResult<axum::response::Response> {
-    // Get request ID from headers
-    let request_id = req
-        .headers()
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
+    // embed the query and the documents in a single request
+    let mut input = Vec::with_capacity(request.documents.len() + 1);
+    input.push(request.query.clone());
+    input.extend(request.documents.iter().cloned());
+    let embedding_request = serde_json::json!({ "input": input });
+
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::embeddings,
+            |embedding_server| {
+                let embeddings_service_url =
+                    format!("{}/embeddings", embedding_server.url.trim_end_matches('/'));
+                dual_info!(
+                    "Forward the rerank documents to {} - request_id: {}",
+                    embeddings_service_url,
+                    request_id
+                );
 
-    dual_info!(
-        "Received a new audio transcription request - request_id: {}",
-        request_id
-    );
-
-    // get the transcribe server
-    let transcription_server = {
-        let servers = state.server_group.read().await;
-        let transcribe_servers = match servers.get(&ServerKind::transcribe) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No transcribe server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
+                let ds_request = reqwest::Client::new()
+                    .post(embeddings_service_url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .json(&embedding_request);
+                if let Some(api_key) = &embedding_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request.header(AUTHORIZATION, api_key)
+                } else if let Some(authorization) = headers.get("authorization") {
+                    ds_request.header(AUTHORIZATION, authorization)
+                } else {
+                    ds_request
+                }
+            },
+            cancel_token,
+            request_id,
+        )
+        .await?;
 
-        match transcribe_servers.next().await {
-            Ok(target_server_info) => target_server_info,
-            Err(e) => {
-                let err_msg = format!("Failed to get the transcribe server: {e}");
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
+    let status = ds_response.status();
+    let bytes = select! {
+        bytes = ds_response.bytes() => {
+            bytes.map_err(|e| {
+                let err_msg = format!("Failed to get the full response as bytes: {e}");
+                dual_error!("{err_msg} - request_id: {request_id}");
+                ServerError::Operation(err_msg)
+            })?
+        }
+        _ = cancel_token.cancelled() => {
+            let warn_msg = "Request was cancelled while reading response";
+            dual_warn!("{} - request_id: {}", warn_msg, request_id);
+            return Err(ServerError::Operation(warn_msg.to_string()));
         }
     };
-
-    let transcription_server_url = format!(
-        "{}/audio/transcriptions",
-        transcription_server.url.trim_end_matches('/')
-    );
-    dual_info!(
-        "Forward the audio transcription request to {} - request_id: {}",
-        transcription_server_url,
-        request_id
-    );
-
-    // Create request client
-    let mut ds_request = reqwest::Client::new().post(transcription_server_url);
-    if let Some(api_key) = &transcription_server.api_key
-        && !api_key.is_empty()
-    {
-        ds_request = ds_request.header(AUTHORIZATION, api_key);
+    if !status.is_success() {
+        let err_msg = format!(
+            "The embeddings server returned {}: {}",
+            status,
+            String::from_utf8_lossy(&bytes)
+        );
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg));
     }
-    for (name, value) in req.headers().iter() {
-        ds_request = ds_request.header(name, value);
+
+    let mut embeddings_response: EmbeddingsResponse =
+        serde_json::from_slice(&bytes).map_err(|e| {
+            let err_msg = format!("Failed to parse the embeddings response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })?;
+    if embeddings_response.data.len() != input.len() {
+        let err_msg = format!(
+            "Expected {} embeddings, but the embeddings server returned {}",
+            input.len(),
+            embeddings_response.data.len()
+        );
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg));
     }
+    embeddings_response.data.sort_by_key(|object| object.index);
+
+    let mut embeddings = embeddings_response
+        .data
+        .into_iter()
+        .map(|object| object.embedding);
+    let query_embedding = embeddings.next().unwrap_or_default();
+    let document_embeddings: Vec<Vec<f64>> = embeddings.collect();
+
+    let rerank_response = RerankResponse {
+        model: embeddings_response.model,
+        results: rank_by_similarity(request, &query_embedding, &document_embeddings),
+    };
 
-    // convert the request body into bytes
-    let body = req.into_body();
-    let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
-        let err_msg = format!("Failed to convert the request body into bytes: {e}");
+    let body = serde_json::to_string(&rerank_response).map_err(|e| {
+        let err_msg = format!("Failed to serialize the rerank response: {e}");
         dual_error!("{err_msg} - request_id: {request_id}");
         ServerError::Operation(err_msg)
     })?;
 
-    ds_request = ds_request.body(body_bytes);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create the response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
+
+pub(crate) async fn moderations_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    headers: HeaderMap,
+    RequestId(request_id): RequestId,
+    Json(request): Json<serde_json::Value>,
+) -> ServerResult<axum::response::Response> {
+    dual_info!(
+        "Received a new moderation request - request_id: {}",
+        request_id
+    );
+
+    if !state.has_downstream_server(ServerKind::moderation).await {
+        let err_msg = "Moderation is not available. Please register a moderation server via the `/admin/servers/register` endpoint.";
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::NotImplemented(err_msg.to_string()));
+    }
 
-    // Use select! to handle request cancellation
-    let ds_response = select! {
-        response = ds_request.send() => {
-            response.map_err(|e| {
-                let err_msg = format!(
-                    "Failed to forward the request to the downstream server: {e}"
+    // Forward the request, failing over to the next moderation server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::moderation,
+            |moderation_server| {
+                let moderation_service_url = format!(
+                    "{}/moderations",
+                    moderation_server.url.trim_end_matches('/')
                 );
-                dual_error!("{err_msg} - request_id: {request_id}");
-                ServerError::Operation(err_msg)
-            })?
-        }
-        _ = cancel_token.cancelled() => {
-            let warn_msg = "Request was cancelled by client";
-            dual_warn!("{} - request_id: {}", warn_msg, request_id);
-            return Err(ServerError::Operation(warn_msg.to_string()));
-        }
-    };
+                dual_info!(
+                    "Forward the moderation request to {} - request_id: {}",
+                    moderation_service_url,
+                    request_id
+                );
+
+                // Create request client
+                let ds_request = reqwest::Client::new()
+                    .post(moderation_service_url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .json(&request);
+                if let Some(api_key) = &moderation_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request.header(AUTHORIZATION, api_key)
+                } else if let Some(authorization) = headers.get("authorization") {
+                    ds_request.header(AUTHORIZATION, authorization)
+                } else {
+                    ds_request
+                }
+            },
+            &cancel_token,
+            &request_id,
+        )
+        .await?;
 
     let status = ds_response.status();
 
@@ -473,7 +1105,7 @@ pub(crate) async fn audio_transcriptions_handler(
     {
         Ok(response) => {
             dual_info!(
-                "Audio transcription request completed successfully - request_id: {}",
+                "Moderation request completed successfully - request_id: {}",
                 request_id
             );
             Ok(response)
@@ -486,96 +1118,112 @@ pub(crate) async fn audio_transcriptions_handler(
     }
 }
 
-pub(crate) async fn audio_translations_handler(
+/// Read the body of a request into bytes, rejecting bodies larger than
+/// `server.max_request_body_bytes`
+async fn read_request_body(
+    state: &AppState,
+    body: Body,
+    request_id: &str,
+) -> ServerResult<bytes::Bytes> {
+    let limit = state.config.read().await.server.max_request_body_bytes;
+
+    let mut body_bytes = bytes::BytesMut::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            let err_msg = format!("Failed to convert the request body into bytes: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })?;
+        if body_bytes.len() + chunk.len() > limit {
+            dual_warn!(
+                "The request body exceeds the limit of {} bytes - request_id: {}",
+                limit,
+                request_id
+            );
+            return Err(ServerError::PayloadTooLarge(limit));
+        }
+        body_bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(body_bytes.freeze())
+}
+
+pub(crate) async fn audio_transcriptions_handler(
     State(state): State<Arc<AppState>>,
     Extension(cancel_token): Extension<CancellationToken>,
+    RequestId(request_id): RequestId,
     req: axum::extract::Request<Body>,
 ) -> ServerResult<axum::response::Response> {
-    // Get request ID from headers
-    let request_id = req
-        .headers()
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
     dual_info!(
-        "Received a new audio translation request - request_id: {}",
+        "Received a new audio transcription request - request_id: {}",
         request_id
     );
 
-    // get the transcribe server
-    let translation_server = {
-        let servers = state.server_group.read().await;
-        let translate_servers = match servers.get(&ServerKind::translate) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No translate server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match translate_servers.next().await {
-            Ok(target_server_info) => target_server_info,
-            Err(e) => {
-                let err_msg = format!("Failed to get the translate server: {e}");
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
+    // convert the request body into bytes
+    let (parts, body) = req.into_parts();
+    let body_bytes = read_request_body(&state, body, &request_id).await?;
+
+    // Forward the request, failing over to the next transcribe server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::transcribe,
+            |transcription_server| {
+                let transcription_server_url = format!(
+                    "{}/audio/transcriptions",
+                    transcription_server.url.trim_end_matches('/')
+                );
+                dual_info!(
+                    "Forward the audio transcription request to {} - request_id: {}",
+                    transcription_server_url,
+                    request_id
+                );
 
-    let translation_server_url = format!(
-        "{}/audio/translations",
-        translation_server.url.trim_end_matches('/')
-    );
-    dual_info!(
-        "Forward the audio translation request to {} - request_id: {}",
-        translation_server_url,
-        request_id
-    );
+                // Create request client
+                let mut ds_request = reqwest::Client::new().post(transcription_server_url);
+                if let Some(api_key) = &transcription_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request = ds_request.header(AUTHORIZATION, api_key);
+                }
+                for (name, value) in parts.headers.iter() {
+                    ds_request = ds_request.header(name, value);
+                }
 
-    // Create request client
-    let mut ds_request = reqwest::Client::new().post(translation_server_url);
-    if let Some(api_key) = &translation_server.api_key
-        && !api_key.is_empty()
-    {
-        ds_request = ds_request.header(AUTHORIZATION, api_key);
-    }
-    for (name, value) in req.headers().iter() {
-        ds_request = ds_request.header(name, value);
-    }
+                ds_request.body(body_bytes.clone())
+            },
+            &cancel_token,
+            &request_id,
+        )
+        .await?;
 
-    // convert the request body into bytes
-    let body = req.into_body();
-    let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
-        let err_msg = format!("Failed to convert the request body into bytes: {e}");
-        dual_error!("{err_msg} - request_id: {request_id}");
-        ServerError::Operation(err_msg)
-    })?;
+    let status = ds_response.status();
 
-    ds_request = ds_request.body(body_bytes);
+    // Pipe a streamed transcription through to the client as its segments arrive
+    if let Some(content_type) = ds_response.headers().get(CONTENT_TYPE).cloned()
+        && content_type
+            .to_str()
+            .is_ok_and(|content_type| content_type.starts_with("text/event-stream"))
+    {
+        dual_info!(
+            "Stream the audio transcription to the client - request_id: {}",
+            request_id
+        );
 
-    // Use select! to handle request cancellation
-    let ds_response = select! {
-        response = ds_request.send() => {
-            response.map_err(|e| {
-                let err_msg = format!(
-                    "Failed to forward the request to the downstream server: {e}"
-                );
+        let segments = ds_response
+            .bytes_stream()
+            .take_until(cancel_token.cancelled_owned());
+        return Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, content_type)
+            .header("Cache-Control", "no-cache")
+            .body(Body::from_stream(segments))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create streaming response: {e}");
                 dual_error!("{err_msg} - request_id: {request_id}");
                 ServerError::Operation(err_msg)
-            })?
-        }
-        _ = cancel_token.cancelled() => {
-            let warn_msg = "Request was cancelled by client";
-            dual_warn!("{} - request_id: {}", warn_msg, request_id);
-            return Err(ServerError::Operation(warn_msg.to_string()));
-        }
-    };
-
-    let status = ds_response.status();
+            });
+    }
 
     // Handle response body reading with cancellation
     let bytes = select! {
@@ -600,7 +1248,7 @@ pub(crate) async fn audio_translations_handler(
     {
         Ok(response) => {
             dual_info!(
-                "Audio translation request completed successfully - request_id: {}",
+                "Audio transcription request completed successfully - request_id: {}",
                 request_id
             );
             Ok(response)
@@ -613,96 +1261,55 @@ pub(crate) async fn audio_translations_handler(
     }
 }
 
-pub(crate) async fn audio_tts_handler(
+pub(crate) async fn audio_translations_handler(
     State(state): State<Arc<AppState>>,
     Extension(cancel_token): Extension<CancellationToken>,
+    RequestId(request_id): RequestId,
     req: axum::extract::Request<Body>,
 ) -> ServerResult<axum::response::Response> {
-    // Get request ID from headers
-    let request_id = req
-        .headers()
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
-    dual_info!(
-        "Received a new audio speech request - request_id: {}",
-        request_id
-    );
-
-    // get the tts server
-    let tts_server = {
-        let servers = state.server_group.read().await;
-        let tts_servers = match servers.get(&ServerKind::tts) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No tts server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match tts_servers.next().await {
-            Ok(target_server_info) => target_server_info,
-            Err(e) => {
-                let err_msg = format!("Failed to get the tts server: {e}");
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
-
-    let tts_server_url = format!("{}/audio/speech", tts_server.url.trim_end_matches('/'));
     dual_info!(
-        "Forward the audio speech request to {} - request_id: {}",
-        tts_server_url,
+        "Received a new audio translation request - request_id: {}",
         request_id
     );
 
-    // Create request client
-    let mut ds_request = reqwest::Client::new().post(tts_server_url);
-    if let Some(api_key) = &tts_server.api_key
-        && !api_key.is_empty()
-    {
-        ds_request = ds_request.header(AUTHORIZATION, api_key);
-    }
-    for (name, value) in req.headers().iter() {
-        ds_request = ds_request.header(name, value);
-    }
-
-    let body = req.into_body();
-    let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
-        let err_msg = format!("Failed to convert the request body into bytes: {e}");
-        dual_error!("{err_msg} - request_id: {request_id}");
-        ServerError::Operation(err_msg)
-    })?;
+    // convert the request body into bytes
+    let (parts, body) = req.into_parts();
+    let body_bytes = read_request_body(&state, body, &request_id).await?;
+
+    // Forward the request, failing over to the next translate server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::translate,
+            |translation_server| {
+                let translation_server_url = format!(
+                    "{}/audio/translations",
+                    translation_server.url.trim_end_matches('/')
+                );
+                dual_info!(
+                    "Forward the audio translation request to {} - request_id: {}",
+                    translation_server_url,
+                    request_id
+                );
 
-    ds_request = ds_request.body(body_bytes);
+                // Create request client
+                let mut ds_request = reqwest::Client::new().post(translation_server_url);
+                if let Some(api_key) = &translation_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request = ds_request.header(AUTHORIZATION, api_key);
+                }
+                for (name, value) in parts.headers.iter() {
+                    ds_request = ds_request.header(name, value);
+                }
 
-    // Use select! to handle request cancellation
-    let ds_response = select! {
-        response = ds_request.send() => {
-            response.map_err(|e| {
-                let err_msg = format!(
-                    "Failed to forward the request to the downstream server: {e}"
-                );
-                dual_error!("{err_msg} - request_id: {request_id}");
-                ServerError::Operation(err_msg)
-            })?
-        }
-        _ = cancel_token.cancelled() => {
-            let warn_msg = "Request was cancelled by client";
-            dual_warn!("{} - request_id: {}", warn_msg, request_id);
-            return Err(ServerError::Operation(warn_msg.to_string()));
-        }
-    };
+                ds_request.body(body_bytes.clone())
+            },
+            &cancel_token,
+            &request_id,
+        )
+        .await?;
 
-    // create a response builder with the status and headers of the downstream response
-    let mut response_builder = Response::builder().status(ds_response.status());
-    for (name, value) in ds_response.headers().iter() {
-        response_builder = response_builder.header(name, value);
-    }
+    let status = ds_response.status();
 
     // Handle response body reading with cancellation
     let bytes = select! {
@@ -720,10 +1327,14 @@ pub(crate) async fn audio_tts_handler(
         }
     };
 
-    match response_builder.body(Body::from(bytes)) {
+    match Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(bytes))
+    {
         Ok(response) => {
             dual_info!(
-                "Audio speech request completed successfully - request_id: {}",
+                "Audio translation request completed successfully - request_id: {}",
                 request_id
             );
             Ok(response)
@@ -736,91 +1347,51 @@ pub(crate) async fn audio_tts_handler(
     }
 }
 
-pub(crate) async fn image_handler(
+pub(crate) async fn audio_tts_handler(
     State(state): State<Arc<AppState>>,
     Extension(cancel_token): Extension<CancellationToken>,
+    RequestId(request_id): RequestId,
     req: axum::extract::Request<Body>,
 ) -> ServerResult<axum::response::Response> {
-    // Get request ID from headers
-    let request_id = req
-        .headers()
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
-    dual_info!("Received a new image request - request_id: {}", request_id);
-
-    // get the image server
-    let image_server = {
-        let servers = state.server_group.read().await;
-        let image_servers = match servers.get(&ServerKind::image) {
-            Some(servers) => servers,
-            None => {
-                let err_msg = "No image server available";
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
-            }
-        };
-
-        match image_servers.next().await {
-            Ok(target_server_info) => target_server_info,
-            Err(e) => {
-                let err_msg = format!("Failed to get the image server: {e}");
-                dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg));
-            }
-        }
-    };
-
-    let image_server_url = format!(
-        "{}/images/generations",
-        image_server.url.trim_end_matches('/')
-    );
     dual_info!(
-        "Forward the image request to {} - request_id: {}",
-        image_server_url,
+        "Received a new audio speech request - request_id: {}",
         request_id
     );
 
-    // Create request client
-    let mut ds_request = reqwest::Client::new().post(image_server_url);
-    if let Some(api_key) = &image_server.api_key
-        && !api_key.is_empty()
-    {
-        ds_request = ds_request.header(AUTHORIZATION, api_key);
-    }
-    for (name, value) in req.headers().iter() {
-        ds_request = ds_request.header(name, value);
-    }
-
     // convert the request body into bytes
-    let body = req.into_body();
-    let body_bytes = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
-        let err_msg = format!("Failed to convert the request body into bytes: {e}");
-        dual_error!("{err_msg} - request_id: {request_id}");
-        ServerError::Operation(err_msg)
-    })?;
+    let (parts, body) = req.into_parts();
+    let body_bytes = read_request_body(&state, body, &request_id).await?;
+
+    // Forward the request, failing over to the next tts server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::tts,
+            |tts_server| {
+                let tts_server_url =
+                    format!("{}/audio/speech", tts_server.url.trim_end_matches('/'));
+                dual_info!(
+                    "Forward the audio speech request to {} - request_id: {}",
+                    tts_server_url,
+                    request_id
+                );
 
-    ds_request = ds_request.body(body_bytes);
+                // Create request client
+                let mut ds_request = reqwest::Client::new().post(tts_server_url);
+                if let Some(api_key) = &tts_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request = ds_request.header(AUTHORIZATION, api_key);
+                }
+                for (name, value) in parts.headers.iter() {
+                    ds_request = ds_request.header(name, value);
+                }
 
-    // Use select! to handle request cancellation
-    let ds_response = select! {
-        response = ds_request.send() => {
-            response.map_err(|e| {
-                let err_msg = format!(
-                    "Failed to forward the request to the downstream server: {e}"
-                );
-                dual_error!("{err_msg} - request_id: {request_id}");
-                ServerError::Operation(err_msg)
-            })?
-        }
-        _ = cancel_token.cancelled() => {
-            let warn_msg = "Request was cancelled by client";
-            dual_warn!("{} - request_id: {}", warn_msg, request_id);
-            return Err(ServerError::Operation(warn_msg.to_string()));
-        }
-    };
+                ds_request.body(body_bytes.clone())
+            },
+            &cancel_token,
+            &request_id,
+        )
+        .await?;
 
     // create a response builder with the status and headers of the downstream response
     let mut response_builder = Response::builder().status(ds_response.status());
@@ -828,12 +1399,125 @@ pub(crate) async fn image_handler(
         response_builder = response_builder.header(name, value);
     }
 
-    // Handle response body reading with cancellation
-    let bytes = select! {
-        bytes = ds_response.bytes() => {
-            bytes.map_err(|e| {
-                let err_msg = format!("Failed to get the full response as bytes: {e}");
-                dual_error!("{err_msg} - request_id: {request_id}");
+    // Stream the audio to the client as it arrives, until the request is cancelled
+    let audio = ds_response
+        .bytes_stream()
+        .take_until(cancel_token.cancelled_owned());
+
+    match response_builder.body(Body::from_stream(audio)) {
+        Ok(response) => {
+            dual_info!(
+                "Audio speech request completed successfully - request_id: {}",
+                request_id
+            );
+            Ok(response)
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to create the response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            Err(ServerError::Operation(err_msg))
+        }
+    }
+}
+
+pub(crate) async fn image_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    RequestId(request_id): RequestId,
+    req: axum::extract::Request<Body>,
+) -> ServerResult<axum::response::Response> {
+    forward_image_request(state, cancel_token, request_id, req, "generations").await
+}
+
+pub(crate) async fn image_edits_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    RequestId(request_id): RequestId,
+    req: axum::extract::Request<Body>,
+) -> ServerResult<axum::response::Response> {
+    forward_image_request(state, cancel_token, request_id, req, "edits").await
+}
+
+pub(crate) async fn image_variations_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    RequestId(request_id): RequestId,
+    req: axum::extract::Request<Body>,
+) -> ServerResult<axum::response::Response> {
+    forward_image_request(state, cancel_token, request_id, req, "variations").await
+}
+
+// forward the raw request, including multipart bodies, to `/images/{operation}` of an image server
+async fn forward_image_request(
+    state: Arc<AppState>,
+    cancel_token: CancellationToken,
+    request_id: String,
+    req: axum::extract::Request<Body>,
+    operation: &str,
+) -> ServerResult<axum::response::Response> {
+    dual_info!(
+        "Received a new image {} request - request_id: {}",
+        operation,
+        request_id
+    );
+
+    // convert the request body into bytes
+    let (parts, body) = req.into_parts();
+    let body_bytes = read_request_body(&state, body, &request_id).await?;
+
+    // the user id can only be read from JSON bodies; multipart requests share the anonymous bucket
+    let user = serde_json::from_slice::<serde_json::Value>(&body_bytes)
+        .ok()
+        .and_then(|body| body.get("user")?.as_str().map(str::to_string));
+    access_log::record_user(user.as_deref());
+    state.check_rate_limit(user.as_deref(), &request_id)?;
+
+    // Forward the request, failing over to the next image server if one is unreachable
+    let (_, ds_response) = state
+        .send_with_failover(
+            ServerKind::image,
+            |image_server| {
+                let image_server_url = format!(
+                    "{}/images/{}",
+                    image_server.url.trim_end_matches('/'),
+                    operation
+                );
+                dual_info!(
+                    "Forward the image request to {} - request_id: {}",
+                    image_server_url,
+                    request_id
+                );
+
+                // Create request client
+                let mut ds_request = reqwest::Client::new().post(image_server_url);
+                if let Some(api_key) = &image_server.api_key
+                    && !api_key.is_empty()
+                {
+                    ds_request = ds_request.header(AUTHORIZATION, api_key);
+                }
+                for (name, value) in parts.headers.iter() {
+                    ds_request = ds_request.header(name, value);
+                }
+
+                ds_request.body(body_bytes.clone())
+            },
+            &cancel_token,
+            &request_id,
+        )
+        .await?;
+
+    // create a response builder with the status and headers of the downstream response
+    let mut response_builder = Response::builder().status(ds_response.status());
+    for (name, value) in ds_response.headers().iter() {
+        response_builder = response_builder.header(name, value);
+    }
+
+    // Handle response body reading with cancellation
+    let bytes = select! {
+        bytes = ds_response.bytes() => {
+            bytes.map_err(|e| {
+                let err_msg = format!("Failed to get the full response as bytes: {e}");
+                dual_error!("{err_msg} - request_id: {request_id}");
                 ServerError::Operation(err_msg)
             })?
         }
@@ -847,7 +1531,8 @@ pub(crate) async fn image_handler(
     match response_builder.body(Body::from(bytes)) {
         Ok(response) => {
             dual_info!(
-                "Image request completed successfully - request_id: {}",
+                "Image {} request completed successfully - request_id: {}",
+                operation,
                 request_id
             );
             Ok(response)
@@ -862,18 +1547,32 @@ pub(crate) async fn image_handler(
 
 pub(crate) async fn models_handler(
     State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
+    RequestId(request_id): RequestId,
 ) -> ServerResult<axum::response::Response> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
     let models = state.models.read().await;
+    let mut data: Vec<Model> = models.values().flatten().cloned().collect();
+
+    // list the aliases of the listed models, as copies of their models
+    let config = state.config.read().await;
+    if config.server.advertise_model_aliases
+        && let Some(model_aliases) = &config.model_aliases
+    {
+        let mut aliases = Vec::new();
+        for (alias, target) in model_aliases {
+            if let Some(model) = data.iter().find(|model| &model.id == target) {
+                aliases.push(Model {
+                    id: alias.clone(),
+                    ..model.clone()
+                });
+            }
+        }
+        aliases.sort_by(|a, b| a.id.cmp(&b.id));
+        data.extend(aliases);
+    }
+
     let list_response = ListModelsResponse {
         object: String::from("list"),
-        data: models.values().flatten().cloned().collect(),
+        data,
     };
 
     let json_body = serde_json::to_string(&list_response).map_err(|e| {
@@ -893,16 +1592,95 @@ pub(crate) async fn models_handler(
         })
 }
 
-pub(crate) async fn info_handler(
+pub(crate) async fn metrics_handler(
     State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
+    RequestId(request_id): RequestId,
 ) -> ServerResult<axum::response::Response> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
+    let (status, content_type, body) = match state.render_metrics().await {
+        Some(text) => (StatusCode::OK, "text/plain; version=0.0.4", text),
+        None => {
+            dual_warn!("Metrics are not enabled - request_id: {}", request_id);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "application/json",
+                serde_json::json!({
+                    "error": "Metrics are not enabled"
+                })
+                .to_string(),
+            )
+        }
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
+
+/// Readiness probe: 200 once every required kind of server has a healthy server registered,
+/// 503 otherwise
+pub(crate) async fn ready_handler(
+    State(state): State<Arc<AppState>>,
+    RequestId(request_id): RequestId,
+) -> ServerResult<axum::response::Response> {
+    let required_kinds = state
+        .config
+        .read()
+        .await
+        .readiness
+        .clone()
+        .unwrap_or_default()
+        .required_kinds;
+
+    let mut missing = vec![];
+    {
+        let groups = state.server_group.read().await;
+        for kind in required_kinds {
+            let ready = match groups.get(&kind) {
+                Some(group) => group.has_healthy_server().await,
+                None => false,
+            };
+            if !ready {
+                missing.push(kind.to_string());
+            }
+        }
+    }
+
+    let (status, json_body) = match missing.is_empty() {
+        true => (StatusCode::OK, serde_json::json!({ "status": "ready" })),
+        false => {
+            dual_warn!(
+                "Not ready, no healthy server of kind: {} - request_id: {}",
+                missing.join(", "),
+                request_id
+            );
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({ "status": "not ready", "missing": missing }),
+            )
+        }
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json_body.to_string()))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
 
+pub(crate) async fn info_handler(
+    State(state): State<Arc<AppState>>,
+    RequestId(request_id): RequestId,
+) -> ServerResult<axum::response::Response> {
     let mut chat_models = vec![];
     let mut embedding_models = vec![];
     let mut image_models = vec![];
@@ -956,15 +1734,9 @@ pub(crate) async fn info_handler(
 /// Handler to get chat history by conversation ID
 pub(crate) async fn get_conversation_history_handler(
     State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
+    RequestId(request_id): RequestId,
     axum::extract::Path(conv_id): axum::extract::Path<String>,
 ) -> ServerResult<axum::response::Response> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
     dual_info!(
         "Getting conversation history for conv_id: {} - request_id: {}",
         conv_id,
@@ -1003,20 +1775,9 @@ pub(crate) async fn get_conversation_history_handler(
                     e,
                     request_id
                 );
-                Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .header(CONTENT_TYPE, "application/json")
-                    .body(Body::from(
-                        serde_json::json!({
-                            "error": format!("Conversation not found: {}", e)
-                        })
-                        .to_string(),
-                    ))
-                    .map_err(|e| {
-                        let err_msg = format!("Failed to create error response: {e}");
-                        dual_error!("{err_msg} - request_id: {request_id}");
-                        ServerError::Operation(err_msg)
-                    })
+                Err(ServerError::NotFound(format!(
+                    "Conversation not found: {e}"
+                )))
             }
         }
     } else {
@@ -1038,18 +1799,130 @@ pub(crate) async fn get_conversation_history_handler(
     }
 }
 
+/// Handler to export conversation history as OpenAI chat messages
+///
+/// Supports `format=jsonl` (default), one message per line, and `format=json`, an array of messages.
+pub(crate) async fn export_conversation_handler(
+    State(state): State<Arc<AppState>>,
+    RequestId(request_id): RequestId,
+    axum::extract::Path(conv_id): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> ServerResult<axum::response::Response> {
+    let format = params.get("format").map(String::as_str).unwrap_or("jsonl");
+
+    dual_info!(
+        "Exporting conversation {} as {} - request_id: {}",
+        conv_id,
+        format,
+        request_id
+    );
+
+    let memory = match &state.memory {
+        Some(memory) => memory,
+        None => {
+            dual_warn!("Memory system is not enabled - request_id: {}", request_id);
+            return json_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Memory system is not enabled".to_string(),
+                &request_id,
+            );
+        }
+    };
+
+    if format != "json" && format != "jsonl" {
+        let err_msg = format!("Unsupported export format: {format}. Use `json` or `jsonl`.");
+        dual_warn!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::BadRequest(err_msg));
+    }
+
+    let messages = match memory.export_conversation(&conv_id).await {
+        Ok(messages) => messages,
+        Err(MemoryError::ConversationNotFound(_)) => {
+            dual_warn!(
+                "Conversation {} not found - request_id: {}",
+                conv_id,
+                request_id
+            );
+            return Err(ServerError::NotFound(format!(
+                "Conversation not found: {conv_id}"
+            )));
+        }
+        Err(e) => {
+            dual_error!(
+                "Failed to export conversation {}: {} - request_id: {}",
+                conv_id,
+                e,
+                request_id
+            );
+            return json_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to export conversation: {e}"),
+                &request_id,
+            );
+        }
+    };
+
+    dual_info!(
+        "Exported {} messages for conversation {} - request_id: {}",
+        messages.len(),
+        conv_id,
+        request_id
+    );
+
+    let messages: Vec<ChatCompletionRequestMessage> =
+        messages.into_iter().map(Into::into).collect();
+
+    let response = match format {
+        "json" => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!(messages).to_string())),
+        _ => {
+            // stream one message per line
+            let lines = messages
+                .into_iter()
+                .map(|message| serde_json::to_string(&message).map(|line| format!("{line}\n")));
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "application/x-ndjson")
+                .body(Body::from_stream(futures_util::stream::iter(lines)))
+        }
+    };
+
+    response.map_err(|e| {
+        let err_msg = format!("Failed to create response: {e}");
+        dual_error!("{err_msg} - request_id: {request_id}");
+        ServerError::Operation(err_msg)
+    })
+}
+
+fn json_error_response(
+    status: StatusCode,
+    err_msg: String,
+    request_id: &str,
+) -> ServerResult<axum::response::Response> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({
+                "error": err_msg
+            })
+            .to_string(),
+        ))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create error response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
+
 /// Handler to get chat history by user ID
 pub(crate) async fn get_user_history_handler(
     State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
+    RequestId(request_id): RequestId,
     axum::extract::Path(user_id): axum::extract::Path<String>,
 ) -> ServerResult<axum::response::Response> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
     dual_info!(
         "Getting user history for user_id: {} - request_id: {}",
         user_id,
@@ -1126,16 +1999,10 @@ pub(crate) async fn get_user_history_handler(
 /// Handler to list conversations for a specific user
 pub(crate) async fn list_user_conversations_handler(
     State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
+    RequestId(request_id): RequestId,
     axum::extract::Path(user_id): axum::extract::Path<String>,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
 ) -> ServerResult<axum::response::Response> {
-    let request_id = headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
-
     dual_info!(
         "Listing conversations for user_id: {} - request_id: {}",
         user_id,
@@ -1213,49 +2080,189 @@ pub(crate) async fn list_user_conversations_handler(
     }
 }
 
-// update the model list
-pub(crate) async fn update_model_list(
+/// Handler to delete a conversation with its messages and summary from memory
+pub(crate) async fn delete_conversation_handler(
     State(state): State<Arc<AppState>>,
-    headers: &HeaderMap,
-    request_id: impl AsRef<str>,
-    server: &Server,
-) -> ServerResult<()> {
-    let request_id = request_id.as_ref();
-    let server_url = &server.url;
-    let server_id = &server.id;
+    RequestId(request_id): RequestId,
+    axum::extract::Path(conv_id): axum::extract::Path<String>,
+) -> ServerResult<axum::response::Response> {
+    dual_info!(
+        "Deleting conversation: {} - request_id: {}",
+        conv_id,
+        request_id
+    );
 
-    // get the models from the downstream server
-    let list_models_url = format!("{server_url}/models");
-    dual_debug!("list_models_url: {}", list_models_url);
-    let response = if let Some(api_key) = &server.api_key
-        && !api_key.is_empty()
-    {
-        let auth_info = if api_key.starts_with("Bearer ") {
-            api_key.clone()
-        } else {
-            format!("Bearer {api_key}")
-        };
+    let (status, body) = match &state.memory {
+        Some(memory) => match memory.delete_conversation(&conv_id).await {
+            Ok(()) => {
+                dual_info!(
+                    "Deleted conversation {} - request_id: {}",
+                    conv_id,
+                    request_id
+                );
+                (
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "conversation_id": conv_id,
+                        "deleted": true
+                    }),
+                )
+            }
+            Err(MemoryError::ConversationNotFound(_)) => {
+                dual_warn!(
+                    "Conversation {} not found - request_id: {}",
+                    conv_id,
+                    request_id
+                );
+                return Err(ServerError::NotFound(format!(
+                    "Conversation not found: {conv_id}"
+                )));
+            }
+            Err(e) => {
+                dual_error!(
+                    "Failed to delete conversation {}: {} - request_id: {}",
+                    conv_id,
+                    e,
+                    request_id
+                );
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    serde_json::json!({
+                        "error": format!("Failed to delete conversation: {}", e)
+                    }),
+                )
+            }
+        },
+        None => {
+            dual_warn!("Memory system is not enabled - request_id: {}", request_id);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({
+                    "error": "Memory system is not enabled"
+                }),
+            )
+        }
+    };
 
-        reqwest::Client::new()
-            .get(&list_models_url)
-            .header(CONTENT_TYPE, "application/json")
-            .header(AUTHORIZATION, auth_info)
-            .send()
-            .await
-            .map_err(|e| {
-                let err_msg = format!("Failed to get the models from the downstream server: {e}");
-                dual_error!("{err_msg} - request_id: {request_id}");
-                ServerError::Operation(err_msg)
-            })?
-    } else if headers.contains_key("authorization") {
-        let authorization = headers
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
+
+pub(crate) async fn delete_user_conversations_handler(
+    State(state): State<Arc<AppState>>,
+    RequestId(request_id): RequestId,
+    axum::extract::Path(user_id): axum::extract::Path<String>,
+) -> ServerResult<axum::response::Response> {
+    dual_info!(
+        "Deleting all conversations of user: {} - request_id: {}",
+        user_id,
+        request_id
+    );
+
+    let (status, body) = match &state.memory {
+        Some(memory) => match memory.delete_user_conversations(&user_id).await {
+            Ok(count) => {
+                dual_info!(
+                    "Deleted {} conversations of user {} - request_id: {}",
+                    count,
+                    user_id,
+                    request_id
+                );
+                (
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "user_id": user_id,
+                        "deleted": count
+                    }),
+                )
+            }
+            Err(e) => {
+                dual_error!(
+                    "Failed to delete conversations of user {}: {} - request_id: {}",
+                    user_id,
+                    e,
+                    request_id
+                );
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    serde_json::json!({
+                        "error": format!("Failed to delete user conversations: {}", e)
+                    }),
+                )
+            }
+        },
+        None => {
+            dual_warn!("Memory system is not enabled - request_id: {}", request_id);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({
+                    "error": "Memory system is not enabled"
+                }),
+            )
+        }
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
+
+// update the model list
+pub(crate) async fn update_model_list(
+    State(state): State<Arc<AppState>>,
+    headers: &HeaderMap,
+    request_id: impl AsRef<str>,
+    server: &Server,
+) -> ServerResult<()> {
+    let request_id = request_id.as_ref();
+    let server_url = &server.url;
+    let server_id = &server.id;
+
+    // get the models from the downstream server
+    let list_models_url = format!("{server_url}/models");
+    dual_debug!("list_models_url: {}", list_models_url);
+    let response = if let Some(api_key) = &server.api_key
+        && !api_key.is_empty()
+    {
+        let auth_info = if api_key.starts_with("Bearer ") {
+            api_key.clone()
+        } else {
+            format!("Bearer {api_key}")
+        };
+
+        server
+            .add_extra_headers(reqwest::Client::new().get(&list_models_url))
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, auth_info)
+            .send()
+            .await
+            .map_err(|e| {
+                let err_msg = format!("Failed to get the models from the downstream server: {e}");
+                dual_error!("{err_msg} - request_id: {request_id}");
+                ServerError::Operation(err_msg)
+            })?
+    } else if headers.contains_key("authorization") {
+        let authorization = headers
             .get("authorization")
             .unwrap()
             .to_str()
             .unwrap()
             .to_string();
-        reqwest::Client::new()
-            .get(&list_models_url)
+        server
+            .add_extra_headers(reqwest::Client::new().get(&list_models_url))
             .header(CONTENT_TYPE, "application/json")
             .header(AUTHORIZATION, authorization)
             .send()
@@ -1266,8 +2273,8 @@ pub(crate) async fn update_model_list(
                 ServerError::Operation(err_msg)
             })?
     } else {
-        reqwest::Client::new()
-            .get(&list_models_url)
+        server
+            .add_extra_headers(reqwest::Client::new().get(&list_models_url))
             .send()
             .await
             .map_err(|e| {
@@ -1340,37 +2347,73 @@ pub(crate) async fn update_model_list(
     Ok(())
 }
 
+/// Handler to get the token usage of a user, optionally between the `start` and `end` unix
+/// timestamps given as query parameters
+pub(crate) async fn user_usage_handler(
+    State(state): State<Arc<AppState>>,
+    RequestId(request_id): RequestId,
+    axum::extract::Path(user_id): axum::extract::Path<String>,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> ServerResult<axum::response::Response> {
+    let mut range = [None, None];
+    for (bound, name) in range.iter_mut().zip(["start", "end"]) {
+        if let Some(value) = params.get(name) {
+            match value.parse::<i64>() {
+                Ok(timestamp) => *bound = Some(timestamp),
+                Err(_) => {
+                    let err_msg = format!("Invalid `{name}` timestamp: {value}");
+                    dual_warn!("{} - request_id: {}", err_msg, request_id);
+                    return Err(ServerError::BadRequest(err_msg));
+                }
+            }
+        }
+    }
+    let [start, end] = range;
+
+    dual_info!(
+        "Getting the token usage of user: {} - request_id: {}",
+        user_id,
+        request_id
+    );
+
+    let totals = state.usage.totals(&user_id, start, end);
+    let json_body = serde_json::json!({
+        "user_id": user_id,
+        "start": start,
+        "end": end,
+        "usage": totals,
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json_body.to_string()))
+        .map_err(|e| {
+            let err_msg = format!("Failed to create response: {e}");
+            dual_error!("{err_msg} - request_id: {request_id}");
+            ServerError::Operation(err_msg)
+        })
+}
+
 pub(crate) mod admin {
     use super::*;
 
     pub(crate) async fn register_downstream_server_handler(
         State(state): State<Arc<AppState>>,
         headers: HeaderMap,
+        RequestId(request_id): RequestId,
         Json(mut server): Json<Server>,
     ) -> ServerResult<axum::response::Response> {
-        // Get request ID from headers
-        let request_id = headers
-            .get("x-request-id")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("unknown")
-            .to_string();
-
         let server_url = server.url.clone();
         let server_kind = server.kind;
         let server_id = server.id.clone();
 
-        // verify the server
-        if server_kind.contains(ServerKind::chat)
-            || server_kind.contains(ServerKind::embeddings)
-            || server_kind.contains(ServerKind::image)
-            || server_kind.contains(ServerKind::transcribe)
-            || server_kind.contains(ServerKind::translate)
-            || server_kind.contains(ServerKind::tts)
-        {
-            dual_warn!(
+        // verify the server kind against the models reported by the server
+        match state.config.read().await.server.verify_server_kind {
+            true => verify_server(State(state.clone()), &headers, &request_id, &server).await?,
+            false => dual_warn!(
                 "Ignore the server verification for: {server_id} - request_id: {request_id}"
-            );
-            // _verify_server(State(state.clone()), &headers, &request_id, &server).await?;
+            ),
         }
 
         // update the model list
@@ -1381,7 +2424,11 @@ pub(crate) mod admin {
         server.health_status.last_check = SystemTime::now();
 
         // register the server
+        let saved_server = server.clone();
         state.register_downstream_server(server).await?;
+        state
+            .save_to_registry(|registry| registry.add(&saved_server))
+            .await;
         dual_info!(
             "Registered successfully. Assigned Server Id: {} - request_id: {}",
             server_id,
@@ -1409,7 +2456,7 @@ pub(crate) mod admin {
     }
 
     // verify the server and get the server info and model list
-    async fn _verify_server(
+    async fn verify_server(
         State(state): State<Arc<AppState>>,
         headers: &HeaderMap,
         request_id: impl AsRef<str>,
@@ -1426,8 +2473,8 @@ pub(crate) mod admin {
         let response = if let Some(api_key) = &server.api_key
             && !api_key.is_empty()
         {
-            client
-                .get(&server_info_url)
+            server
+                .add_extra_headers(client.get(&server_info_url))
                 .header(CONTENT_TYPE, "application/json")
                 .header(AUTHORIZATION, api_key)
                 .send()
@@ -1446,8 +2493,8 @@ pub(crate) mod admin {
                 .unwrap()
                 .to_string();
 
-            client
-                .get(&server_info_url)
+            server
+                .add_extra_headers(client.get(&server_info_url))
                 .header(CONTENT_TYPE, "application/json")
                 .header(AUTHORIZATION, authorization)
                 .send()
@@ -1459,11 +2506,16 @@ pub(crate) mod admin {
                     ServerError::Operation(err_msg)
                 })?
         } else {
-            client.get(&server_info_url).send().await.map_err(|e| {
-                let err_msg = format!("Failed to verify the {server_kind} downstream server: {e}",);
-                dual_error!("{err_msg} - request_id: {request_id}");
-                ServerError::Operation(err_msg)
-            })?
+            server
+                .add_extra_headers(client.get(&server_info_url))
+                .send()
+                .await
+                .map_err(|e| {
+                    let err_msg =
+                        format!("Failed to verify the {server_kind} downstream server: {e}",);
+                    dual_error!("{err_msg} - request_id: {request_id}");
+                    ServerError::Operation(err_msg)
+                })?
         };
         if !response.status().is_success() {
             let err_msg = format!(
@@ -1490,34 +2542,34 @@ pub(crate) mod admin {
             if server_kind.contains(ServerKind::chat) && api_server.chat_model.is_none() {
                 let err_msg = "You are trying to register a chat server. However, the server does not support `chat`. Please check the server kind.";
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+                return Err(ServerError::InvalidServerKind(err_msg.to_string()));
             }
             if server_kind.contains(ServerKind::embeddings) && api_server.embedding_model.is_none()
             {
                 let err_msg = "You are trying to register an embedding server. However, the server does not support `embeddings`. Please check the server kind.";
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+                return Err(ServerError::InvalidServerKind(err_msg.to_string()));
             }
             if server_kind.contains(ServerKind::image) && api_server.image_model.is_none() {
                 let err_msg = "You are trying to register an image server. However, the server does not support `image`. Please check the server kind.";
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+                return Err(ServerError::InvalidServerKind(err_msg.to_string()));
             }
             if server_kind.contains(ServerKind::tts) && api_server.tts_model.is_none() {
                 let err_msg = "You are trying to register a TTS server. However, the server does not support `tts`. Please check the server kind.";
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+                return Err(ServerError::InvalidServerKind(err_msg.to_string()));
             }
             if server_kind.contains(ServerKind::translate) && api_server.translate_model.is_none() {
                 let err_msg = "You are trying to register a translation server. However, the server does not support `translate`. Please check the server kind.";
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+                return Err(ServerError::InvalidServerKind(err_msg.to_string()));
             }
             if server_kind.contains(ServerKind::transcribe) && api_server.transcribe_model.is_none()
             {
                 let err_msg = "You are trying to register a transcription server. However, the server does not support `transcribe`. Please check the server kind.";
                 dual_error!("{} - request_id: {}", err_msg, request_id);
-                return Err(ServerError::Operation(err_msg.to_string()));
+                return Err(ServerError::InvalidServerKind(err_msg.to_string()));
             }
         }
 
@@ -1532,19 +2584,15 @@ pub(crate) mod admin {
 
     pub(crate) async fn remove_downstream_server_handler(
         State(state): State<Arc<AppState>>,
-        headers: HeaderMap,
+        RequestId(request_id): RequestId,
         Json(server_id): Json<ServerIdToRemove>,
     ) -> ServerResult<axum::response::Response> {
-        // Get request ID from headers
-        let request_id = headers
-            .get("x-request-id")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("unknown")
-            .to_string();
-
         state
             .unregister_downstream_server(&server_id.server_id)
             .await?;
+        state
+            .save_to_registry(|registry| registry.remove(&server_id.server_id))
+            .await;
 
         // create a response with status code 200. Content-Type is JSON
         let json_body = serde_json::json!({
@@ -1565,17 +2613,29 @@ pub(crate) mod admin {
         Ok(response)
     }
 
-    pub(crate) async fn list_downstream_servers_handler(
+    /// Report the in-flight and served requests and the registered and healthy servers, as a
+    /// cheap JSON snapshot for autoscaling
+    pub(crate) async fn stats_handler(
         State(state): State<Arc<AppState>>,
-        headers: HeaderMap,
+        RequestId(request_id): RequestId,
     ) -> ServerResult<axum::response::Response> {
-        // Get request ID from headers
-        let request_id = headers
-            .get("x-request-id")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("unknown")
-            .to_string();
+        let json_body = state.stats().await;
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {e}");
+                dual_error!("{err_msg} - request_id: {request_id}");
+                ServerError::Operation(err_msg)
+            })
+    }
 
+    pub(crate) async fn list_downstream_servers_handler(
+        State(state): State<Arc<AppState>>,
+        RequestId(request_id): RequestId,
+    ) -> ServerResult<axum::response::Response> {
         let servers = state.list_downstream_servers().await?;
 
         // compute the total number of servers
@@ -1600,4 +2660,2057 @@ pub(crate) mod admin {
 
         Ok(response)
     }
+
+    pub(crate) async fn update_downstream_server_handler(
+        State(state): State<Arc<AppState>>,
+        RequestId(request_id): RequestId,
+        axum::extract::Path(server_id): axum::extract::Path<String>,
+        Json(update): Json<ServerStatusUpdate>,
+    ) -> ServerResult<axum::response::Response> {
+        let (status, json_body) = match state
+            .set_downstream_server_enabled(&server_id, update.enable)
+            .await
+        {
+            true => {
+                state
+                    .save_to_registry(|registry| registry.set_enabled(&server_id, update.enable))
+                    .await;
+                dual_info!(
+                    "Downstream server {} {} - request_id: {}",
+                    server_id,
+                    if update.enable { "enabled" } else { "disabled" },
+                    request_id
+                );
+                (
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "id": server_id,
+                        "enabled": update.enable,
+                    }),
+                )
+            }
+            false => {
+                dual_warn!(
+                    "Downstream server {} not found - request_id: {}",
+                    server_id,
+                    request_id
+                );
+                return Err(ServerError::NotFound(format!(
+                    "Server not found: {server_id}"
+                )));
+            }
+        };
+
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {e}");
+                dual_error!("{err_msg} - request_id: {request_id}");
+                ServerError::Operation(err_msg)
+            })
+    }
+
+    pub(crate) async fn get_downstream_server_handler(
+        State(state): State<Arc<AppState>>,
+        RequestId(request_id): RequestId,
+        axum::extract::Path(server_id): axum::extract::Path<String>,
+    ) -> ServerResult<axum::response::Response> {
+        let (status, json_body) = match state.get_downstream_server(&server_id).await {
+            Some(server) => {
+                dual_info!(
+                    "Found downstream server {} - request_id: {}",
+                    server_id,
+                    request_id
+                );
+
+                let last_check = server
+                    .health_status
+                    .last_check
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                let models = state
+                    .models
+                    .read()
+                    .await
+                    .get(&server_id)
+                    .cloned()
+                    .unwrap_or_default();
+                let info = state
+                    .server_info
+                    .read()
+                    .await
+                    .servers
+                    .get(&server_id)
+                    .cloned();
+
+                let mut json_body = serde_json::to_value(&server).unwrap();
+                json_body["health_status"] = serde_json::json!({
+                    "is_healthy": server.health_status.is_healthy,
+                    "last_check": last_check,
+                });
+                json_body["models"] = serde_json::json!(models);
+                if let Some(info) = info {
+                    json_body["info"] = serde_json::json!(info);
+                }
+
+                (StatusCode::OK, json_body)
+            }
+            None => {
+                dual_warn!(
+                    "Downstream server {} not found - request_id: {}",
+                    server_id,
+                    request_id
+                );
+                return Err(ServerError::NotFound(format!(
+                    "Server not found: {server_id}"
+                )));
+            }
+        };
+
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_body.to_string()))
+            .map_err(|e| {
+                let err_msg = format!("Failed to create response: {e}");
+                dual_error!("{err_msg} - request_id: {request_id}");
+                ServerError::Operation(err_msg)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, MemoryConfig},
+        info::ServerInfo,
+        memory::CompleteChatMemory,
+    };
+
+    #[tokio::test]
+    async fn test_delete_conversation() {
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = Arc::new(
+            AppState::new(Config::default(), ServerInfo::default()).with_memory(Arc::new(memory)),
+        );
+
+        let memory = state.memory.as_ref().unwrap();
+        let conv_id = memory
+            .create_conversation("test-model", None, None)
+            .await
+            .unwrap();
+        memory
+            .add_user_message(&conv_id, "Hello".to_string())
+            .await
+            .unwrap();
+        memory
+            .add_assistant_message(&conv_id, "Hi there!", vec![])
+            .await
+            .unwrap();
+
+        let path = || axum::extract::Path(conv_id.clone());
+        let response =
+            get_conversation_history_handler(State(state.clone()), RequestId::new(), path())
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = delete_conversation_handler(State(state.clone()), RequestId::new(), path())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["conversation_id"], conv_id.as_str());
+
+        let response =
+            get_conversation_history_handler(State(state.clone()), RequestId::new(), path())
+                .await
+                .unwrap_or_else(axum::response::IntoResponse::into_response);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(memory.get_model_context(&conv_id).await.is_err());
+
+        // deleting again reports the conversation as absent
+        let response = delete_conversation_handler(State(state.clone()), RequestId::new(), path())
+            .await
+            .unwrap_or_else(axum::response::IntoResponse::into_response);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_conversations() {
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = Arc::new(
+            AppState::new(Config::default(), ServerInfo::default()).with_memory(Arc::new(memory)),
+        );
+
+        let memory = state.memory.as_ref().unwrap();
+        let mut conv_ids = Vec::new();
+        for _ in 0..3 {
+            let conv_id = memory
+                .create_conversation("test-model", Some("user-1".to_string()), None)
+                .await
+                .unwrap();
+            memory
+                .add_user_message(&conv_id, "Hello".to_string())
+                .await
+                .unwrap();
+            conv_ids.push(conv_id);
+        }
+        let other_conv_id = memory
+            .create_conversation("test-model", Some("user-2".to_string()), None)
+            .await
+            .unwrap();
+
+        let path = |user_id: &str| axum::extract::Path(user_id.to_string());
+        let response = delete_user_conversations_handler(
+            State(state.clone()),
+            RequestId::new(),
+            path("user-1"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["deleted"], 3);
+
+        for conv_id in &conv_ids {
+            assert!(memory.get_conversation(conv_id).await.is_err());
+            assert!(memory.get_model_context(conv_id).await.is_err());
+        }
+        assert!(
+            memory
+                .list_user_conversations("user-1", None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(memory.get_conversation(&other_conv_id).await.is_ok());
+
+        // a user without conversations is not an error
+        let response = delete_user_conversations_handler(
+            State(state.clone()),
+            RequestId::new(),
+            path("user-1"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["deleted"], 0);
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_export_conversation_as_jsonl() {
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = Arc::new(
+            AppState::new(Config::default(), ServerInfo::default()).with_memory(Arc::new(memory)),
+        );
+
+        let memory = state.memory.as_ref().unwrap();
+        let conv_id = memory
+            .create_conversation("test-model", None, None)
+            .await
+            .unwrap();
+        memory
+            .set_system_message(&conv_id, "You are a helpful assistant.")
+            .await
+            .unwrap();
+        memory
+            .add_user_message(&conv_id, "What is the weather in Paris?".to_string())
+            .await
+            .unwrap();
+        memory
+            .add_assistant_message(
+                &conv_id,
+                "",
+                vec![crate::memory::StoredToolCall {
+                    id: "call-1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments: serde_json::json!({ "city": "Paris" }),
+                    result: Some(crate::memory::StoredToolResult {
+                        content: serde_json::json!("sunny"),
+                        success: true,
+                        error: None,
+                        execution_time_ms: None,
+                        timestamp: chrono::Utc::now(),
+                    }),
+                    sequence: 0,
+                }],
+            )
+            .await
+            .unwrap();
+        memory
+            .add_assistant_message(&conv_id, "It is sunny in Paris.", vec![])
+            .await
+            .unwrap();
+
+        let params = std::collections::HashMap::from([("format".to_string(), "jsonl".to_string())]);
+        let response = export_conversation_handler(
+            State(state.clone()),
+            RequestId::new(),
+            axum::extract::Path(conv_id.clone()),
+            axum::extract::Query(params),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+        // every line is a valid chat message
+        for line in body.lines() {
+            serde_json::from_str::<ChatCompletionRequestMessage>(line).unwrap();
+        }
+
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let roles: Vec<&str> = lines
+            .iter()
+            .map(|line| line["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool", "assistant"]);
+        assert_eq!(lines[1]["content"], "What is the weather in Paris?");
+        assert_eq!(lines[2]["tool_calls"][0]["id"], "call-1");
+        assert_eq!(lines[2]["tool_calls"][0]["type"], "function");
+        assert_eq!(lines[2]["tool_calls"][0]["function"]["name"], "get_weather");
+        assert_eq!(lines[3]["tool_call_id"], "call-1");
+        assert_eq!(lines[3]["content"], "sunny");
+        assert_eq!(lines[4]["content"], "It is sunny in Paris.");
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    fn create_rerank_request() -> RerankRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "test-reranker",
+            "query": "What is the capital of France?",
+            "documents": ["Berlin is in Germany.", "Paris is the capital of France.", "Cats purr."],
+            "top_n": 2,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rerank_is_forwarded_to_rerank_server() {
+        let received = Arc::new(std::sync::Mutex::new(None));
+        let router = axum::Router::new().route(
+            "/v1/rerank",
+            axum::routing::post({
+                let received = received.clone();
+                move |Json(body): Json<serde_json::Value>| async move {
+                    *received.lock().unwrap() = Some(body);
+                    Json(serde_json::json!({
+                        "model": "test-reranker",
+                        "results": [
+                            { "index": 1, "relevance_score": 0.98 },
+                            { "index": 0, "relevance_score": 0.12 }
+                        ]
+                    }))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "rerank")]).await;
+
+        let response = rerank_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(create_rerank_request()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["results"][0]["index"], 1);
+
+        let received = received.lock().unwrap().take().unwrap();
+        assert_eq!(received["query"], "What is the capital of France?");
+        assert_eq!(received["documents"].as_array().unwrap().len(), 3);
+        assert_eq!(received["top_n"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_rerank_falls_back_to_embedding_similarity() {
+        let router = axum::Router::new().route(
+            "/v1/embeddings",
+            axum::routing::post(|| async {
+                // query, then one embedding per document
+                let embeddings = [[1.0, 0.0], [0.0, 1.0], [0.9, 0.1], [-1.0, 0.0]];
+                Json(serde_json::json!({
+                    "object": "list",
+                    "data": embeddings
+                        .iter()
+                        .enumerate()
+                        .map(|(index, embedding)| serde_json::json!({
+                            "index": index,
+                            "object": "embedding",
+                            "embedding": embedding
+                        }))
+                        .collect::<Vec<_>>(),
+                    "model": "test-embedder",
+                    "usage": { "prompt_tokens": 4, "completion_tokens": 0, "total_tokens": 4 }
+                }))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "embeddings")]).await;
+
+        let response = rerank_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(create_rerank_request()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let rerank_response: RerankResponse = serde_json::from_slice(&bytes).unwrap();
+        let indices: Vec<usize> = rerank_response
+            .results
+            .iter()
+            .map(|result| result.index)
+            .collect();
+        assert_eq!(indices, [1, 0]);
+        assert_eq!(
+            rerank_response.results[0].document.text,
+            "Paris is the capital of France."
+        );
+    }
+
+    type ReceivedImageRequest = Arc<std::sync::Mutex<Option<(String, bytes::Bytes)>>>;
+
+    /// Spawn an image server that records the content type and body of the request to `path`
+    async fn spawn_image_server(path: &str, received: ReceivedImageRequest) -> String {
+        let router = axum::Router::new().route(
+            &format!("/v1/images/{path}"),
+            axum::routing::post(move |headers: HeaderMap, body: bytes::Bytes| async move {
+                let content_type = headers
+                    .get(CONTENT_TYPE)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                *received.lock().unwrap() = Some((content_type, body));
+                Json(serde_json::json!({
+                    "created": 1_700_000_000u64,
+                    "data": [{ "url": "http://localhost/image.png" }]
+                }))
+            }),
+        );
+        crate::test_utils::spawn_mock_server(router).await
+    }
+
+    fn create_multipart_request() -> axum::extract::Request<Body> {
+        let body = "--test-boundary\r\n\
+            Content-Disposition: form-data; name=\"image\"; filename=\"image.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            PNGDATA\r\n\
+            --test-boundary--\r\n";
+        axum::extract::Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=test-boundary")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_image_edits_are_forwarded() {
+        let received = ReceivedImageRequest::default();
+        let url = spawn_image_server("edits", received.clone()).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "image")]).await;
+
+        let response = image_edits_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            RequestId::new(),
+            create_multipart_request(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let (content_type, body) = received.lock().unwrap().take().unwrap();
+        assert_eq!(content_type, "multipart/form-data; boundary=test-boundary");
+        assert!(String::from_utf8_lossy(&body).contains("PNGDATA"));
+    }
+
+    #[tokio::test]
+    async fn test_request_body_above_limit_is_rejected() {
+        let received = ReceivedImageRequest::default();
+        let url = spawn_image_server("edits", received.clone()).await;
+        let mut config = Config::default();
+        config.server.max_request_body_bytes = 64;
+        let state = crate::test_utils::create_test_state(config, &[(&url, "image")]).await;
+
+        let err = image_edits_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            RequestId::new(),
+            create_multipart_request(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, ServerError::PayloadTooLarge(64)));
+        assert_eq!(
+            axum::response::IntoResponse::into_response(err).status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert!(received.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_image_variations_are_forwarded() {
+        let received = ReceivedImageRequest::default();
+        let url = spawn_image_server("variations", received.clone()).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "image")]).await;
+
+        let response = image_variations_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            RequestId::new(),
+            create_multipart_request(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["data"][0]["url"], "http://localhost/image.png");
+
+        let (content_type, body) = received.lock().unwrap().take().unwrap();
+        assert_eq!(content_type, "multipart/form-data; boundary=test-boundary");
+        assert!(String::from_utf8_lossy(&body).ends_with("--test-boundary--\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_transcription_segments_arrive_incrementally() {
+        // the transcription server sends a segment every 300ms
+        let router = axum::Router::new().route(
+            "/v1/audio/transcriptions",
+            axum::routing::post(|| async {
+                let segments = futures_util::stream::unfold(0, |i| async move {
+                    if i == 3 {
+                        return None;
+                    }
+                    if i > 0 {
+                        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                    }
+                    let event = format!("data: {{\"text\":\"segment {i}\"}}\n\n");
+                    Some((Ok::<_, std::convert::Infallible>(event), i + 1))
+                });
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/event-stream")
+                    .body(Body::from_stream(segments))
+                    .unwrap()
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "transcribe")]).await;
+
+        let response = audio_transcriptions_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            RequestId::new(),
+            create_multipart_request(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+
+        let start = std::time::Instant::now();
+        let mut body = response.into_body().into_data_stream();
+        let mut received = Vec::new();
+        while let Some(chunk) = body.next().await {
+            received.push((start.elapsed(), chunk.unwrap()));
+        }
+
+        let text: String = received
+            .iter()
+            .map(|(_, chunk)| String::from_utf8_lossy(chunk).to_string())
+            .collect();
+        assert!(text.contains("segment 0") && text.contains("segment 2"));
+
+        // the first segment is received before the server has sent the last one
+        let (first, _) = received.first().unwrap();
+        let (last, _) = received.last().unwrap();
+        assert!(*last - *first >= std::time::Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_speech_audio_is_streamed() {
+        // the tts server holds back the last chunk of the audio until it is released
+        let chunk = vec![7u8; 1024 * 1024];
+        let audio: Vec<u8> = chunk.repeat(4);
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let released = Arc::new(tokio::sync::Mutex::new(Some(released)));
+        let router = axum::Router::new().route(
+            "/v1/audio/speech",
+            axum::routing::post(move || {
+                let chunk = chunk.clone();
+                let released = released.clone();
+                async move {
+                    let released = released.lock().await.take().unwrap();
+                    let head = futures_util::stream::iter(vec![chunk.clone(); 3]);
+                    let last = futures_util::stream::once(async move {
+                        released.await.ok();
+                        chunk
+                    });
+                    let body = head.chain(last).map(Ok::<_, std::convert::Infallible>);
+                    Response::builder()
+                        .header(CONTENT_TYPE, "audio/mpeg")
+                        .header(http::header::CONTENT_LENGTH, 4 * 1024 * 1024)
+                        .body(Body::from_stream(body))
+                        .unwrap()
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state = crate::test_utils::create_test_state(Config::default(), &[(&url, "tts")]).await;
+
+        let request = axum::extract::Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"model":"tts","input":"Hello"}"#))
+            .unwrap();
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            audio_tts_handler(
+                State(state),
+                Extension(CancellationToken::new()),
+                RequestId::new(),
+                request,
+            ),
+        )
+        .await
+        .expect("the response is returned before the whole audio is received")
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "audio/mpeg");
+        assert_eq!(
+            response.headers()[http::header::CONTENT_LENGTH],
+            (4 * 1024 * 1024).to_string()
+        );
+
+        release.send(()).unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(bytes, audio);
+    }
+
+    #[tokio::test]
+    async fn test_moderation_body_and_auth_are_forwarded() {
+        let received = Arc::new(std::sync::Mutex::new(None));
+        let router = axum::Router::new().route(
+            "/v1/moderations",
+            axum::routing::post({
+                let received = received.clone();
+                move |headers: HeaderMap, Json(body): Json<serde_json::Value>| async move {
+                    let authorization = headers
+                        .get(AUTHORIZATION)
+                        .map(|value| value.to_str().unwrap().to_string());
+                    *received.lock().unwrap() = Some((authorization, body));
+                    Json(serde_json::json!({
+                        "id": "modr-test",
+                        "model": "test-moderation",
+                        "results": [{ "flagged": false }]
+                    }))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "moderation")]).await;
+
+        let request = serde_json::json!({
+            "model": "test-moderation",
+            "input": ["I want to learn how to bake bread.", "Another input."]
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer user-key".parse().unwrap());
+
+        let response = moderations_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            headers,
+            RequestId::new(),
+            Json(request.clone()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["results"][0]["flagged"], false);
+
+        let (authorization, forwarded) = received.lock().unwrap().take().unwrap();
+        assert_eq!(authorization.as_deref(), Some("Bearer user-key"));
+        assert_eq!(forwarded, request);
+    }
+
+    #[tokio::test]
+    async fn test_moderation_without_server_is_not_implemented() {
+        let state = crate::test_utils::create_test_state(Config::default(), &[]).await;
+
+        let result = moderations_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(serde_json::json!({ "input": "hello" })),
+        )
+        .await;
+
+        let response = axum::response::IntoResponse::into_response(result.unwrap_err());
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[tokio::test]
+    async fn test_large_embedding_input_is_batched() {
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/v1/embeddings",
+            axum::routing::post({
+                let hits = hits.clone();
+                move |Json(request): Json<EmbeddingRequest>| async move {
+                    hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let InputText::ArrayOfStrings(inputs) = request.input else {
+                        panic!("expected an array of strings");
+                    };
+                    Json(serde_json::json!({
+                        "object": "list",
+                        "data": inputs
+                            .iter()
+                            .enumerate()
+                            .map(|(index, input)| serde_json::json!({
+                                "index": index,
+                                "object": "embedding",
+                                "embedding": [input.trim_start_matches("input-").parse::<f64>().unwrap()]
+                            }))
+                            .collect::<Vec<_>>(),
+                        "model": "test-embedder",
+                        "usage": {
+                            "prompt_tokens": inputs.len(),
+                            "completion_tokens": 0,
+                            "total_tokens": inputs.len()
+                        }
+                    }))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let config = Config {
+            embedding: Some(
+                serde_json::from_value(serde_json::json!({
+                    "url": url,
+                    "api_key": "",
+                    "max_embedding_batch": 1000,
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(config, &[(&url, "embeddings")]).await;
+
+        let inputs: Vec<String> = (0..2500).map(|i| format!("input-{i}")).collect();
+        let request: EmbeddingRequest =
+            serde_json::from_value(serde_json::json!({ "input": inputs })).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+
+        let response = embeddings_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            headers,
+            RequestId::new(),
+            Json(request),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 3);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let embeddings_response: EmbeddingsResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(embeddings_response.data.len(), 2500);
+        for (i, object) in embeddings_response.data.iter().enumerate() {
+            assert_eq!(object.index, i as u64);
+            assert_eq!(object.embedding, [i as f64]);
+        }
+        assert_eq!(embeddings_response.usage.prompt_tokens, 2500);
+        assert_eq!(embeddings_response.usage.total_tokens, 2500);
+    }
+
+    #[tokio::test]
+    async fn test_get_downstream_server() {
+        let state = crate::test_utils::create_test_state(
+            Config::default(),
+            &[("http://localhost:10010/v1", "chat")],
+        )
+        .await;
+        let server_id = state.list_downstream_servers().await.unwrap()[&ServerKind::chat][0]
+            .id
+            .clone();
+        state.models.write().await.insert(
+            server_id.clone(),
+            vec![Model {
+                id: "test-model".to_string(),
+                created: 1_700_000_000,
+                object: "model".to_string(),
+                owned_by: "test".to_string(),
+            }],
+        );
+
+        let response = admin::get_downstream_server_handler(
+            State(state.clone()),
+            RequestId::new(),
+            axum::extract::Path(server_id.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["id"], server_id.as_str());
+        assert_eq!(body["kind"], "chat");
+        assert_eq!(body["url"], "http://localhost:10010/v1");
+        assert_eq!(body["health_status"]["is_healthy"], true);
+        assert_eq!(body["models"][0]["id"], "test-model");
+
+        let response = admin::get_downstream_server_handler(
+            State(state),
+            RequestId::new(),
+            axum::extract::Path("chat-server-unknown".to_string()),
+        )
+        .await
+        .unwrap_or_else(axum::response::IntoResponse::into_response);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_exposition() {
+        let router = axum::Router::new().route(
+            "/v1/embeddings",
+            axum::routing::post(|| async {
+                Json(serde_json::json!({
+                    "object": "list",
+                    "data": [{ "index": 0, "object": "embedding", "embedding": [0.5] }],
+                    "model": "test-embedder",
+                    "usage": { "prompt_tokens": 1, "completion_tokens": 0, "total_tokens": 1 }
+                }))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let config = Config {
+            metrics: Some(serde_json::from_value(serde_json::json!({ "enable": true })).unwrap()),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(config, &[(&url, "embeddings")]).await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        for _ in 0..3 {
+            let request: EmbeddingRequest =
+                serde_json::from_value(serde_json::json!({ "input": "hello" })).unwrap();
+            let response = embeddings_handler(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                headers.clone(),
+                RequestId::new(),
+                Json(request),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        state.record_tool_call("get_weather");
+
+        let response = metrics_handler(State(state), RequestId::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(text.contains("llama_nexus_downstream_requests_total{kind=\"embeddings\"} 3\n"));
+        assert!(
+            text.contains("llama_nexus_downstream_latency_seconds_count{kind=\"embeddings\"} 3\n")
+        );
+        assert!(text.contains("llama_nexus_tool_calls_total{tool=\"get_weather\"} 1\n"));
+        assert!(text.contains("# TYPE llama_nexus_in_flight_requests gauge\n"));
+        assert!(text.contains("llama_nexus_in_flight_requests{kind=\"embeddings\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_user_usage_is_aggregated() {
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                Json(crate::test_utils::chat_completion_json("Hello!"))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "chat")]).await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        for _ in 0..2 {
+            let request: ChatRequest = serde_json::from_value(serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "user": "alice",
+            }))
+            .unwrap();
+            let response = chat_handler(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                headers.clone(),
+                RequestId::new(),
+                Json(request),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let usage = |user_id: &str, params: &[(&str, &str)]| {
+            let state = state.clone();
+            let user_id = user_id.to_string();
+            let params = params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            async move {
+                let response = user_usage_handler(
+                    State(state),
+                    RequestId::new(),
+                    axum::extract::Path(user_id),
+                    axum::extract::Query(params),
+                )
+                .await
+                .unwrap_or_else(axum::response::IntoResponse::into_response);
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+                )
+            }
+        };
+
+        let (status, body) = usage("alice", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["usage"]["prompt_tokens"], 20);
+        assert_eq!(body["usage"]["completion_tokens"], 10);
+        assert_eq!(body["usage"]["total_tokens"], 30);
+        assert_eq!(body["usage"]["requests"], 2);
+
+        let (_, body) = usage("alice", &[("end", "0")]).await;
+        assert_eq!(body["usage"]["requests"], 0);
+
+        let (_, body) = usage("bob", &[]).await;
+        assert_eq!(body["usage"]["requests"], 0);
+
+        let (status, _) = usage("alice", &[("start", "yesterday")]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_user() {
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                Json(crate::test_utils::chat_completion_json("Hello!"))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let config = Config {
+            rate_limit: Some(
+                serde_json::from_value(serde_json::json!({
+                    "enable": true,
+                    "requests_per_minute": 2,
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(config, &[(&url, "chat")]).await;
+
+        let send = |user: Option<&str>| {
+            let state = state.clone();
+            let mut body = serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "Hi" }],
+            });
+            if let Some(user) = user {
+                body["user"] = user.into();
+            }
+            let request: ChatRequest = serde_json::from_value(body).unwrap();
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            async move {
+                match Box::pin(chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    headers,
+                    RequestId::new(),
+                    Json(request),
+                ))
+                .await
+                {
+                    Ok(response) => response,
+                    Err(e) => axum::response::IntoResponse::into_response(e),
+                }
+            }
+        };
+
+        assert_eq!(send(Some("alice")).await.status(), StatusCode::OK);
+        assert_eq!(send(Some("alice")).await.status(), StatusCode::OK);
+        let response = send(Some("alice")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[axum::http::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=30).contains(&retry_after));
+
+        // the bucket of another user is not affected
+        assert_eq!(send(Some("bob")).await.status(), StatusCode::OK);
+
+        // the requests without a user id share a bucket, instead of getting a new id each
+        assert_eq!(send(None).await.status(), StatusCode::OK);
+        assert_eq!(send(None).await.status(), StatusCode::OK);
+        assert_eq!(send(None).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_memory_can_be_disabled_per_request() {
+        use std::sync::Mutex;
+
+        // number of messages received by the downstream server per request
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let received = received.clone();
+                move |Json(body): Json<serde_json::Value>| async move {
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(body["messages"].as_array().unwrap().len());
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = Arc::new(
+            AppState::new(Config::default(), ServerInfo::default()).with_memory(Arc::new(memory)),
+        );
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        state.register_downstream_server(server).await.unwrap();
+
+        let send = |memory: Option<bool>, disable_header: bool| {
+            let state = state.clone();
+            async move {
+                let mut headers = HeaderMap::new();
+                headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+                if disable_header {
+                    headers.insert(DISABLE_MEMORY_HEADER, "true".parse().unwrap());
+                }
+                let mut body = serde_json::json!({
+                    "model": "test-model",
+                    "messages": [{ "role": "user", "content": "Hi" }],
+                    "user": "alice",
+                });
+                if let Some(memory) = memory {
+                    body["memory"] = memory.into();
+                }
+                let response = chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    headers,
+                    RequestId::new(),
+                    Json(serde_json::from_value(body).unwrap()),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+        let history_len = || async {
+            state
+                .memory
+                .as_ref()
+                .unwrap()
+                .get_user_full_history("alice", false)
+                .await
+                .unwrap()
+                .len()
+        };
+
+        send(None, false).await;
+        let history = history_len().await;
+        assert_eq!(history, 2);
+
+        send(Some(false), false).await;
+        send(Some(true), true).await;
+        assert_eq!(history_len().await, history);
+        // the requests without memory are not augmented with the history
+        assert_eq!(*received.lock().unwrap(), [1, 1, 1]);
+
+        send(Some(true), false).await;
+        assert_eq!(history_len().await, history + 2);
+        assert_eq!(received.lock().unwrap()[3], 3);
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_is_scoped_per_caller() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let hits = hits.clone();
+                move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let config = Config {
+            idempotency: Some(crate::config::IdempotencyConfig {
+                enable: true,
+                ttl_secs: 60,
+                max_entries: 10,
+            }),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(config, &[(&url, "chat")]).await;
+
+        let send = |user: Option<&str>, authorization: Option<&str>| {
+            let state = state.clone();
+            let mut body = serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "Hi" }],
+            });
+            if let Some(user) = user {
+                body["user"] = user.into();
+            }
+            let mut headers = HeaderMap::new();
+            headers.insert(IDEMPOTENCY_KEY_HEADER, "shared-key".parse().unwrap());
+            if let Some(authorization) = authorization {
+                headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+            }
+            async move {
+                let response = chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    headers,
+                    RequestId::new(),
+                    Json(serde_json::from_value(body).unwrap()),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+
+        // anonymous callers never share a cached response
+        send(None, None).await;
+        send(None, None).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // the retry of a user is replayed, but not for another caller with the same user id
+        send(Some("alice"), Some("Bearer key-1")).await;
+        send(Some("alice"), Some("Bearer key-1")).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        send(Some("alice"), Some("Bearer key-2")).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_identical_concurrent_requests_are_coalesced() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let hits = hits.clone();
+                move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let config = Config {
+            coalescing: Some(crate::config::CoalescingConfig { enable: true }),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(config, &[(&url, "chat")]).await;
+
+        let send_as = |content: &str, api_key: Option<&str>| {
+            let state = state.clone();
+            let body = serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": content }],
+                "user": "alice",
+            });
+            let mut headers = HeaderMap::new();
+            if let Some(api_key) = api_key {
+                headers.insert(AUTHORIZATION, format!("Bearer {api_key}").parse().unwrap());
+            }
+            async move {
+                let response = Box::pin(chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    headers,
+                    RequestId::new(),
+                    Json(serde_json::from_value(body).unwrap()),
+                ))
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+        let send = |content: &str| send_as(content, None);
+
+        let responses = futures_util::future::join_all((0..10).map(|_| send("Hi"))).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        for response in &responses {
+            assert_eq!(response["choices"][0]["message"]["content"], "Hello!");
+        }
+        // the shared answer is accounted to every request
+        assert_eq!(state.usage.totals("alice", None, None).requests, 10);
+
+        // a different request is not coalesced, nor is the same request once the first completed
+        send("Hello").await;
+        send("Hi").await;
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // nor are the requests of different callers
+        futures_util::future::join(
+            send_as("Hi", Some("first-key")),
+            send_as("Hi", Some("second-key")),
+        )
+        .await;
+        assert_eq!(hits.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_responses_are_cached() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let hits = hits.clone();
+                move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let config = Config {
+            response_cache: Some(crate::config::ResponseCacheConfig {
+                enable: true,
+                ttl_secs: 60,
+                max_entries: 10,
+            }),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(config, &[(&url, "chat")]).await;
+
+        let send_as = |user: &str, temperature: f64, api_key: &str| {
+            let state = state.clone();
+            let body = serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "user": user,
+                "temperature": temperature,
+            });
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, format!("Bearer {api_key}").parse().unwrap());
+            async move {
+                let response = Box::pin(chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    headers,
+                    RequestId::new(),
+                    Json(serde_json::from_value(body).unwrap()),
+                ))
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let send = |user: &str, temperature: f64| send_as(user, temperature, "first-key");
+
+        let first = send("alice", 0.0).await;
+        let second = send("alice", 0.0).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(first, second);
+
+        // the cached response is not shared with another user, nor with another api key
+        send("bob", 0.0).await;
+        send_as("alice", 0.0, "second-key").await;
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // a request sampled at a non-zero temperature is not cached
+        send("alice", 0.7).await;
+        send("alice", 0.7).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_returns_the_assembled_request() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let hits = hits.clone();
+                move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = Arc::new(
+            AppState::new(Config::default(), ServerInfo::default()).with_memory(Arc::new(memory)),
+        );
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        state.register_downstream_server(server).await.unwrap();
+
+        let send = |content: &str, dry_run: bool, dry_run_header: bool| {
+            let state = state.clone();
+            let body = serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": content }],
+                "user": "alice",
+                "dry_run": dry_run,
+                "seed": 42,
+            });
+            async move {
+                let mut headers = HeaderMap::new();
+                if dry_run_header {
+                    headers.insert(DRY_RUN_HEADER, "1".parse().unwrap());
+                }
+                let response = chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    headers,
+                    RequestId::new(),
+                    Json(serde_json::from_value(body).unwrap()),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+        let history_len = || async {
+            state
+                .memory
+                .as_ref()
+                .unwrap()
+                .get_user_full_history("alice", false)
+                .await
+                .unwrap()
+                .len()
+        };
+
+        // a dry run of a new user creates no conversation
+        let assembled = send("Hi", true, false).await;
+        assert_eq!(assembled["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+        assert!(
+            state
+                .memory
+                .as_ref()
+                .unwrap()
+                .find_user_conversation("alice")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        send("Hi", false, false).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(history_len().await, 2);
+
+        // the assembled request carries the memory context and the seed, and nothing is recorded
+        let assembled = send("How are you?", false, true).await;
+        let messages = assembled["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["content"], "Hi");
+        assert_eq!(messages[1]["content"], "Hello!");
+        assert_eq!(messages[2]["content"], "How are you?");
+        assert_eq!(assembled["seed"], 42);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(history_len().await, 2);
+
+        // in react mode the ReAct system prompt is added
+        state.config.write().await.server.chat_mode = crate::config::ChatMode::React;
+        let assembled = send("How are you?", true, false).await;
+        let messages = assembled["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(assembled["stream"], false);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(history_len().await, 2);
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_seed_survives_the_rebuild_of_the_messages() {
+        use std::sync::Mutex;
+
+        // the seed and number of messages received by the downstream server per request
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let received = received.clone();
+                move |Json(body): Json<serde_json::Value>| async move {
+                    received.lock().unwrap().push((
+                        body["seed"].as_u64(),
+                        body["messages"].as_array().unwrap().len(),
+                    ));
+                    Json(crate::test_utils::chat_completion_json(
+                        "<final_answer>Hello!</final_answer>",
+                    ))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+
+        let database_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = Config {
+            chat: Some(
+                serde_json::from_value(serde_json::json!({
+                    "url": url,
+                    "api_key": "",
+                    "default_seed": 7,
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let state =
+            Arc::new(AppState::new(config, ServerInfo::default()).with_memory(Arc::new(memory)));
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        state.register_downstream_server(server).await.unwrap();
+
+        let send = |seed: Option<u64>| {
+            let state = state.clone();
+            async move {
+                let mut body = serde_json::json!({
+                    "model": "test-model",
+                    "messages": [{ "role": "user", "content": "Hi" }],
+                    "user": "alice",
+                });
+                if let Some(seed) = seed {
+                    body["seed"] = seed.into();
+                }
+                let response = chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    HeaderMap::new(),
+                    RequestId::new(),
+                    Json(serde_json::from_value(body).unwrap()),
+                )
+                .await
+                .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+
+        // the messages are rebuilt from the memory, the seed is kept
+        send(Some(42)).await;
+        send(Some(42)).await;
+        // the configured seed fills in a missing one
+        send(None).await;
+        // the ReAct loop adds its system prompt to the messages
+        state.config.write().await.server.chat_mode = ChatMode::React;
+        send(Some(42)).await;
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received[0], (Some(42), 1));
+        assert_eq!(received[1], (Some(42), 3));
+        assert_eq!(received[2].0, Some(7));
+        assert_eq!(received[3].0, Some(42));
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_default_model_fills_in_a_missing_model() {
+        use std::sync::Mutex;
+
+        // the models received by the downstream server
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let received = received.clone();
+                move |Json(body): Json<serde_json::Value>| async move {
+                    received.lock().unwrap().push(body["model"].clone());
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state =
+            crate::test_utils::create_test_state(Config::default(), &[(&url, "chat")]).await;
+
+        let send = || {
+            let state = state.clone();
+            async move {
+                let request: ChatRequest = serde_json::from_value(serde_json::json!({
+                    "messages": [{ "role": "user", "content": "Hi" }],
+                }))
+                .unwrap();
+                chat_handler(
+                    State(state),
+                    Extension(CancellationToken::new()),
+                    HeaderMap::new(),
+                    RequestId::new(),
+                    Json(request),
+                )
+                .await
+                .unwrap_or_else(axum::response::IntoResponse::into_response)
+                .status()
+            }
+        };
+
+        // without a default model, the request is rejected
+        assert_eq!(send().await, StatusCode::BAD_REQUEST);
+        assert!(received.lock().unwrap().is_empty());
+
+        // the default model is forwarded
+        state.config.write().await.server.default_model = Some("test-model".to_string());
+        assert_eq!(send().await, StatusCode::OK);
+        assert_eq!(received.lock().unwrap().last().unwrap(), "test-model");
+
+        // a default model not served by the downstream servers is rejected
+        let server_id = state.list_downstream_servers().await.unwrap()[&ServerKind::chat][0]
+            .id
+            .clone();
+        state.models.write().await.insert(
+            server_id,
+            vec![Model {
+                id: "other-model".to_string(),
+                created: 1_700_000_000,
+                object: "model".to_string(),
+                owned_by: "test".to_string(),
+            }],
+        );
+        assert_eq!(send().await, StatusCode::BAD_REQUEST);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers_are_sent_to_the_server() {
+        use std::sync::Mutex;
+
+        // the extra headers received by the downstream server
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let received = received.clone();
+                move |headers: HeaderMap| async move {
+                    let header =
+                        |name: &str| headers.get(name).map(|h| h.to_str().unwrap().to_string());
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((header("http-referer"), header("x-title")));
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let state = Arc::new(AppState::new(Config::default(), ServerInfo::default()));
+        let server: Server = serde_json::from_value(serde_json::json!({
+            "url": url,
+            "kind": "chat",
+            "extra_headers": {
+                "HTTP-Referer": "https://example.com",
+                "X-Title": "Example",
+            },
+        }))
+        .unwrap();
+        state.register_downstream_server(server).await.unwrap();
+
+        let request: ChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "Hi" }],
+        }))
+        .unwrap();
+        let response = chat_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *received.lock().unwrap(),
+            [(
+                Some("https://example.com".to_string()),
+                Some("Example".to_string())
+            )]
+        );
+
+        // an invalid header is rejected at registration
+        let server = serde_json::from_value::<Server>(serde_json::json!({
+            "url": url,
+            "kind": "chat",
+            "extra_headers": { "X-Title": "line\nbreak" },
+        }));
+        assert!(server.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_model_alias_is_rewritten_and_routed() {
+        use std::sync::Mutex;
+
+        // the server and model of the requests received by the downstream servers
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let spawn_chat_server = |name: &'static str| {
+            let received = received.clone();
+            let router = axum::Router::new().route(
+                "/v1/chat/completions",
+                axum::routing::post(move |Json(body): Json<serde_json::Value>| async move {
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((name, body["model"].as_str().unwrap().to_string()));
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }),
+            );
+            crate::test_utils::spawn_mock_server(router)
+        };
+        let qwen_url = spawn_chat_server("qwen").await;
+        let llama_url = spawn_chat_server("llama").await;
+        let config = Config {
+            model_aliases: Some(std::collections::HashMap::from([(
+                "gpt-4o-mini".to_string(),
+                "Qwen2.5-7B-Instruct".to_string(),
+            )])),
+            ..Default::default()
+        };
+        let state = crate::test_utils::create_test_state(
+            config,
+            &[(&qwen_url, "chat"), (&llama_url, "chat")],
+        )
+        .await;
+        for server in state.list_downstream_servers().await.unwrap()[&ServerKind::chat].iter() {
+            let model = match server.url == qwen_url {
+                true => "Qwen2.5-7B-Instruct",
+                false => "Llama-3.2-3B-Instruct",
+            };
+            state.models.write().await.insert(
+                server.id.clone(),
+                vec![Model {
+                    id: model.to_string(),
+                    created: 1_700_000_000,
+                    object: "model".to_string(),
+                    owned_by: "test".to_string(),
+                }],
+            );
+        }
+
+        for _ in 0..2 {
+            let request: ChatRequest = serde_json::from_value(serde_json::json!({
+                "model": "gpt-4o-mini",
+                "messages": [{ "role": "user", "content": "Hi" }],
+            }))
+            .unwrap();
+            let response = chat_handler(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                HeaderMap::new(),
+                RequestId::new(),
+                Json(request),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![("qwen", "Qwen2.5-7B-Instruct".to_string()); 2]
+        );
+
+        // the alias is listed next to its model once advertised
+        let list_models = || async {
+            let response = models_handler(State(state.clone()), RequestId::new())
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            let mut ids: Vec<String> = body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|model| model["id"].as_str().unwrap().to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert!(!list_models().await.contains(&"gpt-4o-mini".to_string()));
+        state.config.write().await.server.advertise_model_aliases = true;
+        assert_eq!(
+            list_models().await,
+            vec![
+                "Llama-3.2-3B-Instruct",
+                "Qwen2.5-7B-Instruct",
+                "gpt-4o-mini"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readiness_requires_a_healthy_chat_server() {
+        let state = crate::test_utils::create_test_state(Config::default(), &[]).await;
+        let response = ready_handler(State(state.clone()), RequestId::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let server: Server = serde_json::from_value(serde_json::json!({
+            "url": "http://localhost:10010/v1",
+            "kind": "chat"
+        }))
+        .unwrap();
+        let server_id = server.id.clone();
+        state.register_downstream_server(server).await.unwrap();
+        let response = ready_handler(State(state.clone()), RequestId::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // an unhealthy server does not make the gateway ready
+        state.server_group.read().await[&ServerKind::chat]
+            .mark_unhealthy(&server_id)
+            .await;
+        let response = ready_handler(State(state), RequestId::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_server_kind_is_verified_at_registration() {
+        // the server only serves a chat model
+        let router = axum::Router::new()
+            .route(
+                "/v1/info",
+                axum::routing::get(|| async {
+                    Json(serde_json::json!({
+                        "type": "llama",
+                        "version": "0.1.0",
+                        "port": "8080",
+                        "chat_model": { "name": "test-chat", "type": "chat" },
+                        "extras": {}
+                    }))
+                }),
+            )
+            .route(
+                "/v1/models",
+                axum::routing::get(|| async {
+                    Json(serde_json::json!({
+                        "object": "list",
+                        "data": [{
+                            "id": "test-chat",
+                            "created": 1_700_000_000u64,
+                            "object": "model",
+                            "owned_by": "test"
+                        }]
+                    }))
+                }),
+            );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let register = |state: Arc<AppState>, kind: &str| {
+            let server: Server =
+                serde_json::from_value(serde_json::json!({ "url": url, "kind": kind })).unwrap();
+            admin::register_downstream_server_handler(
+                State(state),
+                HeaderMap::new(),
+                RequestId::new(),
+                Json(server),
+            )
+        };
+        let state = Arc::new(AppState::new(Config::default(), ServerInfo::default()));
+
+        // the declared kind matches the reported models
+        let response = register(state.clone(), "chat").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the server does not report an embedding model
+        let err = register(state.clone(), "embeddings").await.unwrap_err();
+        assert!(matches!(err, ServerError::InvalidServerKind(_)));
+        assert_eq!(
+            axum::response::IntoResponse::into_response(err).status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        // the verification can be turned off
+        let mut config = Config::default();
+        config.server.verify_server_kind = false;
+        let state = Arc::new(AppState::new(config, ServerInfo::default()));
+        let response = register(state, "embeddings").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_registered_servers_survive_a_restart() {
+        let router = axum::Router::new().route(
+            "/v1/models",
+            axum::routing::get(|| async {
+                Json(serde_json::json!({
+                    "object": "list",
+                    "data": [{
+                        "id": "test-chat",
+                        "created": 1_700_000_000u64,
+                        "object": "model",
+                        "owned_by": "test"
+                    }]
+                }))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+
+        let registry_path =
+            std::env::temp_dir().join(format!("llama-nexus-{}.json", uuid::Uuid::new_v4()));
+        let mut config = Config::default();
+        config.server.verify_server_kind = false;
+        config.registry = Some(
+            serde_json::from_value(serde_json::json!({
+                "enable": true,
+                "path": registry_path.to_string_lossy(),
+            }))
+            .unwrap(),
+        );
+        let start = || async {
+            let state = Arc::new(AppState::new(config.clone(), ServerInfo::default()));
+            state.register_saved_servers().await.unwrap();
+            state
+        };
+
+        let state = start().await;
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        let response = admin::register_downstream_server_handler(
+            State(state.clone()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(server),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let server_id = body["id"].as_str().unwrap().to_string();
+        admin::update_downstream_server_handler(
+            State(state.clone()),
+            RequestId::new(),
+            axum::extract::Path(server_id.clone()),
+            Json(serde_json::from_value(serde_json::json!({ "enable": false })).unwrap()),
+        )
+        .await
+        .unwrap();
+
+        // after a restart, the server is registered again with its id, state and models
+        let state = start().await;
+        let servers = state.list_downstream_servers().await.unwrap();
+        let server = &servers[&ServerKind::chat][0];
+        assert_eq!(server.id, server_id);
+        assert_eq!(server.url, url);
+        assert!(!server.enabled);
+        assert!(server.health_status.is_healthy);
+        assert_eq!(state.models.read().await[&server_id][0].id, "test-chat");
+
+        // the registry is only readable by the owner, as it holds the api keys of the servers
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&registry_path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // an unregistered server is not registered again
+        admin::remove_downstream_server_handler(
+            State(state),
+            RequestId::new(),
+            Json(serde_json::from_value(serde_json::json!({ "server_id": server_id })).unwrap()),
+        )
+        .await
+        .unwrap();
+        let state = start().await;
+        assert!(state.server_group.read().await.is_empty());
+
+        // a saved server that went away is restored unhealthy
+        let refused_url = crate::test_utils::refused_server_url().await;
+        let entries = serde_json::json!([{
+            "id": "gone-server",
+            "url": refused_url,
+            "kind": "chat",
+            "weight": 1,
+            "enabled": true
+        }]);
+        std::fs::write(&registry_path, entries.to_string()).unwrap();
+        let state = start().await;
+        let servers = state.list_downstream_servers().await.unwrap();
+        assert!(!servers[&ServerKind::chat][0].health_status.is_healthy);
+
+        let _ = std::fs::remove_file(registry_path);
+    }
 }
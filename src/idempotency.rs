@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use bytes::Bytes;
+
+use crate::{
+    access_log::{self, Downstream},
+    config::IdempotencyConfig,
+};
+
+pub(crate) const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// A cached downstream response that can be replayed for a repeated idempotency key
+#[derive(Debug, Clone)]
+pub(crate) struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Downstream server that produced the response
+    pub downstream: Option<Downstream>,
+}
+impl CachedResponse {
+    /// Replay the response, recording its downstream server for the request being handled
+    pub(crate) fn into_response(self) -> Response {
+        if let Some(downstream) = &self.downstream {
+            access_log::replay_downstream(downstream);
+        }
+
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: CachedResponse,
+    inserted_at: Instant,
+}
+
+/// Bounded, TTL-based cache of responses keyed by `(user, idempotency key)`
+#[derive(Debug)]
+pub(crate) struct IdempotencyCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<(String, String), CacheEntry>>,
+}
+impl IdempotencyCache {
+    pub(crate) fn new(config: &IdempotencyConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_entries: config.max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached response for the given user and key if it has not expired
+    pub(crate) fn get(&self, user: &str, key: &str) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let cache_key = (user.to_string(), key.to_string());
+
+        match entries.get(&cache_key) {
+            Some(entry) if entry.inserted_at.elapsed() < self.ttl => Some(entry.response.clone()),
+            Some(_) => {
+                entries.remove(&cache_key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches the response for the given user and key, evicting expired entries first and
+    /// then the oldest entry if the cache is full
+    pub(crate) fn insert(&self, user: &str, key: &str, response: CachedResponse) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let cache_key = (user.to_string(), key.to_string());
+
+        if !entries.contains_key(&cache_key) && entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.inserted_at.elapsed() < ttl);
+
+            if entries.len() >= self.max_entries
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.inserted_at)
+                    .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            cache_key,
+            CacheEntry {
+                response,
+                inserted_at: Instant::now(),
+            },
+        );
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_cache(ttl_secs: u64, max_entries: usize) -> IdempotencyCache {
+        IdempotencyCache::new(&IdempotencyConfig {
+            enable: true,
+            ttl_secs,
+            max_entries,
+        })
+    }
+
+    fn create_response(body: &str) -> CachedResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        CachedResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Bytes::from(body.to_string()),
+            downstream: None,
+        }
+    }
+
+    #[test]
+    fn test_repeated_key_returns_cached_response() {
+        let cache = create_cache(60, 10);
+        let body = r#"{"id":"chatcmpl-1","choices":[]}"#;
+        cache.insert("user-1", "key-1", create_response(body));
+
+        let first = cache.get("user-1", "key-1").unwrap();
+        let second = cache.get("user-1", "key-1").unwrap();
+        assert_eq!(first.status, StatusCode::OK);
+        assert_eq!(first.body, Bytes::from(body));
+        assert_eq!(first.body, second.body);
+        assert_eq!(
+            second.headers.get("content-type").unwrap(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn test_keys_are_scoped_per_user() {
+        let cache = create_cache(60, 10);
+        cache.insert("user-1", "key-1", create_response("a"));
+
+        assert!(cache.get("user-2", "key-1").is_none());
+        assert!(cache.get("user-1", "key-2").is_none());
+    }
+
+    #[test]
+    fn test_expired_entry_is_not_returned() {
+        let cache = create_cache(0, 10);
+        cache.insert("user-1", "key-1", create_response("a"));
+
+        assert!(cache.get("user-1", "key-1").is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let cache = create_cache(60, 2);
+        cache.insert("user-1", "key-1", create_response("a"));
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("user-1", "key-2", create_response("b"));
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("user-1", "key-3", create_response("c"));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("user-1", "key-1").is_none());
+        assert!(cache.get("user-1", "key-3").is_some());
+    }
+}
@@ -1,12 +1,26 @@
+mod access_log;
+mod auth;
 mod chat;
+mod coalesce;
 mod config;
 mod error;
 mod handlers;
+mod idempotency;
 mod info;
 mod mcp;
 mod memory;
+mod metrics;
+mod rate_limit;
+mod registry;
+mod request_id;
+mod rerank;
+mod response_cache;
 mod responses;
 mod server;
+mod shadow;
+#[cfg(test)]
+mod test_utils;
+mod usage;
 mod utils;
 
 use std::{
@@ -15,19 +29,21 @@ use std::{
     path::PathBuf,
     str::FromStr,
     sync::Arc,
+    time::Instant,
 };
 
 use axum::{
     body::Body,
+    extract::{DefaultBodyLimit, State},
     http::{self, HeaderValue, Request},
-    routing::{Router, get, post},
+    routing::{Router, delete, get, post},
 };
 use clap::Parser;
 use config::Config;
 use error::{ServerError, ServerResult};
 use futures_util::stream::{self, StreamExt};
 use once_cell::sync::OnceCell;
-use tokio::{signal, sync::RwLock};
+use tokio::{select, signal, sync::RwLock};
 use tokio_util::sync::CancellationToken;
 use tower_http::{
     cors::{Any, CorsLayer},
@@ -35,13 +51,27 @@ use tower_http::{
     trace::TraceLayer,
 };
 use tracing::Level;
-use uuid::Uuid;
 
 use crate::{
+    coalesce::RequestCoalescer,
+    idempotency::IdempotencyCache,
     info::ServerInfo,
-    server::{Server, ServerGroup, ServerId, ServerKind},
+    metrics::{Metrics, RequestCounters},
+    rate_limit::{ANONYMOUS_USER, RateLimiter},
+    registry::ServerRegistry,
+    request_id::{REQUEST_ID_HEADER, RequestId},
+    response_cache::ResponseCache,
+    server::{RoutingPolicy, Server, ServerGroup, ServerId, ServerKind, TargetServerInfo},
+    shadow::ShadowTraffic,
+    usage::UsageTracker,
+    utils::LogFormat,
 };
 
+/// Response header carrying the url of the downstream server that handled the request
+const UPSTREAM_SERVER_HEADER: &str = "x-upstream-server";
+/// Response header carrying the id of the downstream server that handled the request
+const UPSTREAM_SERVER_ID_HEADER: &str = "x-upstream-server-id";
+
 // Global health check interval for downstream servers in seconds
 pub(crate) static HEALTH_CHECK_INTERVAL: OnceCell<u64> = OnceCell::new();
 
@@ -66,6 +96,9 @@ struct Cli {
     /// Log file path (required when log_destination is "file" or "both")
     #[arg(long)]
     log_file: Option<String>,
+    /// Log format: "pretty" or "json" (one JSON object per line, for log aggregation)
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
 }
 
 #[tokio::main]
@@ -90,7 +123,11 @@ async fn main() -> ServerResult<()> {
     }
 
     // Initialize logging based on destination
-    init_logging(&cli.log_destination, cli.log_file.as_deref())?;
+    init_logging(
+        &cli.log_destination,
+        cli.log_file.as_deref(),
+        cli.log_format,
+    )?;
 
     // log the version of the server
     dual_info!("Version: {}", env!("CARGO_PKG_VERSION"));
@@ -98,9 +135,15 @@ async fn main() -> ServerResult<()> {
     // Load the config based on the command
     let config = Config::load(&cli.config).await?;
 
+    // the health check section of the config file overrides the command line options
+    let (check_health, check_health_interval) = match &config.health_check {
+        Some(health_check) => (health_check.enable, health_check.interval_secs),
+        None => (cli.check_health, cli.check_health_interval),
+    };
+
     // set the health check interval
     HEALTH_CHECK_INTERVAL
-        .set(cli.check_health_interval)
+        .set(check_health_interval)
         .map_err(|e| {
             let err_msg = format!("Failed to set health check interval: {e}");
             dual_error!("{err_msg}");
@@ -130,7 +173,9 @@ async fn main() -> ServerResult<()> {
             match crate::memory::CompleteChatMemory::new(memory_config.clone()).await {
                 Ok(memory_system) => {
                     dual_info!("Memory system initialized successfully");
-                    Some(Arc::new(memory_system))
+                    let memory_system = Arc::new(memory_system);
+                    memory_system.spawn_sweeper();
+                    Some(memory_system)
                 }
                 Err(e) => {
                     dual_error!("Failed to initialize memory system: {}", e);
@@ -148,6 +193,9 @@ async fn main() -> ServerResult<()> {
         None
     };
 
+    // Load the API keys allowed to call the gateway
+    let api_key_auth = auth::ApiKeyAuth::from_config(config.auth.as_ref())?.map(Arc::new);
+
     // Initialize application state
     let mut state = AppState::new(config, ServerInfo::default());
 
@@ -171,8 +219,15 @@ async fn main() -> ServerResult<()> {
     // Register servers defined in configuration file
     state.register_config_servers().await?;
 
+    // Register again the servers saved in the registry
+    state.register_saved_servers().await?;
+
+    // Reload the config file on SIGHUP
+    #[cfg(unix)]
+    spawn_config_reloader(state.clone(), cli.config.clone())?;
+
     // Start the health check task if enabled
-    if cli.check_health {
+    if check_health {
         dual_info!("Health check is enabled");
         Arc::clone(&state).start_health_check_task().await;
     }
@@ -187,6 +242,8 @@ async fn main() -> ServerResult<()> {
     let mut main_router = Router::new()
         .route("/v1/chat/completions", post(handlers::chat_handler))
         .route("/v1/embeddings", post(handlers::embeddings_handler))
+        .route("/v1/rerank", post(handlers::rerank_handler))
+        .route("/v1/moderations", post(handlers::moderations_handler))
         .route(
             "/v1/audio/transcriptions",
             post(handlers::audio_transcriptions_handler),
@@ -197,9 +254,14 @@ async fn main() -> ServerResult<()> {
         )
         .route("/v1/audio/speech", post(handlers::audio_tts_handler))
         .route("/v1/images/generations", post(handlers::image_handler))
-        .route("/v1/images/edits", post(handlers::image_handler))
+        .route("/v1/images/edits", post(handlers::image_edits_handler))
+        .route(
+            "/v1/images/variations",
+            post(handlers::image_variations_handler),
+        )
         .route("/v1/models", get(handlers::models_handler))
         .route("/v1/info", get(handlers::info_handler))
+        .route("/ready", get(handlers::ready_handler))
         .route(
             "/admin/servers/register",
             post(handlers::admin::register_downstream_server_handler),
@@ -211,7 +273,14 @@ async fn main() -> ServerResult<()> {
         .route(
             "/admin/servers",
             get(handlers::admin::list_downstream_servers_handler),
-        );
+        )
+        .route(
+            "/admin/servers/{server_id}",
+            get(handlers::admin::get_downstream_server_handler)
+                .patch(handlers::admin::update_downstream_server_handler),
+        )
+        .route("/admin/stats", get(handlers::admin::stats_handler))
+        .route("/users/{user_id}/usage", get(handlers::user_usage_handler));
 
     // Add memory endpoints only if memory is enabled
     if state.memory.is_some() {
@@ -221,57 +290,66 @@ async fn main() -> ServerResult<()> {
                 "/v1/memory/conversations/{conv_id}/history",
                 get(handlers::get_conversation_history_handler),
             )
+            .route(
+                "/v1/memory/conversations/{conv_id}/export",
+                get(handlers::export_conversation_handler),
+            )
+            .route(
+                "/v1/memory/conversations/{conv_id}",
+                delete(handlers::delete_conversation_handler),
+            )
             .route(
                 "/v1/memory/users/{user_id}/history",
                 get(handlers::get_user_history_handler),
             )
             .route(
                 "/v1/memory/users/{user_id}/conversations",
-                get(handlers::list_user_conversations_handler),
+                get(handlers::list_user_conversations_handler)
+                    .delete(handlers::delete_user_conversations_handler),
             );
     } else {
         dual_info!("Memory endpoints are disabled");
     }
 
+    // Add the metrics endpoint only if metrics are enabled
+    if state.metrics.is_some() {
+        dual_info!("Metrics endpoint is enabled");
+        main_router = main_router.route("/metrics", get(handlers::metrics_handler));
+    }
+
+    // Limit the size of the bodies read by the JSON extractors as well
+    let max_request_body_bytes = state.config.read().await.server.max_request_body_bytes;
+    main_router = main_router.layer(DefaultBodyLimit::max(max_request_body_bytes));
+
     // Add state to main router
     let main_router = main_router.with_state(state.clone());
 
     // Create responses router
     let responses_router = Router::new()
         .route("/v1/responses", post(responses::responses_handler))
+        .route(
+            "/v1/responses/{response_id}",
+            get(responses::get_response_handler).delete(responses::delete_response_handler),
+        )
         .route("/health", get(responses::health_handler))
         .with_state(responses_state);
 
+    // Require an API key on the API endpoints if authentication is enabled
+    let api_router = auth::with_api_key_auth(
+        Router::new().merge(main_router).merge(responses_router),
+        api_key_auth,
+    );
+
     let app =
         Router::new()
-            .merge(main_router)
-            .merge(responses_router)
+            .merge(api_router)
             .layer(cors)
             .layer(TraceLayer::new_for_http())
-            .layer(axum::middleware::from_fn(
-                |mut req: Request<Body>, next: axum::middleware::Next| async move {
-                    // Generate request ID
-                    let request_id = Uuid::new_v4().to_string();
-
-                    // Add request ID to headers
-                    req.headers_mut()
-                        .insert("x-request-id", HeaderValue::from_str(&request_id).unwrap());
-
-                    // Add cancellation token
-                    let cancel_token = CancellationToken::new();
-                    req.extensions_mut().insert(cancel_token);
-
-                    // Log request start
-                    dual_info!("Request started - ID: {}", request_id);
-
-                    let response = next.run(req).await;
-
-                    // Log request completion
-                    dual_info!("Request completed - ID: {}", request_id);
-
-                    response
-                },
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                track_request,
             ))
+            .layer(axum::middleware::from_fn(request_id::propagate_request_id))
             .fallback_service(ServeDir::new(&cli.web_ui).not_found_service(
                 ServeDir::new(&cli.web_ui).append_index_html_on_directories(true),
             ));
@@ -304,6 +382,102 @@ async fn main() -> ServerResult<()> {
     }
 }
 
+/// Middleware that gives the request its cancellation token and logs its completion with the
+/// downstream server that handled it, also returned in the `x-upstream-server` and
+/// `x-upstream-server-id` headers if `server.upstream_server_header` is enabled
+async fn track_request(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<Body>,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    // Request ID set by the request ID middleware
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .cloned()
+        .unwrap_or_default()
+        .0;
+
+    // Add cancellation token
+    let cancel_token = CancellationToken::new();
+    req.extensions_mut().insert(cancel_token);
+
+    // Log request start
+    dual_info!("Request started - ID: {}", request_id);
+
+    // Count the API requests for `/admin/stats`
+    let request_guard = req
+        .uri()
+        .path()
+        .starts_with("/v1/")
+        .then(|| state.requests.track());
+
+    let start = Instant::now();
+    let entry = access_log::AccessLogEntry {
+        request_id: request_id.clone(),
+        route: req.uri().path().to_string(),
+        ..Default::default()
+    };
+    let (mut response, mut entry) = access_log::scope(entry, next.run(req)).await;
+    entry.status = response.status().as_u16();
+    entry.latency_ms = start.elapsed().as_millis() as u64;
+
+    // Tell the client which downstream server handled the request
+    if state.config.read().await.server.upstream_server_header
+        && let (Some(url), Some(id)) = (&entry.downstream_url, &entry.downstream_id)
+        && let (Ok(url), Ok(id)) = (HeaderValue::from_str(url), HeaderValue::from_str(id))
+    {
+        let headers = response.headers_mut();
+        headers.insert(UPSTREAM_SERVER_HEADER, url);
+        headers.insert(UPSTREAM_SERVER_ID_HEADER, id);
+    }
+
+    // Log request completion
+    dual_info!(fields: entry; "Request completed - ID: {}", request_id);
+
+    // a streamed response is only served once its body is sent, or the client went away
+    if let Some(request_guard) = request_guard
+        && response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .is_some_and(|content_type| content_type.as_bytes().starts_with(b"text/event-stream"))
+    {
+        let (parts, body) = response.into_parts();
+        let body = body.into_data_stream().map(move |chunk| {
+            let _ = &request_guard;
+            chunk
+        });
+        return axum::response::Response::from_parts(parts, Body::from_stream(body));
+    }
+
+    response
+}
+
+/// Reload the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+fn spawn_config_reloader(state: Arc<AppState>, path: PathBuf) -> ServerResult<()> {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup()).map_err(|e| {
+        let err_msg = format!("Failed to install the SIGHUP handler: {e}");
+        dual_error!("{}", err_msg);
+        ServerError::Operation(err_msg)
+    })?;
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            dual_info!(
+                "Received SIGHUP, reloading the config file: {}",
+                path.display()
+            );
+            match state.reload_config(&path).await {
+                Ok(()) => dual_info!("Reloaded the config file"),
+                Err(e) => dual_error!("Rejected the reloaded config, keeping the current one: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -333,7 +507,7 @@ async fn shutdown_signal() {
 }
 
 /// Initialize logging based on the specified destination
-fn init_logging(destination: &str, file_path: Option<&str>) -> ServerResult<()> {
+fn init_logging(destination: &str, file_path: Option<&str>, format: LogFormat) -> ServerResult<()> {
     // Store the log destination for later use
     utils::LOG_DESTINATION
         .set(destination.to_string())
@@ -342,20 +516,31 @@ fn init_logging(destination: &str, file_path: Option<&str>) -> ServerResult<()>
             eprintln!("{err_msg}");
             ServerError::Operation(err_msg)
         })?;
+    utils::LOG_FORMAT.set(format).map_err(|_| {
+        let err_msg = "Failed to set log format".to_string();
+        eprintln!("{err_msg}");
+        ServerError::Operation(err_msg)
+    })?;
+    // JSON log lines carry their own timestamp and level, so the subscriber writes them as is
+    let json = format == LogFormat::Json;
 
     let log_level = get_log_level_from_env();
 
     match destination {
         "stdout" => {
             // Terminal output preserves colors
-            tracing_subscriber::fmt()
+            let subscriber = tracing_subscriber::fmt()
                 .with_target(false)
-                .with_level(true)
-                .with_file(true)
-                .with_line_number(true)
-                .with_thread_ids(true)
-                .with_max_level(log_level)
-                .init();
+                .with_level(!json)
+                .with_file(!json)
+                .with_line_number(!json)
+                .with_thread_ids(!json)
+                .with_ansi(!json)
+                .with_max_level(log_level);
+            match json {
+                true => subscriber.without_time().init(),
+                false => subscriber.init(),
+            }
             Ok(())
         }
         "file" => {
@@ -367,16 +552,19 @@ fn init_logging(destination: &str, file_path: Option<&str>) -> ServerResult<()>
                 })?;
 
                 // File output disables ANSI colors
-                tracing_subscriber::fmt()
+                let subscriber = tracing_subscriber::fmt()
                     .with_target(false)
-                    .with_level(true)
-                    .with_file(true)
-                    .with_line_number(true)
-                    .with_thread_ids(true)
+                    .with_level(!json)
+                    .with_file(!json)
+                    .with_line_number(!json)
+                    .with_thread_ids(!json)
                     .with_max_level(log_level)
                     .with_writer(file)
-                    .with_ansi(false) // Disable ANSI colors
-                    .init();
+                    .with_ansi(false); // Disable ANSI colors
+                match json {
+                    true => subscriber.without_time().init(),
+                    false => subscriber.init(),
+                }
                 Ok(())
             } else {
                 Err(ServerError::Operation("Missing log file path".to_string()))
@@ -405,16 +593,19 @@ fn init_logging(destination: &str, file_path: Option<&str>) -> ServerResult<()>
                 let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
 
                 // Configure subscriber, disable ANSI colors
-                tracing_subscriber::fmt()
+                let subscriber = tracing_subscriber::fmt()
                     .with_target(false)
-                    .with_level(true)
-                    .with_file(true)
-                    .with_line_number(true)
-                    .with_thread_ids(true)
+                    .with_level(!json)
+                    .with_file(!json)
+                    .with_line_number(!json)
+                    .with_thread_ids(!json)
                     .with_max_level(log_level)
                     .with_writer(non_blocking)
-                    .with_ansi(false) // Disable ANSI colors
-                    .init();
+                    .with_ansi(false); // Disable ANSI colors
+                match json {
+                    true => subscriber.without_time().init(),
+                    false => subscriber.init(),
+                }
 
                 println!("Logging to both stdout and file: {path}");
 
@@ -451,15 +642,69 @@ pub(crate) struct AppState {
     server_info: Arc<RwLock<ServerInfo>>,
     models: Arc<RwLock<HashMap<ServerId, Vec<endpoints::models::Model>>>>,
     memory: Option<Arc<crate::memory::CompleteChatMemory>>,
+    idempotency: Option<Arc<IdempotencyCache>>,
+    shadow: Option<Arc<ShadowTraffic>>,
+    metrics: Option<Arc<Metrics>>,
+    usage: Arc<UsageTracker>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    registry: Option<Arc<ServerRegistry>>,
+    requests: Arc<RequestCounters>,
+    coalescer: Option<Arc<RequestCoalescer>>,
+    response_cache: Option<Arc<ResponseCache>>,
 }
 impl AppState {
     pub(crate) fn new(config: Config, server_info: ServerInfo) -> Self {
+        let idempotency = config
+            .idempotency
+            .as_ref()
+            .filter(|idempotency_config| idempotency_config.enable)
+            .map(|idempotency_config| Arc::new(IdempotencyCache::new(idempotency_config)));
+        let shadow = config
+            .shadow
+            .as_ref()
+            .filter(|shadow_config| shadow_config.enable)
+            .map(|shadow_config| Arc::new(ShadowTraffic::new(shadow_config)));
+        let metrics = config
+            .metrics
+            .as_ref()
+            .filter(|metrics_config| metrics_config.enable)
+            .map(|_| Arc::new(Metrics::default()));
+        let rate_limiter = config
+            .rate_limit
+            .as_ref()
+            .filter(|rate_limit_config| rate_limit_config.enable)
+            .map(|rate_limit_config| Arc::new(RateLimiter::new(rate_limit_config)));
+        let registry = config
+            .registry
+            .as_ref()
+            .filter(|registry_config| registry_config.enable)
+            .map(|registry_config| Arc::new(ServerRegistry::new(registry_config)));
+        let coalescer = config
+            .coalescing
+            .as_ref()
+            .filter(|coalescing_config| coalescing_config.enable)
+            .map(|_| Arc::new(RequestCoalescer::default()));
+        let response_cache = config
+            .response_cache
+            .as_ref()
+            .filter(|response_cache_config| response_cache_config.enable)
+            .map(|response_cache_config| Arc::new(ResponseCache::new(response_cache_config)));
+
         Self {
             server_group: Arc::new(RwLock::new(HashMap::new())),
             config: Arc::new(RwLock::new(config)),
             server_info: Arc::new(RwLock::new(server_info)),
             models: Arc::new(RwLock::new(HashMap::new())),
             memory: None,
+            idempotency,
+            shadow,
+            metrics,
+            usage: Arc::new(UsageTracker::default()),
+            rate_limiter,
+            registry,
+            requests: Arc::new(RequestCounters::default()),
+            coalescer,
+            response_cache,
         }
     }
 
@@ -469,57 +714,17 @@ impl AppState {
     }
 
     pub(crate) async fn register_downstream_server(&self, server: Server) -> ServerResult<()> {
-        if server.kind.contains(ServerKind::chat) {
-            self.server_group
-                .write()
-                .await
-                .entry(ServerKind::chat)
-                .or_insert(ServerGroup::new(ServerKind::chat))
-                .register(server.clone())
-                .await?;
-        }
-        if server.kind.contains(ServerKind::embeddings) {
-            self.server_group
-                .write()
-                .await
-                .entry(ServerKind::embeddings)
-                .or_insert(ServerGroup::new(ServerKind::embeddings))
-                .register(server.clone())
-                .await?;
-        }
-        if server.kind.contains(ServerKind::image) {
-            self.server_group
-                .write()
-                .await
-                .entry(ServerKind::image)
-                .or_insert(ServerGroup::new(ServerKind::image))
-                .register(server.clone())
-                .await?;
-        }
-        if server.kind.contains(ServerKind::tts) {
-            self.server_group
-                .write()
-                .await
-                .entry(ServerKind::tts)
-                .or_insert(ServerGroup::new(ServerKind::tts))
-                .register(server.clone())
-                .await?;
-        }
-        if server.kind.contains(ServerKind::translate) {
-            self.server_group
-                .write()
-                .await
-                .entry(ServerKind::translate)
-                .or_insert(ServerGroup::new(ServerKind::translate))
-                .register(server.clone())
-                .await?;
-        }
-        if server.kind.contains(ServerKind::transcribe) {
+        let routing_config = self.config.read().await.routing.clone().unwrap_or_default();
+
+        for kind in server.kind.iter() {
             self.server_group
                 .write()
                 .await
-                .entry(ServerKind::transcribe)
-                .or_insert(ServerGroup::new(ServerKind::transcribe))
+                .entry(kind)
+                .or_insert_with(|| {
+                    ServerGroup::new(kind, routing_config.strategy(kind))
+                        .with_circuit_breaker(routing_config.circuit_breaker)
+                })
                 .register(server.clone())
                 .await?;
         }
@@ -527,6 +732,157 @@ impl AppState {
         Ok(())
     }
 
+    /// Send a request to a downstream server of the given kind
+    ///
+    /// If the server cannot be reached, it is marked unhealthy and the request is sent to the
+    /// next server of the group, trying at most `routing.max_attempts` servers (default: all
+    /// servers of the group). Error statuses returned by a reachable server are not retried.
+    /// Unreachable servers and 5xx responses count as failures of the circuit breaker.
+    pub(crate) async fn send_with_failover<F>(
+        &self,
+        kind: ServerKind,
+        build_request: F,
+        cancel_token: &CancellationToken,
+        request_id: &str,
+    ) -> ServerResult<(TargetServerInfo, reqwest::Response)>
+    where
+        F: Fn(&TargetServerInfo) -> reqwest::RequestBuilder,
+    {
+        self.send_with_failover_for_model(kind, None, build_request, cancel_token, request_id)
+            .await
+    }
+
+    /// Send a request for a model to a downstream server of the given kind
+    ///
+    /// Same as [`AppState::send_with_failover`], except that the servers listing the model in
+    /// their `/models` are picked first. The other servers are only used if none of them does.
+    pub(crate) async fn send_with_failover_for_model<F>(
+        &self,
+        kind: ServerKind,
+        model: Option<&str>,
+        build_request: F,
+        cancel_token: &CancellationToken,
+        request_id: &str,
+    ) -> ServerResult<(TargetServerInfo, reqwest::Response)>
+    where
+        F: Fn(&TargetServerInfo) -> reqwest::RequestBuilder,
+    {
+        let preferred = match model {
+            Some(model) => self.servers_with_model(model).await,
+            None => HashSet::new(),
+        };
+
+        let max_attempts = self
+            .config
+            .read()
+            .await
+            .routing
+            .as_ref()
+            .and_then(|routing_config| routing_config.max_attempts);
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+
+            // get the next server of the group
+            let (target_server, num_servers) = {
+                let servers = self.server_group.read().await;
+                let group = match servers.get(&kind) {
+                    Some(group) => group,
+                    None => {
+                        let err_msg = format!(
+                            "No {kind} server available. Please register a {kind} server via the `/admin/servers/register` endpoint."
+                        );
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::NotFoundServer(kind.to_string()));
+                    }
+                };
+
+                // surfaced as is, e.g. a 503 with the time to retry after if all circuits are open
+                let target_server = group.next_preferring(&preferred).await.inspect_err(|e| {
+                    dual_error!(
+                        "Failed to get the {} server: {} - request_id: {}",
+                        kind,
+                        e,
+                        request_id
+                    );
+                })?;
+
+                (target_server, group.len().await)
+            };
+
+            if let Some(metrics) = &self.metrics {
+                metrics.record_request(kind);
+            }
+            // recorded before the response arrives, so that a streaming response can name the
+            // server in its headers
+            access_log::record_downstream(kind, &target_server.id, &target_server.url);
+
+            // Use select! to handle request cancellation
+            let start = Instant::now();
+            let result = select! {
+                response = target_server
+                    .add_extra_headers(build_request(&target_server))
+                    .header(REQUEST_ID_HEADER, request_id)
+                    .send() => response,
+                _ = cancel_token.cancelled() => {
+                    let warn_msg = "Request was cancelled by client";
+                    dual_warn!("{} - request_id: {}", warn_msg, request_id);
+                    return Err(ServerError::Operation(warn_msg.to_string()));
+                }
+            };
+
+            match result {
+                Ok(response) => {
+                    target_server.record_latency(start.elapsed());
+                    if let Some(metrics) = &self.metrics {
+                        metrics.record_latency(kind, start.elapsed());
+                    }
+                    match response.status().is_server_error() {
+                        true => target_server.record_failure(),
+                        false => target_server.record_success(),
+                    }
+                    return Ok((target_server, response));
+                }
+                Err(e) => {
+                    let err_msg = format!(
+                        "Failed to forward the request to the downstream server {}: {e}",
+                        target_server.id
+                    );
+                    target_server.record_failure();
+
+                    if let Some(group) = self.server_group.read().await.get(&kind) {
+                        group.mark_unhealthy(&target_server.id).await;
+                    }
+
+                    let max_attempts = max_attempts.unwrap_or(num_servers).min(num_servers);
+                    if attempts >= max_attempts {
+                        dual_error!("{} - request_id: {}", err_msg, request_id);
+                        return Err(ServerError::Operation(err_msg));
+                    }
+
+                    dual_warn!(
+                        "{}. Fail over to the next {} server - request_id: {}",
+                        err_msg,
+                        kind,
+                        request_id
+                    );
+                }
+            }
+        }
+    }
+
+    /// Ids of the servers listing the model in their `/models`
+    async fn servers_with_model(&self, model: &str) -> HashSet<ServerId> {
+        self.models
+            .read()
+            .await
+            .iter()
+            .filter(|(_, models)| models.iter().any(|m| m.id == model))
+            .map(|(server_id, _)| server_id.clone())
+            .collect()
+    }
+
     pub(crate) async fn unregister_downstream_server(
         &self,
         server_id: impl AsRef<str>,
@@ -579,6 +935,249 @@ impl AppState {
         Ok(())
     }
 
+    /// Returns the downstream server with the given id, if registered
+    pub(crate) async fn get_downstream_server(&self, server_id: &str) -> Option<Server> {
+        let group_map = self.server_group.read().await;
+        for group in group_map.values() {
+            for server_lock in group.servers.read().await.iter() {
+                let server = server_lock.read().await;
+                if server.id == server_id {
+                    return Some(server.clone());
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Enable or disable the downstream server with the given id in all of its groups.
+    /// Returns false if the server is not registered.
+    pub(crate) async fn set_downstream_server_enabled(
+        &self,
+        server_id: &str,
+        enabled: bool,
+    ) -> bool {
+        let group_map = self.server_group.read().await;
+        let mut found = false;
+        for group in group_map.values() {
+            found |= group.set_enabled(server_id, enabled).await;
+        }
+
+        found
+    }
+
+    /// Take a token from the rate limit bucket of the user, if rate limiting is enabled.
+    /// Requests without a user id share the same bucket.
+    pub(crate) fn check_rate_limit(
+        &self,
+        user: Option<&str>,
+        request_id: &str,
+    ) -> ServerResult<()> {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return Ok(());
+        };
+
+        let user = user
+            .filter(|user| !user.is_empty())
+            .unwrap_or(ANONYMOUS_USER);
+        rate_limiter.check(user).map_err(|retry_after| {
+            dual_warn!(
+                "Rate limit exceeded for user: {} - request_id: {}",
+                user,
+                request_id
+            );
+            ServerError::RateLimited {
+                retry_after_secs: retry_after.as_secs_f64().ceil() as u64,
+            }
+        })
+    }
+
+    /// Accumulate the token usage of a chat completion for the user who sent the request
+    pub(crate) fn record_usage(&self, user: Option<&str>, usage: &endpoints::common::Usage) {
+        if let Some(user) = user {
+            self.usage
+                .record(user, usage, chrono::Utc::now().timestamp());
+        }
+    }
+
+    /// Count a tool call if metrics are enabled
+    pub(crate) fn record_tool_call(&self, tool: &str) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_tool_call(tool);
+        }
+    }
+
+    /// Reload the config file and swap it into the state
+    ///
+    /// The registered servers and the memory system are kept. The routing strategies of the
+    /// server groups are updated, the mcp servers already connected are reused, the new ones
+    /// are connected and the removed ones are disconnected. If an mcp server fails to connect,
+    /// the current config is kept. The host and port cannot change without a restart, nor can
+    /// the sections read once at startup, e.g. the api keys, the rate limits and the caches.
+    pub(crate) async fn reload_config(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> ServerResult<()> {
+        let mut new_config = Config::from_file(path)?;
+        let old_config = self.config.read().await.clone();
+
+        if (&new_config.server.host, new_config.server.port)
+            != (&old_config.server.host, old_config.server.port)
+        {
+            dual_warn!("The host and port are not reloaded. Restart the server to change them");
+            new_config.server.host = old_config.server.host.clone();
+            new_config.server.port = old_config.server.port;
+        }
+
+        fn changed<T: serde::Serialize>(old: &T, new: &T) -> bool {
+            serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+        }
+        let restart_only = [
+            ("memory", changed(&old_config.memory, &new_config.memory)),
+            (
+                "idempotency",
+                changed(&old_config.idempotency, &new_config.idempotency),
+            ),
+            ("metrics", changed(&old_config.metrics, &new_config.metrics)),
+            ("auth", changed(&old_config.auth, &new_config.auth)),
+            (
+                "rate_limit",
+                changed(&old_config.rate_limit, &new_config.rate_limit),
+            ),
+            (
+                "registry",
+                changed(&old_config.registry, &new_config.registry),
+            ),
+            (
+                "coalescing",
+                changed(&old_config.coalescing, &new_config.coalescing),
+            ),
+            (
+                "response_cache",
+                changed(&old_config.response_cache, &new_config.response_cache),
+            ),
+        ];
+        for (section, _) in restart_only.iter().filter(|(_, changed)| *changed) {
+            dual_warn!(
+                "The [{}] section is not reloaded. Restart the server to change it",
+                section
+            );
+        }
+
+        // the connected mcp servers which are kept, the others are disconnected after the swap
+        let mut kept = Vec::new();
+        if let Some(mcp_config) = new_config.mcp.as_mut() {
+            for server_config in mcp_config.server.tool_servers.iter_mut() {
+                let connected = old_config.mcp.as_ref().and_then(|old_mcp_config| {
+                    old_mcp_config.server.tool_servers.iter().find(|old| {
+                        server_config.enable
+                            && old.name == server_config.name
+                            && old.url == server_config.url
+                            && old.server_name.is_some()
+                    })
+                });
+                if let Some(connected) = connected {
+                    server_config.server_name = connected.server_name.clone();
+                    server_config.tools = connected.tools.clone();
+                    kept.extend(connected.server_name.clone());
+                }
+            }
+        }
+        let removed: Vec<_> = old_config
+            .mcp
+            .iter()
+            .flat_map(|old_mcp_config| old_mcp_config.server.tool_servers.iter())
+            .filter_map(|old| old.server_name.clone())
+            .filter(|server_name| !kept.contains(server_name))
+            .collect();
+        let removed = mcp::take_mcp_services(&removed).await;
+
+        if let Some(mcp_config) = new_config.mcp.as_mut() {
+            let mut connected = Vec::new();
+            for server_config in mcp_config
+                .server
+                .tool_servers
+                .iter_mut()
+                .filter(|server_config| server_config.server_name.is_none())
+            {
+                if let Err(e) = server_config.connect_mcp_server().await {
+                    mcp::close_mcp_services(mcp::take_mcp_services(&connected).await).await;
+                    mcp::restore_mcp_services(removed).await;
+                    return Err(e);
+                }
+                connected.extend(server_config.server_name.clone());
+            }
+        }
+
+        let routing_config = new_config.routing.clone().unwrap_or_default();
+        for (kind, group) in self.server_group.read().await.iter() {
+            group.set_strategy(routing_config.strategy(*kind));
+        }
+
+        *self.config.write().await = new_config;
+
+        mcp::close_mcp_services(removed).await;
+
+        Ok(())
+    }
+
+    /// Replace the tools of the given mcp server exposed to the model, e.g. after a reconnection
+    pub(crate) async fn refresh_mcp_tools(&self, server_name: &str, tools: Vec<rmcp::model::Tool>) {
+        if let Some(mcp_config) = self.config.write().await.mcp.as_mut() {
+            for server_config in mcp_config
+                .server
+                .tool_servers
+                .iter_mut()
+                .filter(|server_config| server_config.server_name.as_deref() == Some(server_name))
+            {
+                server_config.tools = Some(tools.clone());
+            }
+        }
+    }
+
+    /// Render the metrics in the Prometheus text format, or None if metrics are disabled
+    pub(crate) async fn render_metrics(&self) -> Option<String> {
+        let metrics = self.metrics.as_ref()?;
+
+        let mut in_flight = std::collections::BTreeMap::new();
+        for (kind, group) in self.server_group.read().await.iter() {
+            in_flight.insert(kind.to_string(), group.in_flight().await);
+        }
+
+        Some(metrics.render(&in_flight))
+    }
+
+    /// Snapshot of the load of the gateway: the `/v1` requests being handled and served, and
+    /// per server kind, the requests in flight on the downstream servers and the number of
+    /// registered and healthy servers
+    pub(crate) async fn stats(&self) -> serde_json::Value {
+        let mut servers = serde_json::Map::new();
+        for (kind, group) in self.server_group.read().await.iter() {
+            servers.insert(
+                kind.to_string(),
+                serde_json::json!({
+                    "in_flight": group.in_flight().await,
+                    "registered": group.len().await,
+                    "healthy": group.num_healthy_servers().await,
+                }),
+            );
+        }
+
+        serde_json::json!({
+            "requests_in_flight": self.requests.in_flight(),
+            "requests_served": self.requests.served(),
+            "servers": servers,
+        })
+    }
+
+    /// Returns true if at least one downstream server of the given kind is registered
+    pub(crate) async fn has_downstream_server(&self, kind: ServerKind) -> bool {
+        match self.server_group.read().await.get(&kind) {
+            Some(group) => !group.is_empty().await,
+            None => false,
+        }
+    }
+
     pub(crate) async fn list_downstream_servers(
         &self,
     ) -> ServerResult<HashMap<ServerKind, Vec<Server>>> {
@@ -605,98 +1204,90 @@ impl AppState {
         Ok(server_groups)
     }
 
+    /// Probe the health of every registered server and update its health status, so that the
+    /// router skips the servers that failed the probe
     pub(crate) async fn check_server_health(&self) -> ServerResult<()> {
-        if !self.server_group.read().await.is_empty() {
-            let mut unhealthy_servers = Vec::new();
-
-            // Check health status of downstream servers
-            // 1. Get all registered downstream servers
-            // 2. Check health status of downstream servers
-            //   2.1 If a downstream server has multiple types, only perform one health check
-            //   2.2 If there are multiple downstream servers of the same type, health checks are needed for all
-            //   2.3 If two or more downstream servers have different types but the same URL, only perform one health check
-            // 3. Remove unhealthy downstream servers
-            {
-                let group_map = self.server_group.read().await;
-
-                // check health of unique servers
-                let mut unique_server_ids = HashSet::new();
-                for (kind, group) in group_map.iter() {
-                    if !group.is_empty().await {
-                        let servers = group.servers.read().await;
-                        for server_lock in servers.iter() {
-                            let mut server = server_lock.write().await;
-
-                            if !unique_server_ids.contains(&server.id)
-                                && unique_server_ids.contains(&server.url)
-                            {
-                                dual_info!("Checking health of {}", &server.id);
-
-                                unique_server_ids.insert(server.id.clone());
-                                unique_server_ids.insert(server.url.clone());
-
-                                let is_healthy = server.check_health().await;
-                                if !is_healthy {
-                                    dual_warn!("{} server {} is unhealthy", kind, &server.id);
-                                    unhealthy_servers.push(server.id.clone());
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        if self.server_group.read().await.is_empty() {
+            dual_warn!("No servers registered, skipping health check");
+            return Ok(());
+        }
 
-            // Unregister unhealthy servers
-            if !unhealthy_servers.is_empty() {
-                for server_id in unhealthy_servers {
-                    self.unregister_downstream_server(&server_id).await?;
+        // A server with multiple kinds, or servers of different kinds sharing the same url, are
+        // registered in several groups: probe each url only once
+        let mut urls = HashSet::new();
+        {
+            let group_map = self.server_group.read().await;
+            for group in group_map.values() {
+                for server_lock in group.servers.read().await.iter() {
+                    urls.insert(server_lock.read().await.url.clone());
                 }
             }
+        }
 
-            // Push the healthy servers to the external service if configured
-            if let Some(push_url) = &self.config.read().await.server_health_push_url {
-                // collect the healthy servers by kind
-                let mut healthy_servers: HashMap<ServerKind, Vec<String>> = HashMap::new();
-                {
-                    let group_map = self.server_group.read().await;
-                    for (kind, group) in group_map.iter() {
-                        if group.is_empty().await {
-                            dual_warn!("No {} servers available after health check", kind);
-                        }
-
-                        healthy_servers.insert(
-                            *kind,
-                            group.healthy_servers.read().await.iter().cloned().collect(),
-                        );
+        let results: HashMap<String, bool> =
+            futures_util::future::join_all(urls.into_iter().map(|url| async move {
+                dual_debug!("Checking health of {}", &url);
+                let is_healthy = server::probe_health(&url).await;
+                (url, is_healthy)
+            }))
+            .await
+            .into_iter()
+            .collect();
+
+        // update the health status of the servers and collect the healthy ones by kind
+        let mut healthy_servers: HashMap<ServerKind, Vec<String>> = HashMap::new();
+        {
+            let group_map = self.server_group.read().await;
+            for (kind, group) in group_map.iter() {
+                let healthy = healthy_servers.entry(*kind).or_default();
+                for server_lock in group.servers.read().await.iter() {
+                    let mut server = server_lock.write().await;
+                    if let Some(is_healthy) = results.get(&server.url) {
+                        server.set_health(*is_healthy);
+                    }
+                    if server.health_status.is_healthy {
+                        healthy.push(server.id.clone());
                     }
                 }
 
-                let health_status = serde_json::json!({
-                    "rag": self.config.read().await.rag.as_ref().unwrap().enable,
-                    "servers": healthy_servers,
-                });
+                if healthy.is_empty() {
+                    dual_warn!("No {} servers available after health check", kind);
+                }
+            }
+        }
 
-                dual_debug!(
-                    "Healthy servers:\n{}",
-                    serde_json::to_string_pretty(&health_status).unwrap()
-                );
+        // Push the healthy servers to the external service if configured
+        let (push_url, rag) = {
+            let config = self.config.read().await;
+            (
+                config.server_health_push_url.clone(),
+                config.rag.as_ref().is_some_and(|rag| rag.enable),
+            )
+        };
+        if let Some(push_url) = push_url {
+            let health_status = serde_json::json!({
+                "rag": rag,
+                "servers": healthy_servers,
+            });
+
+            dual_debug!(
+                "Healthy servers:\n{}",
+                serde_json::to_string_pretty(&health_status).unwrap()
+            );
 
-                // Send the healthy servers to the external service
-                reqwest::Client::new()
-                    .post(push_url)
-                    .json(&health_status)
-                    .send()
-                    .await
-                    .map_err(|e| {
-                        let err_msg = format!("Failed to send health check result: {e}");
+            // Send the healthy servers to the external service
+            reqwest::Client::new()
+                .post(push_url)
+                .json(&health_status)
+                .send()
+                .await
+                .map_err(|e| {
+                    let err_msg = format!("Failed to send health check result: {e}");
 
-                        dual_error!("{}", err_msg);
+                    dual_error!("{}", err_msg);
 
-                        ServerError::Operation(err_msg)
-                    })?;
-            }
-        } else {
-            dual_warn!("No servers registered, skipping health check");
+                    ServerError::Operation(err_msg)
+                })?;
         }
 
         Ok(())
@@ -781,4 +1372,520 @@ impl AppState {
 
         Ok(())
     }
+
+    /// Register the servers saved in the registry, if enabled, probe their health and fetch
+    /// their model lists
+    pub(crate) async fn register_saved_servers(self: &Arc<Self>) -> ServerResult<()> {
+        let Some(registry) = &self.registry else {
+            return Ok(());
+        };
+
+        for entry in registry.load().await? {
+            dual_info!(
+                "Registering {} server from the registry: {}",
+                entry.kind,
+                entry.url
+            );
+            let mut server = entry.into_server()?;
+            server.health_status = server::HealthStatus {
+                is_healthy: server::probe_health(&server.url).await,
+                last_check: std::time::SystemTime::now(),
+            };
+
+            let headers = axum::http::HeaderMap::new();
+            if let Err(e) = crate::handlers::update_model_list(
+                axum::extract::State(Arc::clone(self)),
+                &headers,
+                "registry-registration",
+                &server,
+            )
+            .await
+            {
+                dual_warn!(
+                    "Failed to update model list for {} server {}: {}",
+                    server.kind,
+                    server.id,
+                    e
+                );
+                // Continue with registration even if model list update fails
+            }
+
+            self.register_downstream_server(server).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Save a change of the registered servers to the registry, if enabled. A failure is logged
+    /// but does not fail the request, as the change is already applied.
+    pub(crate) async fn save_to_registry<'a, F, Fut>(&'a self, f: F)
+    where
+        F: FnOnce(&'a ServerRegistry) -> Fut,
+        Fut: std::future::Future<Output = ServerResult<()>>,
+    {
+        if let Some(registry) = &self.registry
+            && let Err(e) = f(registry).await
+        {
+            dual_warn!("Failed to save the server registry: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::{Json, http::StatusCode};
+
+    use super::*;
+    use crate::test_utils::{create_test_state, spawn_mock_server};
+
+    /// Spawn a server whose `/info` endpoint reports the given health
+    async fn spawn_server_with_health(healthy: Arc<AtomicBool>) -> String {
+        let router = Router::new().route(
+            "/v1/info",
+            get(move || {
+                let healthy = healthy.clone();
+                async move {
+                    match healthy.load(Ordering::SeqCst) {
+                        true => Ok(Json(serde_json::json!({ "version": "test" }))),
+                        false => Err(StatusCode::SERVICE_UNAVAILABLE),
+                    }
+                }
+            }),
+        );
+        spawn_mock_server(router).await
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_server_is_excluded_after_health_check() {
+        let flaky = Arc::new(AtomicBool::new(true));
+        let flaky_url = spawn_server_with_health(flaky.clone()).await;
+        let stable_url = spawn_server_with_health(Arc::new(AtomicBool::new(true))).await;
+        let state = create_test_state(
+            Config::default(),
+            &[(&flaky_url, "chat"), (&stable_url, "chat")],
+        )
+        .await;
+
+        let next_urls = || async {
+            let groups = state.server_group.read().await;
+            let group = groups.get(&ServerKind::chat).unwrap();
+            let mut urls = HashSet::new();
+            for _ in 0..4 {
+                urls.insert(group.next().await.unwrap().url);
+            }
+            urls
+        };
+
+        state.check_server_health().await.unwrap();
+        assert!(next_urls().await.contains(&flaky_url));
+
+        // the server goes down and is excluded after the next check cycle
+        flaky.store(false, Ordering::SeqCst);
+        state.check_server_health().await.unwrap();
+        let urls = next_urls().await;
+        assert!(!urls.contains(&flaky_url));
+        assert!(urls.contains(&stable_url));
+
+        // it is routed to again once it recovers
+        flaky.store(true, Ordering::SeqCst);
+        state.check_server_health().await.unwrap();
+        assert!(next_urls().await.contains(&flaky_url));
+    }
+
+    #[tokio::test]
+    async fn test_missing_server_is_service_unavailable() {
+        let state = create_test_state(Config::default(), &[]).await;
+
+        let err = state
+            .send_with_failover_for_model(
+                ServerKind::chat,
+                Some("test-model"),
+                |server| reqwest::Client::new().post(&server.url),
+                &CancellationToken::new(),
+                "test-request",
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ServerError::NotFoundServer(_)), "{err:?}");
+        assert_eq!(
+            axum::response::IntoResponse::into_response(err).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_routed_to_the_server_of_the_model() {
+        let llama_url = spawn_mock_server(Router::new()).await;
+        let mistral_url = spawn_mock_server(Router::new()).await;
+        let state = create_test_state(
+            Config::default(),
+            &[(&llama_url, "chat"), (&mistral_url, "chat")],
+        )
+        .await;
+        for server in state.list_downstream_servers().await.unwrap()[&ServerKind::chat].iter() {
+            let model = match server.url == llama_url {
+                true => "llama-3-70b",
+                false => "mistral-7b",
+            };
+            state.models.write().await.insert(
+                server.id.clone(),
+                vec![endpoints::models::Model {
+                    id: model.to_string(),
+                    created: 1_700_000_000,
+                    object: "model".to_string(),
+                    owned_by: "test".to_string(),
+                }],
+            );
+        }
+
+        let send = |model: &'static str| {
+            let state = state.clone();
+            async move {
+                let (target_server, _) = state
+                    .send_with_failover_for_model(
+                        ServerKind::chat,
+                        Some(model),
+                        |server| reqwest::Client::new().get(&server.url),
+                        &CancellationToken::new(),
+                        "req-1",
+                    )
+                    .await
+                    .unwrap();
+                target_server.url
+            }
+        };
+
+        for _ in 0..3 {
+            assert_eq!(send("llama-3-70b").await, llama_url);
+            assert_eq!(send("mistral-7b").await, mistral_url);
+        }
+
+        // a model served by none of the servers goes to the general pool
+        let mut urls = HashSet::new();
+        for _ in 0..4 {
+            urls.insert(send("unknown-model").await);
+        }
+        assert_eq!(urls.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_config_is_reloaded() {
+        let path = std::env::temp_dir().join(format!("llama-nexus-{}.toml", uuid::Uuid::new_v4()));
+        let write_config = |extra: &str| {
+            let config = format!("[server]\nhost = \"127.0.0.1\"\nport = 3389\n{extra}");
+            std::fs::write(&path, config).unwrap();
+        };
+        write_config("");
+        let state = create_test_state(Config::from_file(&path).unwrap(), &[]).await;
+
+        // the new config is swapped in, except for the sections read once at startup
+        write_config(
+            "sse_keepalive_secs = 15\n[routing]\nstrategy = { chat = \"least-connections\" }\n[rate_limit]\nenable = true\nrequests_per_minute = 10\n",
+        );
+        state.reload_config(&path).await.unwrap();
+
+        assert_eq!(state.config.read().await.server.sse_keepalive_secs, 15);
+        let routing = state.config.read().await.routing.clone().unwrap();
+        assert_eq!(
+            routing.strategy(ServerKind::chat),
+            server::RoutingStrategy::LeastConnections
+        );
+        assert!(state.rate_limiter.is_none());
+
+        // an invalid config is rejected and the current one kept
+        std::fs::write(&path, "[server]\nhost = \"not-an-ip\"\nport = 3389\n").unwrap();
+        assert!(state.reload_config(&path).await.is_err());
+        assert_eq!(state.config.read().await.server.sse_keepalive_secs, 15);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_stats_report_the_requests_in_flight() {
+        // the chat server answers once released
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let release_rx = Arc::new(tokio::sync::Mutex::new(Some(release_rx)));
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let release_rx = release_rx.clone();
+                async move {
+                    if let Some(release_rx) = release_rx.lock().await.take() {
+                        let _ = release_rx.await;
+                    }
+                    Json(crate::test_utils::chat_completion_json("Hello!"))
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+        let app = Router::new()
+            .route("/v1/chat/completions", post(handlers::chat_handler))
+            .route("/admin/stats", get(handlers::admin::stats_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                track_request,
+            ))
+            .with_state(state.clone());
+
+        let stats = || async {
+            let request = Request::get("/admin/stats").body(Body::empty()).unwrap();
+            let response = tower::ServiceExt::oneshot(app.clone(), request)
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let request = Request::post("/v1/chat/completions")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"model":"test-model","messages":[{"role":"user","content":"Hi"}]}"#,
+            ))
+            .unwrap();
+        let pending = tokio::spawn(tower::ServiceExt::oneshot(app.clone(), request));
+
+        // the blocked request is counted in flight
+        let mut snapshot = stats().await;
+        for _ in 0..100 {
+            if snapshot["servers"]["chat"]["in_flight"] == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            snapshot = stats().await;
+        }
+        assert_eq!(snapshot["requests_in_flight"], 1);
+        assert_eq!(snapshot["requests_served"], 0);
+        assert_eq!(snapshot["servers"]["chat"]["in_flight"], 1);
+        assert_eq!(snapshot["servers"]["chat"]["registered"], 1);
+        assert_eq!(snapshot["servers"]["chat"]["healthy"], 1);
+
+        // and released once it completes
+        release_tx.send(()).unwrap();
+        let response = pending.await.unwrap().unwrap();
+        assert!(response.status().is_success());
+        let snapshot = stats().await;
+        assert_eq!(snapshot["requests_in_flight"], 0);
+        assert_eq!(snapshot["requests_served"], 1);
+        assert_eq!(snapshot["servers"]["chat"]["in_flight"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_requests_are_served_once_sent() {
+        // the chat server sends the end of the stream once released
+        let release = Arc::new(tokio::sync::Notify::new());
+        let router = {
+            let release = release.clone();
+            Router::new().route(
+                "/v1/chat/completions",
+                post(move || {
+                    let release = release.clone();
+                    async move {
+                        let (tx, rx) = tokio::sync::mpsc::channel::<String>(4);
+                        tokio::spawn(async move {
+                            tx.send(crate::test_utils::sse_chunk("Hi")).await.unwrap();
+                            release.notified().await;
+                            tx.send("data: [DONE]\n\n".to_string()).await.unwrap();
+                        });
+                        let events = stream::unfold(rx, |mut rx| async move {
+                            rx.recv()
+                                .await
+                                .map(|event| (Ok::<_, std::convert::Infallible>(event), rx))
+                        });
+                        (
+                            [(http::header::CONTENT_TYPE, "text/event-stream")],
+                            Body::from_stream(events),
+                        )
+                    }
+                }),
+            )
+        };
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "chat")]).await;
+        let app = Router::new()
+            .route("/v1/chat/completions", post(handlers::chat_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                track_request,
+            ))
+            .with_state(state.clone());
+
+        let request = Request::post("/v1/chat/completions")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"model":"test-model","messages":[{"role":"user","content":"Hi"}],"stream":true}"#,
+            ))
+            .unwrap();
+        let response = tower::ServiceExt::oneshot(app, request).await.unwrap();
+        assert!(response.status().is_success());
+
+        // the request is in flight while its body is streamed
+        let snapshot = state.stats().await;
+        assert_eq!(snapshot["requests_in_flight"], 1);
+        assert_eq!(snapshot["requests_served"], 0);
+
+        release.notify_one();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("[DONE]"));
+        let snapshot = state.stats().await;
+        assert_eq!(snapshot["requests_in_flight"], 0);
+        assert_eq!(snapshot["requests_served"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_server_header_names_the_chosen_server() {
+        // each embeddings server reports its own name as the model
+        let spawn_embedder = |name: &'static str| {
+            let router = Router::new().route(
+                "/v1/embeddings",
+                post(move || async move {
+                    Json(serde_json::json!({
+                        "object": "list",
+                        "data": [{ "index": 0, "object": "embedding", "embedding": [0.5] }],
+                        "model": name,
+                        "usage": { "prompt_tokens": 1, "completion_tokens": 0, "total_tokens": 1 }
+                    }))
+                }),
+            );
+            spawn_mock_server(router)
+        };
+        let first = spawn_embedder("first").await;
+        let second = spawn_embedder("second").await;
+
+        let mut config = Config::default();
+        config.server.upstream_server_header = true;
+        let state = create_test_state(
+            config,
+            &[
+                (first.as_str(), "embeddings"),
+                (second.as_str(), "embeddings"),
+            ],
+        )
+        .await;
+        let app = Router::new()
+            .route("/v1/embeddings", post(handlers::embeddings_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                track_request,
+            ))
+            .with_state(state.clone());
+
+        let servers: Vec<(String, String)> = {
+            let groups = state.server_group.read().await;
+            let mut servers = Vec::new();
+            for server in groups[&ServerKind::embeddings].servers.read().await.iter() {
+                let server = server.read().await;
+                servers.push((server.id.clone(), server.url.clone()));
+            }
+            servers
+        };
+
+        for _ in 0..2 {
+            let request = Request::post("/v1/embeddings")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"input":"hello"}"#))
+                .unwrap();
+            let response = tower::ServiceExt::oneshot(app.clone(), request)
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+
+            let url = response.headers()[UPSTREAM_SERVER_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let id = response.headers()[UPSTREAM_SERVER_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+            // the headers name the server that answered
+            let expected = match body["model"].as_str().unwrap() {
+                "first" => &first,
+                _ => &second,
+            };
+            assert_eq!(&url, expected);
+            assert!(servers.contains(&(id, url)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upstream_server_header_of_streamed_and_cached_responses() {
+        use axum::response::IntoResponse;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move |Json(request): Json<serde_json::Value>| async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if request["stream"] == true {
+                    let events = format!("{}data: [DONE]\n\n", crate::test_utils::sse_chunk("Hi"));
+                    ([(http::header::CONTENT_TYPE, "text/event-stream")], events).into_response()
+                } else {
+                    Json(crate::test_utils::chat_completion_json("Hello!")).into_response()
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+
+        let mut config = Config::default();
+        config.server.upstream_server_header = true;
+        config.server.sse_keepalive_secs = 10;
+        config.response_cache = Some(config::ResponseCacheConfig {
+            enable: true,
+            ttl_secs: 60,
+            max_entries: 10,
+        });
+        let state = create_test_state(config, &[(&url, "chat")]).await;
+        let app = Router::new()
+            .route("/v1/chat/completions", post(handlers::chat_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                track_request,
+            ))
+            .with_state(state.clone());
+
+        let send = |stream: bool| {
+            let body = serde_json::json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "temperature": 0.0,
+                "stream": stream,
+            });
+            let request = Request::post("/v1/chat/completions")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            tower::ServiceExt::oneshot(app.clone(), request)
+        };
+
+        // the streamed response names the server although its headers precede the answer
+        let response = send(true).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()[UPSTREAM_SERVER_HEADER], url.as_str());
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("[DONE]"));
+
+        // the cached response names the server that produced it
+        for _ in 0..2 {
+            let response = send(false).await.unwrap();
+            assert!(response.status().is_success());
+            assert_eq!(response.headers()[UPSTREAM_SERVER_HEADER], url.as_str());
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
 }
@@ -1,11 +1,28 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
+use endpoints::chat::{Tool, ToolFunction};
 use once_cell::sync::OnceCell;
 use rmcp::{
-    RoleClient,
+    RoleClient, ServiceError,
+    model::{CallToolRequestParam, CallToolResult, RawContent, ResourceContents, Tool as RmcpTool},
     service::{DynService, RunningService},
 };
 use tokio::sync::RwLock as TokioRwLock;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    AppState,
+    config::{
+        LongToolNamePolicy, McpConfig, McpRetryConfig, McpToolLimitsConfig, McpToolServerConfig,
+    },
+    dual_debug, dual_error, dual_info, dual_warn,
+    error::{ServerError, ServerResult},
+};
 
 // Global MCP clients
 pub static MCP_SERVICES: OnceCell<TokioRwLock<HashMap<ServiceName, TokioRwLock<McpService>>>> =
@@ -33,6 +50,11 @@ pub struct McpService {
     pub raw: RawMcpService,
     pub tools: Vec<McpToolName>,
     pub fallback_message: Option<String>,
+    /// Config the client was connected with, used to reconnect it
+    pub server_config: Option<McpToolServerConfig>,
+    /// Number of successful reconnections, so that the callers which saw the same broken
+    /// connection reconnect it only once
+    pub generation: u64,
 }
 impl McpService {
     pub fn new(name: impl AsRef<str>, raw: RawMcpService) -> Self {
@@ -41,9 +63,41 @@ impl McpService {
             raw,
             tools: Vec::new(),
             fallback_message: None,
+            server_config: None,
+            generation: 0,
         }
     }
 
+    /// Replace the connection with a new one and list the tools of the mcp server again
+    pub async fn reconnect(&mut self) -> ServerResult<Vec<RmcpTool>> {
+        let Some(server_config) = &self.server_config else {
+            let err_msg = format!(
+                "Cannot reconnect mcp client {}: no server config",
+                self.name
+            );
+            dual_error!("{}", err_msg);
+            return Err(ServerError::McpOperation(err_msg));
+        };
+
+        let raw = server_config.open_connection().await?;
+        let tools = raw.list_all_tools().await.map_err(|e| {
+            let err_msg = format!("Failed to list tools: {e}");
+            dual_error!("{}", &err_msg);
+            ServerError::McpOperation(err_msg)
+        })?;
+
+        self.raw = raw;
+        self.tools = tools.iter().map(|tool| tool.name.to_string()).collect();
+        self.generation += 1;
+        dual_info!(
+            "Reconnected to {} mcp server, found {} tools",
+            self.name,
+            tools.len()
+        );
+
+        Ok(tools)
+    }
+
     pub fn has_fallback_message(&self) -> bool {
         if let Some(fallback_message) = &self.fallback_message {
             !fallback_message.is_empty()
@@ -52,3 +106,852 @@ impl McpService {
         }
     }
 }
+
+/// Take the given mcp clients out of `MCP_SERVICES`, e.g. when their servers are removed from
+/// the config. The clients keep their connections until they are closed.
+pub(crate) async fn take_mcp_services(names: &[ServiceName]) -> Vec<McpService> {
+    let Some(services) = MCP_SERVICES.get() else {
+        return Vec::new();
+    };
+
+    let mut services = services.write().await;
+    names
+        .iter()
+        .filter_map(|name| services.remove(name))
+        .map(TokioRwLock::into_inner)
+        .collect()
+}
+
+/// Put back the mcp clients taken out by [`take_mcp_services`]
+pub(crate) async fn restore_mcp_services(taken: Vec<McpService>) {
+    let Some(services) = MCP_SERVICES.get() else {
+        return;
+    };
+
+    let mut services = services.write().await;
+    for service in taken {
+        services.insert(service.name.clone(), TokioRwLock::new(service));
+    }
+}
+
+/// Close the connections of the mcp clients taken out by [`take_mcp_services`]
+pub(crate) async fn close_mcp_services(taken: Vec<McpService>) {
+    for service in taken {
+        match service.raw.cancel().await {
+            Ok(_) => dual_info!("Disconnected from {} mcp server", service.name),
+            Err(e) => dual_warn!("Failed to close mcp client {}: {}", service.name, e),
+        }
+    }
+}
+
+/// Reject the call of a tool that is not allowed by the mcp config, in case the model names a
+/// tool it was not given
+pub(crate) async fn check_tool_allowed(
+    state: &AppState,
+    server_name: &str,
+    tool_name: &str,
+    request_id: &str,
+) -> ServerResult<()> {
+    let allowed = state
+        .config
+        .read()
+        .await
+        .mcp
+        .as_ref()
+        .is_none_or(|mcp_config| mcp_config.is_tool_allowed(server_name, tool_name));
+    if !allowed {
+        let err_msg =
+            format!("The tool {tool_name} of the {server_name} mcp server is not allowed");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::McpOperation(err_msg));
+    }
+
+    Ok(())
+}
+
+/// Call a tool of the MCP server, retrying the transport errors and timeouts according to the
+/// retry policy
+///
+/// A client whose connection was dropped is reconnected before the call, and again before
+/// retrying a call that failed because of the connection. The tools of the mcp server are
+/// refreshed on every reconnection.
+pub(crate) async fn call_tool_with_retry(
+    state: &AppState,
+    service: &TokioRwLock<McpService>,
+    request_param: CallToolRequestParam,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> Result<CallToolResult, ServiceError> {
+    let retry = state
+        .config
+        .read()
+        .await
+        .mcp
+        .as_ref()
+        .map(|mcp_config| mcp_config.retry.clone())
+        .unwrap_or_default();
+
+    let closed_generation = {
+        let service = service.read().await;
+        service
+            .raw
+            .is_transport_closed()
+            .then_some(service.generation)
+    };
+    if let Some(generation) = closed_generation {
+        reconnect_mcp_service(state, service, generation, request_id).await;
+    }
+
+    // generation of the connection used by the last attempt
+    let generation = AtomicU64::new(0);
+    retry_transient(
+        &retry,
+        cancel_token,
+        request_id,
+        || {
+            let request_param = request_param.clone();
+            let generation = &generation;
+            async move {
+                let service = service.read().await;
+                generation.store(service.generation, Ordering::Relaxed);
+                service.raw.call_tool(request_param).await
+            }
+        },
+        || {
+            reconnect_mcp_service(
+                state,
+                service,
+                generation.load(Ordering::Relaxed),
+                request_id,
+            )
+        },
+    )
+    .await
+}
+
+/// Reconnect the mcp client and refresh the tools exposed to the model, unless the connection of
+/// `generation` was already replaced by a concurrent caller. Failures are logged: the next
+/// attempt reports the broken connection.
+async fn reconnect_mcp_service(
+    state: &AppState,
+    service: &TokioRwLock<McpService>,
+    generation: u64,
+    request_id: &str,
+) {
+    let mut service = service.write().await;
+    if service.generation != generation {
+        dual_debug!(
+            "Mcp client {} was already reconnected - request_id: {}",
+            service.name,
+            request_id
+        );
+        return;
+    }
+
+    match service.reconnect().await {
+        Ok(tools) => state.refresh_mcp_tools(&service.name, tools).await,
+        Err(e) => dual_warn!(
+            "Failed to reconnect mcp client {}: {} - request_id: {}",
+            service.name,
+            e,
+            request_id
+        ),
+    }
+}
+
+/// Run `f` until it succeeds, fails with a non-transient error, or the attempts are exhausted.
+/// `reconnect` is awaited before retrying a call that failed because of the connection.
+/// Cancelling the token stops the retries.
+async fn retry_transient<T, F, Fut, R, RFut>(
+    retry: &McpRetryConfig,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+    mut f: F,
+    mut reconnect: R,
+) -> Result<T, ServiceError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ServiceError>>,
+    R: FnMut() -> RFut,
+    RFut: Future<Output = ()>,
+{
+    let max_attempts = retry.max_attempts.max(1);
+    let mut backoff = Duration::from_millis(retry.initial_backoff_ms);
+    let mut attempt = 1;
+    loop {
+        let err = match f().await {
+            Ok(result) => return Ok(result),
+            Err(err) if attempt < max_attempts && is_transient(&err) => err,
+            Err(err) => return Err(err),
+        };
+
+        dual_warn!(
+            "MCP tool call failed (attempt {}/{}): {}. Retry in {:?} - request_id: {}",
+            attempt,
+            max_attempts,
+            err,
+            backoff,
+            request_id
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = cancel_token.cancelled() => {
+                return Err(ServiceError::Cancelled {
+                    reason: Some("cancelled by client".to_string()),
+                });
+            }
+        }
+
+        if matches!(
+            err,
+            ServiceError::TransportSend(_) | ServiceError::TransportClosed
+        ) {
+            reconnect().await;
+        }
+
+        attempt += 1;
+        backoff = (backoff * 2).min(Duration::from_millis(retry.max_backoff_ms));
+    }
+}
+
+/// Whether the error may go away on a retry
+fn is_transient(err: &ServiceError) -> bool {
+    matches!(
+        err,
+        ServiceError::TransportSend(_)
+            | ServiceError::TransportClosed
+            | ServiceError::Timeout { .. }
+    )
+}
+
+/// Convert the content of a tool result into text the model can consume
+///
+/// Text and embedded text resources are returned as is. Images, audio and binary resources are
+/// replaced by a placeholder describing them, as the chat servers only take text tool results.
+pub(crate) fn content_to_text(content: &RawContent) -> String {
+    // size of the decoded data of a base64 string
+    let decoded_len = |data: &str| data.trim_end_matches('=').len() * 3 / 4;
+
+    match content {
+        RawContent::Text(text) => text.text.clone(),
+        RawContent::Image(image) => format!(
+            "[The tool returned an image ({}, {} bytes)]",
+            image.mime_type,
+            decoded_len(&image.data)
+        ),
+        RawContent::Audio(audio) => format!(
+            "[The tool returned an audio clip ({}, {} bytes)]",
+            audio.mime_type,
+            decoded_len(&audio.data)
+        ),
+        RawContent::Resource(resource) => match &resource.resource {
+            ResourceContents::TextResourceContents { text, .. } => text.clone(),
+            ResourceContents::BlobResourceContents {
+                uri,
+                mime_type,
+                blob,
+                ..
+            } => format!(
+                "[The tool returned the resource {} ({}, {} bytes)]",
+                uri,
+                mime_type.as_deref().unwrap_or("unknown type"),
+                decoded_len(blob)
+            ),
+        },
+        RawContent::ResourceLink(resource) => format!(
+            "[The tool returned a link to the resource {} ({})]",
+            resource.name, resource.uri
+        ),
+    }
+}
+
+/// Build the tools of the enabled MCP servers to inject into the chat requests
+///
+/// The configured tool limits are applied to each tool. A tool whose name collides with a previous
+/// one after truncation is given a numbered suffix, so that both stay callable.
+pub(crate) fn build_mcp_tools(mcp_config: &McpConfig) -> Vec<Tool> {
+    build_named_mcp_tools(mcp_config)
+        .into_iter()
+        .map(|(tool, _)| tool)
+        .collect()
+}
+
+/// Map the name of a tool exposed to the model (`<tool>---<server>`) back to the name of the tool
+/// on its MCP server
+pub(crate) fn resolve_tool_name(mcp_config: &McpConfig, exposed_name: &str) -> Option<McpToolName> {
+    build_named_mcp_tools(mcp_config)
+        .into_iter()
+        .find(|(tool, _)| tool.function.name == exposed_name)
+        .map(|(_, name)| name)
+}
+
+/// Build the tools of the enabled MCP servers along with the name of each tool on its MCP server
+fn build_named_mcp_tools(mcp_config: &McpConfig) -> Vec<(Tool, McpToolName)> {
+    let mut tools = Vec::new();
+    let mut names = HashSet::new();
+    for server_config in mcp_config.server.tool_servers.iter() {
+        if !server_config.enable {
+            continue;
+        }
+
+        let server_name = server_config.server_name.as_deref().unwrap();
+        for mcp_tool in server_config.tools.as_ref().unwrap().iter() {
+            if !mcp_config.is_tool_allowed(server_name, &mcp_tool.name) {
+                dual_info!(
+                    "Skip the tool {} of the {} mcp server: it is not allowed",
+                    mcp_tool.name,
+                    server_name
+                );
+                continue;
+            }
+
+            let Some(mut tool) = build_mcp_tool(mcp_tool, server_name, &mcp_config.tool_limits)
+            else {
+                continue;
+            };
+
+            if names.contains(&tool.function.name) {
+                let Some(name) = dedup_tool_name(
+                    &tool.function.name,
+                    server_name,
+                    mcp_config.tool_limits.max_name_len,
+                    &names,
+                ) else {
+                    dual_warn!(
+                        "Skip the tool {} of the {} mcp server: its name collides with another tool",
+                        mcp_tool.name,
+                        server_name
+                    );
+                    continue;
+                };
+                dual_warn!(
+                    "Rename the tool {} of the {} mcp server to {}: its name collides with another tool",
+                    mcp_tool.name,
+                    server_name,
+                    name
+                );
+                tool.function.name = name;
+            }
+
+            names.insert(tool.function.name.clone());
+            tools.push((tool, mcp_tool.name.to_string()));
+        }
+    }
+
+    tools
+}
+
+/// Give a colliding tool name the first free numbered suffix (`get_weat_2---weather`), shortening
+/// the tool part further to stay within the name limit
+fn dedup_tool_name(
+    name: &str,
+    server_name: &str,
+    max_name_len: Option<usize>,
+    names: &HashSet<String>,
+) -> Option<String> {
+    let server_suffix = format!("{MCP_SEPARATOR}{server_name}");
+    let base = name.strip_suffix(&server_suffix)?;
+
+    (2..100).find_map(|n| {
+        let number = format!("_{n}");
+        let mut end = match max_name_len {
+            Some(max_name_len) => {
+                let available = max_name_len.checked_sub(server_suffix.len() + number.len())?;
+                available.min(base.len())
+            }
+            None => base.len(),
+        };
+        while !base.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            return None;
+        }
+
+        let candidate = format!("{}{number}{server_suffix}", &base[..end]);
+        (!names.contains(&candidate)).then_some(candidate)
+    })
+}
+
+/// Add the MCP tools to the tools of a chat request
+///
+/// The tools supplied by the client take precedence: an MCP tool with the name of one of them is
+/// left out.
+pub(crate) fn merge_mcp_tools(tools: &mut Option<Vec<Tool>>, mcp_tools: Vec<Tool>) {
+    let tools = tools.get_or_insert_with(Vec::new);
+    let names: HashSet<String> = tools
+        .iter()
+        .map(|tool| tool.function.name.clone())
+        .collect();
+    for tool in mcp_tools {
+        if names.contains(&tool.function.name) {
+            dual_warn!(
+                "Skip the MCP tool {}: its name collides with a tool of the request",
+                tool.function.name
+            );
+            continue;
+        }
+
+        tools.push(tool);
+    }
+}
+
+/// Build the tool exposed to the model for an MCP tool, applying the configured limits
+///
+/// Returns `None` if the tool is rejected because of its name length.
+pub(crate) fn build_mcp_tool(
+    mcp_tool: &RmcpTool,
+    server_name: &str,
+    limits: &McpToolLimitsConfig,
+) -> Option<Tool> {
+    let suffix = format!("{MCP_SEPARATOR}{server_name}");
+    let mut tool_name = mcp_tool.name.to_string();
+
+    if let Some(max_name_len) = limits.max_name_len
+        && tool_name.len() + suffix.len() > max_name_len
+    {
+        let available = max_name_len.saturating_sub(suffix.len());
+        if limits.on_long_name == LongToolNamePolicy::Reject || available == 0 {
+            dual_warn!(
+                "Reject the tool {} of the {} mcp server: its name is longer than {} characters",
+                tool_name,
+                server_name,
+                max_name_len
+            );
+            return None;
+        }
+
+        let mut end = available;
+        while !tool_name.is_char_boundary(end) {
+            end -= 1;
+        }
+        dual_warn!(
+            "Truncate the name of the tool {} of the {} mcp server to {}",
+            tool_name,
+            server_name,
+            &tool_name[..end]
+        );
+        tool_name.truncate(end);
+    }
+
+    let mut schema = (*mcp_tool.input_schema).clone();
+    if let Some(max_schema_bytes) = limits.max_schema_bytes {
+        let schema_bytes = serde_json::to_vec(&schema).map_or(0, |bytes| bytes.len());
+        if schema_bytes > max_schema_bytes {
+            dual_warn!(
+                "The input schema of the tool {} of the {} mcp server is {} bytes, above the limit of {} bytes",
+                mcp_tool.name,
+                server_name,
+                schema_bytes,
+                max_schema_bytes
+            );
+
+            if limits.drop_optional_properties {
+                drop_optional_properties(&mut schema);
+            }
+        }
+    }
+
+    Some(Tool::new(ToolFunction {
+        name: format!("{tool_name}{suffix}"),
+        description: mcp_tool.description.as_ref().map(|s| s.to_string()),
+        parameters: Some(schema),
+    }))
+}
+
+/// Remove the properties not listed in `required` from a JSON schema object
+fn drop_optional_properties(schema: &mut serde_json::Map<String, serde_json::Value>) {
+    let required: HashSet<String> = schema
+        .get("required")
+        .and_then(|required| required.as_array())
+        .map(|required| {
+            required
+                .iter()
+                .filter_map(|name| name.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(properties) = schema
+        .get_mut("properties")
+        .and_then(|properties| properties.as_object_mut())
+    {
+        properties.retain(|name, _| required.contains(name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn create_tool(name: &str) -> RmcpTool {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "city": { "type": "string", "description": "The city to get the weather for" },
+                "unit": { "type": "string", "description": "The unit of the temperature" },
+            },
+            "required": ["city"],
+        });
+        RmcpTool::new(
+            name.to_string(),
+            "Get the weather",
+            Arc::new(schema.as_object().unwrap().clone()),
+        )
+    }
+
+    fn create_limits(max_name_len: usize, on_long_name: LongToolNamePolicy) -> McpToolLimitsConfig {
+        McpToolLimitsConfig {
+            max_name_len: Some(max_name_len),
+            on_long_name,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_client_tool_takes_precedence_over_mcp_tool() {
+        let limits = McpToolLimitsConfig::default();
+        let client_tool = Tool::new(ToolFunction {
+            name: "get_weather---weather".to_string(),
+            description: Some("The client's weather tool".to_string()),
+            parameters: None,
+        });
+        let mcp_tools = vec![
+            build_mcp_tool(&create_tool("get_weather"), "weather", &limits).unwrap(),
+            build_mcp_tool(&create_tool("get_forecast"), "weather", &limits).unwrap(),
+        ];
+
+        let mut tools = Some(vec![client_tool]);
+        merge_mcp_tools(&mut tools, mcp_tools.clone());
+        let tools = tools.unwrap();
+        let names: Vec<_> = tools
+            .iter()
+            .map(|tool| tool.function.name.as_str())
+            .collect();
+        assert_eq!(names, ["get_weather---weather", "get_forecast---weather"]);
+        assert_eq!(
+            tools[0].function.description.as_deref(),
+            Some("The client's weather tool")
+        );
+
+        // the MCP tools are the only tools of a request without any
+        let mut tools = None;
+        merge_mcp_tools(&mut tools, mcp_tools);
+        assert_eq!(tools.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_non_text_tool_results_are_described() {
+        // 4 bytes of base64 encoded png data
+        let image = RawContent::image("iVBORw==", "image/png");
+        assert_eq!(
+            content_to_text(&image),
+            "[The tool returned an image (image/png, 4 bytes)]"
+        );
+
+        let resource = RawContent::embedded_text("file:///notes.txt", "meeting at noon");
+        assert_eq!(content_to_text(&resource), "meeting at noon");
+
+        let blob = RawContent::resource(ResourceContents::BlobResourceContents {
+            uri: "file:///report.pdf".to_string(),
+            mime_type: Some("application/pdf".to_string()),
+            blob: "JVBERi0=".to_string(),
+            meta: None,
+        });
+        assert_eq!(
+            content_to_text(&blob),
+            "[The tool returned the resource file:///report.pdf (application/pdf, 5 bytes)]"
+        );
+
+        assert_eq!(content_to_text(&RawContent::text("sunny")), "sunny");
+    }
+
+    #[test]
+    fn test_long_tool_name_is_truncated() {
+        let limits = create_limits(24, LongToolNamePolicy::Truncate);
+        let tool = build_mcp_tool(
+            &create_tool("get_current_weather_by_city"),
+            "weather",
+            &limits,
+        )
+        .unwrap();
+
+        assert_eq!(tool.function.name, "get_current_we---weather");
+        assert!(tool.function.name.len() <= 24);
+    }
+
+    #[test]
+    fn test_colliding_truncated_names_are_deduplicated() {
+        let mut server_config: crate::config::McpToolServerConfig =
+            serde_json::from_value(serde_json::json!({
+                "name": "weather",
+                "transport": "stream-http",
+                "url": "http://127.0.0.1:8000/mcp",
+                "enable": true,
+            }))
+            .unwrap();
+        server_config.server_name = Some("weather".to_string());
+        server_config.tools = Some(vec![
+            create_tool("get_current_weather_by_city"),
+            create_tool("get_current_weather_by_zip"),
+        ]);
+        let mcp_config = McpConfig {
+            server: crate::config::McpServerConfig {
+                tool_servers: vec![server_config],
+            },
+            tool_limits: create_limits(24, LongToolNamePolicy::Truncate),
+            retry: Default::default(),
+            allowed_tools: vec![],
+            blocked_tools: vec![],
+        };
+
+        let names: Vec<_> = build_mcp_tools(&mcp_config)
+            .into_iter()
+            .map(|tool| tool.function.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["get_current_we---weather", "get_current__2---weather"]
+        );
+        assert!(names.iter().all(|name| name.len() <= 24));
+
+        // each exposed name maps back to its own tool
+        assert_eq!(
+            resolve_tool_name(&mcp_config, "get_current_we---weather").as_deref(),
+            Some("get_current_weather_by_city")
+        );
+        assert_eq!(
+            resolve_tool_name(&mcp_config, "get_current__2---weather").as_deref(),
+            Some("get_current_weather_by_zip")
+        );
+        assert_eq!(
+            resolve_tool_name(&mcp_config, "get_current---weather"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_long_tool_name_is_rejected() {
+        let limits = create_limits(24, LongToolNamePolicy::Reject);
+        let tool = build_mcp_tool(
+            &create_tool("get_current_weather_by_city"),
+            "weather",
+            &limits,
+        );
+        assert!(tool.is_none());
+
+        // names within the limit are kept as is
+        let tool = build_mcp_tool(&create_tool("get_weather"), "weather", &limits).unwrap();
+        assert_eq!(tool.function.name, "get_weather---weather");
+    }
+
+    #[test]
+    fn test_oversized_schema_drops_optional_properties() {
+        let limits = McpToolLimitsConfig {
+            max_schema_bytes: Some(16),
+            drop_optional_properties: true,
+            ..Default::default()
+        };
+        let tool = build_mcp_tool(&create_tool("get_weather"), "weather", &limits).unwrap();
+
+        let parameters = tool.function.parameters.unwrap();
+        let properties = parameters["properties"].as_object().unwrap();
+        assert!(properties.contains_key("city"));
+        assert!(!properties.contains_key("unit"));
+    }
+
+    #[tokio::test]
+    async fn test_transient_tool_call_error_is_retried() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        use rmcp::model::Content;
+
+        let retry = McpRetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+        };
+        let call = |fail_with: fn() -> ServiceError, failures: u32| {
+            let attempts = Arc::new(AtomicU32::new(0));
+            let counter = attempts.clone();
+            let f = move || {
+                let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match attempt < failures {
+                        true => Err(fail_with()),
+                        false => Ok(CallToolResult::success(vec![Content::text("sunny")])),
+                    }
+                }
+            };
+            (attempts, f)
+        };
+
+        // a transport error is retried and the final result is returned
+        let (attempts, f) = call(|| ServiceError::TransportClosed, 1);
+        let result =
+            retry_transient(&retry, &CancellationToken::new(), "test", f, || async {}).await;
+        assert_eq!(result.unwrap().is_error, Some(false));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // the attempts are bounded
+        let (attempts, f) = call(|| ServiceError::TransportClosed, 5);
+        let result =
+            retry_transient(&retry, &CancellationToken::new(), "test", f, || async {}).await;
+        assert!(matches!(result, Err(ServiceError::TransportClosed)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // other errors are not retried
+        let (attempts, f) = call(|| ServiceError::UnexpectedResponse, 1);
+        let result =
+            retry_transient(&retry, &CancellationToken::new(), "test", f, || async {}).await;
+        assert!(matches!(result, Err(ServiceError::UnexpectedResponse)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // a cancelled request is not retried
+        let cancel_token = CancellationToken::new();
+        cancel_token.cancel();
+        let retry = McpRetryConfig {
+            initial_backoff_ms: 60_000,
+            ..retry
+        };
+        let (attempts, f) = call(|| ServiceError::TransportClosed, 1);
+        let result = retry_transient(&retry, &cancel_token, "test", f, || async {}).await;
+        assert!(matches!(result, Err(ServiceError::Cancelled { .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_connection_is_reconnected() {
+        use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+        use rmcp::model::Content;
+
+        let retry = McpRetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+        };
+
+        // the connection is dropped until the client reconnects
+        let connected = Arc::new(AtomicBool::new(false));
+        let reconnections = Arc::new(AtomicU32::new(0));
+        let result = retry_transient(
+            &retry,
+            &CancellationToken::new(),
+            "test",
+            || {
+                let connected = connected.load(Ordering::SeqCst);
+                async move {
+                    match connected {
+                        true => Ok(CallToolResult::success(vec![Content::text("sunny")])),
+                        false => Err(ServiceError::TransportClosed),
+                    }
+                }
+            },
+            || {
+                connected.store(true, Ordering::SeqCst);
+                reconnections.fetch_add(1, Ordering::SeqCst);
+                async {}
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap().is_error, Some(false));
+        assert_eq!(reconnections.load(Ordering::SeqCst), 1);
+
+        // the tools listed after the reconnection are exposed to the model
+        let mut server_config: crate::config::McpToolServerConfig =
+            serde_json::from_value(serde_json::json!({
+                "name": "weather",
+                "transport": "stream-http",
+                "url": "http://127.0.0.1:8000/mcp",
+                "enable": true,
+            }))
+            .unwrap();
+        server_config.server_name = Some("weather".to_string());
+        server_config.tools = Some(vec![create_tool("get_weather")]);
+        let config = crate::config::Config {
+            mcp: Some(McpConfig {
+                server: crate::config::McpServerConfig {
+                    tool_servers: vec![server_config],
+                },
+                tool_limits: Default::default(),
+                retry: Default::default(),
+                allowed_tools: vec![],
+                blocked_tools: vec![],
+            }),
+            ..Default::default()
+        };
+        let state = AppState::new(config, crate::info::ServerInfo::default());
+
+        state
+            .refresh_mcp_tools("weather", vec![create_tool("get_forecast")])
+            .await;
+        let tools = build_mcp_tools(state.config.read().await.mcp.as_ref().unwrap());
+        let names: Vec<_> = tools
+            .iter()
+            .map(|tool| tool.function.name.as_str())
+            .collect();
+        assert_eq!(names, ["get_forecast---weather"]);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_tool_is_omitted_and_rejected() {
+        let mut server_config: crate::config::McpToolServerConfig =
+            serde_json::from_value(serde_json::json!({
+                "name": "weather",
+                "transport": "stream-http",
+                "url": "http://127.0.0.1:8000/mcp",
+                "enable": true,
+            }))
+            .unwrap();
+        server_config.server_name = Some("weather".to_string());
+        server_config.tools = Some(vec![
+            create_tool("get_weather"),
+            create_tool("get_forecast"),
+            create_tool("delete_city"),
+        ]);
+        let mcp_config = McpConfig {
+            server: crate::config::McpServerConfig {
+                tool_servers: vec![server_config],
+            },
+            tool_limits: Default::default(),
+            retry: Default::default(),
+            allowed_tools: vec![],
+            blocked_tools: vec!["delete_city---weather".to_string()],
+        };
+
+        let names = |mcp_config: &McpConfig| {
+            build_mcp_tools(mcp_config)
+                .into_iter()
+                .map(|tool| tool.function.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&mcp_config),
+            ["get_weather---weather", "get_forecast---weather"]
+        );
+
+        // the allowed tools restrict the remaining ones
+        let mut restricted = mcp_config.clone();
+        restricted.allowed_tools = vec!["get_weather".to_string(), "delete_city".to_string()];
+        assert_eq!(names(&restricted), ["get_weather---weather"]);
+
+        // a blocked tool cannot be called even if the model names it
+        let config = crate::config::Config {
+            mcp: Some(mcp_config),
+            ..Default::default()
+        };
+        let state = AppState::new(config, crate::info::ServerInfo::default());
+        assert!(
+            check_tool_allowed(&state, "weather", "get_weather", "test")
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            check_tool_allowed(&state, "weather", "delete_city", "test").await,
+            Err(ServerError::McpOperation(_))
+        ));
+    }
+}
@@ -1,15 +1,30 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::Duration,
+};
 
 use chrono::Utc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
 use crate::{
-    config::MemoryConfig,
-    dual_debug, dual_info,
-    memory::{store::MessageStore, summarizer::MessageSummarizer, types::*},
+    config::{MemoryBackend, MemoryConfig},
+    dual_debug, dual_info, dual_warn,
+    memory::{
+        redis::RedisStore,
+        store::{MemoryStore, MessageStore},
+        summarizer::MessageSummarizer,
+        types::*,
+    },
 };
 
+/// Approximate number of characters per token used for token estimation
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Approximate number of tokens taken by a tool call
+const TOKENS_PER_TOOL_CALL: usize = 100;
+
 /// Complete chat memory manager
 ///
 /// Provides complete lifecycle management for conversations, including message storage, context management, automatic summarization and other features.
@@ -21,11 +36,11 @@ use crate::{
 /// * Configuration-driven: Supports customizing behavior parameters through configuration files
 /// * Concurrency-safe: Uses async locks to ensure thread safety
 pub struct CompleteChatMemory {
-    /// Underlying message storage, responsible for persisting data to SQLite database
+    /// Underlying message storage, responsible for persisting data to SQLite database or Redis
     ///
     /// Provides complete CRUD operations, including message storage, conversation management, statistical queries, etc.
     /// All conversation and message data is persisted through this component.
-    store: MessageStore,
+    store: Box<dyn MemoryStore>,
 
     /// Context cache, stores working context for each conversation
     ///
@@ -66,7 +81,7 @@ impl CompleteChatMemory {
     ///
     /// # Description
     /// This method will:
-    /// 1. Initialize the underlying message storage (MessageStore)
+    /// 1. Initialize the underlying message storage selected by `config.backend`
     /// 2. Create message summarizer (MessageSummarizer)
     /// 3. Initialize context cache
     /// 4. Apply configuration parameters
@@ -75,7 +90,17 @@ impl CompleteChatMemory {
     /// * `MemoryError::DatabaseError` - When database connection or initialization fails
     pub async fn new(config: MemoryConfig) -> MemoryResult<Self> {
         // Initialize message storage
-        let store = MessageStore::new(&config.database_path).await?;
+        let store: Box<dyn MemoryStore> = match config.backend {
+            MemoryBackend::Sqlite => Box::new(MessageStore::new(&config.database_path).await?),
+            MemoryBackend::Redis => {
+                let url = config.redis_url.as_deref().ok_or_else(|| {
+                    MemoryError::InvalidConfig(
+                        "`redis_url` is required by the redis memory backend".to_string(),
+                    )
+                })?;
+                Box::new(RedisStore::new(url).await?)
+            }
+        };
 
         // Create message summarizer
         let summarizer = MessageSummarizer::new(
@@ -197,14 +222,8 @@ impl CompleteChatMemory {
         model_name: &str,
     ) -> MemoryResult<String> {
         // Try to get any conversation for the user (regardless of model)
-        if let Some(recent_conv) = self
-            .store
-            .get_recent_conversation_by_user(user_id, None)
-            .await?
-        {
-            // Conversation exists, directly reuse, ensure it's in cache
-            self.ensure_conversation_in_cache(&recent_conv.id).await?;
-            return Ok(recent_conv.id);
+        if let Some(conv_id) = self.find_user_conversation(user_id).await? {
+            return Ok(conv_id);
         }
 
         // No conversation found, create new one
@@ -212,6 +231,28 @@ impl CompleteChatMemory {
             .await
     }
 
+    /// Find the conversation of a user without creating one
+    ///
+    /// # Parameters
+    /// * `user_id` - User ID
+    ///
+    /// # Returns
+    /// * `MemoryResult<Option<String>>` - Returns the conversation ID on success, None if the user has no conversation
+    pub async fn find_user_conversation(&self, user_id: &str) -> MemoryResult<Option<String>> {
+        match self
+            .store
+            .get_recent_conversation_by_user(user_id, None)
+            .await?
+        {
+            Some(recent_conv) => {
+                // Conversation exists, directly reuse, ensure it's in cache
+                self.ensure_conversation_in_cache(&recent_conv.id).await?;
+                Ok(Some(recent_conv.id))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Ensure conversation is in cache
     ///
     /// # Parameters
@@ -355,6 +396,72 @@ impl CompleteChatMemory {
         Ok(MessageResult::new(message, summarization_status))
     }
 
+    /// Get the sequence the next message of a conversation will be stored with
+    pub async fn next_sequence(&self, conv_id: &str) -> MemoryResult<i64> {
+        self.store.get_next_sequence(conv_id).await
+    }
+
+    /// Remove the messages of a conversation from the given sequence (inclusive), e.g. the user
+    /// message of a turn that failed before it was answered
+    ///
+    /// # Returns
+    /// * `MemoryResult<usize>` - Returns the number of removed messages
+    pub async fn rollback_messages(
+        &self,
+        conv_id: &str,
+        from_sequence: i64,
+    ) -> MemoryResult<usize> {
+        let deleted = self
+            .store
+            .delete_messages_from_sequence(conv_id, from_sequence)
+            .await?;
+
+        let mut cache = self.context_cache.lock().await;
+        if let Some(context) = cache.get_mut(conv_id) {
+            context
+                .working_messages
+                .retain(|message| message.sequence < from_sequence);
+            context.total_tokens = self.calculate_total_tokens(&context.working_messages);
+        }
+
+        Ok(deleted)
+    }
+
+    /// Replace the content of the last message of a conversation if it is an answer of the
+    /// assistant, e.g. once the answer has been rewritten before it is returned to the client
+    ///
+    /// # Returns
+    /// * `MemoryResult<bool>` - Returns false if the last message is not an answer of the assistant
+    pub async fn replace_last_assistant_message(
+        &self,
+        conv_id: &str,
+        content: &str,
+    ) -> MemoryResult<bool> {
+        let Some(last) = self.store.get_recent_messages(conv_id, 1).await?.pop() else {
+            return Ok(false);
+        };
+        if last.role != MessageRole::Assistant || !last.tool_calls.is_empty() {
+            return Ok(false);
+        }
+
+        self.store
+            .update_message_content(conv_id, &last.id, content)
+            .await?;
+
+        let mut cache = self.context_cache.lock().await;
+        if let Some(context) = cache.get_mut(conv_id)
+            && let Some(message) = context
+                .working_messages
+                .iter_mut()
+                .find(|message| message.id == last.id)
+        {
+            message.content = content.to_string();
+            context.total_tokens = self.calculate_total_tokens(&context.working_messages);
+        }
+
+        Ok(true)
+    }
+
     /// Add or update conversation's system message
     ///
     /// # Parameters
@@ -439,6 +546,45 @@ impl CompleteChatMemory {
     /// * `MemoryError::ConversationNotFound` - When specified conversation doesn't exist
     #[allow(dead_code)]
     pub async fn get_model_context(&self, conv_id: &str) -> MemoryResult<Vec<ModelMessage>> {
+        self.build_model_context(conv_id, None, None).await
+    }
+
+    /// Get the context messages the model would receive if a system message and a user message
+    /// were added to the conversation, without storing them
+    ///
+    /// # Parameters
+    /// * `conv_id` - Target conversation ID
+    /// * `system_message` - System message replacing the stored one, if any
+    /// * `user_message` - User message appended to the working context
+    ///
+    /// # Returns
+    /// * `MemoryResult<Vec<ModelMessage>>` - Returns formatted message list on success, MemoryError on failure
+    pub async fn preview_model_context(
+        &self,
+        conv_id: &str,
+        system_message: Option<&str>,
+        user_message: &str,
+    ) -> MemoryResult<Vec<ModelMessage>> {
+        let message = StoredMessage {
+            id: Uuid::new_v4().to_string(),
+            conversation_id: conv_id.to_string(),
+            role: MessageRole::User,
+            content: user_message.to_string(),
+            timestamp: Utc::now(),
+            sequence: 0,
+            tokens: None,
+            tool_calls: Vec::new(),
+        };
+        self.build_model_context(conv_id, system_message, Some(message))
+            .await
+    }
+
+    async fn build_model_context(
+        &self,
+        conv_id: &str,
+        system_message: Option<&str>,
+        new_message: Option<StoredMessage>,
+    ) -> MemoryResult<Vec<ModelMessage>> {
         let cache = self.context_cache.lock().await;
         let context = cache
             .get(conv_id)
@@ -453,8 +599,10 @@ impl CompleteChatMemory {
         let mut system_content_parts = Vec::new();
 
         // First add stored system message (if exists)
-        if let Some(system_message) = &conversation.system_message {
-            system_content_parts.push(system_message.clone());
+        if let Some(system_message) = system_message.or(conversation.system_message.as_deref())
+            && !system_message.is_empty()
+        {
+            system_content_parts.push(system_message.to_string());
         }
 
         // Then add conversation summary (if exists)
@@ -472,49 +620,20 @@ impl CompleteChatMemory {
             });
         }
 
-        // Convert working messages to model format
-        for stored_msg in &context.working_messages {
-            // Handle tool calls for Assistant messages
-            let tool_calls = if !stored_msg.tool_calls.is_empty() {
-                Some(self.convert_to_model_tool_calls(&stored_msg.tool_calls))
-            } else {
-                None
-            };
-
-            // Add Assistant message (contains tool call requests, but not results)
-            model_messages.push(ModelMessage {
-                role: stored_msg.role.into(),
-                content: stored_msg.content.clone(),
-                tool_calls,
-                tool_call_id: None,
-            });
+        // Convert working messages to model format, grouping each message with its tool results
+        let mut groups = Vec::with_capacity(context.working_messages.len());
+        for stored_msg in context.working_messages.iter().chain(&new_message) {
+            groups.push(self.to_model_messages(stored_msg));
+        }
 
-            // Generate independent tool message for each tool call with execution result
-            for tool_call in &stored_msg.tool_calls {
-                if let Some(result) = &tool_call.result {
-                    let tool_result_content = if result.success {
-                        // Successful tool call: return actual result
-                        match &result.content {
-                            serde_json::Value::String(s) => s.clone(),
-                            other => other.to_string(),
-                        }
-                    } else {
-                        // Failed tool call: return error information
-                        format!(
-                            "Tool execution failed: {}",
-                            result.error.as_deref().unwrap_or("Unknown error")
-                        )
-                    };
-
-                    model_messages.push(ModelMessage {
-                        role: ModelRole::Tool,
-                        content: tool_result_content,
-                        tool_calls: None,
-                        tool_call_id: Some(tool_call.id.clone()),
-                    });
-                }
-            }
+        if let Some(budget) = self.config.context_token_budget {
+            let system_tokens = model_messages
+                .iter()
+                .map(estimate_model_message_tokens)
+                .sum();
+            trim_to_token_budget(conv_id, &mut groups, system_tokens, budget);
         }
+        model_messages.extend(groups.into_iter().flatten());
 
         Ok(model_messages)
     }
@@ -816,11 +935,61 @@ impl CompleteChatMemory {
 
     fn estimate_message_tokens(&self, message: &StoredMessage) -> usize {
         // Simplified token estimation
-        let content_tokens = message.content.len() / 4;
-        let tool_tokens = message.tool_calls.len() * 100; // About 100 tokens per tool call
+        let content_tokens = message.content.len() / CHARS_PER_TOKEN;
+        let tool_tokens = message.tool_calls.len() * TOKENS_PER_TOOL_CALL;
         content_tokens + tool_tokens
     }
 
+    /// Convert a stored message into model messages
+    ///
+    /// The message itself comes first, followed by an independent tool message for each of its
+    /// tool calls that has an execution result.
+    fn to_model_messages(&self, stored_msg: &StoredMessage) -> Vec<ModelMessage> {
+        let mut group = Vec::new();
+        // Handle tool calls for Assistant messages
+        let tool_calls = if !stored_msg.tool_calls.is_empty() {
+            Some(self.convert_to_model_tool_calls(&stored_msg.tool_calls))
+        } else {
+            None
+        };
+
+        // Add Assistant message (contains tool call requests, but not results)
+        group.push(ModelMessage {
+            role: stored_msg.role.into(),
+            content: stored_msg.content.clone(),
+            tool_calls,
+            tool_call_id: None,
+        });
+
+        // Generate independent tool message for each tool call with execution result
+        for tool_call in &stored_msg.tool_calls {
+            if let Some(result) = &tool_call.result {
+                let tool_result_content = if result.success {
+                    // Successful tool call: return actual result
+                    match &result.content {
+                        serde_json::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    }
+                } else {
+                    // Failed tool call: return error information
+                    format!(
+                        "Tool execution failed: {}",
+                        result.error.as_deref().unwrap_or("Unknown error")
+                    )
+                };
+
+                group.push(ModelMessage {
+                    role: ModelRole::Tool,
+                    content: tool_result_content,
+                    tool_calls: None,
+                    tool_call_id: Some(tool_call.id.clone()),
+                });
+            }
+        }
+
+        group
+    }
+
     fn convert_to_model_tool_calls(&self, tool_calls: &[StoredToolCall]) -> Vec<ModelToolCall> {
         tool_calls
             .iter()
@@ -983,6 +1152,30 @@ impl CompleteChatMemory {
         Ok(messages)
     }
 
+    /// Export complete conversation history as model messages
+    ///
+    /// # Parameters
+    /// * `conv_id` - Unique identifier of the conversation
+    ///
+    /// # Returns
+    /// * `MemoryResult<Vec<ModelMessage>>` - Returns the exported messages on success, MemoryError on failure
+    ///
+    /// # Description
+    /// Returns the complete history including the system message, in the same shape that is sent to the model:
+    /// tool calls are attached to assistant messages and each tool result follows as a tool message.
+    /// Unlike `get_model_context`, summarized messages are included and no token budget is applied.
+    ///
+    /// # Errors
+    /// * `MemoryError::ConversationNotFound` - When specified conversation doesn't exist
+    pub async fn export_conversation(&self, conv_id: &str) -> MemoryResult<Vec<ModelMessage>> {
+        let messages = self.get_full_history(conv_id, true).await?;
+
+        Ok(messages
+            .iter()
+            .flat_map(|stored_msg| self.to_model_messages(stored_msg))
+            .collect())
+    }
+
     /// Get complete chat history by user ID
     ///
     /// # Parameters
@@ -1041,15 +1234,46 @@ impl CompleteChatMemory {
     /// 1. Context data in memory cache
     /// 2. Conversation records and all messages in database
     ///
-    /// Note: This operation is irreversible, please use with caution. Due to foreign key constraint cascade deletion,
-    /// deleting a conversation will automatically delete all messages under that conversation.
-    #[allow(dead_code)]
+    /// Note: This operation is irreversible, please use with caution. The cached working messages and summary
+    /// are dropped together with the persisted conversation and its messages.
+    ///
+    /// # Errors
+    /// * `MemoryError::ConversationNotFound` - When specified conversation doesn't exist
     pub async fn delete_conversation(&self, conv_id: &str) -> MemoryResult<()> {
         // Remove from cache
-        self.context_cache.lock().await.remove(conv_id);
+        let cached = self.context_cache.lock().await.remove(conv_id).is_some();
 
         // Delete from database
-        self.store.delete_conversation(conv_id).await
+        let deleted = self.store.delete_conversation(conv_id).await?;
+
+        match cached || deleted {
+            true => Ok(()),
+            false => Err(MemoryError::ConversationNotFound(conv_id.to_string())),
+        }
+    }
+
+    /// Delete all conversations of the specified user and all their related data
+    ///
+    /// # Parameters
+    /// * `user_id` - User's unique identifier
+    ///
+    /// # Returns
+    /// * `MemoryResult<usize>` - Returns the number of deleted conversations on success, MemoryError on failure
+    ///
+    /// # Description
+    /// Removes every conversation owned by the user from the database and drops their context cache.
+    /// A user without conversations is not an error, and 0 is returned.
+    ///
+    /// Note: This operation is irreversible, please use with caution.
+    pub async fn delete_user_conversations(&self, user_id: &str) -> MemoryResult<usize> {
+        let conv_ids = self.store.delete_conversations_by_user(user_id).await?;
+
+        let mut cache = self.context_cache.lock().await;
+        for conv_id in &conv_ids {
+            cache.remove(conv_id);
+        }
+
+        Ok(conv_ids.len())
     }
 
     /// Get all historical messages for full history summarization
@@ -1123,4 +1347,308 @@ impl CompleteChatMemory {
     pub async fn get_stats(&self) -> MemoryResult<MemoryStats> {
         self.store.get_stats().await
     }
+
+    /// Delete the conversations idle for longer than `conversation_ttl_secs`
+    ///
+    /// # Returns
+    /// * `MemoryResult<usize>` - Returns the number of deleted conversations on success, MemoryError on failure
+    ///
+    /// # Description
+    /// The last activity of a conversation is the `updated_at` time of the conversation, which is
+    /// refreshed every time a message is added by `add_user_message` or `add_assistant_message`.
+    /// The expired conversations are removed from the database and from the context cache.
+    /// Nothing is deleted if the TTL is 0.
+    pub async fn sweep_expired_conversations(&self) -> MemoryResult<usize> {
+        if self.config.conversation_ttl_secs == 0 {
+            return Ok(0);
+        }
+
+        let ttl = chrono::Duration::seconds(self.config.conversation_ttl_secs as i64);
+        let conv_ids = self
+            .store
+            .delete_conversations_idle_since(Utc::now() - ttl)
+            .await?;
+
+        let mut cache = self.context_cache.lock().await;
+        for conv_id in &conv_ids {
+            cache.remove(conv_id);
+        }
+
+        if !conv_ids.is_empty() {
+            dual_info!("Deleted {} expired conversations", conv_ids.len());
+        }
+
+        Ok(conv_ids.len())
+    }
+
+    /// Spawn a task sweeping the expired conversations periodically
+    ///
+    /// # Description
+    /// The sweep runs every `conversation_ttl_secs`, at most every minute. No task is spawned if
+    /// the TTL is 0, and the task stops once the memory manager is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>) {
+        if self.config.conversation_ttl_secs == 0 {
+            return;
+        }
+
+        let period = Duration::from_secs(self.config.conversation_ttl_secs.min(60));
+        let memory: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(memory) = memory.upgrade() else {
+                    break;
+                };
+                if let Err(e) = memory.sweep_expired_conversations().await {
+                    dual_warn!("Failed to sweep the expired conversations: {}", e);
+                }
+            }
+        });
+    }
+}
+
+fn estimate_model_message_tokens(message: &ModelMessage) -> usize {
+    let content_tokens = message.content.len() / CHARS_PER_TOKEN;
+    let tool_tokens = message
+        .tool_calls
+        .as_ref()
+        .map_or(0, |tool_calls| tool_calls.len())
+        * TOKENS_PER_TOOL_CALL;
+    content_tokens + tool_tokens
+}
+
+/// Drop the oldest message groups until the estimated token count of the context fits the budget
+///
+/// A group is a working message together with its tool results, so tool messages are never
+/// separated from the assistant message calling them. The group of the latest user message and
+/// all groups after it are always kept, even if they alone exceed the budget.
+fn trim_to_token_budget(
+    conv_id: &str,
+    groups: &mut Vec<Vec<ModelMessage>>,
+    system_tokens: usize,
+    budget: usize,
+) {
+    let group_tokens = |group: &Vec<ModelMessage>| {
+        group
+            .iter()
+            .map(estimate_model_message_tokens)
+            .sum::<usize>()
+    };
+
+    let original_tokens = system_tokens + groups.iter().map(group_tokens).sum::<usize>();
+    let latest_user_idx = groups
+        .iter()
+        .rposition(|group| group.first().is_some_and(|msg| msg.role == ModelRole::User))
+        .unwrap_or(groups.len());
+
+    let mut total_tokens = original_tokens;
+    let mut drop_count = 0;
+    while total_tokens > budget && drop_count < latest_user_idx {
+        total_tokens -= group_tokens(&groups[drop_count]);
+        drop_count += 1;
+    }
+
+    if drop_count > 0 {
+        groups.drain(..drop_count);
+        dual_info!(
+            "Trimmed {} messages from the context of conversation {}: ~{} -> ~{} tokens (budget: {})",
+            drop_count,
+            conv_id,
+            original_tokens,
+            total_tokens,
+            budget
+        );
+    }
+
+    if total_tokens > budget {
+        dual_warn!(
+            "The context of conversation {} still exceeds the token budget after trimming: ~{} > {}",
+            conv_id,
+            total_tokens,
+            budget
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Json, Router, routing::post};
+
+    use super::*;
+    use crate::test_utils::{chat_completion_json, spawn_mock_server};
+
+    #[tokio::test]
+    async fn test_model_context_is_trimmed_to_token_budget() {
+        let database_path = std::env::temp_dir().join(format!("llama-nexus-{}.db", Uuid::new_v4()));
+        let budget = 500;
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            context_window: 1_000_000,
+            auto_summarize: false,
+            max_stored_messages: 1000,
+            context_token_budget: Some(budget),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let conv_id = memory
+            .create_conversation("test-model", None, None)
+            .await
+            .unwrap();
+        memory
+            .set_system_message(&conv_id, "You are a helpful assistant.")
+            .await
+            .unwrap();
+
+        // ~100 tokens per message, ~4000 tokens in total
+        for i in 0..20 {
+            memory
+                .add_user_message(&conv_id, format!("question {i}: {}", "q".repeat(400)))
+                .await
+                .unwrap();
+            memory
+                .add_assistant_message(
+                    &conv_id,
+                    &format!("answer {i}: {}", "a".repeat(400)),
+                    vec![],
+                )
+                .await
+                .unwrap();
+        }
+        memory
+            .add_user_message(&conv_id, "latest question".to_string())
+            .await
+            .unwrap();
+
+        let context = memory.get_model_context(&conv_id).await.unwrap();
+        let total_tokens: usize = context.iter().map(estimate_model_message_tokens).sum();
+
+        assert!(total_tokens <= budget, "total tokens: {total_tokens}");
+        assert!(context.len() < 42);
+        assert_eq!(context[0].role, ModelRole::System);
+        assert_eq!(context[0].content, "You are a helpful assistant.");
+        let latest = context.last().unwrap();
+        assert_eq!(latest.role, ModelRole::User);
+        assert_eq!(latest.content, "latest question");
+        // the newest history is kept
+        assert!(context[context.len() - 2].content.starts_with("answer 19"));
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_idle_conversations_expire() {
+        let database_path = std::env::temp_dir().join(format!("llama-nexus-{}.db", Uuid::new_v4()));
+        let memory = CompleteChatMemory::new(MemoryConfig {
+            enable: true,
+            database_path: database_path.to_string_lossy().to_string(),
+            auto_summarize: false,
+            conversation_ttl_secs: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let idle_id = memory
+            .create_conversation("test-model", Some("alice".to_string()), None)
+            .await
+            .unwrap();
+        memory
+            .add_user_message(&idle_id, "hello".to_string())
+            .await
+            .unwrap();
+
+        // the timestamps of SQLite have a one-second resolution
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+
+        let active_id = memory
+            .create_conversation("test-model", Some("bob".to_string()), None)
+            .await
+            .unwrap();
+        memory
+            .add_user_message(&active_id, "hello".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(memory.sweep_expired_conversations().await.unwrap(), 1);
+        assert!(matches!(
+            memory.get_conversation(&idle_id).await,
+            Err(MemoryError::ConversationNotFound(_))
+        ));
+        assert!(memory.get_working_messages(&idle_id).await.is_err());
+        assert_eq!(
+            memory
+                .get_conversation(&active_id)
+                .await
+                .unwrap()
+                .message_count,
+            1
+        );
+
+        let _ = std::fs::remove_file(database_path);
+    }
+
+    #[tokio::test]
+    async fn test_older_messages_are_summarized_after_threshold() {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async { Json(chat_completion_json("The user asked about the weather.")) }),
+        );
+        let summary_service_base_url = spawn_mock_server(router).await;
+        let database_path = std::env::temp_dir().join(format!("llama-nexus-{}.db", Uuid::new_v4()));
+        let config: MemoryConfig = serde_json::from_value(serde_json::json!({
+            "enable": true,
+            "database_path": database_path.to_string_lossy(),
+            "context_window": 1_000_000,
+            "auto_summarize": true,
+            "summarization_strategy": "Incremental",
+            "summarize_threshold": 4,
+            "summarize_after": 6,
+            "summary_service_base_url": summary_service_base_url,
+            "summary_service_api_key": "",
+        }))
+        .unwrap();
+        assert_eq!(config.max_stored_messages, 6);
+        let memory = CompleteChatMemory::new(config).await.unwrap();
+
+        let conv_id = memory
+            .create_conversation("test-model", None, None)
+            .await
+            .unwrap();
+        let mut summarized = false;
+        for i in 0..5 {
+            let result = memory
+                .add_user_message(&conv_id, format!("question {i}"))
+                .await
+                .unwrap();
+            summarized |= result.summarization.triggered;
+            memory
+                .add_assistant_message(&conv_id, &format!("answer {i}"), vec![])
+                .await
+                .unwrap();
+        }
+        assert!(summarized);
+
+        let context = memory.get_model_context(&conv_id).await.unwrap();
+        assert_eq!(context[0].role, ModelRole::System);
+        assert_eq!(
+            context[0].content,
+            "Previous conversation summary: The user asked about the weather."
+        );
+        // the summary replaces the first turn: 1 system message + 4 turns
+        assert_eq!(context.len(), 9);
+        assert_eq!(context[1].content, "question 1");
+        assert!(
+            context
+                .iter()
+                .all(|msg| msg.content != "question 0" && msg.content != "answer 0")
+        );
+        assert_eq!(context.last().unwrap().content, "answer 4");
+
+        let _ = std::fs::remove_file(database_path);
+    }
 }
@@ -1,4 +1,5 @@
 pub mod manager;
+pub mod redis;
 pub mod store;
 pub mod summarizer;
 pub mod types;
@@ -0,0 +1,1045 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::Mutex,
+    time::timeout,
+};
+
+use crate::{
+    dual_error, dual_info,
+    memory::{store::MemoryStore, types::*},
+};
+
+/// Prefix of all the keys written by the store
+const KEY_PREFIX: &str = "llama-nexus:memory";
+
+/// Default number of conversations returned by the list operations
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+/// Time allowed to connect to Redis, and to exchange a command and its reply
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lua function updating a conversation in place and moving it to the top of the update-time
+/// indexes. Returns false if the conversation does not exist.
+macro_rules! update_conversation_lua {
+    () => {
+        r#"
+local function update_conversation(conv_key, index_key, user_index_prefix, fields, increments, score)
+    local json = redis.call('GET', conv_key)
+    if not json then
+        return false
+    end
+    local conv = cjson.decode(json)
+    for field, value in pairs(fields) do
+        conv[field] = value
+    end
+    for field, value in pairs(increments) do
+        conv[field] = conv[field] + value
+    end
+    redis.call('SET', conv_key, cjson.encode(conv))
+    redis.call('ZADD', index_key, score, conv.id)
+    if conv.user_id ~= cjson.null then
+        redis.call('ZADD', user_index_prefix .. conv.user_id .. ':conversations', score, conv.id)
+    end
+    return true
+end
+"#
+    };
+}
+
+/// KEYS: conversation, conversations index. ARGV: fields to set (JSON), fields to increment
+/// (JSON), score, prefix of the user indexes
+const UPDATE_CONVERSATION_SCRIPT: &str = concat!(
+    update_conversation_lua!(),
+    r#"
+if update_conversation(KEYS[1], KEYS[2], ARGV[4], cjson.decode(ARGV[1]), cjson.decode(ARGV[2]), ARGV[3]) then
+    return 1
+end
+return 0
+"#
+);
+
+/// KEYS: conversation, messages, conversations index. ARGV: message (JSON), fields to set
+/// (JSON), fields to increment (JSON), score, prefix of the user indexes
+const STORE_MESSAGE_SCRIPT: &str = concat!(
+    update_conversation_lua!(),
+    r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+redis.call('RPUSH', KEYS[2], ARGV[1])
+update_conversation(KEYS[1], KEYS[3], ARGV[5], cjson.decode(ARGV[2]), cjson.decode(ARGV[3]), ARGV[4])
+return 1
+"#
+);
+
+/// KEYS: conversation, messages, conversations index. ARGV: first sequence to delete, fields to
+/// set (JSON), score, prefix of the user indexes. Returns the number of deleted messages.
+const DELETE_MESSAGES_SCRIPT: &str = concat!(
+    update_conversation_lua!(),
+    r#"
+local messages = redis.call('LRANGE', KEYS[2], 0, -1)
+local from_sequence = tonumber(ARGV[1])
+local keep, tokens = #messages, 0
+for i = #messages, 1, -1 do
+    local message = cjson.decode(messages[i])
+    if message.sequence < from_sequence then
+        break
+    end
+    keep = i - 1
+    if type(message.tokens) == 'number' then
+        tokens = tokens + message.tokens
+    end
+end
+local deleted = #messages - keep
+if deleted == 0 then
+    return 0
+end
+if keep == 0 then
+    redis.call('DEL', KEYS[2])
+else
+    redis.call('LTRIM', KEYS[2], 0, keep - 1)
+end
+update_conversation(KEYS[1], KEYS[3], ARGV[4], cjson.decode(ARGV[2]), { message_count = -deleted, total_tokens = -tokens }, ARGV[3])
+return deleted
+"#
+);
+
+/// KEYS: messages. ARGV: index, expected message (JSON), new message (JSON). Replaces the message
+/// only if it was not changed since it was read.
+const REPLACE_MESSAGE_SCRIPT: &str = r#"
+if redis.call('LINDEX', KEYS[1], ARGV[1]) ~= ARGV[2] then
+    return 0
+end
+redis.call('LSET', KEYS[1], ARGV[1], ARGV[3])
+return 1
+"#;
+
+/// KEYS: conversation, conversations index, user index (empty if none). ARGV: conversation
+/// (JSON), score, conversation id. Returns 0 if the conversation already exists.
+const CREATE_CONVERSATION_SCRIPT: &str = r#"
+if not redis.call('SET', KEYS[1], ARGV[1], 'NX') then
+    return 0
+end
+redis.call('ZADD', KEYS[2], ARGV[2], ARGV[3])
+if KEYS[3] ~= '' then
+    redis.call('ZADD', KEYS[3], ARGV[2], ARGV[3])
+end
+return 1
+"#;
+
+/// KEYS: conversation, messages, conversations index. ARGV: conversation id, prefix of the user
+/// indexes. Returns 0 if the conversation does not exist.
+const DELETE_CONVERSATION_SCRIPT: &str = r#"
+local json = redis.call('GET', KEYS[1])
+if not json then
+    return 0
+end
+local conv = cjson.decode(json)
+redis.call('DEL', KEYS[1], KEYS[2])
+redis.call('ZREM', KEYS[3], ARGV[1])
+if conv.user_id ~= cjson.null then
+    redis.call('ZREM', ARGV[2] .. conv.user_id .. ':conversations', ARGV[1])
+end
+return 1
+"#;
+
+/// Conversation memory kept in a Redis instance
+///
+/// Layout of the keys:
+/// - `{prefix}:conv:{id}`: JSON of the `StoredConversation`
+/// - `{prefix}:conv:{id}:messages`: list of the JSON of the `StoredMessage`s, in sequence order
+/// - `{prefix}:conversations`: sorted set of the conversation ids scored by update time (ms)
+/// - `{prefix}:user:{user_id}:conversations`: the same sorted set restricted to a user
+///
+/// The operations updating several keys, or reading a key before writing it, run as Lua scripts
+/// so that they are atomic. The scripts derive the user index keys from the conversation, so the
+/// store needs a standalone Redis instance rather than a cluster.
+pub struct RedisStore {
+    connection: Mutex<Option<RedisConnection>>,
+    address: String,
+    password: Option<String>,
+    db: Option<u32>,
+}
+
+impl RedisStore {
+    /// Connect to the Redis instance at `url` (`redis://[:password@]host[:port][/db]`)
+    pub async fn new(url: &str) -> MemoryResult<Self> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| {
+            let err_msg = format!("Invalid Redis URL {url}: {e}");
+            dual_error!("{err_msg}");
+            MemoryError::InvalidConfig(err_msg)
+        })?;
+        if parsed.scheme() != "redis" {
+            let err_msg = format!("Invalid Redis URL {url}: the scheme must be `redis`");
+            dual_error!("{err_msg}");
+            return Err(MemoryError::InvalidConfig(err_msg));
+        }
+        let host = parsed.host_str().unwrap_or("127.0.0.1");
+        let port = parsed.port().unwrap_or(6379);
+        let db = match parsed.path().trim_start_matches('/') {
+            "" => None,
+            db => Some(db.parse::<u32>().map_err(|e| {
+                let err_msg = format!("Invalid Redis database in {url}: {e}");
+                dual_error!("{err_msg}");
+                MemoryError::InvalidConfig(err_msg)
+            })?),
+        };
+
+        let store = Self {
+            connection: Mutex::new(None),
+            address: format!("{host}:{port}"),
+            password: parsed.password().map(str::to_string),
+            db,
+        };
+
+        // fail early if the instance is unreachable
+        store.command(&["PING"]).await?;
+        dual_info!("Connected to the Redis memory store at {}", store.address);
+
+        Ok(store)
+    }
+
+    /// Send a command, connecting first if needed. The connection is taken out of its slot for
+    /// the exchange and put back once the reply is read, so that an exchange failing, timing out
+    /// or cancelled halfway drops the connection, and the next command reconnects.
+    async fn command(&self, args: &[&str]) -> MemoryResult<Reply> {
+        let mut guard = self.connection.lock().await;
+        let mut connection = match guard.take() {
+            Some(connection) => connection,
+            None => timeout(COMMAND_TIMEOUT, self.connect())
+                .await
+                .map_err(|_| {
+                    let err_msg = format!("Timed out connecting to Redis at {}", self.address);
+                    dual_error!("{err_msg}");
+                    MemoryError::Redis(err_msg)
+                })??,
+        };
+
+        match timeout(COMMAND_TIMEOUT, connection.send(args)).await {
+            Ok(Ok(Reply::Error(e))) => {
+                *guard = Some(connection);
+                let err_msg = format!("Redis command {} failed: {e}", args[0]);
+                dual_error!("{err_msg}");
+                Err(MemoryError::Redis(err_msg))
+            }
+            Ok(Ok(reply)) => {
+                *guard = Some(connection);
+                Ok(reply)
+            }
+            Ok(Err(e)) => {
+                let err_msg = format!("Redis command {} failed: {e}", args[0]);
+                dual_error!("{err_msg}");
+                Err(MemoryError::Redis(err_msg))
+            }
+            Err(_) => {
+                let err_msg = format!("Redis command {} timed out", args[0]);
+                dual_error!("{err_msg}");
+                Err(MemoryError::Redis(err_msg))
+            }
+        }
+    }
+
+    /// Run a Lua script with its keys and arguments
+    async fn eval(&self, script: &str, keys: &[&str], args: &[&str]) -> MemoryResult<Reply> {
+        let num_keys = keys.len().to_string();
+        let mut command = vec!["EVAL", script, &num_keys];
+        command.extend_from_slice(keys);
+        command.extend_from_slice(args);
+        self.command(&command).await
+    }
+
+    async fn connect(&self) -> MemoryResult<RedisConnection> {
+        let stream = TcpStream::connect(&self.address).await.map_err(|e| {
+            let err_msg = format!("Failed to connect to Redis at {}: {e}", self.address);
+            dual_error!("{err_msg}");
+            MemoryError::Redis(err_msg)
+        })?;
+        let mut connection = RedisConnection {
+            stream: BufReader::new(stream),
+        };
+
+        let mut setup = Vec::new();
+        if let Some(password) = &self.password {
+            setup.push(vec!["AUTH".to_string(), password.clone()]);
+        }
+        if let Some(db) = self.db {
+            setup.push(vec!["SELECT".to_string(), db.to_string()]);
+        }
+        for args in setup {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            match connection.send(&args).await {
+                Ok(Reply::Error(e)) => {
+                    let err_msg = format!("Redis command {} failed: {e}", args[0]);
+                    dual_error!("{err_msg}");
+                    return Err(MemoryError::Redis(err_msg));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let err_msg = format!("Redis command {} failed: {e}", args[0]);
+                    dual_error!("{err_msg}");
+                    return Err(MemoryError::Redis(err_msg));
+                }
+            }
+        }
+
+        Ok(connection)
+    }
+
+    fn conversation_key(conv_id: &str) -> String {
+        format!("{KEY_PREFIX}:conv:{conv_id}")
+    }
+
+    fn messages_key(conv_id: &str) -> String {
+        format!("{KEY_PREFIX}:conv:{conv_id}:messages")
+    }
+
+    fn conversations_key() -> String {
+        format!("{KEY_PREFIX}:conversations")
+    }
+
+    fn user_conversations_key(user_id: &str) -> String {
+        format!(
+            "{}{user_id}:conversations",
+            Self::user_conversations_prefix()
+        )
+    }
+
+    /// Prefix of the user indexes, completed by the scripts with the user id
+    fn user_conversations_prefix() -> String {
+        format!("{KEY_PREFIX}:user:")
+    }
+
+    async fn find_conversation(&self, conv_id: &str) -> MemoryResult<Option<StoredConversation>> {
+        let reply = self
+            .command(&["GET", &Self::conversation_key(conv_id)])
+            .await?;
+        match reply.into_string()? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Set and increment fields of a conversation, and move it to the top of the update-time
+    /// indexes
+    async fn update_conversation(
+        &self,
+        conv_id: &str,
+        mut fields: serde_json::Value,
+        increments: serde_json::Value,
+    ) -> MemoryResult<()> {
+        let now = Utc::now();
+        fields["updated_at"] = serde_json::to_value(now)?;
+
+        let reply = self
+            .eval(
+                UPDATE_CONVERSATION_SCRIPT,
+                &[&Self::conversation_key(conv_id), &Self::conversations_key()],
+                &[
+                    &fields.to_string(),
+                    &increments.to_string(),
+                    &now.timestamp_millis().to_string(),
+                    &Self::user_conversations_prefix(),
+                ],
+            )
+            .await?;
+        match reply {
+            Reply::Integer(0) => Err(MemoryError::ConversationNotFound(conv_id.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ids of the conversations of a sorted set, most recently updated first
+    async fn conversation_ids(&self, key: &str, limit: Option<usize>) -> MemoryResult<Vec<String>> {
+        let stop = match limit {
+            Some(0) => return Ok(Vec::new()),
+            Some(limit) => (limit - 1).to_string(),
+            None => "-1".to_string(),
+        };
+        self.command(&["ZREVRANGE", key, "0", &stop])
+            .await?
+            .into_strings()
+    }
+
+    async fn conversation_summaries(
+        &self,
+        key: &str,
+        limit: Option<usize>,
+    ) -> MemoryResult<Vec<ConversationSummary>> {
+        let ids = self
+            .conversation_ids(key, Some(limit.unwrap_or(DEFAULT_LIST_LIMIT)))
+            .await?;
+
+        let mut summaries = Vec::new();
+        for id in ids {
+            if let Some(conv) = self.find_conversation(&id).await? {
+                summaries.push(ConversationSummary {
+                    id: conv.id,
+                    user_id: conv.user_id,
+                    title: conv.title,
+                    model_name: conv.model_name,
+                    message_count: conv.message_count,
+                    last_message_at: conv.updated_at,
+                    created_at: conv.created_at,
+                });
+            }
+        }
+
+        Ok(summaries)
+    }
+
+    async fn messages(
+        &self,
+        conv_id: &str,
+        start: &str,
+        stop: &str,
+    ) -> MemoryResult<Vec<StoredMessage>> {
+        self.command(&["LRANGE", &Self::messages_key(conv_id), start, stop])
+            .await?
+            .into_strings()?
+            .iter()
+            .map(|json| serde_json::from_str(json).map_err(MemoryError::from))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl MemoryStore for RedisStore {
+    async fn create_conversation(&self, conv: &StoredConversation) -> MemoryResult<()> {
+        let json = serde_json::to_string(conv)?;
+        let user_key = conv
+            .user_id
+            .as_deref()
+            .map(Self::user_conversations_key)
+            .unwrap_or_default();
+        let reply = self
+            .eval(
+                CREATE_CONVERSATION_SCRIPT,
+                &[
+                    &Self::conversation_key(&conv.id),
+                    &Self::conversations_key(),
+                    &user_key,
+                ],
+                &[
+                    &json,
+                    &conv.updated_at.timestamp_millis().to_string(),
+                    &conv.id,
+                ],
+            )
+            .await?;
+        if reply == Reply::Integer(0) {
+            let err_msg = format!("Conversation already exists: {}", conv.id);
+            dual_error!("{err_msg}");
+            return Err(MemoryError::InvalidData(err_msg));
+        }
+
+        Ok(())
+    }
+
+    async fn store_message(&self, message: &StoredMessage) -> MemoryResult<()> {
+        let conv_id = &message.conversation_id;
+        let now = Utc::now();
+        let fields = serde_json::json!({ "updated_at": now });
+        let increments = serde_json::json!({
+            "message_count": 1,
+            "total_tokens": message.tokens.unwrap_or(0),
+        });
+
+        let reply = self
+            .eval(
+                STORE_MESSAGE_SCRIPT,
+                &[
+                    &Self::conversation_key(conv_id),
+                    &Self::messages_key(conv_id),
+                    &Self::conversations_key(),
+                ],
+                &[
+                    &serde_json::to_string(message)?,
+                    &fields.to_string(),
+                    &increments.to_string(),
+                    &now.timestamp_millis().to_string(),
+                    &Self::user_conversations_prefix(),
+                ],
+            )
+            .await?;
+        match reply {
+            Reply::Integer(0) => Err(MemoryError::ConversationNotFound(conv_id.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_conversation(&self, conv_id: &str) -> MemoryResult<StoredConversation> {
+        self.find_conversation(conv_id)
+            .await?
+            .ok_or_else(|| MemoryError::ConversationNotFound(conv_id.to_string()))
+    }
+
+    async fn get_recent_conversation_by_user(
+        &self,
+        user_id: &str,
+        model_name: Option<&str>,
+    ) -> MemoryResult<Option<StoredConversation>> {
+        let ids = self
+            .conversation_ids(&Self::user_conversations_key(user_id), None)
+            .await?;
+        for id in ids {
+            if let Some(conv) = self.find_conversation(&id).await?
+                && model_name.is_none_or(|model| conv.model_name == model)
+            {
+                return Ok(Some(conv));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn get_full_history(&self, conv_id: &str) -> MemoryResult<Vec<StoredMessage>> {
+        self.messages(conv_id, "0", "-1").await
+    }
+
+    async fn get_recent_messages(
+        &self,
+        conv_id: &str,
+        limit: usize,
+    ) -> MemoryResult<Vec<StoredMessage>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        self.messages(conv_id, &format!("-{limit}"), "-1").await
+    }
+
+    async fn get_messages_from_sequence(
+        &self,
+        conv_id: &str,
+        from_sequence: i64,
+    ) -> MemoryResult<Vec<StoredMessage>> {
+        let mut messages = self.get_full_history(conv_id).await?;
+        messages.retain(|message| message.sequence >= from_sequence);
+        Ok(messages)
+    }
+
+    async fn get_next_sequence(&self, conv_id: &str) -> MemoryResult<i64> {
+        let last = self.messages(conv_id, "-1", "-1").await?;
+        Ok(last.first().map_or(0, |message| message.sequence) + 1)
+    }
+
+    async fn update_conversation_summary(
+        &self,
+        conv_id: &str,
+        summary: &str,
+        last_sequence: Option<i64>,
+    ) -> MemoryResult<()> {
+        let fields = serde_json::json!({
+            "summary": summary,
+            "last_summary_sequence": last_sequence,
+        });
+        self.update_conversation(conv_id, fields, serde_json::json!({}))
+            .await
+    }
+
+    async fn update_system_message(
+        &self,
+        conv_id: &str,
+        system_message: Option<&str>,
+    ) -> MemoryResult<()> {
+        let fields = serde_json::json!({
+            "system_message": system_message,
+            "system_message_hash":
+                system_message.map(|msg| format!("{:x}", md5::compute(msg.as_bytes()))),
+            "system_message_updated_at": Utc::now(),
+        });
+        self.update_conversation(conv_id, fields, serde_json::json!({}))
+            .await
+    }
+
+    async fn delete_messages_from_sequence(
+        &self,
+        conv_id: &str,
+        from_sequence: i64,
+    ) -> MemoryResult<usize> {
+        let now = Utc::now();
+        let fields = serde_json::json!({ "updated_at": now });
+        let reply = self
+            .eval(
+                DELETE_MESSAGES_SCRIPT,
+                &[
+                    &Self::conversation_key(conv_id),
+                    &Self::messages_key(conv_id),
+                    &Self::conversations_key(),
+                ],
+                &[
+                    &from_sequence.to_string(),
+                    &fields.to_string(),
+                    &now.timestamp_millis().to_string(),
+                    &Self::user_conversations_prefix(),
+                ],
+            )
+            .await?;
+
+        Ok(reply.into_integer()? as usize)
+    }
+
+    async fn update_message_content(
+        &self,
+        conv_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> MemoryResult<()> {
+        let key = Self::messages_key(conv_id);
+
+        // the message is replaced only if no other writer changed it in the meantime
+        for _ in 0..3 {
+            let messages = self
+                .command(&["LRANGE", &key, "0", "-1"])
+                .await?
+                .into_strings()?;
+            let mut found = None;
+            for (index, json) in messages.iter().enumerate().rev() {
+                let message: StoredMessage = serde_json::from_str(json)?;
+                if message.id == message_id {
+                    found = Some((index, json, message));
+                    break;
+                }
+            }
+            let Some((index, json, message)) = found else {
+                return Ok(());
+            };
+
+            let replaced = serde_json::to_string(&StoredMessage {
+                content: content.to_string(),
+                ..message
+            })?;
+            let reply = self
+                .eval(
+                    REPLACE_MESSAGE_SCRIPT,
+                    &[&key],
+                    &[&index.to_string(), json, &replaced],
+                )
+                .await?;
+            if reply != Reply::Integer(0) {
+                return Ok(());
+            }
+        }
+
+        let err_msg = format!("The message {message_id} kept changing while it was updated");
+        dual_error!("{err_msg}");
+        Err(MemoryError::Redis(err_msg))
+    }
+
+    async fn list_conversations(
+        &self,
+        limit: Option<usize>,
+    ) -> MemoryResult<Vec<ConversationSummary>> {
+        self.conversation_summaries(&Self::conversations_key(), limit)
+            .await
+    }
+
+    async fn list_conversations_by_user(
+        &self,
+        user_id: &str,
+        limit: Option<usize>,
+    ) -> MemoryResult<Vec<ConversationSummary>> {
+        self.conversation_summaries(&Self::user_conversations_key(user_id), limit)
+            .await
+    }
+
+    async fn get_stats(&self) -> MemoryResult<MemoryStats> {
+        let ids = self
+            .conversation_ids(&Self::conversations_key(), None)
+            .await?;
+
+        let mut stats = MemoryStats {
+            total_conversations: 0,
+            total_messages: 0,
+            total_tool_calls: 0,
+            total_tokens: 0,
+            database_size_mb: 0.0,
+            most_used_tools: vec![],
+            conversations_by_model: vec![],
+        };
+        for id in ids {
+            if let Some(conv) = self.find_conversation(&id).await? {
+                stats.total_conversations += 1;
+                stats.total_messages += conv.message_count;
+                stats.total_tokens += conv.total_tokens;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    async fn delete_conversation(&self, conv_id: &str) -> MemoryResult<bool> {
+        let reply = self
+            .eval(
+                DELETE_CONVERSATION_SCRIPT,
+                &[
+                    &Self::conversation_key(conv_id),
+                    &Self::messages_key(conv_id),
+                    &Self::conversations_key(),
+                ],
+                &[conv_id, &Self::user_conversations_prefix()],
+            )
+            .await?;
+
+        Ok(reply.into_integer()? != 0)
+    }
+
+    async fn delete_conversations_by_user(&self, user_id: &str) -> MemoryResult<Vec<String>> {
+        let ids = self
+            .conversation_ids(&Self::user_conversations_key(user_id), None)
+            .await?;
+        for id in &ids {
+            self.delete_conversation(id).await?;
+        }
+        self.command(&["DEL", &Self::user_conversations_key(user_id)])
+            .await?;
+
+        Ok(ids)
+    }
+
+    async fn delete_conversations_idle_since(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> MemoryResult<Vec<String>> {
+        let max = format!("({}", cutoff.timestamp_millis());
+        let ids = self
+            .command(&["ZRANGEBYSCORE", &Self::conversations_key(), "-inf", &max])
+            .await?
+            .into_strings()?;
+        for id in &ids {
+            self.delete_conversation(id).await?;
+        }
+
+        Ok(ids)
+    }
+}
+
+/// Reply of a Redis command, as defined by the RESP2 protocol
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn into_string(self) -> MemoryResult<Option<String>> {
+        match self {
+            Reply::Bulk(None) => Ok(None),
+            Reply::Bulk(Some(bytes)) => String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|e| MemoryError::Redis(format!("Invalid UTF-8 in the reply: {e}"))),
+            Reply::Status(status) => Ok(Some(status)),
+            reply => Err(MemoryError::Redis(format!(
+                "Unexpected reply, expected a string: {reply:?}"
+            ))),
+        }
+    }
+
+    fn into_integer(self) -> MemoryResult<i64> {
+        match self {
+            Reply::Integer(value) => Ok(value),
+            reply => Err(MemoryError::Redis(format!(
+                "Unexpected reply, expected an integer: {reply:?}"
+            ))),
+        }
+    }
+
+    fn into_strings(self) -> MemoryResult<Vec<String>> {
+        match self {
+            Reply::Array(None) => Ok(Vec::new()),
+            Reply::Array(Some(items)) => items
+                .into_iter()
+                .filter_map(|item| item.into_string().transpose())
+                .collect(),
+            reply => Err(MemoryError::Redis(format!(
+                "Unexpected reply, expected an array: {reply:?}"
+            ))),
+        }
+    }
+}
+
+struct RedisConnection {
+    stream: BufReader<TcpStream>,
+}
+
+impl RedisConnection {
+    async fn send(&mut self, args: &[&str]) -> std::io::Result<Reply> {
+        self.stream
+            .get_mut()
+            .write_all(&encode_command(args))
+            .await?;
+        read_reply(&mut self.stream).await
+    }
+}
+
+/// Encode a command as a RESP array of bulk strings
+fn encode_command(args: &[&str]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+
+async fn read_reply<R: AsyncBufRead + Unpin + Send>(reader: &mut R) -> std::io::Result<Reply> {
+    let line = read_line(reader).await?;
+    let (kind, value) = line.split_at(1);
+
+    match kind {
+        "+" => Ok(Reply::Status(value.to_string())),
+        "-" => Ok(Reply::Error(value.to_string())),
+        ":" => Ok(Reply::Integer(parse_int(value)?)),
+        "$" => {
+            let len = parse_int(value)?;
+            if len < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            let mut bytes = vec![0; len as usize + 2];
+            reader.read_exact(&mut bytes).await?;
+            bytes.truncate(len as usize);
+            Ok(Reply::Bulk(Some(bytes)))
+        }
+        "*" => {
+            let len = parse_int(value)?;
+            if len < 0 {
+                return Ok(Reply::Array(None));
+            }
+            let mut items = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                items.push(Box::pin(read_reply(reader)).await?);
+            }
+            Ok(Reply::Array(Some(items)))
+        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid RESP reply: {line}"),
+        )),
+    }
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "Connection closed by Redis",
+        ));
+    }
+    let line = line.trim_end_matches("\r\n").to_string();
+    if line.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Empty RESP reply",
+        ));
+    }
+    Ok(line)
+}
+
+fn parse_int(value: &str) -> std::io::Result<i64> {
+    value.parse().map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid RESP integer {value}: {e}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resp_round_trip() {
+        assert_eq!(
+            encode_command(&["SET", "key", "a b"]),
+            b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$3\r\na b\r\n".to_vec()
+        );
+
+        let mut reader: &[u8] =
+            b"*4\r\n+OK\r\n:42\r\n$5\r\nhe\r\nl\r\n$-1\r\n-ERR wrong type\r\n*-1\r\n";
+        assert_eq!(
+            read_reply(&mut reader).await.unwrap(),
+            Reply::Array(Some(vec![
+                Reply::Status("OK".to_string()),
+                Reply::Integer(42),
+                Reply::Bulk(Some(b"he\r\nl".to_vec())),
+                Reply::Bulk(None),
+            ]))
+        );
+        assert_eq!(
+            read_reply(&mut reader).await.unwrap(),
+            Reply::Error("ERR wrong type".to_string())
+        );
+        assert_eq!(read_reply(&mut reader).await.unwrap(), Reply::Array(None));
+        assert!(read_reply(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_command_drops_the_connection() {
+        // a fake Redis replying to each command with its name, slowly for `SLOW`
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    while let Ok(Reply::Array(Some(args))) = read_reply(&mut stream).await {
+                        let Some(Reply::Bulk(Some(name))) = args.into_iter().next() else {
+                            return;
+                        };
+                        if name == b"SLOW" {
+                            tokio::time::sleep(Duration::from_millis(300)).await;
+                        }
+                        let reply = format!("+{}\r\n", String::from_utf8_lossy(&name));
+                        if stream.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        let store = RedisStore::new(&format!("redis://{address}"))
+            .await
+            .unwrap();
+
+        // the reply of the cancelled command is not read by the next one
+        let cancelled = timeout(Duration::from_millis(50), store.command(&["SLOW"])).await;
+        assert!(cancelled.is_err());
+        assert_eq!(
+            store.command(&["ECHO"]).await.unwrap(),
+            Reply::Status("ECHO".to_string())
+        );
+    }
+
+    /// Runs against the Redis instance at `LLAMA_NEXUS_TEST_REDIS_URL`, skipped if unset
+    #[tokio::test]
+    async fn test_redis_store() {
+        let Ok(url) = std::env::var("LLAMA_NEXUS_TEST_REDIS_URL") else {
+            eprintln!("LLAMA_NEXUS_TEST_REDIS_URL is not set, skipping the Redis store test");
+            return;
+        };
+        let store = RedisStore::new(&url).await.unwrap();
+
+        let user_id = format!("user-{}", Uuid::new_v4());
+        let conv_id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        store
+            .create_conversation(&StoredConversation {
+                id: conv_id.clone(),
+                user_id: Some(user_id.clone()),
+                title: None,
+                model_name: "test-model".to_string(),
+                created_at: now,
+                updated_at: now,
+                message_count: 0,
+                total_tokens: 0,
+                summary: None,
+                last_summary_sequence: None,
+                system_message: None,
+                system_message_hash: None,
+                system_message_updated_at: None,
+            })
+            .await
+            .unwrap();
+        store
+            .update_system_message(&conv_id, Some("You are helpful."))
+            .await
+            .unwrap();
+
+        for (role, content) in [
+            (MessageRole::User, "weather?"),
+            (MessageRole::Assistant, ""),
+        ] {
+            let sequence = store.get_next_sequence(&conv_id).await.unwrap();
+            let tool_calls = match role {
+                MessageRole::Assistant => vec![StoredToolCall {
+                    id: "call_1".to_string(),
+                    name: "get_weather".to_string(),
+                    arguments: serde_json::json!({"city": "Paris"}),
+                    result: None,
+                    sequence: 0,
+                }],
+                _ => vec![],
+            };
+            store
+                .store_message(&StoredMessage {
+                    id: Uuid::new_v4().to_string(),
+                    conversation_id: conv_id.clone(),
+                    role,
+                    content: content.to_string(),
+                    timestamp: Utc::now(),
+                    sequence,
+                    tokens: Some(10),
+                    tool_calls,
+                })
+                .await
+                .unwrap();
+        }
+
+        let conv = store.get_conversation(&conv_id).await.unwrap();
+        assert_eq!(conv.message_count, 2);
+        assert_eq!(conv.total_tokens, 20);
+        assert_eq!(conv.system_message.as_deref(), Some("You are helpful."));
+
+        let history = store.get_full_history(&conv_id).await.unwrap();
+        assert_eq!(
+            history.iter().map(|m| m.sequence).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(history[1].tool_calls[0].name, "get_weather");
+        assert_eq!(history[1].tool_calls[0].arguments["city"], "Paris");
+        let recent = store.get_recent_messages(&conv_id, 1).await.unwrap();
+        assert_eq!(recent[0].role, MessageRole::Assistant);
+
+        store
+            .update_message_content(&conv_id, &history[0].id, "weather in Paris?")
+            .await
+            .unwrap();
+        let history = store.get_full_history(&conv_id).await.unwrap();
+        assert_eq!(history[0].content, "weather in Paris?");
+
+        // the messages of a failed turn are rolled back along with the statistics
+        assert_eq!(
+            store
+                .delete_messages_from_sequence(&conv_id, 2)
+                .await
+                .unwrap(),
+            1
+        );
+        let conv = store.get_conversation(&conv_id).await.unwrap();
+        assert_eq!(conv.message_count, 1);
+        assert_eq!(conv.total_tokens, 10);
+        assert_eq!(conv.system_message.as_deref(), Some("You are helpful."));
+
+        let recent_conv = store
+            .get_recent_conversation_by_user(&user_id, Some("test-model"))
+            .await
+            .unwrap();
+        assert_eq!(recent_conv.unwrap().id, conv_id);
+        let listed = store
+            .list_conversations_by_user(&user_id, None)
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+
+        let deleted = store.delete_conversations_by_user(&user_id).await.unwrap();
+        assert_eq!(deleted, [conv_id.clone()]);
+        assert!(matches!(
+            store.get_conversation(&conv_id).await,
+            Err(MemoryError::ConversationNotFound(_))
+        ));
+    }
+}
@@ -1,10 +1,115 @@
 use std::str::FromStr;
 
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use sqlx::{Row, SqlitePool};
 
 use crate::{dual_error, memory::types::*};
 
+/// Storage backend of the conversation memory
+///
+/// Holds the conversations and their messages. The SQLite `MessageStore` is the default
+/// backend; `RedisStore` keeps the same data in a Redis instance.
+#[async_trait]
+pub trait MemoryStore: Send + Sync {
+    /// Create a new conversation. The conversation id must be unique.
+    async fn create_conversation(&self, conv: &StoredConversation) -> MemoryResult<()>;
+
+    /// Append a message to its conversation and update the statistics of the conversation
+    async fn store_message(&self, message: &StoredMessage) -> MemoryResult<()>;
+
+    /// Get the metadata of a conversation, without its messages
+    async fn get_conversation(&self, conv_id: &str) -> MemoryResult<StoredConversation>;
+
+    /// Get the most recently updated conversation of a user, optionally for the given model
+    async fn get_recent_conversation_by_user(
+        &self,
+        user_id: &str,
+        model_name: Option<&str>,
+    ) -> MemoryResult<Option<StoredConversation>>;
+
+    /// Get all the messages of a conversation, ordered by sequence
+    async fn get_full_history(&self, conv_id: &str) -> MemoryResult<Vec<StoredMessage>>;
+
+    /// Get the last `limit` messages of a conversation, ordered by sequence
+    async fn get_recent_messages(
+        &self,
+        conv_id: &str,
+        limit: usize,
+    ) -> MemoryResult<Vec<StoredMessage>>;
+
+    /// Get the messages of a conversation from the given sequence (inclusive)
+    #[allow(dead_code)]
+    async fn get_messages_from_sequence(
+        &self,
+        conv_id: &str,
+        from_sequence: i64,
+    ) -> MemoryResult<Vec<StoredMessage>>;
+
+    /// Get the sequence of the next message of a conversation, starting at 1
+    async fn get_next_sequence(&self, conv_id: &str) -> MemoryResult<i64>;
+
+    /// Set the summary of a conversation and the last sequence it covers
+    async fn update_conversation_summary(
+        &self,
+        conv_id: &str,
+        summary: &str,
+        last_sequence: Option<i64>,
+    ) -> MemoryResult<()>;
+
+    /// Set or clear the system message of a conversation
+    async fn update_system_message(
+        &self,
+        conv_id: &str,
+        system_message: Option<&str>,
+    ) -> MemoryResult<()>;
+
+    /// Delete the messages of a conversation from the given sequence (inclusive). Returns the
+    /// number of deleted messages.
+    async fn delete_messages_from_sequence(
+        &self,
+        conv_id: &str,
+        from_sequence: i64,
+    ) -> MemoryResult<usize>;
+
+    /// Replace the content of a message of a conversation
+    async fn update_message_content(
+        &self,
+        conv_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> MemoryResult<()>;
+
+    /// List the most recently updated conversations (100 by default)
+    async fn list_conversations(
+        &self,
+        limit: Option<usize>,
+    ) -> MemoryResult<Vec<ConversationSummary>>;
+
+    /// List the most recently updated conversations of a user (100 by default)
+    async fn list_conversations_by_user(
+        &self,
+        user_id: &str,
+        limit: Option<usize>,
+    ) -> MemoryResult<Vec<ConversationSummary>>;
+
+    /// Get the statistics of the store
+    async fn get_stats(&self) -> MemoryResult<MemoryStats>;
+
+    /// Delete a conversation and its messages. Returns false if the conversation does not exist.
+    async fn delete_conversation(&self, conv_id: &str) -> MemoryResult<bool>;
+
+    /// Delete all the conversations of a user. Returns the ids of the deleted conversations.
+    async fn delete_conversations_by_user(&self, user_id: &str) -> MemoryResult<Vec<String>>;
+
+    /// Delete the conversations last updated before `cutoff`. Returns the ids of the deleted
+    /// conversations.
+    async fn delete_conversations_idle_since(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> MemoryResult<Vec<String>>;
+}
+
 pub struct MessageStore {
     pool: SqlitePool,
 }
@@ -43,6 +148,9 @@ impl MessageStore {
     }
 
     async fn initialize_schema(&self) -> MemoryResult<()> {
+        // 在同一个连接上完成建表和迁移，避免其他连接缓存迁移前的表结构
+        let mut conn = self.pool.acquire().await?;
+
         // 首先创建基础表结构
         sqlx::query(
             r#"
@@ -74,24 +182,24 @@ impl MessageStore {
             );
             "#,
         )
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await?;
 
         // 添加user_id列（如果不存在）
         let _ = sqlx::query("ALTER TABLE conversations ADD COLUMN user_id TEXT")
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await;
 
         // 添加system message相关列（如果不存在）
         let _ = sqlx::query("ALTER TABLE conversations ADD COLUMN system_message TEXT")
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await;
         let _ = sqlx::query("ALTER TABLE conversations ADD COLUMN system_message_hash TEXT")
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await;
         let _ =
             sqlx::query("ALTER TABLE conversations ADD COLUMN system_message_updated_at DATETIME")
-                .execute(&self.pool)
+                .execute(&mut *conn)
                 .await;
 
         // 创建索引
@@ -102,11 +210,37 @@ impl MessageStore {
             CREATE INDEX IF NOT EXISTS idx_conversations_user_updated ON conversations(user_id, updated_at DESC);
             CREATE INDEX IF NOT EXISTS idx_messages_timestamp ON messages(timestamp DESC);
             "#
+        ).execute(&mut *conn).await?;
+
+        Ok(())
+    }
+
+    async fn update_conversation_stats(&self, conv_id: &str) -> MemoryResult<()> {
+        let row = sqlx::query(
+            "SELECT COUNT(*) as msg_count, SUM(COALESCE(tokens, 0)) as total_tokens
+             FROM messages WHERE conversation_id = ?",
+        )
+        .bind(conv_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let msg_count: i64 = row.get(0);
+        let total_tokens: Option<i64> = row.get(1);
+        let total_tokens: i64 = total_tokens.unwrap_or(0);
+
+        sqlx::query!(
+            "UPDATE conversations SET message_count = ?, total_tokens = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+            msg_count,
+            total_tokens,
+            conv_id
         ).execute(&self.pool).await?;
 
         Ok(())
     }
+}
 
+#[async_trait]
+impl MemoryStore for MessageStore {
     /// 创建一个新的对话记录
     ///
     /// # 参数
@@ -117,7 +251,7 @@ impl MessageStore {
     ///
     /// # 说明
     /// 在数据库中插入一条新的对话记录。对话 ID 必须是唯一的。
-    pub async fn create_conversation(&self, conv: &StoredConversation) -> MemoryResult<()> {
+    async fn create_conversation(&self, conv: &StoredConversation) -> MemoryResult<()> {
         let query = "INSERT INTO conversations (id, user_id, title, model_name, created_at, updated_at, system_message, system_message_hash, system_message_updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)";
         sqlx::query(query)
             .bind(&conv.id)
@@ -146,7 +280,7 @@ impl MessageStore {
     /// # 说明
     /// 存储消息到数据库并自动更新对应对话的统计信息（消息数量、token 总数等）。
     /// 如果消息包含工具调用，会将其序列化为 JSON 格式存储。
-    pub async fn store_message(&self, message: &StoredMessage) -> MemoryResult<()> {
+    async fn store_message(&self, message: &StoredMessage) -> MemoryResult<()> {
         let tool_calls_json = if message.tool_calls.is_empty() {
             None
         } else {
@@ -192,7 +326,7 @@ impl MessageStore {
     /// # 错误
     /// * `MemoryError::ConversationNotFound` - 当指定的对话不存在时
     /// * `MemoryError::InvalidData` - 当数据库查询失败时
-    pub async fn get_conversation(&self, conv_id: &str) -> MemoryResult<StoredConversation> {
+    async fn get_conversation(&self, conv_id: &str) -> MemoryResult<StoredConversation> {
         let row = sqlx::query("SELECT * FROM conversations WHERE id = ?")
             .bind(conv_id)
             .fetch_optional(&self.pool)
@@ -266,7 +400,7 @@ impl MessageStore {
     ///
     /// # 错误
     /// * `MemoryError::InvalidData` - 当数据库查询失败时
-    pub async fn get_recent_conversation_by_user(
+    async fn get_recent_conversation_by_user(
         &self,
         user_id: &str,
         model_name: Option<&str>,
@@ -342,7 +476,7 @@ impl MessageStore {
     /// # 说明
     /// 返回对话中的所有消息，按照序列号升序排列。
     /// 工具调用信息会从 JSON 格式反序列化为结构化数据。
-    pub async fn get_full_history(&self, conv_id: &str) -> MemoryResult<Vec<StoredMessage>> {
+    async fn get_full_history(&self, conv_id: &str) -> MemoryResult<Vec<StoredMessage>> {
         let rows = sqlx::query!(
             "SELECT * FROM messages WHERE conversation_id = ? ORDER BY sequence",
             conv_id
@@ -392,7 +526,7 @@ impl MessageStore {
     /// # 说明
     /// 返回对话中最近的N条消息，按照序列号升序排列。
     /// 工具调用信息会从 JSON 格式反序列化为结构化数据。
-    pub async fn get_recent_messages(
+    async fn get_recent_messages(
         &self,
         conv_id: &str,
         limit: usize,
@@ -457,8 +591,7 @@ impl MessageStore {
     /// # 说明
     /// 用于获取对话中从某个特定序列号开始的所有消息，常用于增量加载或断点续传场景。
     /// 返回的消息按序列号升序排列。
-    #[allow(dead_code)]
-    pub async fn get_messages_from_sequence(
+    async fn get_messages_from_sequence(
         &self,
         conv_id: &str,
         from_sequence: i64,
@@ -514,7 +647,7 @@ impl MessageStore {
     /// # 说明
     /// 返回对话中下一个可用的序列号，用于确保新消息的序列号唯一且连续。
     /// 如果对话中没有消息，返回 1。
-    pub async fn get_next_sequence(&self, conv_id: &str) -> MemoryResult<i64> {
+    async fn get_next_sequence(&self, conv_id: &str) -> MemoryResult<i64> {
         let row = sqlx::query!(
             "SELECT COALESCE(MAX(sequence), 0) + 1 as next_seq FROM messages WHERE conversation_id = ?",
             conv_id
@@ -536,7 +669,7 @@ impl MessageStore {
     /// # 说明
     /// 更新对话的摘要信息，通常在消息过多需要压缩历史时调用。
     /// 同时会更新对话的最后修改时间。
-    pub async fn update_conversation_summary(
+    async fn update_conversation_summary(
         &self,
         conv_id: &str,
         summary: &str,
@@ -564,7 +697,7 @@ impl MessageStore {
     /// # 说明
     /// 更新对话的系统消息并计算内容哈希以便检测变化。
     /// 如果系统消息内容发生变化，会更新哈希值和更新时间。
-    pub async fn update_system_message(
+    async fn update_system_message(
         &self,
         conv_id: &str,
         system_message: Option<&str>,
@@ -587,6 +720,62 @@ impl MessageStore {
         Ok(())
     }
 
+    /// 删除对话中从指定序号开始（包含）的所有消息
+    ///
+    /// # 参数
+    /// * `conv_id` - 目标对话的 ID
+    /// * `from_sequence` - 起始消息序号
+    ///
+    /// # 返回值
+    /// * `MemoryResult<usize>` - 成功时返回被删除的消息数量，失败时返回 MemoryError
+    ///
+    /// # 说明
+    /// 用于回滚一轮未完成的对话，删除后会重新计算对话的统计信息。
+    async fn delete_messages_from_sequence(
+        &self,
+        conv_id: &str,
+        from_sequence: i64,
+    ) -> MemoryResult<usize> {
+        let result =
+            sqlx::query("DELETE FROM messages WHERE conversation_id = ? AND sequence >= ?")
+                .bind(conv_id)
+                .bind(from_sequence)
+                .execute(&self.pool)
+                .await?;
+        self.update_conversation_stats(conv_id).await?;
+
+        Ok(result.rows_affected() as usize)
+    }
+
+    /// 替换对话中某条消息的内容
+    ///
+    /// # 参数
+    /// * `conv_id` - 目标对话的 ID
+    /// * `message_id` - 要更新的消息 ID
+    /// * `content` - 新的消息内容
+    ///
+    /// # 返回值
+    /// * `MemoryResult<()>` - 成功时返回 ()，失败时返回 MemoryError
+    async fn update_message_content(
+        &self,
+        conv_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> MemoryResult<()> {
+        sqlx::query("UPDATE messages SET content = ? WHERE id = ? AND conversation_id = ?")
+            .bind(content)
+            .bind(message_id)
+            .bind(conv_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("UPDATE conversations SET updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(conv_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     /// 获取对话列表摘要
     ///
     /// # 参数
@@ -598,8 +787,7 @@ impl MessageStore {
     /// # 说明
     /// 返回按最后更新时间降序排列的对话摘要列表，用于在 UI 中显示对话列表。
     /// 每个摘要包含对话的基本信息但不包含详细的消息内容。
-    #[allow(dead_code)]
-    pub async fn list_conversations(
+    async fn list_conversations(
         &self,
         limit: Option<usize>,
     ) -> MemoryResult<Vec<ConversationSummary>> {
@@ -651,7 +839,7 @@ impl MessageStore {
     /// # 说明
     /// 返回指定用户按最后更新时间降序排列的对话摘要列表。
     /// 每个摘要包含对话的基本信息但不包含详细的消息内容。
-    pub async fn list_conversations_by_user(
+    async fn list_conversations_by_user(
         &self,
         user_id: &str,
         limit: Option<usize>,
@@ -702,8 +890,7 @@ impl MessageStore {
     /// # 说明
     /// 返回包含对话总数、消息总数、token 总数等统计信息的结构。
     /// 某些统计项（如工具调用数量、数据库大小）当前为简化实现，可能返回默认值。
-    #[allow(dead_code)]
-    pub async fn get_stats(&self) -> MemoryResult<MemoryStats> {
+    async fn get_stats(&self) -> MemoryResult<MemoryStats> {
         let conv_count = sqlx::query!("SELECT COUNT(*) as count FROM conversations")
             .fetch_one(&self.pool)
             .await?
@@ -732,45 +919,96 @@ impl MessageStore {
         })
     }
 
-    async fn update_conversation_stats(&self, conv_id: &str) -> MemoryResult<()> {
-        let row = sqlx::query(
-            "SELECT COUNT(*) as msg_count, SUM(COALESCE(tokens, 0)) as total_tokens
-             FROM messages WHERE conversation_id = ?",
-        )
-        .bind(conv_id)
-        .fetch_one(&self.pool)
-        .await?;
-
-        let msg_count: i64 = row.get(0);
-        let total_tokens: Option<i64> = row.get(1);
-        let total_tokens: i64 = total_tokens.unwrap_or(0);
-
-        sqlx::query!(
-            "UPDATE conversations SET message_count = ?, total_tokens = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
-            msg_count,
-            total_tokens,
-            conv_id
-        ).execute(&self.pool).await?;
-
-        Ok(())
-    }
-
     /// 删除指定的对话及其所有消息
     ///
     /// # 参数
     /// * `conv_id` - 要删除的对话的唯一标识符
     ///
     /// # 返回值
-    /// * `MemoryResult<()>` - 成功时返回 ()，失败时返回 MemoryError
+    /// * `MemoryResult<bool>` - 对话存在并被删除时返回 true，失败时返回 MemoryError
     ///
     /// # 说明
     /// 由于设置了外键约束的级联删除，删除对话记录时会自动删除该对话下的所有消息。
     /// 此操作不可逆，请谨慎使用。
-    #[allow(dead_code)]
-    pub async fn delete_conversation(&self, conv_id: &str) -> MemoryResult<()> {
-        sqlx::query!("DELETE FROM conversations WHERE id = ?", conv_id)
+    async fn delete_conversation(&self, conv_id: &str) -> MemoryResult<bool> {
+        sqlx::query("DELETE FROM messages WHERE conversation_id = ?")
+            .bind(conv_id)
             .execute(&self.pool)
             .await?;
-        Ok(())
+        let result = sqlx::query!("DELETE FROM conversations WHERE id = ?", conv_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// 删除指定用户的所有对话及其所有消息
+    ///
+    /// # 参数
+    /// * `user_id` - 用户的唯一标识符
+    ///
+    /// # 返回值
+    /// * `MemoryResult<Vec<String>>` - 成功时返回被删除的对话 ID 列表，失败时返回 MemoryError
+    ///
+    /// # 说明
+    /// 所有删除在同一个事务中完成。用户没有任何对话时返回空列表。
+    /// 此操作不可逆，请谨慎使用。
+    async fn delete_conversations_by_user(&self, user_id: &str) -> MemoryResult<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+
+        let conv_ids: Vec<String> =
+            sqlx::query_scalar("SELECT id FROM conversations WHERE user_id = ?")
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await?;
+        sqlx::query(
+            "DELETE FROM messages WHERE conversation_id IN (SELECT id FROM conversations WHERE user_id = ?)",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM conversations WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(conv_ids)
+    }
+
+    /// 删除在指定时间之前最后更新的所有对话及其消息
+    ///
+    /// # 参数
+    /// * `cutoff` - 截止时间，`updated_at` 早于该时间的对话会被删除
+    ///
+    /// # 返回值
+    /// * `MemoryResult<Vec<String>>` - 成功时返回被删除的对话 ID 列表，失败时返回 MemoryError
+    ///
+    /// # 说明
+    /// 用于清理过期的闲置对话。所有删除在同一个事务中完成。
+    async fn delete_conversations_idle_since(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> MemoryResult<Vec<String>> {
+        let cutoff = cutoff.naive_utc();
+        let mut tx = self.pool.begin().await?;
+
+        let conv_ids: Vec<String> =
+            sqlx::query_scalar("SELECT id FROM conversations WHERE updated_at < ?")
+                .bind(cutoff)
+                .fetch_all(&mut *tx)
+                .await?;
+        sqlx::query(
+            "DELETE FROM messages WHERE conversation_id IN (SELECT id FROM conversations WHERE updated_at < ?)",
+        )
+        .bind(cutoff)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM conversations WHERE updated_at < ?")
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(conv_ids)
     }
 }
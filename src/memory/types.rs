@@ -235,6 +235,9 @@ pub enum MemoryError {
 
     #[error("Invalid data: {0}")]
     InvalidData(String),
+
+    #[error("Redis error: {0}")]
+    Redis(String),
 }
 
 /// Summarization status information
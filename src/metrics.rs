@@ -0,0 +1,233 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the downstream latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Number of observations per bucket, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(idx) = LATENCY_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[idx] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Counters of the `/v1` API requests handled by the gateway, kept whether metrics are enabled
+/// or not
+#[derive(Debug, Default)]
+pub(crate) struct RequestCounters {
+    in_flight: AtomicUsize,
+    served: AtomicU64,
+}
+impl RequestCounters {
+    /// Count a request as in flight until the returned guard is dropped, then as served
+    pub(crate) fn track(self: &Arc<Self>) -> RequestGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        RequestGuard(self.clone())
+    }
+
+    /// Number of requests being handled
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests handled since startup
+    pub(crate) fn served(&self) -> u64 {
+        self.served.load(Ordering::Relaxed)
+    }
+}
+
+/// Releases the in-flight count of a request on drop, including when the client disconnects
+#[derive(Debug)]
+pub(crate) struct RequestGuard(Arc<RequestCounters>);
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.0.served.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Registry of the operational metrics, rendered in the Prometheus text exposition format
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    requests: Mutex<BTreeMap<String, u64>>,
+    latency: Mutex<BTreeMap<String, Histogram>>,
+    tool_calls: Mutex<BTreeMap<String, u64>>,
+}
+impl Metrics {
+    /// Count a request forwarded to a downstream server of the given kind
+    pub(crate) fn record_request(&self, kind: impl ToString) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry(kind.to_string())
+            .or_default() += 1;
+    }
+
+    /// Record the response latency of a downstream server of the given kind
+    pub(crate) fn record_latency(&self, kind: impl ToString, latency: Duration) {
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(kind.to_string())
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Count a call of the given tool
+    pub(crate) fn record_tool_call(&self, tool: impl ToString) {
+        *self
+            .tool_calls
+            .lock()
+            .unwrap()
+            .entry(tool.to_string())
+            .or_default() += 1;
+    }
+
+    /// Render the metrics. `in_flight` holds the number of requests in flight per server kind.
+    pub(crate) fn render(&self, in_flight: &BTreeMap<String, usize>) -> String {
+        let mut text = String::new();
+
+        write_header(
+            &mut text,
+            "llama_nexus_downstream_requests_total",
+            "Number of requests forwarded to downstream servers",
+            "counter",
+        );
+        for (kind, count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                text,
+                "llama_nexus_downstream_requests_total{{kind=\"{}\"}} {count}",
+                escape_label(kind)
+            );
+        }
+
+        write_header(
+            &mut text,
+            "llama_nexus_downstream_latency_seconds",
+            "Response latency of downstream servers",
+            "histogram",
+        );
+        for (kind, histogram) in self.latency.lock().unwrap().iter() {
+            let kind = escape_label(kind);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "llama_nexus_downstream_latency_seconds_bucket{{kind=\"{kind}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                text,
+                "llama_nexus_downstream_latency_seconds_bucket{{kind=\"{kind}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                text,
+                "llama_nexus_downstream_latency_seconds_sum{{kind=\"{kind}\"}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                text,
+                "llama_nexus_downstream_latency_seconds_count{{kind=\"{kind}\"}} {}",
+                histogram.count
+            );
+        }
+
+        write_header(
+            &mut text,
+            "llama_nexus_tool_calls_total",
+            "Number of tool calls executed",
+            "counter",
+        );
+        for (tool, count) in self.tool_calls.lock().unwrap().iter() {
+            let _ = writeln!(
+                text,
+                "llama_nexus_tool_calls_total{{tool=\"{}\"}} {count}",
+                escape_label(tool)
+            );
+        }
+
+        write_header(
+            &mut text,
+            "llama_nexus_in_flight_requests",
+            "Number of requests in flight on downstream servers",
+            "gauge",
+        );
+        for (kind, count) in in_flight.iter() {
+            let _ = writeln!(
+                text,
+                "llama_nexus_in_flight_requests{{kind=\"{}\"}} {count}",
+                escape_label(kind)
+            );
+        }
+
+        text
+    }
+}
+
+fn write_header(text: &mut String, name: &str, help: &str, ty: &str) {
+    let _ = writeln!(text, "# HELP {name} {help}");
+    let _ = writeln!(text, "# TYPE {name} {ty}");
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_render_metrics() {
+    let metrics = Metrics::default();
+    metrics.record_tool_call("get_weather");
+    metrics.record_tool_call("get_weather");
+    metrics.record_tool_call("say \"hi\"");
+    metrics.record_latency("chat", Duration::from_millis(30));
+    metrics.record_latency("chat", Duration::from_secs(120));
+
+    let text = metrics.render(&BTreeMap::from([("chat".to_string(), 1)]));
+    assert!(text.contains("# TYPE llama_nexus_tool_calls_total counter\n"));
+    assert!(text.contains("llama_nexus_tool_calls_total{tool=\"get_weather\"} 2\n"));
+    assert!(text.contains("llama_nexus_tool_calls_total{tool=\"say \\\"hi\\\"\"} 1\n"));
+    assert!(
+        text.contains(
+            "llama_nexus_downstream_latency_seconds_bucket{kind=\"chat\",le=\"0.025\"} 0\n"
+        )
+    );
+    assert!(
+        text.contains(
+            "llama_nexus_downstream_latency_seconds_bucket{kind=\"chat\",le=\"0.05\"} 1\n"
+        )
+    );
+    assert!(
+        text.contains("llama_nexus_downstream_latency_seconds_bucket{kind=\"chat\",le=\"60\"} 1\n")
+    );
+    assert!(
+        text.contains(
+            "llama_nexus_downstream_latency_seconds_bucket{kind=\"chat\",le=\"+Inf\"} 2\n"
+        )
+    );
+    assert!(text.contains("llama_nexus_in_flight_requests{kind=\"chat\"} 1\n"));
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::RateLimitConfig;
+
+/// Key of the bucket shared by the requests that do not identify their user
+pub(crate) const ANONYMOUS_USER: &str = "anonymous";
+
+/// Number of buckets above which the full, idle buckets are dropped
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token-bucket rate limiter keyed by user id
+///
+/// Every user gets a bucket of `burst` tokens, refilled at `requests_per_minute`. A request
+/// takes one token and is rejected if the bucket is empty.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+impl RateLimiter {
+    pub(crate) fn new(config: &RateLimitConfig) -> Self {
+        let requests_per_minute = config.requests_per_minute.max(1) as f64;
+        Self {
+            capacity: config
+                .burst
+                .map_or(requests_per_minute, |burst| burst.max(1) as f64),
+            refill_per_sec: requests_per_minute / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the bucket of the user. Returns how long to wait before retrying if
+    /// the bucket is empty.
+    pub(crate) fn check(&self, user: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_IDLE_BUCKETS && !buckets.contains_key(user) {
+            let (capacity, refill_per_sec) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+                bucket.tokens + elapsed * refill_per_sec < capacity
+            });
+        }
+
+        let bucket = buckets.entry(user.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+
+        // refill the bucket with the tokens earned since the last request
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_per_sec,
+            ))
+        }
+    }
+}
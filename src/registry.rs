@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    config::RegistryConfig,
+    dual_error,
+    error::{ServerError, ServerResult},
+    server::{HealthStatus, Server, ServerId, ServerKind},
+};
+
+/// A server registered via `/admin/servers/register`, as saved in the registry file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RegistryEntry {
+    pub id: ServerId,
+    pub url: String,
+    pub kind: ServerKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    pub weight: u32,
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra_headers: HashMap<String, String>,
+}
+impl RegistryEntry {
+    fn from_server(server: &Server) -> Self {
+        Self {
+            id: server.id.clone(),
+            url: server.url.clone(),
+            kind: server.kind,
+            api_key: server.api_key.clone(),
+            weight: server.weight,
+            enabled: server.enabled,
+            extra_headers: server.extra_headers.clone(),
+        }
+    }
+
+    /// Rebuild the server, keeping its id. It is unhealthy until it is probed, so that no request
+    /// is routed to a server that went away while the gateway was down.
+    pub(crate) fn into_server(self) -> ServerResult<Server> {
+        let mut server: Server = serde_json::from_value(serde_json::json!({
+            "url": self.url,
+            "kind": self.kind,
+            "api_key": self.api_key,
+            "weight": self.weight,
+            "extra_headers": self.extra_headers,
+        }))
+        .map_err(|e| {
+            let err_msg = format!("Invalid server {} in the registry: {e}", self.id);
+            dual_error!("{}", &err_msg);
+            ServerError::Operation(err_msg)
+        })?;
+        server.id = self.id;
+        server.enabled = self.enabled;
+        server.health_status = HealthStatus {
+            is_healthy: false,
+            last_check: UNIX_EPOCH,
+        };
+
+        Ok(server)
+    }
+}
+
+/// JSON file keeping the servers registered at runtime across restarts
+#[derive(Debug)]
+pub(crate) struct ServerRegistry {
+    path: PathBuf,
+    /// Serializes the read-modify-write cycles of the file
+    lock: Mutex<()>,
+}
+impl ServerRegistry {
+    pub(crate) fn new(config: &RegistryConfig) -> Self {
+        Self {
+            path: PathBuf::from(&config.path),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Read the saved servers. A missing file means that no server was registered yet.
+    pub(crate) async fn load(&self) -> ServerResult<Vec<RegistryEntry>> {
+        let _guard = self.lock.lock().await;
+        read_entries(&self.path).await
+    }
+
+    /// Save a newly registered server
+    pub(crate) async fn add(&self, server: &Server) -> ServerResult<()> {
+        self.update(|entries| {
+            entries.retain(|entry| entry.id != server.id);
+            entries.push(RegistryEntry::from_server(server));
+        })
+        .await
+    }
+
+    /// Forget an unregistered server
+    pub(crate) async fn remove(&self, server_id: &str) -> ServerResult<()> {
+        self.update(|entries| entries.retain(|entry| entry.id != server_id))
+            .await
+    }
+
+    /// Save whether a server is enabled
+    pub(crate) async fn set_enabled(&self, server_id: &str, enabled: bool) -> ServerResult<()> {
+        self.update(|entries| {
+            entries
+                .iter_mut()
+                .filter(|entry| entry.id == server_id)
+                .for_each(|entry| entry.enabled = enabled)
+        })
+        .await
+    }
+
+    async fn update(&self, f: impl FnOnce(&mut Vec<RegistryEntry>)) -> ServerResult<()> {
+        let _guard = self.lock.lock().await;
+        let mut entries = read_entries(&self.path).await?;
+        f(&mut entries);
+        write_entries(&self.path, &entries).await
+    }
+}
+
+async fn read_entries(path: &Path) -> ServerResult<Vec<RegistryEntry>> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            let err_msg = format!("Failed to read the server registry {}: {e}", path.display());
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::Operation(err_msg));
+        }
+    };
+
+    serde_json::from_slice(&bytes).map_err(|e| {
+        let err_msg = format!(
+            "Failed to parse the server registry {}: {e}",
+            path.display()
+        );
+        dual_error!("{}", &err_msg);
+        ServerError::Operation(err_msg)
+    })
+}
+
+// write to a temporary file first, so that a crash never leaves a truncated registry. The file
+// holds the api keys of the servers, so it is only readable by the owner.
+async fn write_entries(path: &Path, entries: &[RegistryEntry]) -> ServerResult<()> {
+    let to_err = |e: std::io::Error| {
+        let err_msg = format!(
+            "Failed to write the server registry {}: {e}",
+            path.display()
+        );
+        dual_error!("{}", &err_msg);
+        ServerError::Operation(err_msg)
+    };
+
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(parent).await.map_err(to_err)?;
+    }
+
+    let json = serde_json::to_vec_pretty(entries).map_err(|e| {
+        let err_msg = format!("Failed to serialize the server registry: {e}");
+        dual_error!("{}", &err_msg);
+        ServerError::Operation(err_msg)
+    })?;
+    let tmp_path = path.with_extension("tmp");
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp_path).await.map_err(to_err)?;
+    file.write_all(&json).await.map_err(to_err)?;
+    file.sync_all().await.map_err(to_err)?;
+    tokio::fs::rename(&tmp_path, path).await.map_err(to_err)
+}
@@ -0,0 +1,143 @@
+use std::convert::Infallible;
+
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{HeaderValue, Request, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+/// Header carrying the id of a request, from the client to the downstream servers and back
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Id of the request being handled
+///
+/// Taken from the `x-request-id` header of the request, or generated if the client does not send
+/// one. Handlers extract it from the request extensions set by [`propagate_request_id`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RequestId(pub String);
+impl RequestId {
+    pub(crate) fn new() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+}
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<S: Send + Sync> FromRequestParts<S> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Middleware that reuses the `x-request-id` header of the request or generates a new id,
+/// stores it in the request extensions and headers, and returns it in the response headers
+pub(crate) async fn propagate_request_id(mut req: Request<Body>, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty())
+        .map(|id| RequestId(id.to_string()))
+        .unwrap_or_default();
+    let header_value = HeaderValue::from_str(&request_id.0).unwrap();
+
+    req.headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value.clone());
+    req.extensions_mut().insert(request_id);
+
+    let mut response = next.run(req).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        Extension, Json, Router,
+        http::{HeaderMap, header::CONTENT_TYPE},
+        routing::post,
+    };
+    use tokio_util::sync::CancellationToken;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, handlers::embeddings_handler, test_utils::*};
+
+    #[tokio::test]
+    async fn test_request_id_is_generated_and_propagated() {
+        // the embeddings server records the request ids it receives
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new().route(
+            "/v1/embeddings",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap| async move {
+                    let request_id = headers
+                        .get(REQUEST_ID_HEADER)
+                        .map(|h| h.to_str().unwrap().to_string());
+                    received.lock().unwrap().push(request_id);
+                    Json(serde_json::json!({
+                        "object": "list",
+                        "data": [{ "index": 0, "object": "embedding", "embedding": [0.5] }],
+                        "model": "test-embedder",
+                        "usage": { "prompt_tokens": 1, "completion_tokens": 0, "total_tokens": 1 }
+                    }))
+                }
+            }),
+        );
+        let url = spawn_mock_server(router).await;
+        let state = create_test_state(Config::default(), &[(&url, "embeddings")]).await;
+        let app = Router::new()
+            .route("/v1/embeddings", post(embeddings_handler))
+            .with_state(state)
+            .layer(Extension(CancellationToken::new()))
+            .layer(axum::middleware::from_fn(propagate_request_id));
+
+        let send = |request_id: Option<&str>| {
+            let mut request =
+                Request::post("/v1/embeddings").header(CONTENT_TYPE, "application/json");
+            if let Some(request_id) = request_id {
+                request = request.header(REQUEST_ID_HEADER, request_id);
+            }
+            let request = request.body(Body::from(r#"{"input":"hello"}"#)).unwrap();
+            app.clone().oneshot(request)
+        };
+
+        // a request without id gets a generated one, sent to the downstream server
+        let response = send(None).await.unwrap();
+        assert!(response.status().is_success());
+        let generated = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+        assert_eq!(
+            received.lock().unwrap().last().unwrap().as_deref(),
+            Some(generated.as_str())
+        );
+
+        // the id sent by the client is kept
+        let response = send(Some("client-id")).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "client-id");
+        assert_eq!(
+            received.lock().unwrap().last().unwrap().as_deref(),
+            Some("client-id")
+        );
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// A rerank request, as accepted by the `/v1/rerank` endpoint
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct RerankRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub query: String,
+    pub documents: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<usize>,
+    /// Other fields understood by the downstream rerank server are forwarded as is
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The response of a rerank request computed from embeddings
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct RerankResponse {
+    pub model: String,
+    pub results: Vec<RerankResult>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct RerankResult {
+    /// The index of the document in the request
+    pub index: usize,
+    pub relevance_score: f64,
+    pub document: RerankDocument,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct RerankDocument {
+    pub text: String,
+}
+
+/// Rank the documents by the cosine similarity of their embeddings to the query embedding
+///
+/// The results are ordered by descending relevance and truncated to `top_n` if given.
+pub(crate) fn rank_by_similarity(
+    request: &RerankRequest,
+    query_embedding: &[f64],
+    document_embeddings: &[Vec<f64>],
+) -> Vec<RerankResult> {
+    let mut results: Vec<RerankResult> = request
+        .documents
+        .iter()
+        .zip(document_embeddings)
+        .enumerate()
+        .map(|(index, (text, embedding))| RerankResult {
+            index,
+            relevance_score: cosine_similarity(query_embedding, embedding),
+            document: RerankDocument { text: text.clone() },
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+    if let Some(top_n) = request.top_n {
+        results.truncate(top_n);
+    }
+
+    results
+}
+
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    match norm_a == 0.0 || norm_b == 0.0 {
+        true => 0.0,
+        false => dot / (norm_a * norm_b),
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use endpoints::chat::ChatCompletionRequest;
+
+use crate::{config::ResponseCacheConfig, idempotency::CachedResponse};
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: CachedResponse,
+    inserted_at: Instant,
+}
+
+/// Bounded, TTL-based cache of the responses to deterministic chat requests, keyed by the hash
+/// of the normalized request
+#[derive(Debug)]
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<u64, CacheEntry>>,
+}
+impl ResponseCache {
+    pub(crate) fn new(config: &ResponseCacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_entries: config.max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cache key of the request if its response can be cached: a non-streaming
+    /// request without tools, with a zero temperature or a seed.
+    ///
+    /// `caller` is the identity and the user of the caller, so that a cached response is only
+    /// replayed to the caller it was produced for; the anonymous callers share their responses.
+    /// `extra` holds what changes the answer besides the request itself.
+    pub(crate) fn key(
+        request: &ChatCompletionRequest,
+        seed: Option<u64>,
+        caller: Option<&str>,
+        extra: impl Hash,
+    ) -> Option<u64> {
+        let deterministic = request.temperature == Some(0.0) || seed.is_some();
+        let has_tools = request
+            .tools
+            .as_ref()
+            .is_some_and(|tools| !tools.is_empty());
+        if !deterministic || has_tools || request.stream == Some(true) {
+            return None;
+        }
+
+        // the user is part of the caller, the one generated for an anonymous caller is ignored
+        let mut body = serde_json::to_value(request).ok()?;
+        if let Some(body) = body.as_object_mut() {
+            body.remove("user");
+        }
+        let body = body.to_string();
+
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        seed.hash(&mut hasher);
+        caller.hash(&mut hasher);
+        extra.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    /// Returns the cached response for the key if it has not expired
+    pub(crate) fn get(&self, key: u64) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(&key) {
+            Some(entry) if entry.inserted_at.elapsed() < self.ttl => Some(entry.response.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches the response for the key, evicting expired entries first and then the oldest
+    /// entry if the cache is full
+    pub(crate) fn insert(&self, key: u64, response: CachedResponse) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| entry.inserted_at.elapsed() < ttl);
+
+            if entries.len() >= self.max_entries
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.inserted_at)
+                    .map(|(k, _)| *k)
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                response,
+                inserted_at: Instant::now(),
+            },
+        );
+    }
+}
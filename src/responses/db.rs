@@ -2,7 +2,7 @@ use std::sync::Mutex;
 
 use rusqlite::{Connection, Result, params};
 
-use crate::responses::models::{Session, SessionRow};
+use crate::responses::models::{ResponseReply, Session, SessionRow};
 
 pub struct Database {
     conn: Mutex<Connection>,
@@ -29,6 +29,15 @@ impl Database {
             )",
             [],
         )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS responses(
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                reply_data TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
         Ok(())
     }
 
@@ -103,6 +112,71 @@ impl Database {
         Ok(None)
     }
 
+    pub fn save_response(&self, session_id: &str, reply: &ResponseReply) -> Result<()> {
+        let reply_json = serde_json::to_string(reply)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO responses (id, session_id, reply_data, created_at)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![reply.id, session_id, reply_json, reply.created_at],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_response(&self, response_id: &str) -> Result<Option<ResponseReply>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT reply_data FROM responses WHERE id = ?1")?;
+
+        let mut reply_iter = stmt.query_map([response_id], |row| {
+            let reply_data: String = row.get(0)?;
+            Ok(reply_data)
+        })?;
+
+        if let Some(reply_result) = reply_iter.next() {
+            let reply_data = reply_result?;
+            let reply: ResponseReply = serde_json::from_str(&reply_data).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })?;
+            return Ok(Some(reply));
+        }
+
+        Ok(None)
+    }
+
+    /// Delete a response together with the session it belongs to. Returns false if the
+    /// response does not exist.
+    pub fn delete_response(&self, response_id: &str) -> Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let session_id: Option<String> = tx
+            .query_row(
+                "SELECT session_id FROM responses WHERE id = ?1",
+                [response_id],
+                |row| row.get(0),
+            )
+            .map(Some)
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                e => Err(e),
+            })?;
+        let Some(session_id) = session_id else {
+            return Ok(false);
+        };
+
+        tx.execute("DELETE FROM responses WHERE id = ?1", params![response_id])?;
+        tx.execute("DELETE FROM sessions WHERE id = ?1", params![session_id])?;
+        tx.commit()?;
+
+        Ok(true)
+    }
+
     #[allow(dead_code)]
     pub fn list_sessions(&self) -> Result<Vec<SessionRow>> {
         let conn = self.conn.lock().unwrap();
@@ -247,14 +321,14 @@ mod tests {
             let db_clone = Arc::clone(&db);
             let handle = thread::spawn(move || {
                 let mut session = Session::new(
-                    format!("session_{}", i),
+                    format!("session_{i}"),
                     "test_model".to_string(),
                     Some("System prompt".to_string()),
                 );
 
                 session.add_message(
                     "user".to_string(),
-                    format!("Message from thread {}", i),
+                    format!("Message from thread {i}"),
                     5,
                     None,
                     None,
@@ -262,10 +336,10 @@ mod tests {
 
                 session.add_message(
                     "assistant".to_string(),
-                    format!("Response from thread {}", i),
+                    format!("Response from thread {i}"),
                     8,
                     Some(100),
-                    Some(format!("resp_{}", i)),
+                    Some(format!("resp_{i}")),
                 );
 
                 db_clone.save_session(&session).unwrap();
@@ -274,7 +348,7 @@ mod tests {
                 assert!(retrieved.is_some());
 
                 let found = db_clone
-                    .find_session_by_response_id(&format!("resp_{}", i))
+                    .find_session_by_response_id(&format!("resp_{i}"))
                     .unwrap();
                 assert!(found.is_some());
             });
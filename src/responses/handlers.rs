@@ -1,17 +1,28 @@
 use std::sync::Arc;
 
-use axum::{extract::State, http::StatusCode, response::Json};
+use axum::{
+    body::Body,
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
 use endpoints::chat::{
     ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionUserMessageContent,
 };
+use futures_util::{StreamExt, stream};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     AppState as MainAppState,
+    chat::SseContentCollector,
+    dual_error, dual_warn,
+    error::{ServerError, ServerResult},
+    request_id::RequestId,
     responses::{
         db::Database,
         models::{ResponseReply, ResponseRequest, Session},
     },
-    server::RoutingPolicy,
 };
 
 pub struct AppState {
@@ -21,8 +32,11 @@ pub struct AppState {
 
 pub async fn responses_handler(
     State(state): State<Arc<AppState>>,
+    Extension(cancel_token): Extension<CancellationToken>,
+    headers: HeaderMap,
+    RequestId(request_id): RequestId,
     Json(req): Json<ResponseRequest>,
-) -> Result<Json<ResponseReply>, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
     let model = req.model.clone();
 
     let response_id = format!("resp_{}", uuid::Uuid::new_v4().simple());
@@ -85,18 +99,45 @@ pub async fn responses_handler(
         model: Some(model.clone()),
         messages,
         user: Some("responses-api".to_string()),
-        stream: Some(false),
+        stream: Some(req.stream),
         ..Default::default()
     };
 
-    let chat_result = match call_chat_backend(&state.main_state, chat_request).await {
+    if req.stream {
+        let ds_response = match send_chat_request(
+            &state.main_state,
+            &headers,
+            &chat_request,
+            &cancel_token,
+            &request_id,
+        )
+        .await
+        {
+            Ok(ds_response) => ds_response,
+            // surfaced with its status, e.g. a 503 with Retry-After if all circuits are open
+            Err(e) => return Ok(e.into_response()),
+        };
+
+        let stream_context = StreamContext {
+            response_id,
+            model,
+            input_tokens: user_tokens,
+            previous_response_id: req.previous_response_id,
+        };
+        return Ok(stream_response(state, session, ds_response, stream_context));
+    }
+
+    let chat_result = match call_chat_backend(
+        &state.main_state,
+        &headers,
+        chat_request,
+        &cancel_token,
+        &request_id,
+    )
+    .await
+    {
         Ok(result) => result,
-        Err(e) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Chat backend error: {e}"),
-            ));
-        }
+        Err(e) => return Ok(e.into_response()),
     };
 
     let output_tokens = estimate_tokens(&chat_result);
@@ -126,7 +167,211 @@ pub async fn responses_handler(
         req.previous_response_id,
     );
 
-    Ok(Json(response))
+    if let Err(e) = state.db.save_response(&session.response_id, &response) {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to save response: {e}"),
+        ));
+    }
+
+    Ok(Json(response).into_response())
+}
+
+pub async fn get_response_handler(
+    State(state): State<Arc<AppState>>,
+    Path(response_id): Path<String>,
+) -> Result<Json<ResponseReply>, (StatusCode, String)> {
+    match state.db.get_response(&response_id) {
+        Ok(Some(reply)) => Ok(Json(reply)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Response not found: {response_id}"),
+        )),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Database error: {e}"),
+        )),
+    }
+}
+
+/// Delete a response. The session of the response is deleted as well, so the conversation
+/// can no longer be continued from any of its responses.
+pub async fn delete_response_handler(
+    State(state): State<Arc<AppState>>,
+    Path(response_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    match state.db.delete_response(&response_id) {
+        Ok(true) => Ok(Json(serde_json::json!({
+            "id": response_id,
+            "object": "response",
+            "deleted": true,
+        }))),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Response not found: {response_id}"),
+        )),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Database error: {e}"),
+        )),
+    }
+}
+
+/// Identifies the response produced by a stream
+struct StreamContext {
+    response_id: String,
+    model: String,
+    input_tokens: i32,
+    previous_response_id: Option<String>,
+}
+
+/// Translate the chunks of a streamed chat completion into Responses API events
+///
+/// A `response.output_text.delta` event is sent for every content delta, and the stream ends
+/// with `response.output_text.done` and `response.completed`. The session is saved once the
+/// downstream stream ends, or with the partial answer if the client disconnects mid-stream.
+fn stream_response(
+    state: Arc<AppState>,
+    mut session: Session,
+    ds_response: reqwest::Response,
+    context: StreamContext,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<String>(16);
+
+    tokio::spawn(async move {
+        let message_id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+
+        let created = sse_event(
+            "response.created",
+            serde_json::json!({
+                "response": {
+                    "id": context.response_id,
+                    "object": "response",
+                    "created_at": chrono::Utc::now().timestamp(),
+                    "status": "in_progress",
+                    "model": context.model,
+                    "output": [],
+                    "previous_response_id": context.previous_response_id,
+                }
+            }),
+        );
+        let mut connected = tx.send(created).await.is_ok();
+
+        let mut collector = SseContentCollector::default();
+        let mut body = ds_response.bytes_stream();
+        'stream: while connected {
+            let chunk = match body.next().await {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    dual_error!(
+                        "Failed to read the chat stream of response {}: {}",
+                        context.response_id,
+                        e
+                    );
+                    break;
+                }
+                None => break,
+            };
+
+            for delta in collector.feed(&chunk) {
+                let event = sse_event(
+                    "response.output_text.delta",
+                    serde_json::json!({
+                        "item_id": message_id,
+                        "output_index": 0,
+                        "content_index": 0,
+                        "delta": delta,
+                    }),
+                );
+                if tx.send(event).await.is_err() {
+                    connected = false;
+                    break 'stream;
+                }
+            }
+        }
+
+        if !connected {
+            dual_warn!(
+                "Client disconnected from the stream of response {}. Save the partial answer",
+                context.response_id
+            );
+        }
+
+        // save the answer, even if partial, so that the conversation can be continued
+        let text = collector.content;
+        let output_tokens = estimate_tokens(&text);
+        session.add_message(
+            "assistant".to_string(),
+            text.clone(),
+            output_tokens,
+            None,
+            Some(context.response_id.clone()),
+        );
+        if let Err(e) = state.db.save_session(&session) {
+            dual_error!(
+                "Failed to save the session of response {}: {}",
+                context.response_id,
+                e
+            );
+        }
+
+        let mut reply = ResponseReply::with_message_id(
+            context.response_id.clone(),
+            message_id.clone(),
+            context.model,
+            text.clone(),
+            context.input_tokens,
+            output_tokens,
+            context.previous_response_id,
+        );
+        if !connected {
+            reply.status = "incomplete".to_string();
+            reply.output[0].status = "incomplete".to_string();
+        }
+        if let Err(e) = state.db.save_response(&session.response_id, &reply) {
+            dual_error!("Failed to save response {}: {}", context.response_id, e);
+        }
+
+        if connected {
+            let done = sse_event(
+                "response.output_text.done",
+                serde_json::json!({
+                    "item_id": message_id,
+                    "output_index": 0,
+                    "content_index": 0,
+                    "text": text,
+                }),
+            );
+            let completed = sse_event(
+                "response.completed",
+                serde_json::json!({ "response": reply }),
+            );
+            for event in [done, completed] {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|event| (Ok::<_, std::convert::Infallible>(event), rx))
+    });
+
+    let mut response = Response::new(Body::from_stream(body));
+    let headers = response.headers_mut();
+    headers.insert("Content-Type", "text/event-stream".parse().unwrap());
+    headers.insert("Cache-Control", "no-cache".parse().unwrap());
+    headers.insert("Connection", "keep-alive".parse().unwrap());
+    response
+}
+
+/// Format a Responses API event as an SSE event. The event type is added to the data.
+fn sse_event(event_type: &str, mut data: serde_json::Value) -> String {
+    data["type"] = event_type.into();
+    format!("event: {event_type}\ndata: {data}\n\n")
 }
 
 fn estimate_tokens(text: &str) -> i32 {
@@ -135,54 +380,58 @@ fn estimate_tokens(text: &str) -> i32 {
 
 async fn call_chat_backend(
     main_state: &Arc<MainAppState>,
+    headers: &HeaderMap,
     request: ChatCompletionRequest,
-) -> Result<String, String> {
-    let servers = main_state.server_group.read().await;
-    let chat_servers = match servers.get(&crate::server::ServerKind::chat) {
-        Some(servers) => servers,
-        None => return Err("No chat server available".to_string()),
-    };
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<String> {
+    let response =
+        send_chat_request(main_state, headers, &request, cancel_token, request_id).await?;
+
+    let chat_response: endpoints::chat::ChatCompletionObject =
+        response.json().await.map_err(|e| {
+            let err_msg = format!("Failed to parse response: {e}");
+            dual_error!("{} - request_id: {}", err_msg, request_id);
+            ServerError::Operation(err_msg)
+        })?;
 
-    let target_server = match chat_servers.next().await {
-        Ok(server) => server,
-        Err(e) => return Err(format!("Failed to get chat server: {e}")),
-    };
+    let text = chat_response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.as_ref())
+        .map(|content| content.to_string())
+        .unwrap_or_else(|| "No response content".to_string());
 
-    let url = format!(
-        "{}/chat/completions",
-        target_server.url.trim_end_matches('/')
-    );
+    Ok(text)
+}
 
-    let client = reqwest::Client::new();
-    let response = client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {e}"))?;
+/// Send the chat request to a chat server serving the model and return its successful response
+///
+/// Sent like the requests of the chat endpoint: fails over to another chat server if the picked
+/// one cannot be reached, reports the outcome to its circuit breaker, and forwards the request id
+/// and the `authorization` header of the client.
+async fn send_chat_request(
+    main_state: &Arc<MainAppState>,
+    headers: &HeaderMap,
+    request: &ChatCompletionRequest,
+    cancel_token: &CancellationToken,
+    request_id: &str,
+) -> ServerResult<reqwest::Response> {
+    let (_, response) =
+        crate::chat::send_chat_request(main_state, headers, request, cancel_token, request_id)
+            .await?;
 
     if !response.status().is_success() {
         let error_text = response
             .text()
             .await
             .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Chat API Error: {error_text}"));
+        let err_msg = format!("Chat API Error: {error_text}");
+        dual_error!("{} - request_id: {}", err_msg, request_id);
+        return Err(ServerError::Operation(err_msg));
     }
 
-    let chat_response: endpoints::chat::ChatCompletionObject = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse response: {e}"))?;
-
-    let text = chat_response
-        .choices
-        .first()
-        .and_then(|choice| choice.message.content.as_ref())
-        .map(|content| content.to_string())
-        .unwrap_or_else(|| "No response content".to_string());
-
-    Ok(text)
+    Ok(response)
 }
 
 pub async fn health_handler() -> Json<serde_json::Value> {
@@ -195,6 +444,7 @@ pub async fn health_handler() -> Json<serde_json::Value> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::ServerKind;
 
     #[test]
     fn test_estimate_tokens() {
@@ -219,4 +469,258 @@ mod tests {
         assert_eq!(json_value["status"], "ok");
         assert_eq!(json_value["service"], "responses-api");
     }
+
+    #[tokio::test]
+    async fn test_stream_response_events() {
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                let events = format!(
+                    "{}{}{}data: [DONE]\n\n",
+                    crate::test_utils::sse_chunk("Hello"),
+                    crate::test_utils::sse_chunk(", "),
+                    crate::test_utils::sse_chunk("world!")
+                );
+                ([("content-type", "text/event-stream")], events)
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let main_state = crate::test_utils::create_test_state(
+            crate::config::Config::default(),
+            &[(&url, "chat")],
+        )
+        .await;
+        let db_path = std::env::temp_dir().join(format!("llama-nexus-{}.db", uuid::Uuid::new_v4()));
+        let state = Arc::new(AppState {
+            db: Database::new(db_path.to_str().unwrap()).unwrap(),
+            main_state,
+        });
+
+        let req: ResponseRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "input": "Say hello",
+            "stream": true,
+        }))
+        .unwrap();
+        let response = responses_handler(
+            State(state.clone()),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(req),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let events: Vec<serde_json::Value> = String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+
+        let types: Vec<&str> = events
+            .iter()
+            .map(|event| event["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "response.created",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.completed",
+            ]
+        );
+        let completed = &events[5]["response"];
+        assert_eq!(completed["status"], "completed");
+        assert_eq!(
+            completed["output"][0]["content"][0]["text"],
+            "Hello, world!"
+        );
+        assert_eq!(completed["output"][0]["id"], events[1]["item_id"]);
+
+        // the answer is saved once the stream is complete
+        let response_id = completed["id"].as_str().unwrap();
+        let session = state
+            .db
+            .find_session_by_response_id(response_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            session.get_conversation_history().last().unwrap(),
+            &("assistant".to_string(), "Hello, world!".to_string())
+        );
+
+        let _ = std::fs::remove_file(db_path);
+    }
+
+    #[tokio::test]
+    async fn test_get_and_delete_response() {
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                Json(crate::test_utils::chat_completion_json("Hello!"))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let main_state = crate::test_utils::create_test_state(
+            crate::config::Config::default(),
+            &[(&url, "chat")],
+        )
+        .await;
+        let state = Arc::new(AppState {
+            db: Database::new(":memory:").unwrap(),
+            main_state,
+        });
+
+        let req: ResponseRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "input": "Say hello",
+        }))
+        .unwrap();
+        let response = responses_handler(
+            State(state.clone()),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(req),
+        )
+        .await
+        .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let response_id = created["id"].as_str().unwrap().to_string();
+
+        let Json(reply) = get_response_handler(State(state.clone()), Path(response_id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(reply.id, response_id);
+        assert_eq!(reply.output[0].content[0].text, "Hello!");
+        assert_eq!(reply.output[0].id, created["output"][0]["id"]);
+
+        let Json(deleted) =
+            delete_response_handler(State(state.clone()), Path(response_id.clone()))
+                .await
+                .unwrap();
+        assert_eq!(deleted["id"], response_id.as_str());
+        assert_eq!(deleted["deleted"], true);
+
+        // the session of the response is gone too
+        assert!(
+            state
+                .db
+                .find_session_by_response_id(&response_id)
+                .unwrap()
+                .is_none()
+        );
+
+        let (status, _) = get_response_handler(State(state.clone()), Path(response_id.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = delete_response_handler(State(state), Path(response_id))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_chat_request_fails_over_to_a_reachable_server() {
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                Json(crate::test_utils::chat_completion_json("Hello!"))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let refused_url = crate::test_utils::refused_server_url().await;
+        let main_state = crate::test_utils::create_test_state(
+            crate::config::Config::default(),
+            &[(&refused_url, "chat"), (&url, "chat")],
+        )
+        .await;
+        let state = Arc::new(AppState {
+            db: Database::new(":memory:").unwrap(),
+            main_state,
+        });
+
+        // whichever server is picked first, the request is answered
+        for _ in 0..2 {
+            let req: ResponseRequest = serde_json::from_value(serde_json::json!({
+                "model": "test-model",
+                "input": "Say hello",
+            }))
+            .unwrap();
+            let response = responses_handler(
+                State(state.clone()),
+                Extension(CancellationToken::new()),
+                HeaderMap::new(),
+                RequestId::new(),
+                Json(req),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let created: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(created["output"][0]["content"][0]["text"], "Hello!");
+        }
+
+        // the unreachable server is taken out of the rotation
+        let servers = state.main_state.list_downstream_servers().await.unwrap();
+        let refused = servers[&ServerKind::chat]
+            .iter()
+            .find(|server| server.url == refused_url)
+            .unwrap();
+        assert!(!refused.health_status.is_healthy);
+    }
+
+    #[tokio::test]
+    async fn test_chat_request_forwards_request_id_and_authorization() {
+        let router = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|headers: HeaderMap| async move {
+                assert_eq!(headers["x-request-id"], "client-id");
+                assert_eq!(headers["authorization"], "Bearer client-key");
+                Json(crate::test_utils::chat_completion_json("Hello!"))
+            }),
+        );
+        let url = crate::test_utils::spawn_mock_server(router).await;
+        let main_state = crate::test_utils::create_test_state(
+            crate::config::Config::default(),
+            &[(&url, "chat")],
+        )
+        .await;
+        let state = Arc::new(AppState {
+            db: Database::new(":memory:").unwrap(),
+            main_state,
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer client-key".parse().unwrap());
+        let req: ResponseRequest = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "input": "Say hello",
+        }))
+        .unwrap();
+        let response = responses_handler(
+            State(state),
+            Extension(CancellationToken::new()),
+            headers,
+            RequestId("client-id".to_string()),
+            Json(req),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 }
@@ -3,6 +3,8 @@ pub mod handlers;
 pub mod models;
 
 pub use db::Database;
-pub use handlers::{AppState, health_handler, responses_handler};
+pub use handlers::{
+    AppState, delete_response_handler, get_response_handler, health_handler, responses_handler,
+};
 #[allow(unused_imports)] // These are part of the public API and used in handlers
 pub use models::{ResponseReply, ResponseRequest, Session};
@@ -8,9 +8,11 @@ pub struct ResponseRequest {
     pub input: String,
     pub instructions: Option<String>,
     pub previous_response_id: Option<String>,
+    #[serde(default)]
+    pub stream: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseReply {
     pub id: String,
     pub object: String,
@@ -22,7 +24,7 @@ pub struct ResponseReply {
     pub previous_response_id: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OutputItem {
     #[serde(rename = "type")]
     pub item_type: String,
@@ -32,7 +34,7 @@ pub struct OutputItem {
     pub content: Vec<ContentItem>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ContentItem {
     #[serde(rename = "type")]
     pub content_type: String,
@@ -152,8 +154,30 @@ impl ResponseReply {
         output_tokens: i32,
         previous_id: Option<String>,
     ) -> Self {
-        let now = chrono::Utc::now().timestamp();
         let message_id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+        Self::with_message_id(
+            response_id,
+            message_id,
+            model,
+            content,
+            input_tokens,
+            output_tokens,
+            previous_id,
+        )
+    }
+
+    /// Create a reply whose output message has the given id, e.g. the id already announced in
+    /// the events of a streamed response
+    pub fn with_message_id(
+        response_id: String,
+        message_id: String,
+        model: String,
+        content: String,
+        input_tokens: i32,
+        output_tokens: i32,
+        previous_id: Option<String>,
+    ) -> Self {
+        let now = chrono::Utc::now().timestamp();
 
         ResponseReply {
             id: response_id,
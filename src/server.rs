@@ -1,7 +1,10 @@
 use std::{
-    collections::HashSet,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::{Duration, SystemTime},
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use async_trait::async_trait;
@@ -11,13 +14,25 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
-    HEALTH_CHECK_INTERVAL, dual_error, dual_warn,
+    HEALTH_CHECK_INTERVAL,
+    config::CircuitBreakerConfig,
+    dual_error, dual_info, dual_warn,
     error::{ServerError, ServerResult},
 };
 
 /// Timeout duration for health checks (in seconds)
 const TIMEOUT: u64 = 10;
 
+/// Default routing weight of a server
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// Smoothing factor of the latency EWMA: the weight given to the latest measurement
+const LATENCY_EWMA_ALPHA: f64 = 0.3;
+
+/// Half-life of a latency measurement (in seconds). Stale measurements decay towards zero so
+/// that a server which was slow in the past gets probed again.
+const LATENCY_HALF_LIFE: f64 = 60.0;
+
 pub(crate) type ServerId = String;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +40,12 @@ pub(crate) struct ServerIdToRemove {
     pub server_id: ServerId,
 }
 
+/// Request body to enable or disable a registered server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ServerStatusUpdate {
+    pub enable: bool,
+}
+
 /// Represents the health status of a server
 #[derive(Debug, Clone)]
 pub struct HealthStatus {
@@ -49,9 +70,29 @@ pub struct Server {
     pub kind: ServerKind,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_key: Option<String>,
+    /// Relative share of requests the server receives under weighted round-robin routing
+    #[serde(skip_serializing_if = "is_default_weight")]
+    pub weight: u32,
+    /// A disabled server stays registered but is never picked by the router
+    #[serde(skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Headers added to every request forwarded to the server, e.g. the `HTTP-Referer` and
+    /// `X-Title` headers of OpenRouter
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub extra_headers: HashMap<String, String>,
     #[serde(skip)]
     connections: AtomicUsize,
     #[serde(skip)]
+    in_flight: Arc<AtomicUsize>,
+    /// EWMA of the response latency of the server, in milliseconds
+    #[serde(
+        rename = "latency_ms",
+        skip_serializing_if = "LatencyTracker::is_empty"
+    )]
+    latency: Arc<LatencyTracker>,
+    #[serde(skip)]
+    breaker: Arc<CircuitBreaker>,
+    #[serde(skip)]
     pub health_status: HealthStatus,
 }
 impl<'de> Deserialize<'de> for Server {
@@ -65,11 +106,31 @@ impl<'de> Deserialize<'de> for Server {
             url: String,
             kind: ServerKind,
             api_key: Option<String>,
+            weight: Option<u32>,
+            #[serde(default)]
+            extra_headers: HashMap<String, String>,
         }
 
         // Deserialize into the helper struct
         let helper = ServerHelper::deserialize(deserializer)?;
 
+        let weight = helper.weight.unwrap_or(DEFAULT_WEIGHT);
+        if weight == 0 {
+            return Err(serde::de::Error::custom(
+                "The weight of a server must be greater than 0",
+            ));
+        }
+
+        for (name, value) in &helper.extra_headers {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                || reqwest::header::HeaderValue::from_str(value).is_err()
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "Invalid extra header of the server: {name}"
+                )));
+            }
+        }
+
         let kind = helper.kind.to_string().trim().replace(',', "-");
         let id = format!("{}-server-{}", kind, uuid::Uuid::new_v4());
 
@@ -79,7 +140,13 @@ impl<'de> Deserialize<'de> for Server {
             url: helper.url,
             kind: helper.kind,
             api_key: helper.api_key,
+            weight,
+            enabled: true,
+            extra_headers: helper.extra_headers,
             connections: AtomicUsize::new(0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(LatencyTracker::default()),
+            breaker: Arc::new(CircuitBreaker::default()),
             health_status: HealthStatus::default(),
         })
     }
@@ -91,55 +158,318 @@ impl Clone for Server {
             url: self.url.clone(),
             kind: self.kind,
             api_key: self.api_key.clone(),
+            weight: self.weight,
+            enabled: self.enabled,
+            extra_headers: self.extra_headers.clone(),
             connections: AtomicUsize::new(self.connections.load(Ordering::Relaxed)),
+            in_flight: self.in_flight.clone(),
+            latency: Arc::new(self.latency.snapshot()),
+            breaker: Arc::new(CircuitBreaker::new(self.breaker.config)),
             health_status: self.health_status.clone(),
         }
     }
 }
-impl Server {
-    pub(crate) async fn check_health(&mut self) -> bool {
-        // If the server is currently healthy, check if a new health check is needed
-        if self.health_status.is_healthy {
-            let now = SystemTime::now();
-            if let Ok(duration) = now.duration_since(self.health_status.last_check) {
-                let check_interval =
-                    Duration::from_secs(*HEALTH_CHECK_INTERVAL.get().unwrap_or(&60));
-                if duration < check_interval {
-                    // If the time since last check is less than the interval, return current status
-                    return true;
-                }
+/// Tracks an exponentially weighted moving average of the response latency of a server
+#[derive(Debug, Default)]
+pub(crate) struct LatencyTracker {
+    sample: Mutex<Option<LatencySample>>,
+}
+#[derive(Debug, Clone, Copy)]
+struct LatencySample {
+    ewma_ms: f64,
+    updated_at: Instant,
+}
+impl LatencyTracker {
+    /// Fold a new latency measurement into the moving average
+    pub(crate) fn record(&self, latency: Duration) {
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let mut sample = self.sample.lock().unwrap();
+        let ewma_ms = match *sample {
+            Some(previous) => {
+                LATENCY_EWMA_ALPHA * latency_ms + (1.0 - LATENCY_EWMA_ALPHA) * previous.ewma_ms
             }
+            None => latency_ms,
+        };
+        *sample = Some(LatencySample {
+            ewma_ms,
+            updated_at: Instant::now(),
+        });
+    }
+
+    /// The moving average, or `None` if no request has been measured yet
+    pub(crate) fn ewma_ms(&self) -> Option<f64> {
+        self.sample.lock().unwrap().map(|sample| sample.ewma_ms)
+    }
+
+    /// The moving average decayed by the age of the latest measurement, used for routing
+    fn score_ms(&self) -> f64 {
+        match *self.sample.lock().unwrap() {
+            Some(sample) => {
+                let age = sample.updated_at.elapsed().as_secs_f64();
+                sample.ewma_ms * 0.5f64.powf(age / LATENCY_HALF_LIFE)
+            }
+            None => 0.0,
         }
+    }
 
-        // Perform new health check
-        let client = reqwest::Client::new();
-        let health_url = format!("{}/info", self.url);
-
-        // Use configured timeout duration
-        let timeout = Duration::from_secs(TIMEOUT);
-        let is_healthy = match client.get(&health_url).timeout(timeout).send().await {
-            Ok(response) => {
-                // Consider server healthy if response is timeout (408)
-                if response.status() == reqwest::StatusCode::REQUEST_TIMEOUT {
-                    dual_warn!("Health check: {} server {} is in use", self.kind, self.id);
-                    true
-                } else {
-                    response.status().is_success()
-                }
+    fn snapshot(&self) -> Self {
+        Self {
+            sample: Mutex::new(*self.sample.lock().unwrap()),
+        }
+    }
+
+    fn is_empty(latency: &Arc<Self>) -> bool {
+        latency.ewma_ms().is_none()
+    }
+}
+impl Serialize for LatencyTracker {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.ewma_ms().serialize(serializer)
+    }
+}
+
+/// Probe the health of the server at the given base url via its `/info` endpoint, falling back
+/// to `/models` for servers without one. A server that is too busy to answer in time is
+/// considered healthy.
+pub(crate) async fn probe_health(url: &str) -> bool {
+    let client = reqwest::Client::new();
+    let timeout = Duration::from_secs(TIMEOUT);
+    let url = url.trim_end_matches('/');
+
+    for path in ["info", "models"] {
+        match client
+            .get(format!("{url}/{path}"))
+            .timeout(timeout)
+            .send()
+            .await
+        {
+            // the server has no such endpoint: try the next one
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => continue,
+            // consider the server healthy if it is in use
+            Ok(response) if response.status() == reqwest::StatusCode::REQUEST_TIMEOUT => {
+                dual_warn!("Health check: server {} is in use", url);
+                return true;
+            }
+            Ok(response) => return response.status().is_success(),
+            Err(e) if e.is_timeout() => {
+                dual_warn!("Health check: server {} is in use", url);
+                return true;
             }
-            Err(e) => {
-                // Consider server healthy if error is timeout
-                dual_warn!("Health check: {} server {} is in use", self.kind, self.id);
-                e.is_timeout()
+            Err(_) => return false,
+        }
+    }
+
+    false
+}
+
+/// Per-server circuit breaker
+///
+/// The circuit is closed while the server works. Once `failure_threshold` failures happen in a
+/// row within the window, it opens and the router skips the server until the cooldown has
+/// elapsed. The circuit is then half-open: a single probe request is let through, and its
+/// outcome closes or re-opens the circuit.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    config: Option<CircuitBreakerConfig>,
+    state: Mutex<CircuitState>,
+    /// Reference point of `probe_sent_at_ms`
+    epoch: Instant,
+    /// When the probe of the half-open circuit was sent, in milliseconds since `epoch`, or 0 if
+    /// no probe was sent. Claimed with a compare-and-exchange so that a single request probes.
+    probe_sent_at_ms: AtomicU64,
+}
+#[derive(Debug, Default)]
+struct CircuitState {
+    /// Times of the consecutive failures within the window
+    failures: Vec<Instant>,
+    /// When the circuit was opened, if it is open or half-open
+    opened_at: Option<Instant>,
+}
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+impl CircuitBreaker {
+    pub(crate) fn new(config: Option<CircuitBreakerConfig>) -> Self {
+        Self {
+            config,
+            state: Mutex::new(CircuitState::default()),
+            epoch: Instant::now(),
+            probe_sent_at_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Milliseconds elapsed since `epoch`, at least 1 so that 0 means no probe
+    fn now_ms(&self) -> u64 {
+        (self.epoch.elapsed().as_millis() as u64).max(1)
+    }
+
+    /// How long to wait before the server may be sent a request, or `None` if it may be sent one
+    /// now. A half-open circuit allows a single probe, or another one if the previous probe never
+    /// reported back within the cooldown.
+    fn retry_after(&self) -> Option<Duration> {
+        let config = self.config?;
+        let cooldown = Duration::from_secs(config.cooldown_secs);
+
+        let opened_at = self.state.lock().unwrap().opened_at?;
+        if let Some(remaining) = cooldown.checked_sub(opened_at.elapsed())
+            && !remaining.is_zero()
+        {
+            return Some(remaining);
+        }
+
+        match self.probe_sent_at_ms.load(Ordering::Acquire) {
+            0 => None,
+            sent_at_ms => {
+                let elapsed = Duration::from_millis(self.now_ms().saturating_sub(sent_at_ms));
+                cooldown
+                    .checked_sub(elapsed)
+                    .filter(|remaining| !remaining.is_zero())
             }
+        }
+    }
+
+    /// Claim the right to send a request to the server. A closed circuit always allows it; a
+    /// half-open circuit allows it only to the request that wins the probe.
+    fn try_acquire(&self) -> bool {
+        let Some(config) = self.config else {
+            return true;
+        };
+        let cooldown_ms = config.cooldown_secs * 1000;
+
+        let Some(opened_at) = self.state.lock().unwrap().opened_at else {
+            return true;
+        };
+        if opened_at.elapsed() < Duration::from_secs(config.cooldown_secs) {
+            return false;
+        }
+
+        let now_ms = self.now_ms();
+        let sent_at_ms = self.probe_sent_at_ms.load(Ordering::Acquire);
+        if sent_at_ms != 0 && now_ms.saturating_sub(sent_at_ms) < cooldown_ms {
+            return false;
+        }
+        self.probe_sent_at_ms
+            .compare_exchange(sent_at_ms, now_ms, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub(crate) fn record_success(&self) {
+        if self.config.is_none() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.opened_at.is_some() {
+            dual_info!("Circuit breaker closed after a successful probe");
+        }
+        *state = CircuitState::default();
+        self.probe_sent_at_ms.store(0, Ordering::Release);
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let Some(config) = self.config else {
+            return;
         };
+        let window = Duration::from_secs(config.window_secs);
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        // a failed probe re-opens the circuit for another cooldown
+        if state.opened_at.is_some() {
+            state.opened_at = Some(now);
+            self.probe_sent_at_ms.store(0, Ordering::Release);
+            return;
+        }
+
+        state
+            .failures
+            .retain(|failure| now.duration_since(*failure) < window);
+        state.failures.push(now);
+        if state.failures.len() >= config.failure_threshold {
+            dual_warn!(
+                "Circuit breaker opened after {} failures within {}s",
+                state.failures.len(),
+                config.window_secs
+            );
+            state.failures.clear();
+            state.opened_at = Some(now);
+        }
+    }
+}
+
+fn is_default_weight(weight: &u32) -> bool {
+    *weight == DEFAULT_WEIGHT
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+fn add_extra_headers(
+    request: reqwest::RequestBuilder,
+    extra_headers: &HashMap<String, String>,
+) -> reqwest::RequestBuilder {
+    extra_headers
+        .iter()
+        .fold(request, |request, (name, value)| {
+            request.header(name, value)
+        })
+}
+
+impl Server {
+    /// Add the extra headers of the server to a request sent to it
+    pub(crate) fn add_extra_headers(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        add_extra_headers(request, &self.extra_headers)
+    }
+
+    /// Whether the server is healthy. A server marked unhealthy is skipped until the health check
+    /// interval has elapsed, after which it is given another chance.
+    fn is_available(&self) -> bool {
+        if self.health_status.is_healthy {
+            return true;
+        }
+
+        let check_interval = Duration::from_secs(*HEALTH_CHECK_INTERVAL.get().unwrap_or(&60));
+        self.health_status
+            .last_check
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed >= check_interval)
+    }
+
+    /// Number of requests in flight on the server
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Update the health status of the server with the result of a health check
+    pub(crate) fn set_health(&mut self, is_healthy: bool) {
+        if self.health_status.is_healthy && !is_healthy {
+            dual_warn!(
+                "Health check: {} server {} is unhealthy",
+                self.kind,
+                self.id
+            );
+        } else if !self.health_status.is_healthy && is_healthy {
+            dual_info!(
+                "Health check: {} server {} is healthy again",
+                self.kind,
+                self.id
+            );
+        }
 
         self.health_status = HealthStatus {
             is_healthy,
             last_check: SystemTime::now(),
         };
-
-        is_healthy
     }
 
     pub(crate) fn from_chat_config(chat_config: &crate::config::ChatConfig) -> ServerResult<Self> {
@@ -160,7 +490,13 @@ impl Server {
             url: chat_config.url.clone(),
             kind: ServerKind::chat,
             api_key,
+            weight: DEFAULT_WEIGHT,
+            enabled: true,
+            extra_headers: HashMap::new(),
             connections: AtomicUsize::new(0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(LatencyTracker::default()),
+            breaker: Arc::new(CircuitBreaker::default()),
             health_status: HealthStatus::default(),
         })
     }
@@ -185,7 +521,13 @@ impl Server {
             url: embedding_config.url.clone(),
             kind: ServerKind::embeddings,
             api_key,
+            weight: DEFAULT_WEIGHT,
+            enabled: true,
+            extra_headers: HashMap::new(),
             connections: AtomicUsize::new(0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(LatencyTracker::default()),
+            breaker: Arc::new(CircuitBreaker::default()),
             health_status: HealthStatus::default(),
         })
     }
@@ -214,7 +556,13 @@ fn test_serialize_server() {
         url: "http://localhost:8000".to_string(),
         kind: ServerKind::chat | ServerKind::tts,
         api_key: None,
+        weight: DEFAULT_WEIGHT,
+        enabled: true,
+        extra_headers: HashMap::new(),
         connections: AtomicUsize::new(0),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        latency: Arc::new(LatencyTracker::default()),
+        breaker: Arc::new(CircuitBreaker::default()),
         health_status: HealthStatus::default(),
     };
     let serialized = serde_json::to_string(&server).unwrap();
@@ -229,7 +577,13 @@ fn test_serialize_server() {
         url: "http://localhost:8000".to_string(),
         kind: ServerKind::chat,
         api_key: Some("test-api-key".to_string()),
+        weight: DEFAULT_WEIGHT,
+        enabled: true,
+        extra_headers: HashMap::new(),
         connections: AtomicUsize::new(0),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        latency: Arc::new(LatencyTracker::default()),
+        breaker: Arc::new(CircuitBreaker::default()),
         health_status: HealthStatus::default(),
     };
     let serialized = serde_json::to_string(&server).unwrap();
@@ -249,6 +603,8 @@ bitflags! {
         const tts = 1 << 3;
         const translate = 1 << 4;
         const transcribe = 1 << 5;
+        const rerank = 1 << 6;
+        const moderation = 1 << 7;
     }
 }
 impl std::fmt::Display for ServerKind {
@@ -272,6 +628,12 @@ impl std::fmt::Display for ServerKind {
         if self.contains(ServerKind::transcribe) {
             kind_str.push_str("transcribe,");
         }
+        if self.contains(ServerKind::rerank) {
+            kind_str.push_str("rerank,");
+        }
+        if self.contains(ServerKind::moderation) {
+            kind_str.push_str("moderation,");
+        }
 
         if !kind_str.is_empty() {
             kind_str = kind_str.trim_end_matches(',').to_string();
@@ -295,6 +657,8 @@ impl std::str::FromStr for ServerKind {
                 "tts" => kind.set(Self::tts, true),
                 "translate" => kind.set(Self::translate, true),
                 "transcribe" => kind.set(Self::transcribe, true),
+                "rerank" => kind.set(Self::rerank, true),
+                "moderation" => kind.set(Self::moderation, true),
                 _ => return Err(ServerError::InvalidServerKind(s.to_string())),
             }
         }
@@ -326,6 +690,12 @@ impl Serialize for ServerKind {
         if self.contains(ServerKind::transcribe) {
             kind_str.push_str("transcribe,");
         }
+        if self.contains(ServerKind::rerank) {
+            kind_str.push_str("rerank,");
+        }
+        if self.contains(ServerKind::moderation) {
+            kind_str.push_str("moderation,");
+        }
 
         // Remove trailing comma if present
         if !kind_str.is_empty() {
@@ -385,22 +755,85 @@ fn test_deserialize_server_kind() {
     // assert_eq!(kind, ServerKind::vdb);
 }
 
+/// Strategy used by a server group to pick the next downstream server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoutingStrategy {
+    /// Spread requests evenly by picking the server that has been dispatched the fewest requests
+    #[default]
+    #[serde(rename = "round-robin")]
+    RoundRobin,
+    /// Pick the server with the fewest requests currently in flight
+    #[serde(rename = "least-connections")]
+    LeastConnections,
+    /// Dispatch requests in proportion to the server weights using smooth weighted round-robin
+    #[serde(rename = "weighted-round-robin")]
+    WeightedRoundRobin,
+    /// Pick the server with the lowest moving average of recent response latencies
+    #[serde(rename = "lowest-latency")]
+    LowestLatency,
+}
+
 #[derive(Debug)]
 pub(crate) struct ServerGroup {
     pub(crate) servers: RwLock<Vec<RwLock<Server>>>,
     pub(crate) healthy_servers: RwLock<HashSet<ServerId>>,
     ty: ServerKind,
+    strategy: std::sync::RwLock<RoutingStrategy>,
+    /// Current weights of the servers for smooth weighted round-robin
+    current_weights: Mutex<HashMap<ServerId, i64>>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
 }
 impl ServerGroup {
-    pub(crate) fn new(ty: ServerKind) -> Self {
+    pub(crate) fn new(ty: ServerKind, strategy: RoutingStrategy) -> Self {
         Self {
             servers: RwLock::new(Vec::new()),
             healthy_servers: RwLock::new(HashSet::new()),
             ty,
+            strategy: std::sync::RwLock::new(strategy),
+            current_weights: Mutex::new(HashMap::new()),
+            circuit_breaker: None,
+        }
+    }
+
+    /// Change the routing strategy of the group, e.g. after a config reload
+    pub(crate) fn set_strategy(&self, strategy: RoutingStrategy) {
+        *self.strategy.write().unwrap() = strategy;
+    }
+
+    /// Enable the circuit breaker for the servers registered in the group
+    pub(crate) fn with_circuit_breaker(mut self, config: Option<CircuitBreakerConfig>) -> Self {
+        self.circuit_breaker = config;
+        self
+    }
+
+    /// Pick the index of the next server with the smooth weighted round-robin algorithm:
+    /// every server gains its weight, the one with the highest current weight is picked and
+    /// loses the total weight. This keeps the dispatch proportional to the weights while
+    /// interleaving the servers instead of sending bursts to the heaviest one.
+    fn next_weighted(&self, weights: &[(ServerId, u32)]) -> usize {
+        let mut current_weights = self.current_weights.lock().unwrap();
+        current_weights.retain(|id, _| weights.iter().any(|(server_id, _)| server_id == id));
+
+        let total: i64 = weights.iter().map(|(_, weight)| *weight as i64).sum();
+        let mut selected = 0;
+        let mut max_weight = i64::MIN;
+        for (idx, (id, weight)) in weights.iter().enumerate() {
+            let current = current_weights.entry(id.clone()).or_insert(0);
+            *current += *weight as i64;
+            if *current > max_weight {
+                max_weight = *current;
+                selected = idx;
+            }
+        }
+
+        if let Some(current) = current_weights.get_mut(&weights[selected].0) {
+            *current -= total;
         }
+
+        selected
     }
 
-    pub(crate) async fn register(&self, server: Server) -> ServerResult<()> {
+    pub(crate) async fn register(&self, mut server: Server) -> ServerResult<()> {
         // check if the server is already registered
         if self.healthy_servers.read().await.contains(&server.id) {
             let err_msg = format!("Server already registered: {}", server.url);
@@ -408,6 +841,7 @@ impl ServerGroup {
             return Err(ServerError::Operation(err_msg));
         }
 
+        server.breaker = Arc::new(CircuitBreaker::new(self.circuit_breaker));
         self.healthy_servers.write().await.insert(server.id.clone());
         self.servers.write().await.push(RwLock::new(server));
 
@@ -458,10 +892,88 @@ impl ServerGroup {
     pub(crate) async fn is_empty(&self) -> bool {
         self.healthy_servers.read().await.is_empty()
     }
+
+    /// Whether the group has an enabled server that passed its last health check
+    pub(crate) async fn has_healthy_server(&self) -> bool {
+        for server in self.servers.read().await.iter() {
+            let server = server.read().await;
+            if server.enabled && server.health_status.is_healthy {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Number of enabled servers that passed their last health check
+    pub(crate) async fn num_healthy_servers(&self) -> usize {
+        let mut count = 0;
+        for server in self.servers.read().await.iter() {
+            let server = server.read().await;
+            if server.enabled && server.health_status.is_healthy {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Number of requests in flight on the servers of the group
+    pub(crate) async fn in_flight(&self) -> usize {
+        let mut count = 0;
+        for server in self.servers.read().await.iter() {
+            count += server.read().await.in_flight();
+        }
+        count
+    }
+
+    /// Number of servers registered in the group
+    pub(crate) async fn len(&self) -> usize {
+        self.servers.read().await.len()
+    }
+
+    /// Enable or disable a server. Returns false if the server is not in the group
+    pub(crate) async fn set_enabled(&self, server_id: impl AsRef<str>, enabled: bool) -> bool {
+        for server in self.servers.read().await.iter() {
+            if server.read().await.id != server_id.as_ref() {
+                continue;
+            }
+
+            let mut server = server.write().await;
+            dual_info!(
+                "{} {} server {}",
+                if enabled { "Enable" } else { "Disable" },
+                self.ty,
+                server.id
+            );
+            server.enabled = enabled;
+            return true;
+        }
+
+        false
+    }
+
+    /// Mark a server unhealthy so that the router skips it until the next health check
+    pub(crate) async fn mark_unhealthy(&self, server_id: impl AsRef<str>) {
+        for server in self.servers.read().await.iter() {
+            if server.read().await.id != server_id.as_ref() {
+                continue;
+            }
+
+            let mut server = server.write().await;
+            dual_warn!("Mark {} server {} as unhealthy", self.ty, server.id);
+            server.health_status = HealthStatus {
+                is_healthy: false,
+                last_check: SystemTime::now(),
+            };
+            break;
+        }
+    }
 }
 #[async_trait]
 impl RoutingPolicy for ServerGroup {
-    async fn next(&self) -> Result<TargetServerInfo, ServerError> {
+    async fn next_preferring(
+        &self,
+        preferred: &HashSet<ServerId>,
+    ) -> Result<TargetServerInfo, ServerError> {
         let servers = self.servers.read().await;
         if servers.is_empty() {
             let err_msg = format!("No {} server found", self.ty);
@@ -469,36 +981,131 @@ impl RoutingPolicy for ServerGroup {
             return Err(ServerError::NotFoundServer(self.ty.to_string()));
         }
 
-        let server_lock = if servers.len() == 1 {
-            servers.first().unwrap()
-        } else {
-            // Find server with minimum connections - need to read each server
-            let mut min_connections = usize::MAX;
-            let mut min_server = &servers[0];
-
-            for server in servers.iter() {
-                let guard = server.read().await;
-                let connections = guard.connections.load(Ordering::Relaxed);
-                if connections < min_connections {
-                    min_connections = connections;
-                    min_server = server;
+        // Disabled servers are never picked
+        let mut enabled = Vec::with_capacity(servers.len());
+        for server in servers.iter() {
+            if server.read().await.enabled {
+                enabled.push(server);
+            }
+        }
+        if enabled.is_empty() {
+            let err_msg = format!("All {} servers are disabled", self.ty);
+            dual_error!("{}", &err_msg);
+            return Err(ServerError::NotFoundServer(self.ty.to_string()));
+        }
+
+        // Keep the preferred servers, unless none of them is enabled
+        if !preferred.is_empty() {
+            let mut matching = Vec::with_capacity(enabled.len());
+            for server in enabled.iter() {
+                if preferred.contains(&server.read().await.id) {
+                    matching.push(*server);
                 }
             }
-            min_server
-        };
+            if !matching.is_empty() {
+                enabled = matching;
+            }
+        }
+
+        // Never pick a server whose circuit is open
+        let mut closed = Vec::with_capacity(enabled.len());
+        let mut retry_after: Option<Duration> = None;
+        for server in enabled {
+            match server.read().await.breaker.retry_after() {
+                None => closed.push(server),
+                Some(wait) => retry_after = Some(retry_after.map_or(wait, |min| min.min(wait))),
+            }
+        }
+
+        // Skip the servers marked unhealthy, unless none of the servers is available
+        let mut candidates = Vec::with_capacity(closed.len());
+        for server in closed.iter() {
+            if server.read().await.is_available() {
+                candidates.push(*server);
+            }
+        }
+        if candidates.is_empty() {
+            candidates = closed;
+        }
+
+        let strategy = *self.strategy.read().unwrap();
+        loop {
+            if candidates.is_empty() {
+                let retry_after_secs =
+                    retry_after.map_or(1, |wait| wait.as_secs_f64().ceil() as u64);
+                let err_msg = format!(
+                    "The circuits of all the {} servers are open. Retry after {}s",
+                    self.ty, retry_after_secs
+                );
+                dual_error!("{}", &err_msg);
+                return Err(ServerError::CircuitOpen {
+                    kind: self.ty.to_string(),
+                    retry_after_secs,
+                });
+            }
+
+            let index = if candidates.len() == 1 {
+                0
+            } else if strategy == RoutingStrategy::WeightedRoundRobin {
+                let mut weights = Vec::with_capacity(candidates.len());
+                for server in candidates.iter() {
+                    let guard = server.read().await;
+                    weights.push((guard.id.clone(), guard.weight));
+                }
+                self.next_weighted(&weights)
+            } else {
+                // Find the server with minimum load - need to read each server
+                let mut min_load = (usize::MAX, usize::MAX);
+                let mut min_index = 0;
+
+                for (index, server) in candidates.iter().enumerate() {
+                    let guard = server.read().await;
+                    let connections = guard.connections.load(Ordering::Relaxed);
+                    let load = match strategy {
+                        RoutingStrategy::RoundRobin | RoutingStrategy::WeightedRoundRobin => {
+                            (connections, 0)
+                        }
+                        RoutingStrategy::LeastConnections => {
+                            (guard.in_flight.load(Ordering::Relaxed), connections)
+                        }
+                        RoutingStrategy::LowestLatency => (
+                            (guard.latency.score_ms() * 1000.0) as usize,
+                            guard.in_flight.load(Ordering::Relaxed),
+                        ),
+                    };
+                    if load < min_load {
+                        min_load = load;
+                        min_index = index;
+                    }
+                }
+
+                min_index
+            };
+
+            // Access the chosen server. A half-open circuit lets a single request through, so
+            // another server is picked if a concurrent request already took the probe.
+            let server = candidates[index].write().await;
+            if !server.breaker.try_acquire() {
+                drop(server);
+                let server = candidates.remove(index);
+                if let Some(wait) = server.read().await.breaker.retry_after() {
+                    retry_after = Some(retry_after.map_or(wait, |min| min.min(wait)));
+                }
+                continue;
+            }
 
-        // Access the chosen server
-        let target_server_info = {
-            let server = server_lock.write().await;
             server.connections.fetch_add(1, Ordering::Relaxed);
-            TargetServerInfo {
+            return Ok(TargetServerInfo {
                 id: server.id.clone(),
                 url: server.url.clone(),
                 api_key: server.api_key.clone(),
-            }
-        };
-
-        Ok(target_server_info)
+                weight: server.weight,
+                extra_headers: server.extra_headers.clone(),
+                latency: server.latency.clone(),
+                breaker: server.breaker.clone(),
+                _in_flight: Arc::new(InFlightGuard::new(server.in_flight.clone())),
+            });
+        }
     }
 }
 
@@ -508,9 +1115,376 @@ pub struct TargetServerInfo {
     pub id: ServerId,
     pub url: String,
     pub api_key: Option<String>,
+    pub weight: u32,
+    pub extra_headers: HashMap<String, String>,
+    latency: Arc<LatencyTracker>,
+    breaker: Arc<CircuitBreaker>,
+    /// Keeps the request counted as in flight on the server until the last clone is dropped
+    _in_flight: Arc<InFlightGuard>,
+}
+
+impl TargetServerInfo {
+    /// Add the extra headers of the server to a request sent to it
+    pub(crate) fn add_extra_headers(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        add_extra_headers(request, &self.extra_headers)
+    }
+
+    /// Record the response latency of the server for latency-based routing
+    pub(crate) fn record_latency(&self, latency: Duration) {
+        self.latency.record(latency);
+    }
+
+    /// Record a successful request, closing the circuit of the server
+    pub(crate) fn record_success(&self) {
+        self.breaker.record_success();
+    }
+
+    /// Record a failed request, which may open the circuit of the server
+    pub(crate) fn record_failure(&self) {
+        self.breaker.record_failure();
+    }
+}
+
+/// Increments the in-flight counter of a server on creation and decrements it on drop, so
+/// that the count is released on every exit path of a handler, including errors and cancellation
+#[derive(Debug)]
+struct InFlightGuard(Arc<AtomicUsize>);
+impl InFlightGuard {
+    fn new(counter: Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 #[async_trait]
 pub(crate) trait RoutingPolicy: Sync + Send {
-    async fn next(&self) -> Result<TargetServerInfo, ServerError>;
+    #[cfg(test)]
+    async fn next(&self) -> Result<TargetServerInfo, ServerError> {
+        self.next_preferring(&HashSet::new()).await
+    }
+
+    /// Pick the next server among the `preferred` ones, or among all servers if none of the
+    /// preferred ones is enabled
+    async fn next_preferring(
+        &self,
+        preferred: &HashSet<ServerId>,
+    ) -> Result<TargetServerInfo, ServerError>;
+}
+
+#[cfg(test)]
+async fn create_test_group(strategy: RoutingStrategy, urls: &[&str]) -> ServerGroup {
+    let servers = urls
+        .iter()
+        .map(|url| (*url, DEFAULT_WEIGHT))
+        .collect::<Vec<_>>();
+    create_weighted_test_group(strategy, &servers).await
+}
+
+#[cfg(test)]
+async fn create_weighted_test_group(
+    strategy: RoutingStrategy,
+    servers: &[(&str, u32)],
+) -> ServerGroup {
+    let group = ServerGroup::new(ServerKind::chat, strategy);
+    for (url, weight) in servers {
+        let server: Server = serde_json::from_value(
+            serde_json::json!({ "url": url, "kind": "chat", "weight": weight }),
+        )
+        .unwrap();
+        group.register(server).await.unwrap();
+    }
+    group
+}
+
+#[tokio::test]
+async fn test_round_robin_routing() {
+    let group = create_test_group(
+        RoutingStrategy::RoundRobin,
+        &["http://localhost:8000", "http://localhost:8001"],
+    )
+    .await;
+
+    let mut held = Vec::new();
+    for _ in 0..10 {
+        held.push(group.next().await.unwrap());
+    }
+
+    let fast = held
+        .iter()
+        .filter(|target| target.url == "http://localhost:8000")
+        .count();
+    assert_eq!(fast, 5);
+}
+
+#[tokio::test]
+async fn test_least_connections_routing() {
+    let group = create_test_group(
+        RoutingStrategy::LeastConnections,
+        &["http://slow:8000", "http://fast:8001"],
+    )
+    .await;
+
+    // flood the group concurrently: requests to the slow server stay in flight while the
+    // fast server completes its requests almost immediately
+    let group = Arc::new(group);
+    let slow_hits = Arc::new(AtomicUsize::new(0));
+    let mut handles = Vec::new();
+    for _ in 0..20 {
+        let group = group.clone();
+        let slow_hits = slow_hits.clone();
+        handles.push(tokio::spawn(async move {
+            let target = group.next().await.unwrap();
+            if target.url == "http://slow:8000" {
+                slow_hits.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            } else {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    let slow_hits = slow_hits.load(Ordering::Relaxed);
+    assert!(slow_hits < 5, "slow server received {slow_hits} requests");
+
+    // all in-flight counters are released once the requests complete
+    for server in group.servers.read().await.iter() {
+        assert_eq!(server.read().await.in_flight.load(Ordering::Relaxed), 0);
+    }
+}
+
+#[tokio::test]
+async fn test_weighted_round_robin_routing() {
+    let group = create_weighted_test_group(
+        RoutingStrategy::WeightedRoundRobin,
+        &[("http://large:8000", 3), ("http://small:8001", 1)],
+    )
+    .await;
+
+    let mut dispatched = Vec::new();
+    for _ in 0..40 {
+        dispatched.push(group.next().await.unwrap().url);
+    }
+
+    let large = dispatched
+        .iter()
+        .filter(|url| *url == "http://large:8000")
+        .count();
+    assert_eq!(large, 30);
+    assert_eq!(dispatched.len() - large, 10);
+
+    // smooth weighted round-robin interleaves the small server into every window of 4
+    for window in dispatched.chunks(4) {
+        assert_eq!(
+            window
+                .iter()
+                .filter(|url| *url == "http://small:8001")
+                .count(),
+            1
+        );
+    }
+}
+
+#[test]
+fn test_deserialize_server_weight() {
+    let server: Server =
+        serde_json::from_str(r#"{"url": "http://localhost:8000", "kind": "chat"}"#).unwrap();
+    assert_eq!(server.weight, DEFAULT_WEIGHT);
+
+    let server: Server =
+        serde_json::from_str(r#"{"url": "http://localhost:8000", "kind": "chat", "weight": 3}"#)
+            .unwrap();
+    assert_eq!(server.weight, 3);
+
+    let result = serde_json::from_str::<Server>(
+        r#"{"url": "http://localhost:8000", "kind": "chat", "weight": 0}"#,
+    );
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_unhealthy_server_is_skipped() {
+    let group = create_test_group(
+        RoutingStrategy::RoundRobin,
+        &["http://a:8000", "http://b:8001"],
+    )
+    .await;
+
+    let first = group.next().await.unwrap();
+    group.mark_unhealthy(&first.id).await;
+    for _ in 0..4 {
+        assert_ne!(group.next().await.unwrap().id, first.id);
+    }
+
+    // every server is unhealthy: fall back to all of them rather than failing
+    let second = group.next().await.unwrap();
+    group.mark_unhealthy(&second.id).await;
+    assert!(group.next().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_lowest_latency_routing() {
+    let group = create_test_group(
+        RoutingStrategy::LowestLatency,
+        &["http://slow:8000", "http://fast:8001"],
+    )
+    .await;
+
+    // unmeasured servers are probed first
+    let first = group.next().await.unwrap();
+    let second = group.next().await.unwrap();
+    assert_ne!(first.id, second.id);
+    for server in [first, second] {
+        let latency = if server.url == "http://slow:8000" {
+            Duration::from_millis(200)
+        } else {
+            Duration::from_millis(20)
+        };
+        server.record_latency(latency);
+    }
+
+    for _ in 0..5 {
+        assert_eq!(group.next().await.unwrap().url, "http://fast:8001");
+    }
+}
+
+#[test]
+fn test_latency_ewma() {
+    let tracker = LatencyTracker::default();
+    assert!(tracker.ewma_ms().is_none());
+    assert_eq!(tracker.score_ms(), 0.0);
+
+    tracker.record(Duration::from_millis(100));
+    assert_eq!(tracker.ewma_ms(), Some(100.0));
+
+    tracker.record(Duration::from_millis(200));
+    let ewma = tracker.ewma_ms().unwrap();
+    assert!((ewma - 130.0).abs() < 1e-6);
+    assert!(tracker.score_ms() <= ewma);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_skips_failing_server() {
+    let group = ServerGroup::new(ServerKind::chat, RoutingStrategy::RoundRobin)
+        .with_circuit_breaker(Some(CircuitBreakerConfig {
+            failure_threshold: 3,
+            window_secs: 60,
+            cooldown_secs: 1,
+        }));
+    for url in ["http://failing:8000", "http://healthy:8001"] {
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": "chat" })).unwrap();
+        group.register(server).await.unwrap();
+    }
+
+    // the failing server fails three times in a row
+    let mut failures = 0;
+    while failures < 3 {
+        let target = group.next().await.unwrap();
+        if target.url == "http://failing:8000" {
+            target.record_failure();
+            failures += 1;
+        } else {
+            target.record_success();
+        }
+    }
+
+    // the circuit is open: the failing server is skipped during the cooldown
+    for _ in 0..5 {
+        assert_eq!(group.next().await.unwrap().url, "http://healthy:8001");
+    }
+
+    // after the cooldown, a single probe is let through
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let probe = group.next().await.unwrap();
+    assert_eq!(probe.url, "http://failing:8000");
+    for _ in 0..3 {
+        assert_eq!(group.next().await.unwrap().url, "http://healthy:8001");
+    }
+
+    // a successful probe closes the circuit
+    probe.record_success();
+    let mut urls = Vec::new();
+    for _ in 0..4 {
+        urls.push(group.next().await.unwrap().url);
+    }
+    assert!(urls.iter().any(|url| url == "http://failing:8000"));
+}
+
+#[tokio::test]
+async fn test_open_circuits_are_rejected_with_retry_after() {
+    let group = ServerGroup::new(ServerKind::chat, RoutingStrategy::RoundRobin)
+        .with_circuit_breaker(Some(CircuitBreakerConfig {
+            failure_threshold: 1,
+            window_secs: 60,
+            cooldown_secs: 1,
+        }));
+    let server: Server =
+        serde_json::from_value(serde_json::json!({ "url": "http://failing:8000", "kind": "chat" }))
+            .unwrap();
+    group.register(server).await.unwrap();
+
+    // the only server is never picked while its circuit is open
+    group.next().await.unwrap().record_failure();
+    assert!(matches!(
+        group.next().await,
+        Err(ServerError::CircuitOpen {
+            retry_after_secs: 1,
+            ..
+        })
+    ));
+
+    // once half-open, a single one of the concurrent requests is let through as the probe
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let group = Arc::new(group);
+    let handles: Vec<_> = (0..10)
+        .map(|_| {
+            let group = group.clone();
+            tokio::spawn(async move { group.next().await })
+        })
+        .collect();
+    let mut probes = Vec::new();
+    for handle in handles {
+        match handle.await.unwrap() {
+            Ok(target) => probes.push(target),
+            Err(e) => assert!(matches!(e, ServerError::CircuitOpen { .. })),
+        }
+    }
+    assert_eq!(probes.len(), 1);
+}
+
+#[tokio::test]
+async fn test_disabled_server_is_not_selected() {
+    let group = create_test_group(
+        RoutingStrategy::RoundRobin,
+        &["http://server-a:8000", "http://server-b:8001"],
+    )
+    .await;
+    let disabled_id = group.servers.read().await[0].read().await.id.clone();
+
+    assert!(group.set_enabled(&disabled_id, false).await);
+    for _ in 0..6 {
+        assert_eq!(group.next().await.unwrap().url, "http://server-b:8001");
+    }
+
+    // re-enabling puts the server back in rotation right away
+    assert!(group.set_enabled(&disabled_id, true).await);
+    let mut urls = Vec::new();
+    for _ in 0..4 {
+        urls.push(group.next().await.unwrap().url);
+    }
+    assert!(urls.iter().any(|url| url == "http://server-a:8000"));
+
+    assert!(!group.set_enabled("chat-server-unknown", false).await);
 }
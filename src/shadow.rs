@@ -0,0 +1,238 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+
+use crate::{config::ShadowConfig, dual_info, dual_warn};
+
+/// Mirrors a sample of the chat requests to a shadow chat server
+///
+/// The shadow requests are fire-and-forget: they never affect the response returned to the
+/// client, and the shadow responses are discarded once their latency and status are recorded.
+#[derive(Debug)]
+pub(crate) struct ShadowTraffic {
+    config: ShadowConfig,
+    stats: ShadowStats,
+}
+
+/// Counters of the mirrored requests
+#[derive(Debug, Default)]
+pub(crate) struct ShadowStats {
+    pub requests: AtomicU64,
+    pub errors: AtomicU64,
+    pub total_latency_ms: AtomicU64,
+}
+
+impl ShadowTraffic {
+    pub(crate) fn new(config: &ShadowConfig) -> Self {
+        Self {
+            config: config.clone(),
+            stats: ShadowStats::default(),
+        }
+    }
+
+    /// Decide whether the current request is sampled for mirroring
+    pub(crate) fn should_mirror(&self) -> bool {
+        if self.config.sample_rate <= 0.0 {
+            return false;
+        }
+        if self.config.sample_rate >= 1.0 {
+            return true;
+        }
+
+        let (random, _) = uuid::Uuid::new_v4().as_u64_pair();
+        (random as f64 / u64::MAX as f64) < self.config.sample_rate
+    }
+
+    /// Send a copy of the chat request to the shadow server in the background
+    ///
+    /// The copy is always sent in non-stream mode so that the latency covers the whole answer.
+    pub(crate) fn mirror(self: &Arc<Self>, mut request: serde_json::Value, request_id: String) {
+        if let Some(request) = request.as_object_mut() {
+            request.insert("stream".to_string(), serde_json::Value::Bool(false));
+            request.remove("stream_options");
+        }
+
+        let shadow = self.clone();
+        tokio::spawn(async move {
+            let url = format!(
+                "{}/chat/completions",
+                shadow.config.url.trim_end_matches('/')
+            );
+            let mut client = reqwest::Client::new()
+                .post(&url)
+                .header(CONTENT_TYPE, "application/json");
+            if let Some(api_key) = &shadow.config.api_key
+                && !api_key.is_empty()
+            {
+                client = client.header(AUTHORIZATION, format!("Bearer {api_key}"));
+            }
+
+            let start = Instant::now();
+            let result = match client.json(&request).send().await {
+                Ok(response) if response.status().is_success() => {
+                    // read the full answer so that the latency covers the generation
+                    response
+                        .bytes()
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| format!("Failed to read the shadow response: {e}"))
+                }
+                Ok(response) => Err(format!(
+                    "The shadow server returned status {}",
+                    response.status()
+                )),
+                Err(e) => Err(format!("Failed to send the shadow request: {e}")),
+            };
+            let latency_ms = start.elapsed().as_millis() as u64;
+
+            shadow.record(latency_ms, result.is_err());
+            let requests = shadow.stats.requests.load(Ordering::Relaxed);
+            let errors = shadow.stats.errors.load(Ordering::Relaxed);
+            let avg_latency_ms = shadow.stats.total_latency_ms.load(Ordering::Relaxed) / requests;
+            match result {
+                Ok(()) => dual_info!(
+                    "Shadow request completed in {} ms (requests: {}, errors: {}, average latency: {} ms) - request_id: {}",
+                    latency_ms,
+                    requests,
+                    errors,
+                    avg_latency_ms,
+                    request_id
+                ),
+                Err(err_msg) => dual_warn!(
+                    "{} after {} ms (requests: {}, errors: {}, average latency: {} ms) - request_id: {}",
+                    err_msg,
+                    latency_ms,
+                    requests,
+                    errors,
+                    avg_latency_ms,
+                    request_id
+                ),
+            }
+        });
+    }
+
+    fn record(&self, latency_ms: u64, is_error: bool) {
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .total_latency_ms
+            .fetch_add(latency_ms, Ordering::Relaxed);
+        if is_error {
+            self.stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn stats(&self) -> &ShadowStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::AtomicUsize, time::Duration};
+
+    use axum::{
+        Json, Router,
+        extract::{Extension, State},
+        http::{HeaderMap, StatusCode},
+        routing::post,
+    };
+    use endpoints::chat::ChatCompletionObject;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{config::Config, handlers::chat_handler, request_id::RequestId, test_utils::*};
+
+    /// Spawn a chat server answering with the given content and count the requests it receives
+    async fn spawn_chat_server(content: &'static str, hits: Arc<AtomicUsize>) -> String {
+        let router = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let hits = hits.clone();
+                async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    Json(chat_completion_json(content))
+                }
+            }),
+        );
+        spawn_mock_server(router).await
+    }
+
+    #[tokio::test]
+    async fn test_shadow_traffic_does_not_alter_primary_response() {
+        let primary_hits = Arc::new(AtomicUsize::new(0));
+        let shadow_hits = Arc::new(AtomicUsize::new(0));
+        let primary_url = spawn_chat_server("primary answer", primary_hits.clone()).await;
+        let shadow_url = spawn_chat_server("shadow answer", shadow_hits.clone()).await;
+
+        let config = Config {
+            shadow: Some(ShadowConfig {
+                enable: true,
+                url: shadow_url,
+                api_key: None,
+                sample_rate: 1.0,
+            }),
+            ..Default::default()
+        };
+        let state = create_test_state(config, &[(&primary_url, "chat")]).await;
+
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "test-model",
+            "messages": [{ "role": "user", "content": "Hello" }],
+        }))
+        .unwrap();
+        let response = chat_handler(
+            State(state.clone()),
+            Extension(CancellationToken::new()),
+            HeaderMap::new(),
+            RequestId::new(),
+            Json(request),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let chat_completion: ChatCompletionObject = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            chat_completion.choices[0].message.content.as_deref(),
+            Some("primary answer")
+        );
+        assert_eq!(primary_hits.load(Ordering::SeqCst), 1);
+
+        // the shadow request completes in the background
+        let shadow = state.shadow.as_ref().unwrap();
+        for _ in 0..100 {
+            if shadow.stats().requests.load(Ordering::Relaxed) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(shadow_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(shadow.stats().requests.load(Ordering::Relaxed), 1);
+        assert_eq!(shadow.stats().errors.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_sample_rate_bounds() {
+        let create_shadow = |sample_rate| {
+            ShadowTraffic::new(&ShadowConfig {
+                enable: true,
+                url: "http://localhost:10086/v1".to_string(),
+                api_key: None,
+                sample_rate,
+            })
+        };
+
+        assert!(!create_shadow(0.0).should_mirror());
+        assert!(create_shadow(1.0).should_mirror());
+    }
+}
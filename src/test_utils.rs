@@ -0,0 +1,70 @@
+//! Helpers shared by the unit tests that need a running downstream server
+
+use std::sync::Arc;
+
+use axum::Router;
+
+use crate::{AppState, config::Config, info::ServerInfo, server::Server};
+
+/// Serve the router on an ephemeral local port and return its base url
+pub(crate) async fn spawn_mock_server(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    format!("http://{addr}/v1")
+}
+
+/// Return the base url of a local port that refuses connections
+pub(crate) async fn refused_server_url() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    format!("http://{addr}/v1")
+}
+
+/// Create the application state and register a downstream server of the given kind for each url
+pub(crate) async fn create_test_state(config: Config, servers: &[(&str, &str)]) -> Arc<AppState> {
+    let state = Arc::new(AppState::new(config, ServerInfo::default()));
+    for (url, kind) in servers {
+        let server: Server =
+            serde_json::from_value(serde_json::json!({ "url": url, "kind": kind })).unwrap();
+        state.register_downstream_server(server).await.unwrap();
+    }
+    state
+}
+
+/// A minimal chat completion object as returned by a downstream chat server
+pub(crate) fn chat_completion_json(content: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion",
+        "created": 1_700_000_000u64,
+        "model": "test-model",
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "logprobs": null,
+            "finish_reason": "stop"
+        }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+    })
+}
+
+/// A chat completion chunk with the given content delta, as an SSE event
+pub(crate) fn sse_chunk(content: &str) -> String {
+    let chunk = serde_json::json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion.chunk",
+        "created": 1_700_000_000u64,
+        "model": "test-model",
+        "choices": [{
+            "index": 0,
+            "delta": { "role": "assistant", "content": content },
+            "logprobs": null,
+            "finish_reason": null
+        }]
+    });
+    format!("data: {chunk}\n\n")
+}
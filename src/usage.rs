@@ -0,0 +1,134 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use endpoints::common::Usage;
+use serde::Serialize;
+
+/// How long the usage of a user is kept (seconds)
+const USAGE_RETENTION_SECS: i64 = 7 * 24 * 3600;
+
+/// Number of users above which the users without usage within the retention period are dropped
+const MAX_IDLE_USERS: usize = 10_000;
+
+/// Token usage accumulated over a period of time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct UsageTotals {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// Number of chat completions produced for the user, including the intermediate
+    /// completions of tool-call round trips and the answers shared with identical requests
+    pub requests: u64,
+}
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.requests += other.requests;
+    }
+}
+
+/// Per-user token usage, aggregated in one-minute buckets kept for `USAGE_RETENTION_SECS`
+#[derive(Debug, Default)]
+pub(crate) struct UsageTracker {
+    users: Mutex<HashMap<String, BTreeMap<i64, UsageTotals>>>,
+}
+impl UsageTracker {
+    /// Record the usage of a chat completion for the given user at the given unix timestamp
+    pub(crate) fn record(&self, user: &str, usage: &Usage, timestamp: i64) {
+        let totals = UsageTotals {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+            requests: 1,
+        };
+
+        let minute = timestamp.div_euclid(60) * 60;
+        let oldest = minute - USAGE_RETENTION_SECS;
+        let mut users = self.users.lock().unwrap();
+
+        if users.len() >= MAX_IDLE_USERS && !users.contains_key(user) {
+            users.retain(|_, buckets| {
+                buckets
+                    .last_key_value()
+                    .is_some_and(|(minute, _)| *minute >= oldest)
+            });
+        }
+
+        let buckets = users.entry(user.to_string()).or_default();
+        buckets.entry(minute).or_default().add(&totals);
+        *buckets = buckets.split_off(&oldest);
+    }
+
+    /// Sum the usage of the given user between `start` and `end` (unix timestamps, inclusive).
+    /// The bounds are rounded down to the minute.
+    pub(crate) fn totals(&self, user: &str, start: Option<i64>, end: Option<i64>) -> UsageTotals {
+        let start = start.map_or(i64::MIN, |start| start.div_euclid(60) * 60);
+        let end = end.unwrap_or(i64::MAX);
+
+        let mut totals = UsageTotals::default();
+        if start > end {
+            return totals;
+        }
+        if let Some(buckets) = self.users.lock().unwrap().get(user) {
+            for bucket in buckets.range(start..=end).map(|(_, bucket)| bucket) {
+                totals.add(bucket);
+            }
+        }
+
+        totals
+    }
+}
+
+#[test]
+fn test_usage_totals_by_time_range() {
+    let tracker = UsageTracker::default();
+    let usage = |prompt_tokens, completion_tokens| Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    };
+    tracker.record("alice", &usage(10, 5), 1_000);
+    tracker.record("alice", &usage(20, 7), 1_030);
+    tracker.record("alice", &usage(1, 1), 5_000);
+    tracker.record("bob", &usage(3, 3), 1_000);
+
+    let all = tracker.totals("alice", None, None);
+    assert_eq!(all.prompt_tokens, 31);
+    assert_eq!(all.completion_tokens, 13);
+    assert_eq!(all.requests, 3);
+
+    let early = tracker.totals("alice", Some(0), Some(2_000));
+    assert_eq!(early.prompt_tokens, 30);
+    assert_eq!(early.requests, 2);
+
+    assert_eq!(tracker.totals("carol", None, None), UsageTotals::default());
+}
+
+#[test]
+fn test_usage_is_bounded() {
+    let tracker = UsageTracker::default();
+    let usage = Usage {
+        prompt_tokens: 1,
+        completion_tokens: 1,
+        total_tokens: 2,
+    };
+
+    // the usage older than the retention period is forgotten
+    tracker.record("alice", &usage, 0);
+    tracker.record("alice", &usage, USAGE_RETENTION_SECS + 60);
+    assert_eq!(tracker.totals("alice", None, None).requests, 1);
+
+    // the idle users are dropped once there are too many users
+    for idx in 0..MAX_IDLE_USERS {
+        tracker.record(&format!("user-{idx}"), &usage, 0);
+    }
+    tracker.record("bob", &usage, 2 * USAGE_RETENTION_SECS);
+    assert_eq!(tracker.totals("user-0", None, None).requests, 0);
+    assert_eq!(tracker.totals("alice", None, None).requests, 1);
+    assert_eq!(tracker.totals("bob", None, None).requests, 1);
+    assert!(tracker.users.lock().unwrap().len() <= 3);
+}
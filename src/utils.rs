@@ -1,43 +1,109 @@
 use once_cell::sync::OnceCell;
+use serde_json::{Map, Value};
 
 // Global log configuration
 pub(crate) static LOG_DESTINATION: OnceCell<String> = OnceCell::new();
+pub(crate) static LOG_FORMAT: OnceCell<LogFormat> = OnceCell::new();
+
+/// Format of the log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable log lines
+    #[default]
+    Pretty,
+    /// One JSON object per log line, for log aggregation
+    Json,
+}
+
+/// Format a log message and its optional structured fields in the configured log format
+///
+/// In the JSON format, the line is an object with `timestamp`, `level` and `message` keys plus
+/// the fields. In the pretty format, the fields are appended to the message as `key=value` pairs.
+pub fn format_log_line(level: &str, msg: &str, fields: Option<Value>) -> String {
+    let fields = match fields {
+        Some(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+
+    match LOG_FORMAT.get().copied().unwrap_or_default() {
+        LogFormat::Json => {
+            let mut line = Map::new();
+            line.insert(
+                "timestamp".to_string(),
+                Value::from(chrono::Utc::now().to_rfc3339()),
+            );
+            line.insert("level".to_string(), Value::from(level));
+            line.insert("message".to_string(), Value::from(msg));
+            line.extend(fields);
+            Value::Object(line).to_string()
+        }
+        LogFormat::Pretty => {
+            let mut line = msg.to_string();
+            for (key, value) in fields {
+                match value {
+                    Value::Null => continue,
+                    Value::String(value) => line.push_str(&format!(" {key}={value}")),
+                    value => line.push_str(&format!(" {key}={value}")),
+                }
+            }
+            line
+        }
+    }
+}
 
 // Helper macro for dual logging (to both stdout and log file)
+//
+// Structured fields can be attached to the message with `fields: <serializable>;` before the
+// format string.
 #[macro_export]
 macro_rules! dual_log {
-    ($level:expr, $($arg:tt)+) => {{
+    (@emit $level:expr, $fields:expr, $($arg:tt)+) => {{
         let msg = format!($($arg)+);
+        let line = $crate::utils::format_log_line($level, &msg, $fields);
         if $crate::utils::LOG_DESTINATION.get().map_or(false, |d| d == "both") {
-            println!("{}: {}", $level, msg);
+            match $crate::utils::LOG_FORMAT.get() {
+                Some($crate::utils::LogFormat::Json) => println!("{}", line),
+                _ => println!("{}: {}", $level, line),
+            }
         }
         match $level {
-            "INFO" => tracing::info!("{}", msg),
-            "WARN" => tracing::warn!("{}", msg),
-            "ERROR" => tracing::error!("{}", msg),
-            "DEBUG" => tracing::debug!("{}", msg),
-            _ => tracing::trace!("{}", msg),
+            "INFO" => tracing::info!("{}", line),
+            "WARN" => tracing::warn!("{}", line),
+            "ERROR" => tracing::error!("{}", line),
+            "DEBUG" => tracing::debug!("{}", line),
+            _ => tracing::trace!("{}", line),
         }
     }};
+    ($level:expr, fields: $fields:expr; $($arg:tt)+) => {{
+        let fields = serde_json::to_value(&$fields).ok();
+        $crate::dual_log!(@emit $level, fields, $($arg)+)
+    }};
+    ($level:expr, $($arg:tt)+) => {
+        $crate::dual_log!(@emit $level, None, $($arg)+)
+    };
 }
 
 // Convenience macros for each log level
 #[macro_export]
 macro_rules! dual_info {
+    (fields: $fields:expr; $($arg:tt)+) => { $crate::dual_log!("INFO", fields: $fields; $($arg)+) };
     ($($arg:tt)+) => { $crate::dual_log!("INFO", $($arg)+) };
 }
 
 #[macro_export]
 macro_rules! dual_warn {
+    (fields: $fields:expr; $($arg:tt)+) => { $crate::dual_log!("WARN", fields: $fields; $($arg)+) };
     ($($arg:tt)+) => { $crate::dual_log!("WARN", $($arg)+) };
 }
 
 #[macro_export]
 macro_rules! dual_error {
+    (fields: $fields:expr; $($arg:tt)+) => { $crate::dual_log!("ERROR", fields: $fields; $($arg)+) };
     ($($arg:tt)+) => { $crate::dual_log!("ERROR", $($arg)+) };
 }
 
 #[macro_export]
 macro_rules! dual_debug {
+    (fields: $fields:expr; $($arg:tt)+) => { $crate::dual_log!("DEBUG", fields: $fields; $($arg)+) };
     ($($arg:tt)+) => { $crate::dual_log!("DEBUG", $($arg)+) };
 }